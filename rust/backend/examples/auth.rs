@@ -6,39 +6,95 @@ extern crate tokio;
 extern crate warp;
 
 use futures::lazy;
+#[cfg(feature = "webauthn_auth")]
+use futures::{
+    future::{ok, Either},
+    Future,
+};
 use literium::{
+    access::{get_audit_records, FileAudit, HasAuditSink},
     auth::{
         auth_scope,
         native::NativeAuth,
-        oauth2::{self, HasOAuth2Providers, OAuth2Auth, OAuth2Options},
-        otpass::{EmailOTPass, EmailOTPassFormatter, OTPassAuth},
-        stub::{Sessions, UserAuth},
-        HasAuthMethod, HasSessionStorage, HasUserAuth, SessionArg,
+        oauth2::{
+            self, account_scope, has_own_login, HasLoginMethods, HasOAuth2, HasOAuth2Providers,
+            OAuth2Auth, OAuth2Options,
+        },
+        otpass::{
+            EmailOTPass, EmailOTPassFormatter, HasMagicLinkStorage, HasOTPassStorage, MagicLinkAuth,
+            MagicLinkCache, MagicLinkOptions, OTPassAuth, OTPassCache, PhoneOTPass,
+            PhoneOTPassFormatter,
+        },
+        stub::{Sessions, Tokens, UserAuth},
+        totp::{totp_scope, HasTotpOptions, TotpOptions},
+        session_sweeper, token_scope, AuthError, HasAuthMethod, HasNonceStorage,
+        HasProvisionPolicy, HasSessionOptions, HasSessionStorage, HasThrottleOptions,
+        HasThrottleStorage, HasTokenStorage, HasUserAuth, NonceCache, ProvisionOptions, SessionArg,
+        SessionOptions, ThrottleCache, ThrottleOptions, Throttled,
     },
-    base::{BoxFilter, HasFilter},
-    crypto::{CryptoKeys, HasPublicKey, HasSecretKey},
+    base::{BoxFilter, BoxFuture, HasFilter},
+    crypto::{CanKeygen, CryptoKeys, HasPublicKey, HasSecretKey, HasSecureKey, SecureKey},
     dns::{NameResolver, ResolverOptions},
+    filters::{ClientAddrOptions, HasClientAddrOptions},
     http::client::{HasHttpClient, HttpClient},
     mail::{HasMailer, SmtpConfig, SmtpMailer},
+    sms::{HasSmsGateway, LogSmsGateway},
     third::{github, google},
     user::{
+        add_user_data, password_reset_scope, put_user_password,
         stub::{Accounts, UserData, Users},
-        HasAccountStorage, HasUserStorage,
+        HasAccountStorage, HasPasswordPolicy, HasPasswordReset, HasUserStorage, PasswordOptions,
+        PasswordPolicy, PasswordResetOptions, UserId,
+    },
+};
+#[cfg(feature = "webauthn_auth")]
+use literium::auth::{
+    stub::Credentials,
+    webauthn::{
+        has_webauthn_login, webauthn_scope, HasWebAuthn, HasWebAuthnStorage, WebAuthnAuth,
+        WebAuthnOptions,
     },
 };
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::run;
+use tokio::{run, spawn};
 use warp::Filter;
 
+type OTPassMethod = OTPassAuth<
+    State,
+    (
+        EmailOTPass<EmailOTPassFormatter>,
+        PhoneOTPass<PhoneOTPassFormatter>,
+    ),
+>;
+
+#[cfg(feature = "webauthn_auth")]
+type AuthMethod = (
+    Throttled<NativeAuth>,
+    OTPassMethod,
+    OAuth2Auth,
+    MagicLinkAuth<EmailOTPassFormatter>,
+    WebAuthnAuth,
+);
+
+#[cfg(not(feature = "webauthn_auth"))]
 type AuthMethod = (
-    NativeAuth,
-    OTPassAuth<State, EmailOTPass<EmailOTPassFormatter>>,
+    Throttled<NativeAuth>,
+    OTPassMethod,
     OAuth2Auth,
+    MagicLinkAuth<EmailOTPassFormatter>,
 );
 
 pub struct Config {
     server_keys: CryptoKeys,
+    secure_key: SecureKey,
+    session_options: SessionOptions,
+    totp_options: TotpOptions,
+    throttle_options: ThrottleOptions,
+    password_policy: PasswordPolicy,
+    password_reset: PasswordResetOptions,
+    provision_options: ProvisionOptions,
+    client_addr: ClientAddrOptions,
     auth_method: AuthMethod,
 }
 
@@ -46,8 +102,17 @@ pub struct Config {
 pub struct State {
     config: Arc<Config>,
     mailer: SmtpMailer,
+    sms_gateway: LogSmsGateway,
     users: Users,
     sessions: Sessions,
+    tokens: Tokens,
+    nonces: NonceCache,
+    throttle: ThrottleCache,
+    otpass: OTPassCache,
+    magic: MagicLinkCache,
+    #[cfg(feature = "webauthn_auth")]
+    credentials: Credentials,
+    audit: FileAudit,
     accounts: Accounts,
     client: HttpClient<NameResolver>,
     services: Arc<(github::Service, google::Service)>,
@@ -75,6 +140,16 @@ impl HasSecretKey for State {
     type SecretKey = CryptoKeys;
 }
 
+impl AsRef<SecureKey> for State {
+    fn as_ref(&self) -> &SecureKey {
+        &self.config.secure_key
+    }
+}
+
+impl HasSecureKey for State {
+    type SecureKey = SecureKey;
+}
+
 impl AsRef<Users> for State {
     fn as_ref(&self) -> &Users {
         &self.users
@@ -95,6 +170,173 @@ impl HasSessionStorage for State {
     type SessionStorage = Sessions;
 }
 
+impl AsRef<SessionOptions> for State {
+    fn as_ref(&self) -> &SessionOptions {
+        &self.config.session_options
+    }
+}
+
+impl HasSessionOptions for State {}
+
+impl AsRef<ClientAddrOptions> for State {
+    fn as_ref(&self) -> &ClientAddrOptions {
+        &self.config.client_addr
+    }
+}
+
+impl HasClientAddrOptions for State {}
+
+impl AsRef<TotpOptions> for State {
+    fn as_ref(&self) -> &TotpOptions {
+        &self.config.totp_options
+    }
+}
+
+impl HasTotpOptions for State {}
+
+impl AsRef<ThrottleOptions> for State {
+    fn as_ref(&self) -> &ThrottleOptions {
+        &self.config.throttle_options
+    }
+}
+
+impl HasThrottleOptions for State {}
+
+impl AsRef<PasswordPolicy> for State {
+    fn as_ref(&self) -> &PasswordPolicy {
+        &self.config.password_policy
+    }
+}
+
+impl HasPasswordPolicy for State {}
+
+impl AsRef<PasswordResetOptions> for State {
+    fn as_ref(&self) -> &PasswordResetOptions {
+        &self.config.password_reset
+    }
+}
+
+impl HasPasswordReset for State {}
+
+impl AsRef<ProvisionOptions> for State {
+    fn as_ref(&self) -> &ProvisionOptions {
+        &self.config.provision_options
+    }
+}
+
+impl HasProvisionPolicy for State {}
+
+impl AsRef<Tokens> for State {
+    fn as_ref(&self) -> &Tokens {
+        &self.tokens
+    }
+}
+
+impl HasTokenStorage for State {
+    type TokenStorage = Tokens;
+}
+
+impl AsRef<NonceCache> for State {
+    fn as_ref(&self) -> &NonceCache {
+        &self.nonces
+    }
+}
+
+impl HasNonceStorage for State {
+    type NonceStorage = NonceCache;
+}
+
+impl AsRef<ThrottleCache> for State {
+    fn as_ref(&self) -> &ThrottleCache {
+        &self.throttle
+    }
+}
+
+impl HasThrottleStorage for State {
+    type ThrottleStorage = ThrottleCache;
+}
+
+impl AsRef<OTPassCache> for State {
+    fn as_ref(&self) -> &OTPassCache {
+        &self.otpass
+    }
+}
+
+impl HasOTPassStorage for State {
+    type OTPassStorage = OTPassCache;
+}
+
+impl AsRef<MagicLinkCache> for State {
+    fn as_ref(&self) -> &MagicLinkCache {
+        &self.magic
+    }
+}
+
+impl HasMagicLinkStorage for State {
+    type MagicLinkStorage = MagicLinkCache;
+}
+
+#[cfg(feature = "webauthn_auth")]
+impl AsRef<Credentials> for State {
+    fn as_ref(&self) -> &Credentials {
+        &self.credentials
+    }
+}
+
+#[cfg(feature = "webauthn_auth")]
+impl HasWebAuthnStorage for State {
+    type WebAuthnStorage = Credentials;
+}
+
+#[cfg(feature = "webauthn_auth")]
+impl AsRef<WebAuthnAuth> for State {
+    fn as_ref(&self) -> &WebAuthnAuth {
+        &self.config.auth_method.4
+    }
+}
+
+#[cfg(feature = "webauthn_auth")]
+impl HasWebAuthn for State {}
+
+impl AsRef<OAuth2Auth> for State {
+    fn as_ref(&self) -> &OAuth2Auth {
+        &self.config.auth_method.2
+    }
+}
+
+impl HasOAuth2 for State {}
+
+#[cfg(feature = "webauthn_auth")]
+impl HasLoginMethods for State {
+    fn has_other_login(&self, user: UserId) -> BoxFuture<bool, AuthError> {
+        let state = self.clone();
+        Box::new(has_own_login(self, user).and_then(move |own| {
+            if own {
+                Either::A(ok(true))
+            } else {
+                Either::B(has_webauthn_login(&state, user))
+            }
+        }))
+    }
+}
+
+#[cfg(not(feature = "webauthn_auth"))]
+impl HasLoginMethods for State {
+    fn has_other_login(&self, user: UserId) -> BoxFuture<bool, AuthError> {
+        has_own_login(self, user)
+    }
+}
+
+impl AsRef<FileAudit> for State {
+    fn as_ref(&self) -> &FileAudit {
+        &self.audit
+    }
+}
+
+impl HasAuditSink for State {
+    type AuditSink = FileAudit;
+}
+
 impl AsRef<Accounts> for State {
     fn as_ref(&self) -> &Accounts {
         &self.accounts
@@ -111,6 +353,16 @@ impl AsRef<SmtpMailer> for State {
     }
 }
 
+impl AsRef<LogSmsGateway> for State {
+    fn as_ref(&self) -> &LogSmsGateway {
+        &self.sms_gateway
+    }
+}
+
+impl HasSmsGateway for State {
+    type SmsGateway = LogSmsGateway;
+}
+
 impl AsRef<HttpClient<NameResolver>> for State {
     fn as_ref(&self) -> &HttpClient<NameResolver> {
         &self.client
@@ -163,6 +415,7 @@ fn main() {
                 client_id: "github_client_id".into(),
                 client_secret: "github_client_secret".into(),
             },
+            provider: None,
         });
 
         oauth2_options.services.push(oauth2::ClientOptions {
@@ -171,16 +424,51 @@ fn main() {
                 client_id: "google_client_id".into(),
                 client_secret: "google_client_secret".into(),
             },
+            provider: None,
         });
 
+        let native_auth = Throttled::new(NativeAuth, ThrottleOptions::default());
+
+        let otpass_auth = OTPassAuth::new(
+            (
+                EmailOTPass::new(EmailOTPassFormatter),
+                PhoneOTPass::new(PhoneOTPassFormatter),
+            ),
+            Default::default(),
+        );
+
+        let magic_auth = MagicLinkAuth::new(
+            EmailOTPassFormatter,
+            MagicLinkOptions::new("http://localhost:8081/login"),
+        );
+
+        #[cfg(feature = "webauthn_auth")]
         let auth_method = (
-            NativeAuth,
-            OTPassAuth::new(EmailOTPass::new(EmailOTPassFormatter), Default::default()),
+            native_auth,
+            otpass_auth,
             OAuth2Auth::new(oauth2_options),
+            magic_auth,
+            WebAuthnAuth::new(WebAuthnOptions::new("localhost")),
+        );
+
+        #[cfg(not(feature = "webauthn_auth"))]
+        let auth_method = (
+            native_auth,
+            otpass_auth,
+            OAuth2Auth::new(oauth2_options),
+            magic_auth,
         );
 
         let config = Arc::new(Config {
             server_keys,
+            secure_key: SecureKey::gen_key(),
+            session_options: SessionOptions::default(),
+            totp_options: TotpOptions::default(),
+            throttle_options: ThrottleOptions::default(),
+            password_policy: PasswordPolicy::new(PasswordOptions::default()).unwrap(),
+            password_reset: PasswordResetOptions::new("http://localhost:8081/reset"),
+            provision_options: ProvisionOptions::default(),
+            client_addr: ClientAddrOptions::default(),
             auth_method,
         });
 
@@ -190,6 +478,10 @@ fn main() {
 
         let sessions = Sessions::new();
 
+        let tokens = Tokens::new();
+
+        let audit = FileAudit::new("audit.jsonl");
+
         let accounts = Accounts::new();
 
         let mut smtp_config = SmtpConfig::default();
@@ -198,8 +490,17 @@ fn main() {
         let state = State {
             config,
             mailer: SmtpMailer::new(&smtp_config).unwrap(),
+            sms_gateway: LogSmsGateway,
             users,
             sessions,
+            tokens,
+            nonces: NonceCache::new(),
+            throttle: ThrottleCache::new(),
+            otpass: OTPassCache::new(),
+            magic: MagicLinkCache::new(),
+            #[cfg(feature = "webauthn_auth")]
+            credentials: Credentials::new(),
+            audit,
             accounts,
             services: Arc::new((
                 github::Service::new(github::Config::default()),
@@ -208,8 +509,23 @@ fn main() {
             client: HttpClient::new(NameResolver::new(ResolverOptions::default())),
         };
 
+        let auth = auth_scope(&state)
+            .or(token_scope(&state))
+            .or(totp_scope(&state))
+            .or(account_scope(&state))
+            .or(put_user_password(&state));
+
+        #[cfg(feature = "webauthn_auth")]
+        let auth = auth.or(webauthn_scope(&state));
+
         let base = warp::path("auth");
-        let app = base.and(auth_scope(&state));
+        let app = base
+            .and(auth)
+            .or(add_user_data(&state))
+            .or(password_reset_scope(&state))
+            .or(get_audit_records(&state));
+
+        spawn(session_sweeper(&state));
 
         warp::serve(app).bind("0.0.0.0:8081".parse::<SocketAddr>().unwrap())
     }));
@@ -0,0 +1,499 @@
+use super::{AccessError, HasAccess};
+use base::{BoxFuture, IsBackend, TimeStamp};
+use futures::{
+    future::{ok, result, Either},
+    sync::oneshot,
+    Future,
+};
+use serde_json;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Error as IoError, ErrorKind, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    Arc, Mutex, RwLock,
+};
+use std::thread;
+use std::time::Duration;
+use user::UserId;
+use warp::{reject::custom, Rejection};
+
+/// The subject of access decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditSubject {
+    /// User identifier
+    pub user: UserId,
+    /// Session identifier
+    ///
+    /// Zero value means no interactive session (guest or API token).
+    pub sess: u32,
+}
+
+/// Something which can be a subject of access decision
+pub trait IsAuditSubject {
+    /// Get audit subject
+    fn audit_subject(&self) -> AuditSubject;
+}
+
+/// Access decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditDecision {
+    #[serde(rename = "allow")]
+    Allow,
+    #[serde(rename = "deny")]
+    Deny,
+}
+
+/// Access decision audit record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Decision time
+    pub time: TimeStamp,
+    /// Decision subject
+    #[serde(flatten)]
+    pub subject: AuditSubject,
+    /// Accessed object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<String>,
+    /// Requested grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grant: Option<String>,
+    /// Decision
+    pub decision: AuditDecision,
+    /// Reason of denial
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Client IP address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addr: Option<IpAddr>,
+}
+
+impl AuditRecord {
+    /// Create new record with current time
+    pub fn new(subject: AuditSubject, decision: AuditDecision) -> Self {
+        Self {
+            time: TimeStamp::now(),
+            subject,
+            object: None,
+            grant: None,
+            decision,
+            reason: None,
+            addr: None,
+        }
+    }
+
+    /// Set accessed object
+    pub fn with_object<O: Debug>(mut self, object: &O) -> Self {
+        self.object = Some(format!("{:?}", object));
+        self
+    }
+
+    /// Set requested grant
+    pub fn with_grant<G: Debug>(mut self, grant: &G) -> Self {
+        self.grant = Some(format!("{:?}", grant));
+        self
+    }
+
+    /// Set reason of denial
+    pub fn with_reason<R: ToString>(mut self, reason: R) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    /// Set client address
+    pub fn with_addr(mut self, addr: Option<IpAddr>) -> Self {
+        self.addr = addr;
+        self
+    }
+}
+
+/// Audit records query
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuditQuery {
+    /// Only records of this user
+    #[serde(default)]
+    pub user: Option<UserId>,
+    /// Only records created since this time
+    #[serde(default)]
+    pub since: Option<TimeStamp>,
+    /// Only records created until this time
+    #[serde(default)]
+    pub until: Option<TimeStamp>,
+}
+
+impl AuditQuery {
+    /// Check that record matches query
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.user.map(|user| user == record.subject.user).unwrap_or(true)
+            && self.since.map(|since| since <= record.time).unwrap_or(true)
+            && self.until.map(|until| record.time < until).unwrap_or(true)
+    }
+}
+
+/// Audit log options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditOptions {
+    /// Record allowed decisions
+    ///
+    /// The denied decisions is recorded always.
+    /// Disable it to reduce log size on busy servers.
+    #[serde(default = "default_allowed")]
+    pub allowed: bool,
+}
+
+fn default_allowed() -> bool {
+    true
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        Self {
+            allowed: default_allowed(),
+        }
+    }
+}
+
+/// Audit records storage
+pub trait IsAuditSink: IsBackend {
+    /// Append audit record
+    fn put_record(&self, record: AuditRecord) -> BoxFuture<(), Self::Error>;
+
+    /// Find audit records using query
+    fn find_records(&self, query: &AuditQuery) -> BoxFuture<Vec<AuditRecord>, Self::Error>;
+
+    /// Should allowed decisions be recorded
+    fn record_allowed(&self) -> bool {
+        true
+    }
+}
+
+/// Access to audit records storage
+pub trait HasAuditSink
+where
+    Self: AsRef<<Self as HasAuditSink>::AuditSink>,
+{
+    type AuditSink: IsAuditSink;
+}
+
+/// Check access to object with recording decision to audit log
+pub fn audit_access_to<S, A, O, G>(
+    state: &S,
+    addr: Option<IpAddr>,
+    auth: A,
+    object: &O,
+    grant: &G,
+) -> impl Future<Item = A, Error = Rejection>
+where
+    S: HasAuditSink,
+    A: HasAccess<O, G> + IsAuditSubject,
+    O: Debug,
+    G: Debug,
+{
+    let allow = auth.has_access_to(object, grant);
+    let record = AuditRecord::new(auth.audit_subject(), AuditDecision::Allow)
+        .with_object(object)
+        .with_grant(grant);
+    put_decision(state, record.with_addr(addr), auth, allow)
+}
+
+/// Check access with recording decision to audit log
+///
+/// Unlike [`audit_access_to`](access::audit_access_to) the object isn't known yet,
+/// so the object type should be given explicitly.
+pub fn audit_access<S, A, O, G>(
+    state: &S,
+    addr: Option<IpAddr>,
+    auth: A,
+    grant: &G,
+) -> impl Future<Item = A, Error = Rejection>
+where
+    S: HasAuditSink,
+    A: HasAccess<O, G> + IsAuditSubject,
+    G: Debug,
+{
+    let allow = auth.has_access(grant);
+    let record = AuditRecord::new(auth.audit_subject(), AuditDecision::Allow).with_grant(grant);
+    put_decision(state, record.with_addr(addr), auth, allow)
+}
+
+/// Record denial which isn't a result of access check
+///
+/// This is useful for the denials by provisioning policy and similar restrictions.
+/// The failure of audit sink is logged only.
+pub fn audit_denial<S>(state: &S, mut record: AuditRecord) -> impl Future<Item = (), Error = ()>
+where
+    S: HasAuditSink,
+{
+    record.decision = AuditDecision::Deny;
+
+    (state.as_ref() as &S::AuditSink)
+        .put_record(record)
+        .then(|res| {
+            if let Err(error) = res {
+                error!("Unable to put audit record: {}", error);
+            }
+            Ok(())
+        })
+}
+
+fn put_decision<S, A>(
+    state: &S,
+    mut record: AuditRecord,
+    auth: A,
+    allow: bool,
+) -> impl Future<Item = A, Error = Rejection>
+where
+    S: HasAuditSink,
+{
+    let sink = state.as_ref() as &S::AuditSink;
+
+    if allow {
+        if !sink.record_allowed() {
+            return Either::A(ok(auth));
+        }
+    } else {
+        warn!(
+            "Denied access to {:?} with: {:?}",
+            record.object, record.grant
+        );
+        record.decision = AuditDecision::Deny;
+        record.reason = Some(AccessError::Denied.to_string());
+    }
+
+    Either::B(sink.put_record(record).then(move |res| {
+        if let Err(error) = res {
+            error!("Unable to put audit record: {}", error);
+        }
+        if allow {
+            Ok(auth)
+        } else {
+            Err(custom(AccessError::Denied))
+        }
+    }))
+}
+
+/// In-memory audit log
+#[derive(Clone)]
+pub struct MemoryAudit {
+    records: Arc<RwLock<Vec<AuditRecord>>>,
+    options: Arc<AuditOptions>,
+}
+
+impl MemoryAudit {
+    /// Create in-memory audit log
+    pub fn new() -> Self {
+        Self::with_options(AuditOptions::default())
+    }
+
+    /// Create in-memory audit log using options
+    pub fn with_options(options: AuditOptions) -> Self {
+        Self {
+            records: Arc::new(RwLock::new(Vec::new())),
+            options: Arc::new(options),
+        }
+    }
+}
+
+impl IsBackend for MemoryAudit {
+    type Error = IoError;
+}
+
+impl IsAuditSink for MemoryAudit {
+    fn put_record(&self, record: AuditRecord) -> BoxFuture<(), Self::Error> {
+        Box::new(result(
+            self.records
+                .write()
+                .map(|mut records| records.push(record))
+                .map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn find_records(&self, query: &AuditQuery) -> BoxFuture<Vec<AuditRecord>, Self::Error> {
+        Box::new(result(
+            self.records
+                .read()
+                .map(|records| {
+                    records
+                        .iter()
+                        .filter(|record| query.matches(record))
+                        .cloned()
+                        .collect()
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn record_allowed(&self) -> bool {
+        self.options.allowed
+    }
+}
+
+/// Max time to keep written records in buffer
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum FileCommand {
+    Put(AuditRecord),
+    Find(
+        AuditQuery,
+        oneshot::Sender<Result<Vec<AuditRecord>, IoError>>,
+    ),
+}
+
+/** File audit log
+
+Each record is appended to file as single line of JSON.
+
+The file is accessed by dedicated writer thread, so the handlers never block on disk IO.
+The written records is buffered and flushed each second and before search.
+
+*/
+#[derive(Clone)]
+pub struct FileAudit {
+    sender: Arc<Mutex<Sender<FileCommand>>>,
+    options: Arc<AuditOptions>,
+}
+
+impl FileAudit {
+    /// Create file audit log
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self::with_options(path, AuditOptions::default())
+    }
+
+    /// Create file audit log using options
+    pub fn with_options<P: Into<PathBuf>>(path: P, options: AuditOptions) -> Self {
+        let path = path.into();
+        let (sender, receiver) = channel();
+
+        thread::spawn(move || file_audit_proc(&path, receiver));
+
+        Self {
+            sender: Arc::new(Mutex::new(sender)),
+            options: Arc::new(options),
+        }
+    }
+
+    fn send(&self, command: FileCommand) -> Result<(), IoError> {
+        self.sender
+            .lock()
+            .map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock"))?
+            .send(command)
+            .map_err(|_| IoError::new(ErrorKind::Other, "Audit writer stopped"))
+    }
+}
+
+fn file_audit_proc(path: &Path, receiver: Receiver<FileCommand>) {
+    let mut writer: Option<BufWriter<File>> = None;
+    let mut dirty = false;
+
+    loop {
+        let command = if dirty {
+            receiver.recv_timeout(FLUSH_INTERVAL)
+        } else {
+            receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+
+        match command {
+            Ok(FileCommand::Put(record)) => match append_record(path, &mut writer, &record) {
+                Ok(_) => dirty = true,
+                Err(error) => {
+                    error!("Unable to write audit record: {}", error);
+                    // reopen file on next record
+                    writer = None;
+                }
+            },
+            Ok(FileCommand::Find(query, reply)) => {
+                let res = flush_records(&mut writer, &mut dirty)
+                    .and_then(|_| search_records(path, &query));
+                let _ = reply.send(res);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(error) = flush_records(&mut writer, &mut dirty) {
+                    error!("Unable to flush audit records: {}", error);
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                if let Err(error) = flush_records(&mut writer, &mut dirty) {
+                    error!("Unable to flush audit records: {}", error);
+                }
+                break;
+            }
+        }
+    }
+}
+
+fn append_record(
+    path: &Path,
+    writer: &mut Option<BufWriter<File>>,
+    record: &AuditRecord,
+) -> Result<(), IoError> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    if writer.is_none() {
+        *writer = Some(BufWriter::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ));
+    }
+
+    writer.as_mut().unwrap().write_all(&line)
+}
+
+fn flush_records(writer: &mut Option<BufWriter<File>>, dirty: &mut bool) -> Result<(), IoError> {
+    if let Some(writer) = writer {
+        writer.flush()?;
+    }
+    *dirty = false;
+    Ok(())
+}
+
+fn search_records(path: &Path, query: &AuditQuery) -> Result<Vec<AuditRecord>, IoError> {
+    let file = match OpenOptions::new().read(true).open(path) {
+        Ok(file) => file,
+        Err(ref error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+
+    let mut records = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let record: AuditRecord = serde_json::from_str(&line)?;
+        if query.matches(&record) {
+            records.push(record);
+        }
+    }
+
+    Ok(records)
+}
+
+impl IsBackend for FileAudit {
+    type Error = IoError;
+}
+
+impl IsAuditSink for FileAudit {
+    fn put_record(&self, record: AuditRecord) -> BoxFuture<(), Self::Error> {
+        Box::new(result(self.send(FileCommand::Put(record))))
+    }
+
+    fn find_records(&self, query: &AuditQuery) -> BoxFuture<Vec<AuditRecord>, Self::Error> {
+        let (reply, receiver) = oneshot::channel();
+
+        if let Err(error) = self.send(FileCommand::Find(query.clone(), reply)) {
+            return Box::new(result(Err(error)));
+        }
+
+        Box::new(
+            receiver
+                .map_err(|_| IoError::new(ErrorKind::Other, "Audit writer stopped"))
+                .and_then(result),
+        )
+    }
+
+    fn record_allowed(&self) -> bool {
+        self.options.allowed
+    }
+}
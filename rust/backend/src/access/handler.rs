@@ -0,0 +1,54 @@
+use super::{audit_access_to, AuditQuery, Grant, HasAccess, HasAuditSink, IsAuditSink};
+use auth::{
+    AuthError, HasSessionOptions, HasSessionStorage, HasTokenStorage, HasUserAuth, IsTokenAuth,
+};
+use crypto::HasSecretKey;
+use filters::{x_client_addr, HasClientAddrOptions};
+use futures::Future;
+use user::HasUserStorage;
+use warp::{Filter, Rejection, Reply};
+use x_auth;
+
+/// Handle get audit records
+///
+/// The records can be filtered by user and time range using query string
+/// (see [`AuditQuery`](access::AuditQuery)).
+pub fn get_audit_records<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<AuditQuery, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::get2()
+        .and(warp::path("audit"))
+        .and(warp::query::<AuditQuery>())
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |query: AuditQuery, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &query, &Grant::Read).map(move |_| query)
+            }
+        }).and_then(move |query: AuditQuery| {
+            (state.as_ref() as &S::AuditSink)
+                .find_records(&query)
+                .map(|records| warp::reply::json(&records))
+                .map_err(|error| {
+                    error!("Unable to find audit records: {}", error);
+                    warp::reject::custom(AuthError::BackendError)
+                })
+        }).recover(AuthError::recover)
+}
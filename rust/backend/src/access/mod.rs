@@ -108,19 +108,44 @@ impl IsUserRole for UserRole {
 }
 ```
 
+### Access audit
+
+Access decisions made by handlers are recorded to audit log through [`IsAuditSink`](access::IsAuditSink) backend.
+Each [`AuditRecord`](access::AuditRecord) includes subject user and session, accessed object, requested grant, decision with reason, client IP address and time stamp.
+
+There are two audit sinks out of the box:
+
+* [`MemoryAudit`](access::MemoryAudit) which keeps records in memory
+* [`FileAudit`](access::FileAudit) which appends records to file as JSON lines
+
+The denied decisions is recorded always, the recording of allowed decisions can be turned off
+using [`AuditOptions`](access::AuditOptions).
+
  */
 
+mod audit;
+#[cfg(feature = "auth")]
+mod handler;
 mod traits;
 mod types;
 #[macro_use]
 mod macros;
 
+pub use self::audit::*;
+#[cfg(feature = "auth")]
+pub use self::handler::*;
 pub use self::traits::*;
 pub use self::types::*;
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use futures::Future;
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+    use std::path::PathBuf;
+    use std::process;
+    use user::UserId;
 
     #[derive(Clone, Copy)]
     enum UserPerm {
@@ -182,4 +207,170 @@ mod test {
         assert_eq!(admin.has_perm(UserPerm::Edit), true);
         assert_eq!(admin.has_perm(UserPerm::Publish), true);
     }
+
+    #[test]
+    fn scoped_perm() {
+        let roles = vec![UserRole::Editor, UserRole::Director];
+
+        let full = Scoped::<_, Vec<UserRole>>::new(roles.clone());
+
+        assert_eq!(full.has_perm(UserPerm::Edit), true);
+        assert_eq!(full.has_perm(UserPerm::Publish), true);
+
+        let editor = Scoped::new(roles.clone()).with_scope(vec![UserRole::Editor]);
+
+        assert_eq!(editor.has_perm(UserPerm::View), true);
+        assert_eq!(editor.has_perm(UserPerm::Edit), true);
+        assert_eq!(editor.has_perm(UserPerm::Publish), false);
+
+        let guest = Scoped::new(vec![UserRole::Guest]).with_scope(vec![UserRole::Director]);
+
+        assert_eq!(guest.has_perm(UserPerm::View), true);
+        assert_eq!(guest.has_perm(UserPerm::Edit), false);
+        assert_eq!(guest.has_perm(UserPerm::Publish), false);
+    }
+
+    #[test]
+    fn audit_query() {
+        let record = AuditRecord::new(AuditSubject { user: 1, sess: 2 }, AuditDecision::Deny);
+        let time = record.time;
+
+        assert_eq!(AuditQuery::default().matches(&record), true);
+
+        let query = AuditQuery {
+            user: Some(1),
+            since: Some(time),
+            until: Some(time.with_secs(1)),
+        };
+
+        assert_eq!(query.matches(&record), true);
+        assert_eq!(
+            AuditQuery {
+                user: Some(2),
+                ..query.clone()
+            }.matches(&record),
+            false
+        );
+        assert_eq!(
+            AuditQuery {
+                until: Some(time),
+                ..query.clone()
+            }.matches(&record),
+            false
+        );
+    }
+
+    struct Auth;
+
+    impl HasAccess<UserId, Grant> for Auth {
+        fn has_access_to(&self, user: &UserId, grant: &Grant) -> bool {
+            *user == 1 && *grant == Grant::Read
+        }
+    }
+
+    impl IsAuditSubject for Auth {
+        fn audit_subject(&self) -> AuditSubject {
+            AuditSubject { user: 1, sess: 1 }
+        }
+    }
+
+    struct State<A>(A);
+
+    impl<A> AsRef<A> for State<A> {
+        fn as_ref(&self) -> &A {
+            &self.0
+        }
+    }
+
+    impl<A: IsAuditSink> HasAuditSink for State<A> {
+        type AuditSink = A;
+    }
+
+    fn check_access<A: IsAuditSink>(state: &State<A>, user: UserId, grant: Grant) -> bool {
+        audit_access_to(state, None, Auth, &user, &grant)
+            .wait()
+            .is_ok()
+    }
+
+    #[test]
+    fn memory_audit() {
+        let state = State(MemoryAudit::new());
+
+        assert!(check_access(&state, 1, Grant::Read));
+        assert!(!check_access(&state, 2, Grant::Read));
+
+        let records = state.0.find_records(&AuditQuery::default()).wait().unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| (record.object.as_ref().unwrap().as_str(), record.decision))
+                .collect::<Vec<_>>(),
+            vec![("1", AuditDecision::Allow), ("2", AuditDecision::Deny)]
+        );
+        assert_eq!(records[1].reason, Some(AccessError::Denied.to_string()));
+
+        // allowed decisions is not recorded
+        let state = State(MemoryAudit::with_options(AuditOptions { allowed: false }));
+
+        assert!(check_access(&state, 1, Grant::Read));
+        assert!(!check_access(&state, 1, Grant::Delete));
+
+        let records = state.0.find_records(&AuditQuery::default()).wait().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].decision, AuditDecision::Deny);
+        assert_eq!(records[0].grant, Some("Delete".into()));
+    }
+
+    #[test]
+    fn file_audit() {
+        let path = temp_dir().join(format!("literium-audit-{}.jsonl", process::id()));
+        let _ = remove_file(&path);
+        // delete file even when test fails
+        let _file = TempFile(path.clone());
+
+        let state = State(FileAudit::new(&path));
+
+        assert_eq!(
+            state.0.find_records(&AuditQuery::default()).wait().unwrap(),
+            vec![]
+        );
+
+        assert!(check_access(&state, 1, Grant::Read));
+        assert!(!check_access(&state, 2, Grant::Update));
+
+        let records = state.0.find_records(&AuditQuery::default()).wait().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].decision, AuditDecision::Allow);
+        assert_eq!(records[1].decision, AuditDecision::Deny);
+
+        // records is appended to existing file
+        let other = State(FileAudit::with_options(
+            &path,
+            AuditOptions { allowed: false },
+        ));
+
+        assert!(check_access(&other, 1, Grant::Read));
+        assert!(!check_access(&other, 3, Grant::Read));
+
+        let records = other.0.find_records(&AuditQuery::default()).wait().unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| (record.object.as_ref().unwrap().as_str(), record.decision))
+                .collect::<Vec<_>>(),
+            vec![
+                ("1", AuditDecision::Allow),
+                ("2", AuditDecision::Deny),
+                ("3", AuditDecision::Deny),
+            ]
+        );
+    }
+
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = remove_file(&self.0);
+        }
+    }
 }
@@ -160,7 +160,7 @@ where
         Object: Debug,
         Grant: Debug,
     {
-        if self.has_access(grant) {
+        if self.has_access_to(object, grant) {
             Ok(self)
         } else {
             warn!("Denied access to {:?} with: {:?}", object, grant);
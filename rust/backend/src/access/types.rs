@@ -1,23 +1,78 @@
+use super::HasPerm;
 use http::StatusCode;
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use warp::{reply::with_status, Rejection, Reply};
 
 /// Generic resource grants
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Grant {
     /// The ability to read existing resource
+    #[serde(rename = "read")]
     Read,
     /// The ability to create new resource
+    #[serde(rename = "create")]
     Create,
     /// The ability to update existing resource
+    #[serde(rename = "update")]
     Update,
     /// The ability to remove existing resource
+    #[serde(rename = "delete")]
     Delete,
     /// The ability to change special resource properties like owner, creation date and etc.
+    #[serde(rename = "manage")]
     Manage,
 }
 
+impl HasPerm<Grant> for Grant {
+    fn has_perm(&self, grant: Grant) -> bool {
+        *self == grant
+    }
+}
+
+/// Permissions restricted by scope
+///
+/// This wrapper has only the permissions which both roles and scope have,
+/// i.e. effective permissions is an intersection of user roles and scope (of API token for example).
+/// Missing scope means no restrictions.
+#[derive(Debug, Clone)]
+pub struct Scoped<R, S> {
+    /// User roles or permissions
+    pub roles: R,
+    /// Optional restricting scope
+    pub scope: Option<S>,
+}
+
+impl<R, S> Scoped<R, S> {
+    /// Wrap unrestricted roles
+    pub fn new(roles: R) -> Self {
+        Self { roles, scope: None }
+    }
+
+    /// Restrict roles by scope
+    pub fn with_scope(self, scope: S) -> Self {
+        Self {
+            roles: self.roles,
+            scope: Some(scope),
+        }
+    }
+}
+
+impl<R, S, P> HasPerm<P> for Scoped<R, S>
+where
+    R: HasPerm<P>,
+    S: HasPerm<P>,
+    P: Copy,
+{
+    fn has_perm(&self, perm: P) -> bool {
+        self.roles.has_perm(perm) && self
+            .scope
+            .as_ref()
+            .map(|scope| scope.has_perm(perm))
+            .unwrap_or(true)
+    }
+}
+
 /// Access error
 #[derive(Debug, Clone, Copy)]
 pub enum AccessError {
@@ -1,7 +1,8 @@
-use http::StatusCode;
+use base::TimeStamp;
+use httplib::{header::RETRY_AFTER, Response, StatusCode};
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use warp::{reply::with_status, Rejection, Reply};
+use warp::{Rejection, Reply};
 
 /// Authentication error
 #[derive(Debug, Clone, Copy)]
@@ -32,9 +33,26 @@ pub enum AuthError {
     NeedRetry,
     /// Restricted access
     Restricted,
+    /// Too many failed attempts
+    ///
+    /// Contains the time in milliseconds after which client may retry.
+    TooManyAttempts(TimeStamp),
 }
 
 impl AuthError {
+    /// Error is caused by client request
+    ///
+    /// The client errors is reported to client as is, unlike the other errors
+    /// which is reported as `BackendError`.
+    pub fn is_client_error(&self) -> bool {
+        use self::AuthError::*;
+        match self {
+            BadMethod | BadService | BadIdent | BadUser | Restricted | NeedRetry | Outdated
+            | TooManyAttempts(_) => true,
+            _ => false,
+        }
+    }
+
     /// Convert auth error into reply
     pub fn recover(error: Rejection) -> Result<impl Reply, Rejection> {
         if let Some(error) = &error.find_cause::<AuthError>() {
@@ -45,8 +63,16 @@ impl AuthError {
                 BadSession | BadUser | LostSession | Outdated | BadIdent | MissingAuth
                 | BadAuth | Restricted => StatusCode::FORBIDDEN,
                 NeedRetry => StatusCode::CREATED,
+                TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
             };
-            return Ok(with_status(error.to_string(), code));
+            let mut response = Response::builder();
+            response.status(code);
+            if let TooManyAttempts(delay) = error {
+                let delay: i64 = (*delay).into();
+                // round up to seconds
+                response.header(RETRY_AFTER, ((delay + 999) / 1000).to_string());
+            }
+            return Ok(response.body(error.to_string()));
         }
         Err(error)
     }
@@ -71,6 +97,7 @@ impl Display for AuthError {
             BadIdent => f.write_str("Bad user ident"),
             NeedRetry => f.write_str("Retry auth"),
             Restricted => f.write_str("Restricted access"),
+            TooManyAttempts(_) => f.write_str("Too many attempts"),
         }
     }
 }
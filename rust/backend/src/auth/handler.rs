@@ -1,38 +1,87 @@
 use super::{
-    AuthError, AuthInfo, AuthRequest, AuthResponse, BaseSessionData, HasAuthMethod,
-    HasSessionStorage, HasUserAuth, IsAuthMethod, IsSessionData, IsSessionStorage, SessionArg,
-    SessionId, SessionInfo,
+    cancel_throttle_attempt, del_throttle_counter, reserve_throttle_attempt, token_secret,
+    totp::{totp_throttle_key, HasTotpData, HasTotpOptions, TotpIdent, TotpOptions},
+    AuthContext, AuthError, AuthInfo, AuthRequest, AuthResponse, AuthResult, BaseSessionData,
+    BaseTokenData, BearerToken, FactorRequest, FactorRequired, FactorTicket, HasAuthMethod,
+    HasNonceStorage, HasSessionOptions, HasSessionStorage, HasThrottleStorage, HasTokenStorage,
+    HasUserAuth, IsAuthMethod, IsNonceStorage, IsSessionData, IsSessionStorage, IsTokenAuth,
+    IsTokenData, IsTokenStorage, SessionArg, SessionId, SessionInfo, SessionMeta, SessionOptions,
+    TimeInfo, TimeRequest, TokenArg, TokenId, TokenInfo, TokenRequest, TokenResponse,
+};
+use access::{
+    audit_access, audit_access_to, audit_denial, AuditDecision, AuditRecord, Grant, HasAccess,
+    HasAuditSink, IsAuditSubject,
 };
-use access::{Grant, HasAccess};
 use base::{CanCreateView, HasFilter, TimeStamp};
-use crypto::{HasPublicKey, HasSecretKey, PublicKey};
+use crypto::{CanDecrypt, CanEncrypt, HasPublicKey, HasSecretKey, HasSecureKey, PublicKey};
 use futures::{
-    future::{err, Either},
+    future::{err, ok, result, Either},
     Future,
 };
+use std::net::IpAddr;
 use user::{HasUserStorage, IsUserData, IsUserStorage, UserId};
 use warp::{any, Filter, Rejection, Reply};
+use filters::{x_client_addr, x_session_meta, HasClientAddrOptions};
 use {reply, x_auth, x_json};
 
+/// Handle get server time
+///
+/// The lightweight endpoint for client clock synchronization.
+/// Client may pass own time in `ctime` query parameter to estimate round-trip time.
+pub fn get_server_time() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::get2()
+        .and(warp::path("time"))
+        .and(warp::path::end())
+        .and(
+            warp::query::<TimeRequest>()
+                .or(any().map(TimeRequest::default))
+                .unify(),
+        ).map(|req: TimeRequest| {
+            warp::reply::json(&TimeInfo {
+                ctime: req.ctime,
+                stime: TimeStamp::now(),
+            })
+        })
+}
+
 /// Handle get server auth data
 pub fn get_auth_info<S>(
     state: &S,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
 where
-    S: HasPublicKey + HasSecretKey + HasUserAuth + HasSessionStorage + HasAuthMethod + Send + Clone,
+    S: HasPublicKey
+        + HasSecretKey
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasAuthMethod
+        + HasClientAddrOptions
+        + Send
+        + Clone,
     S::PublicKey: AsRef<PublicKey>,
     S::AuthMethod: IsAuthMethod<S>,
-    S::UserAuth: HasAccess<SessionArg, Grant>,
+    S::UserAuth: HasAccess<SessionArg, Grant> + IsTokenAuth<S>,
 {
     let state = state.clone();
     warp::get2()
         .and(x_auth(&state))
-        .and_then(|auth: S::UserAuth| auth.access(&Grant::Create))
-        .map(move |_| warp::reply::json(&get_auth_info_fn(&state)))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |auth: S::UserAuth, addr| {
+                audit_access::<_, _, SessionArg, _>(&state, addr, auth, &Grant::Create)
+                    .map(move |_| addr)
+            }
+        }).map(move |addr| warp::reply::json(&get_auth_info_fn(&state, addr)))
         .recover(AuthError::recover)
 }
 
-fn get_auth_info_fn<S>(state: &S) -> AuthInfo<<S::AuthMethod as IsAuthMethod<S>>::AuthInfo>
+fn get_auth_info_fn<S>(
+    state: &S,
+    addr: Option<IpAddr>,
+) -> AuthInfo<<S::AuthMethod as IsAuthMethod<S>>::AuthInfo>
 where
     S: HasPublicKey + HasUserStorage + HasAuthMethod,
     S::PublicKey: AsRef<PublicKey>,
@@ -40,7 +89,10 @@ where
 {
     AuthInfo::new(
         (state.as_ref() as &S::PublicKey).as_ref().clone(),
-        (state.as_ref() as &S::AuthMethod).get_auth_info(&state),
+        (state.as_ref() as &S::AuthMethod).get_auth_info_from(
+            &state,
+            &AuthContext { addr, pbkey: None },
+        ),
     )
 }
 
@@ -49,29 +101,54 @@ pub fn do_user_auth<S>(state: &S) -> impl Filter<Extract = (impl Reply,), Error
 where
     S: HasPublicKey
         + HasSecretKey
+        + HasSecureKey
         + HasUserStorage
         + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
         + HasSessionStorage
+        + HasNonceStorage
+        + HasTotpOptions
         + HasAuthMethod
         + HasFilter<SessionArg>
+        + HasClientAddrOptions
         + Send
         + Sync
         + Clone,
     S::AuthMethod: IsAuthMethod<S>,
-    <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, SessionArg>,
+    <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, SessionArg> + HasTotpData,
     <S::SessionStorage as IsSessionStorage>::Session: From<(BaseSessionData, S::Arg)>,
-    S::UserAuth: HasAccess<SessionArg, Grant>,
+    S::UserAuth: HasAccess<SessionArg, Grant> + IsTokenAuth<S>,
 {
     let state = state.clone();
 
     warp::post2()
         .and(x_auth(&state))
-        .and_then(|auth: S::UserAuth| auth.access(&Grant::Create))
-        .and(x_json(&state))
+        .and(x_session_meta(&state))
+        .and_then({
+            let state = state.clone();
+            move |auth: S::UserAuth, meta: SessionMeta| {
+                audit_access::<_, _, SessionArg, _>(&state, meta.addr, auth, &Grant::Create)
+                    .map(move |auth| (auth, meta))
+            }
+        }).and(x_json(&state))
         .and(state.filter())
-        .and_then(move |auth, req, extra| {
-            do_user_auth_fn(&state, auth, req, extra)
-                .map(|data| reply::x_json(&data, &state))
+        .and_then(move |(auth, meta): (S::UserAuth, SessionMeta), req, extra| {
+            // the denials by provisioning policy is recorded too
+            let record = AuditRecord::new(auth.audit_subject(), AuditDecision::Deny)
+                .with_grant(&Grant::Create)
+                .with_addr(meta.addr);
+            do_user_auth_fn(&state, auth, req, extra, meta)
+                .or_else({
+                    let state = state.clone();
+                    move |error| match error {
+                        AuthError::Restricted => Either::A(
+                            audit_denial(&state, record.with_reason(error)).then(move |_| Err(error)),
+                        ),
+                        _ => Either::B(err(error)),
+                    }
+                }).map(|data| reply::x_json(&data, &state))
                 .map_err(warp::reject::custom)
         }).recover(AuthError::recover)
 }
@@ -81,72 +158,331 @@ fn do_user_auth_fn<S>(
     auth: S::UserAuth,
     req: AuthRequest<<S::AuthMethod as IsAuthMethod<S>>::UserIdent>,
     extra: S::Arg,
-) -> impl Future<
-    Item = AuthResponse<
-        <<S::UserStorage as IsUserStorage>::User as CanCreateView<S::UserAuth, SessionArg>>::View,
-    >,
-    Error = AuthError,
->
+    meta: SessionMeta,
+) -> impl Future<Item = AuthResult<AuthView<S>>, Error = AuthError>
 where
     S: HasSecretKey
+        + HasSecureKey
         + HasUserStorage
         + HasUserAuth
         + HasSessionStorage
+        + HasSessionOptions
+        + HasNonceStorage
+        + HasTotpOptions
         + HasFilter<SessionArg>
         + HasAuthMethod
         + Send
         + Clone,
     S::AuthMethod: IsAuthMethod<S>,
-    <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, SessionArg>,
+    <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, SessionArg> + HasTotpData,
     <S::SessionStorage as IsSessionStorage>::Session: From<(BaseSessionData, S::Arg)>,
 {
-    if TimeStamp::now().abs_delta(&req.ctime) > TimeStamp::default().with_secs(3) {
+    let options = state.as_ref() as &SessionOptions;
+
+    if TimeStamp::now().abs_delta(&req.ctime) > options.clock_skew {
         return Either::A(err(AuthError::Outdated));
     }
 
+    let nonce = if let Some(nonce) = &req.nonce {
+        Either::A(
+            (state.as_ref() as &S::NonceStorage)
+                .use_nonce(nonce, req.ctime + options.clock_skew)
+                .map_err(|error| {
+                    error!("Backend error on use_nonce(): {}", error);
+                    AuthError::BackendError
+                }).and_then(|fresh| {
+                    if fresh {
+                        Ok(())
+                    } else {
+                        error!("Reused nonce");
+                        Err(AuthError::Outdated)
+                    }
+                }),
+        )
+    } else if options.require_nonce {
+        return Either::A(err(AuthError::BadAuth));
+    } else {
+        Either::B(ok(()))
+    };
+
     let state = state.clone();
     let ctime = req.ctime;
     let pbkey = req.pbkey;
+    let ctx = AuthContext {
+        addr: meta.addr,
+        pbkey: Some(pbkey),
+    };
+    let meta = SessionMeta {
+        device: req.device.clone(),
+        ..meta
+    };
 
     Either::B(
-        (state.as_ref() as &S::AuthMethod)
-            .try_user_auth(&state, &req.ident)
-            .map_err(|error| {
-                error!("Backend error on check_user_ident(): {}", error);
-                AuthError::BackendError
+        nonce
+            .and_then({
+                let state = state.clone();
+                move |_| {
+                    (state.as_ref() as &S::AuthMethod)
+                        .try_user_auth_from(&state, &req.ident, &ctx)
+                        .map_err(|error| {
+                            if error.is_client_error() {
+                                error
+                            } else {
+                                error!("Backend error on check_user_ident(): {}", error);
+                                AuthError::BackendError
+                            }
+                        })
+                }
             }).and_then(move |user| {
-                (state.as_ref() as &S::SessionStorage)
-                    .find_user_session(user.get_user_id(), ctime)
-                    .map_err(|error| {
-                        error!("Backend error on find_user_session(): {}", error);
-                        AuthError::BackendError
-                    }).and_then(move |sess| {
-                        if sess.is_some() {
-                            Either::A(err(AuthError::Outdated))
-                        } else {
-                            let session = <S::SessionStorage as IsSessionStorage>::Session::from((
-                                BaseSessionData::new(user.get_user_id(), pbkey),
+                if user.has_totp_enabled() {
+                    Either::A(result(
+                        new_factor_ticket(&state, user.get_user_id(), pbkey, ctime, meta)
+                            .map(AuthResult::Factor),
+                    ))
+                } else {
+                    Either::B(
+                        new_user_session(&state, auth, user, pbkey, ctime, meta, extra)
+                            .map(AuthResult::Done),
+                    )
+                }
+            }),
+    )
+}
+
+type AuthView<S> = <<<S as HasUserStorage>::UserStorage as IsUserStorage>::User as CanCreateView<
+    <S as HasUserAuth>::UserAuth,
+    SessionArg,
+>>::View;
+
+fn new_factor_ticket<S>(
+    state: &S,
+    user: UserId,
+    pbkey: PublicKey,
+    ctime: TimeStamp,
+    meta: SessionMeta,
+) -> Result<FactorRequired, AuthError>
+where
+    S: HasSecureKey + HasTotpOptions,
+{
+    let etime = TimeStamp::now() + (state.as_ref() as &TotpOptions).ticket_time;
+
+    (state.as_ref() as &S::SecureKey)
+        .seal_json_b64(&FactorTicket {
+            user,
+            pbkey,
+            ctime,
+            meta,
+            etime,
+        }).map(|ticket| FactorRequired {
+            factor: "totp".into(),
+            ticket,
+            etime,
+        }).map_err(|error| {
+            error!("Unable to seal factor ticket: {}", error);
+            AuthError::BackendError
+        })
+}
+
+fn new_user_session<S>(
+    state: &S,
+    auth: S::UserAuth,
+    user: <S::UserStorage as IsUserStorage>::User,
+    pbkey: PublicKey,
+    ctime: TimeStamp,
+    meta: SessionMeta,
+    extra: S::Arg,
+) -> impl Future<Item = AuthResponse<AuthView<S>>, Error = AuthError>
+where
+    S: HasUserStorage + HasUserAuth + HasSessionStorage + HasFilter<SessionArg> + Send + Clone,
+    <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, SessionArg>,
+    <S::SessionStorage as IsSessionStorage>::Session: From<(BaseSessionData, S::Arg)>,
+{
+    let state = state.clone();
+
+    (state.as_ref() as &S::SessionStorage)
+        .find_user_session(user.get_user_id(), ctime)
+        .map_err(|error| {
+            error!("Backend error on find_user_session(): {}", error);
+            AuthError::BackendError
+        }).and_then(move |sess| {
+            if sess.is_some() {
+                Either::A(err(AuthError::Outdated))
+            } else {
+                let session = <S::SessionStorage as IsSessionStorage>::Session::from((
+                    BaseSessionData::new(user.get_user_id(), pbkey).with_meta(meta),
+                    extra,
+                ));
+                Either::B(
+                    (state.as_ref() as &S::SessionStorage)
+                        .put_user_session(session)
+                        .map_err(|error| {
+                            error!("Backend error on new_user_session(): {}", error);
+                            AuthError::BackendError
+                        }).map(move |session| {
+                            let extra = user.create_view(&auth);
+                            let data = session.session_data();
+                            AuthResponse {
+                                user: user.get_user_id(),
+                                sess: data.sess,
+                                token: data.token.clone(),
                                 extra,
-                            ));
-                            Either::B(
-                                (state.as_ref() as &S::SessionStorage)
-                                    .put_user_session(session)
-                                    .map_err(|error| {
-                                        error!("Backend error on new_user_session(): {}", error);
-                                        AuthError::BackendError
-                                    }).map(move |session| {
-                                        let extra = user.create_view(&auth);
-                                        let data = session.session_data();
-                                        AuthResponse {
-                                            user: user.get_user_id(),
-                                            sess: data.sess,
-                                            token: data.token.clone(),
-                                            extra,
-                                        }
-                                    }),
-                            )
+                            }
+                        }),
+                )
+            }
+        })
+}
+
+/// Handle second factor requests
+pub fn do_user_factor<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasPublicKey
+        + HasSecretKey
+        + HasSecureKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasThrottleStorage
+        + HasTotpOptions
+        + HasFilter<SessionArg>
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, SessionArg> + HasTotpData,
+    <S::SessionStorage as IsSessionStorage>::Session: From<(BaseSessionData, S::Arg)>,
+    S::UserAuth: HasAccess<SessionArg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::post2()
+        .and(warp::path("factor"))
+        .and(warp::path::end())
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |auth: S::UserAuth, addr| {
+                audit_access::<_, _, SessionArg, _>(&state, addr, auth, &Grant::Create)
+            }
+        }).and(x_json(&state))
+        .and(state.filter())
+        .and_then(move |auth, req, extra| {
+            do_user_factor_fn(&state, auth, req, extra)
+                .map({
+                    let state = state.clone();
+                    move |data| reply::x_json(&data, &state)
+                }).map_err(warp::reject::custom)
+        }).recover(AuthError::recover)
+}
+
+fn do_user_factor_fn<S>(
+    state: &S,
+    auth: S::UserAuth,
+    req: FactorRequest<TotpIdent>,
+    extra: S::Arg,
+) -> impl Future<Item = AuthResponse<AuthView<S>>, Error = AuthError>
+where
+    S: HasSecureKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionStorage
+        + HasThrottleStorage
+        + HasTotpOptions
+        + HasFilter<SessionArg>
+        + Send
+        + Clone,
+    <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, SessionArg> + HasTotpData,
+    <S::SessionStorage as IsSessionStorage>::Session: From<(BaseSessionData, S::Arg)>,
+{
+    let ticket: FactorTicket = match (state.as_ref() as &S::SecureKey).open_json_b64(&req.ticket) {
+        Ok(ticket) => ticket,
+        Err(error) => {
+            error!("Unable to open factor ticket: {}", error);
+            return Either::A(err(AuthError::BadAuth));
+        }
+    };
+
+    if ticket.etime < TimeStamp::now() {
+        return Either::A(err(AuthError::Outdated));
+    }
+
+    let state = state.clone();
+    let options = (state.as_ref() as &TotpOptions).clone();
+    // failures are counted per user during ticket life time
+    let keys = vec![totp_throttle_key(ticket.user)];
+
+    Either::B(
+        reserve_throttle_attempt(&state, &options.throttle_options(), &keys)
+            .and_then({
+                let state = state.clone();
+                move |_| {
+                    (state.as_ref() as &S::UserStorage)
+                        .get_user_data(ticket.user)
+                        .map_err(|error| {
+                            error!("Backend error on get_user_data(): {}", error);
+                            AuthError::BackendError
+                        }).and_then(|user| user.ok_or(AuthError::BadUser))
+                        .map(move |user| (user, ticket))
+                        .or_else({
+                            let state = state.clone();
+                            let keys = keys.clone();
+                            move |error| {
+                                cancel_throttle_attempt(&state, &keys).and_then(move |_| err(error))
+                            }
+                        }).map(move |(user, ticket)| (user, ticket, keys))
+                }
+            }).and_then(move |(mut user, ticket, keys)| {
+                let data = user.get_totp_data().filter(|data| data.enabled).cloned();
+                let data = data.and_then(|mut data| {
+                    let valid = match &req.ident {
+                        TotpIdent::Totp { code } => {
+                            data.verify_code(&options, code, TimeStamp::now())
                         }
-                    })
+                        TotpIdent::Recovery { code } => data.use_recovery_code(code),
+                    };
+                    if valid {
+                        Some(data)
+                    } else {
+                        None
+                    }
+                });
+
+                if let Some(data) = data {
+                    user.set_totp_data(Some(data));
+                    Either::A(
+                        (state.as_ref() as &S::UserStorage)
+                            .put_user_data(user)
+                            .map_err(|error| {
+                                error!("Backend error on put_user_data(): {}", error);
+                                AuthError::BackendError
+                            }).and_then({
+                                let state = state.clone();
+                                move |user| {
+                                    del_throttle_counter(&state, &keys[0]).map(move |_| user)
+                                }
+                            }).and_then(move |user| {
+                                new_user_session(
+                                    &state,
+                                    auth,
+                                    user,
+                                    ticket.pbkey,
+                                    ticket.ctime,
+                                    ticket.meta,
+                                    extra,
+                                )
+                            }),
+                    )
+                } else {
+                    // the failure is already counted
+                    warn!("Invalid second factor");
+                    Either::B(err(AuthError::BadIdent))
+                }
             }),
     )
 }
@@ -156,8 +492,18 @@ pub fn get_user_sessions<S>(
     state: &S,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
 where
-    S: HasSecretKey + HasUserStorage + HasUserAuth + HasSessionStorage + Send + Sync + Clone,
-    S::UserAuth: HasAccess<SessionArg, Grant>,
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<SessionArg, Grant> + IsTokenAuth<S>,
 {
     let state = state.clone();
 
@@ -165,9 +511,13 @@ where
         .and(warp::path::param()) // user id
         .and(warp::path("session"))
         .and(x_auth(&state))
-        .and_then(|user, auth: S::UserAuth| {
-            auth.access_to(&SessionArg { user }, &Grant::Read)
-                .map(|_| user)
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &SessionArg { user }, &Grant::Read)
+                    .map(move |_| user)
+            }
         }).and_then(move |user| {
             get_user_sessions_fn(&state, user)
                 .map_err(warp::reject::custom)
@@ -200,8 +550,18 @@ pub fn del_user_sessions<S>(
     state: &S,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
 where
-    S: HasSecretKey + HasUserStorage + HasUserAuth + HasSessionStorage + Send + Sync + Clone,
-    S::UserAuth: HasAccess<SessionArg, Grant>,
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<SessionArg, Grant> + IsTokenAuth<S>,
 {
     let state = state.clone();
 
@@ -215,9 +575,13 @@ where
                 .unify(),
         ) // session id
         .and(x_auth(&state))
-        .and_then(|user, session, auth: S::UserAuth| {
-            auth.access_to(&SessionArg { user }, &Grant::Delete)
-                .map(|_| (user, session))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, session, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &SessionArg { user }, &Grant::Delete)
+                    .map(move |_| (user, session))
+            }
         }).untuple_one()
         .and_then(move |user, session| {
             del_user_sessions_fn(&state, user, session)
@@ -258,27 +622,487 @@ where
     }
 }
 
+type TokenScope<S> =
+    <<<S as HasTokenStorage>::TokenStorage as IsTokenStorage>::Token as IsTokenData>::Scope;
+
+/// Handle get user API tokens
+pub fn get_user_tokens<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<TokenArg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::get2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("token"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &TokenArg { user }, &Grant::Read)
+                    .map(move |_| user)
+            }
+        }).and_then(move |user| {
+            get_user_tokens_fn(&state, user)
+                .map_err(warp::reject::custom)
+                .map(|tokens| warp::reply::json(&tokens))
+        }).recover(AuthError::recover)
+}
+
+fn get_user_tokens_fn<S>(
+    state: &S,
+    user: UserId,
+) -> impl Future<Item = Vec<TokenInfo<TokenScope<S>>>, Error = AuthError>
+where
+    S: HasUserStorage + HasTokenStorage + HasUserAuth + Send + Sync + Clone,
+{
+    (state.as_ref() as &S::TokenStorage)
+        .get_user_tokens(user)
+        .map(|tokens| {
+            tokens
+                .iter()
+                .map(|token| (token.token_data(), token.token_scope()).into())
+                .collect()
+        }).map_err(|error| {
+            error!("Unable to get user tokens: {}", error);
+            AuthError::BackendError
+        })
+}
+
+/// Handle create user API token
+pub fn add_user_token<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<TokenArg, Grant> + IsTokenAuth<S>,
+    <S::TokenStorage as IsTokenStorage>::Token: From<(BaseTokenData, TokenScope<S>)>,
+{
+    let state = state.clone();
+
+    warp::post2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("token"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &TokenArg { user }, &Grant::Create)
+                    .map(move |_| user)
+            }
+        }).and(warp::body::json())
+        .and_then(move |user, req: TokenRequest<TokenScope<S>>| {
+            add_user_token_fn(&state, user, req)
+                .map_err(warp::reject::custom)
+                .map(|token| warp::reply::json(&token))
+        }).recover(AuthError::recover)
+}
+
+fn add_user_token_fn<S>(
+    state: &S,
+    user: UserId,
+    req: TokenRequest<TokenScope<S>>,
+) -> impl Future<Item = TokenResponse, Error = AuthError>
+where
+    S: HasUserStorage + HasTokenStorage + HasUserAuth + Send + Sync + Clone,
+    <S::TokenStorage as IsTokenStorage>::Token: From<(BaseTokenData, TokenScope<S>)>,
+{
+    let TokenRequest { name, scope, ttl } = req;
+    let secret = token_secret();
+    let token = <S::TokenStorage as IsTokenStorage>::Token::from((
+        BaseTokenData::new(user, name, &secret, ttl),
+        scope,
+    ));
+
+    (state.as_ref() as &S::TokenStorage)
+        .put_user_token(token)
+        .map(move |token| {
+            let data = token.token_data();
+            TokenResponse {
+                id: data.id,
+                token: BearerToken::new(data, secret).to_string(),
+            }
+        }).map_err(|error| {
+            error!("Unable to put user token: {}", error);
+            AuthError::BackendError
+        })
+}
+
+/// Handle delete user API token
+pub fn del_user_token<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<TokenArg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::delete2()
+        .and(warp::path::param::<UserId>()) // user id
+        .and(warp::path("token"))
+        .and(warp::path::param::<TokenId>()) // token id
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, token, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &TokenArg { user }, &Grant::Delete)
+                    .map(move |_| (user, token))
+            }
+        }).and_then(move |(user, token)| {
+            (state.as_ref() as &S::TokenStorage)
+                .del_user_token(user, token)
+                .map_err(|error| {
+                    error!("Unable to delete user token: {}", error);
+                    warp::reject::custom(AuthError::BackendError)
+                }).and_then(|res| {
+                    res.map(|_| warp::reply())
+                        .ok_or_else(warp::reject::not_found)
+                })
+        }).recover(AuthError::recover)
+}
+
+/// Scope with user API tokens handlers
+pub fn token_scope<S>(state: &S) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasUserStorage
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<TokenArg, Grant> + IsTokenAuth<S>,
+    <S::TokenStorage as IsTokenStorage>::Token: From<(BaseTokenData, TokenScope<S>)>,
+{
+    get_user_tokens(state)
+        .or(add_user_token(state))
+        .or(del_user_token(state))
+}
+
 /// Scope with user auth handlers
 pub fn auth_scope<S>(state: &S) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
 where
     S: HasPublicKey
         + HasSecretKey
+        + HasSecureKey
         + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
         + HasUserStorage
         + HasSessionStorage
+        + HasNonceStorage
+        + HasThrottleStorage
+        + HasTotpOptions
         + HasFilter<SessionArg>
         + HasAuthMethod
+        + HasClientAddrOptions
         + Send
         + Sync
         + Clone,
     S::PublicKey: AsRef<PublicKey>,
     S::AuthMethod: IsAuthMethod<S>,
-    S::UserAuth: HasAccess<SessionArg, Grant>,
-    <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, SessionArg>,
+    S::UserAuth: HasAccess<SessionArg, Grant> + IsTokenAuth<S>,
+    <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, SessionArg> + HasTotpData,
     <S::SessionStorage as IsSessionStorage>::Session: From<(BaseSessionData, S::Arg)>,
 {
-    get_auth_info(state)
+    get_server_time()
+        .or(get_auth_info(state))
+        .or(do_user_factor(state))
         .or(do_user_auth(state))
         .or(get_user_sessions(state))
         .or(del_user_sessions(state))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use access::{AccessError, HasAuditSink, MemoryAudit};
+    use auth::{
+        stub::{SessionData, Sessions, Tokens, UserAuth},
+        AuthData, HasSessionOptions,
+    };
+    use crypto::CryptoKeys;
+    use filters::ClientAddrOptions;
+    use httplib::StatusCode;
+    use serde_json::from_slice;
+    use std::sync::Arc;
+    use user::stub::{UserData, Users};
+    use warp::test::request;
+
+    #[derive(Clone)]
+    struct State {
+        keys: Arc<CryptoKeys>,
+        users: Users,
+        sessions: Sessions,
+        session_options: Arc<SessionOptions>,
+        tokens: Tokens,
+        audit: MemoryAudit,
+        client_addr: Arc<ClientAddrOptions>,
+    }
+
+    impl AsRef<ClientAddrOptions> for State {
+        fn as_ref(&self) -> &ClientAddrOptions {
+            &self.client_addr
+        }
+    }
+
+    impl HasClientAddrOptions for State {}
+
+    impl AsRef<CryptoKeys> for State {
+        fn as_ref(&self) -> &CryptoKeys {
+            &self.keys
+        }
+    }
+
+    impl HasSecretKey for State {
+        type SecretKey = CryptoKeys;
+    }
+
+    impl AsRef<Users> for State {
+        fn as_ref(&self) -> &Users {
+            &self.users
+        }
+    }
+
+    impl HasUserStorage for State {
+        type UserStorage = Users;
+    }
+
+    impl AsRef<Sessions> for State {
+        fn as_ref(&self) -> &Sessions {
+            &self.sessions
+        }
+    }
+
+    impl HasSessionStorage for State {
+        type SessionStorage = Sessions;
+    }
+
+    impl AsRef<SessionOptions> for State {
+        fn as_ref(&self) -> &SessionOptions {
+            &self.session_options
+        }
+    }
+
+    impl HasSessionOptions for State {}
+
+    impl AsRef<Tokens> for State {
+        fn as_ref(&self) -> &Tokens {
+            &self.tokens
+        }
+    }
+
+    impl HasTokenStorage for State {
+        type TokenStorage = Tokens;
+    }
+
+    impl AsRef<MemoryAudit> for State {
+        fn as_ref(&self) -> &MemoryAudit {
+            &self.audit
+        }
+    }
+
+    impl HasAuditSink for State {
+        type AuditSink = MemoryAudit;
+    }
+
+    impl HasUserAuth for State {
+        type UserAuth = UserAuth;
+    }
+
+    #[test]
+    fn user_tokens() {
+        let client_keys = CryptoKeys::default();
+        let sessions = Sessions::new();
+        let session = sessions
+            .put_user_session(SessionData::new(
+                1,
+                (client_keys.as_ref() as &PublicKey).clone(),
+            )).wait()
+            .unwrap();
+
+        let state = State {
+            keys: Arc::new(CryptoKeys::default()),
+            users: Users::new()
+                .with_user(UserData::new(1, "yumi"))
+                .with_user(UserData::new(2, "kayo")),
+            sessions,
+            session_options: Arc::new(SessionOptions::default()),
+            client_addr: Arc::new(ClientAddrOptions::default()),
+            tokens: Tokens::new(),
+            audit: MemoryAudit::new(),
+        };
+
+        let mut serno = session.serno;
+        let mut auth_header = || {
+            let auth = AuthData {
+                user: session.user,
+                sess: session.sess,
+                token: session.token.clone(),
+                serno,
+            };
+            serno += 1;
+            state.keys.seal_json_b64(&auth).unwrap()
+        };
+
+        let app = token_scope(&state);
+
+        let add_token = |auth: String, name: &str| {
+            let res = request()
+                .method("POST")
+                .path("/1/token")
+                .header("x-auth", auth)
+                .json(&TokenRequest {
+                    name: name.into(),
+                    scope: vec![Grant::Read],
+                    ttl: None,
+                }).reply(&app);
+            assert_eq!(res.status(), StatusCode::OK);
+            from_slice::<TokenResponse>(res.body()).unwrap()
+        };
+
+        let first = add_token(auth_header(), "ci");
+        let second = add_token(auth_header(), "backup");
+        assert_eq!(first.id, 1);
+        assert_eq!(second.id, 2);
+
+        let res = request()
+            .method("DELETE")
+            .path("/1/token/1")
+            .header("x-auth", auth_header())
+            .reply(&app);
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // deleted ids is not reused
+        let third = add_token(auth_header(), "deploy");
+        assert_eq!(third.id, 3);
+
+        let res = request()
+            .method("GET")
+            .path("/1/token")
+            .header("x-auth", auth_header())
+            .reply(&app);
+        assert_eq!(res.status(), StatusCode::OK);
+        let tokens: Vec<TokenInfo<Vec<Grant>>> = from_slice(res.body()).unwrap();
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|token| (token.id, token.name.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(2, "backup"), (3, "deploy")]
+        );
+
+        assert!(
+            match request()
+                .method("DELETE")
+                .path("/1/token/1")
+                .header("x-auth", auth_header())
+                .filter(&del_user_token(&state))
+            {
+                Err(rejection) => rejection.is_not_found(),
+                Ok(_) => false,
+            }
+        );
+
+        let denied = |res: Result<_, Rejection>| match res {
+            Err(rejection) => rejection.find_cause::<AccessError>().is_some(),
+            Ok(_) => false,
+        };
+
+        // tokens of other user
+        assert!(denied(
+            request()
+                .method("GET")
+                .path("/2/token")
+                .header("x-auth", auth_header())
+                .filter(&app)
+        ));
+
+        // tokens can't be managed using token
+        assert!(denied(
+            request()
+                .method("GET")
+                .path("/1/token")
+                .header("authorization", format!("Bearer {}", second.token))
+                .filter(&app)
+        ));
+
+        // guest
+        assert!(denied(
+            request().method("GET").path("/1/token").filter(&app)
+        ));
+
+        // other authorization scheme is handled as guest
+        assert!(denied(
+            request()
+                .method("GET")
+                .path("/1/token")
+                .header("authorization", "Basic eXVtaTpzZWNyZXQ=")
+                .filter(&app)
+        ));
+
+        // token secret is not stored
+        let bearer: BearerToken = second.token.parse().unwrap();
+        let stored = state.tokens.get_user_token(1, 2).wait().unwrap().unwrap();
+        assert!(stored.token_data().token != bearer.token);
+
+        // wrong token secret
+        let res = request()
+            .method("GET")
+            .path("/1/token")
+            .header(
+                "authorization",
+                format!(
+                    "Bearer {}",
+                    BearerToken {
+                        token: vec![0; 32],
+                        ..bearer
+                    }
+                ),
+            ).reply(&app);
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(res.body(), "Bad auth data");
+    }
+}
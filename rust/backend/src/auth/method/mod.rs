@@ -23,8 +23,22 @@ This method uses user accounts on third party services which implements OAuth2 t
 
 See [examples/auth.rs]
 
+### WebAuthn authentication
+
+This method uses passkeys and security keys through Web Authentication API.
+
+1. Server data: (`webauthn: data{}`)
+  * Relying party and fresh challenge
+2. User identification data:
+  * Signed assertion of authenticator
+
+### Auth throttling
+
+Any method can be wrapped by [`Throttled`](auth::method::Throttled) to protect it from brute-force attacks.
+
  */
 
+mod throttle;
 mod traits;
 
 #[cfg(feature = "native_auth")]
@@ -33,5 +47,8 @@ pub mod native;
 pub mod oauth2;
 #[cfg(feature = "otpass_auth")]
 pub mod otpass;
+#[cfg(feature = "webauthn_auth")]
+pub mod webauthn;
 
+pub use self::throttle::*;
 pub use self::traits::*;
@@ -4,12 +4,28 @@
 
 This method provides classic authorization with *username* and *password*.
 
+The password hash of user will be updated on successful login
+when it was created using other algorithm or parameters (see [`PasswordHashOptions`](user::PasswordHashOptions)).
+The login is not failed when the updated user data cannot be stored.
+
+The users which is not approved yet cannot login (see [`ProvisionOptions`](auth::ProvisionOptions)).
+
+The hash options is taken from password policy so the state should implement
+[`HasPasswordPolicy`](user::HasPasswordPolicy). The states which did not use password policy before
+can use [`PasswordPolicy::default()`](user::PasswordPolicy) to keep the previous behavior.
+
 */
 
-use auth::{AuthError, IsAuthMethod};
+use auth::{approved_user, AuthError, IsAuthMethod, IsThrottleIdent};
 use base::BoxFuture;
-use futures::Future;
-use user::{verify_password, HasPasswordHash, HasUserStorage, IsUserStorage};
+use futures::{
+    future::{ok, Either},
+    Future,
+};
+use user::{
+    create_password_with, password_needs_rehash, verify_password, HasPasswordHash,
+    HasPasswordPolicy, HasUserStorage, IsUserStorage, PasswordPolicy,
+};
 
 /// Native auth method information
 #[derive(Debug, Serialize)]
@@ -18,20 +34,28 @@ pub struct AuthInfo {
 }
 
 /// Native auth user identification
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub enum UserIdent {
     #[serde(rename = "native")]
     Native { name: String, pass: String },
 }
 
+impl IsThrottleIdent for UserIdent {
+    fn throttle_key(&self) -> Option<String> {
+        match self {
+            UserIdent::Native { name, .. } => Some(name.clone()),
+        }
+    }
+}
+
 /// Native auth method
 #[derive(Clone, Copy)]
 pub struct NativeAuth;
 
 impl<S> IsAuthMethod<S> for NativeAuth
 where
-    S: HasUserStorage,
-    <S::UserStorage as IsUserStorage>::User: HasPasswordHash,
+    S: HasUserStorage + HasPasswordPolicy + Clone + Send + 'static,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + Clone,
 {
     type AuthInfo = AuthInfo;
     type UserIdent = UserIdent;
@@ -48,6 +72,7 @@ where
         match ident {
             UserIdent::Native { name, pass } => {
                 let pass = pass.clone();
+                let state = state.clone();
                 Box::new(
                     (state.as_ref() as &S::UserStorage)
                         .find_user_data(name)
@@ -67,7 +92,29 @@ where
                                     None
                                 }
                             }).ok_or(AuthError::BadIdent)
-                        }),
+                                .map(move |user| (user, pass))
+                        }).and_then(move |(mut user, pass)| {
+                            let options = &(state.as_ref() as &PasswordPolicy).options().hash;
+                            if !user
+                                .get_password_hash()
+                                .map(|hash| password_needs_rehash(hash, options))
+                                .unwrap_or(false)
+                            {
+                                return Either::A(ok(user));
+                            }
+
+                            // the upgrade of hash is optional so the login should not fail
+                            let verified = user.clone();
+                            user.set_password_hash(Some(create_password_with(&pass, options)));
+                            Either::B(
+                                (state.as_ref() as &S::UserStorage)
+                                    .put_user_data(user)
+                                    .or_else(move |error| {
+                                        error!("Unable to rehash user password: {}", error);
+                                        ok(verified)
+                                    }),
+                            )
+                        }).and_then(approved_user),
                 )
             }
         }
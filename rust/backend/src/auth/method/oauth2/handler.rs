@@ -0,0 +1,198 @@
+use super::{
+    AccountInfo, AccountLink, HasLoginMethods, HasOAuth2, HasOAuth2Providers, OAuth2Arg,
+    OAuth2Auth,
+};
+use access::{audit_access_to, Grant, HasAccess, HasAuditSink};
+use auth::{
+    AuthError, HasNonceStorage, HasSessionOptions, HasSessionStorage, HasTokenStorage, HasUserAuth,
+    IsTokenAuth,
+};
+use crypto::{HasSecretKey, HasSecureKey};
+use filters::{x_client_addr, HasClientAddrOptions};
+use futures::Future;
+use http::client::HasHttpClient;
+use user::{AccountId, HasAccountStorage, IsAccountData, IsAccountStorage};
+use warp::{Filter, Rejection, Reply};
+use x_auth;
+
+impl<'a, A: IsAccountData> From<&'a A> for AccountInfo {
+    fn from(account: &'a A) -> Self {
+        AccountInfo {
+            id: account.get_account_id(),
+            service: account.get_account_service().into(),
+            name: account.get_account_name().into(),
+        }
+    }
+}
+
+/// Handle get user linked accounts
+pub fn get_user_accounts<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasAccountStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<OAuth2Arg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::get2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("accounts"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &OAuth2Arg { user }, &Grant::Read)
+                    .map(move |_| user)
+            }
+        }).and_then(move |user| {
+            (state.as_ref() as &S::AccountStorage)
+                .get_user_accounts(user)
+                .map_err(|error| {
+                    error!("Unable to get user accounts: {}", error);
+                    warp::reject::custom(AuthError::BackendError)
+                }).map(|accounts| {
+                    warp::reply::json(&accounts.iter().map(AccountInfo::from).collect::<Vec<_>>())
+                })
+        }).recover(AuthError::recover)
+}
+
+/// Handle link account to user
+///
+/// The request contains the same data as OAuth2 user identification.
+/// Returns linked account info.
+pub fn add_user_account<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasSecureKey
+        + HasNonceStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasAccountStorage
+        + HasHttpClient
+        + HasOAuth2Providers
+        + HasOAuth2
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone
+        + 'static,
+    S::UserAuth: HasAccess<OAuth2Arg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::post2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("accounts"))
+        .and(warp::path::end())
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &OAuth2Arg { user }, &Grant::Create)
+                    .map(move |_| user)
+            }
+        }).and(warp::body::json())
+        .and_then(move |user, req: AccountLink| {
+            (state.as_ref() as &OAuth2Auth)
+                .link_account(&state, user, &req.name, &req.code, &req.state, &req.key)
+                .map(|account| warp::reply::json(&AccountInfo::from(&account)))
+                .map_err(warp::reject::custom)
+        }).recover(AuthError::recover)
+}
+
+/// Handle unlink account from user
+///
+/// The last account cannot be unlinked when user has no other login methods
+/// (see [`HasLoginMethods`](auth::oauth2::HasLoginMethods)).
+pub fn del_user_account<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasAccountStorage
+        + HasLoginMethods
+        + HasOAuth2
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone
+        + 'static,
+    S::UserAuth: HasAccess<OAuth2Arg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::delete2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("accounts"))
+        .and(warp::path::param()) // account id
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, id: AccountId, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &OAuth2Arg { user }, &Grant::Delete)
+                    .map(move |_| (user, id))
+            }
+        }).and_then(move |(user, id)| {
+            (state.as_ref() as &OAuth2Auth)
+                .unlink_account(&state, user, id)
+                .map_err(warp::reject::custom)
+                .and_then(|res| {
+                    res.map(|_| warp::reply())
+                        .ok_or_else(warp::reject::not_found)
+                })
+        }).recover(AuthError::recover)
+}
+
+/// Scope with user linked accounts handlers
+pub fn account_scope<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasSecureKey
+        + HasNonceStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasAccountStorage
+        + HasLoginMethods
+        + HasHttpClient
+        + HasOAuth2Providers
+        + HasOAuth2
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone
+        + 'static,
+    S::UserAuth: HasAccess<OAuth2Arg, Grant> + IsTokenAuth<S>,
+{
+    get_user_accounts(state)
+        .or(add_user_account(state))
+        .or(del_user_account(state))
+}
@@ -1,27 +1,76 @@
 use super::{
-    AccessTokenRequest, AccessTokenResponse, AuthInfo, HasOAuth2Providers, IsOAuth2Providers,
-    OAuth2Options, ServiceInfo, UserIdent,
+    AccessTokenRequest, AccessTokenResponse, AuthInfo, HasLoginMethods, HasOAuth2Providers,
+    IsOAuth2Providers, OAuth2Options, RefreshTokenRequest, ServiceInfo, UserIdent,
 };
-use auth::{AuthError, IsAuthMethod};
-use base::{BoxFuture, CanUpdateFrom};
+use auth::{
+    approved_user, AuthError, HasNonceStorage, HasProvisionPolicy, IsAuthMethod, IsNonceStorage,
+    SignUp,
+};
+use base::{BoxFuture, CanUpdateFrom, TimeStamp};
+use base64lib::{encode_config, URL_SAFE_NO_PAD};
+use bytes::Bytes;
+use crypto::{random_bytes, CanDecrypt, CanEncrypt, HasSecureKey};
 use futures::{
-    future::{err, Either},
-    Future,
+    future::{err, ok, result, Either},
+    Future, IntoFuture,
 };
 use http::{
     client::{HasHttpClient, IsHttpClient},
     request,
 };
+use serde::Serialize;
+use serde_json;
+use serde_qs;
+use sodiumoxide::crypto::hash::sha256;
 use std::sync::Arc;
+use third::ThirdError;
 use user::{
-    HasAccountStorage, HasUserStorage, IsAccountData, IsAccountStorage, IsUserData, IsUserStorage,
+    AccountId, AccountTokens, HasAccountStorage, HasEmail, HasPasswordHash, HasUserStorage,
+    IsAccountData, IsAccountStorage, IsUserData, IsUserStorage, UserId,
 };
 
+/// Login attempt state
+///
+/// The data which sealed by server secure key and passed through client as `state` parameter.
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginState {
+    /// Unique identifier
+    id: String,
+    /// Service name
+    name: String,
+    /// PKCE code verifier
+    verifier: String,
+    /// Hash of client key
+    key: String,
+    /// Expiration time
+    etime: TimeStamp,
+}
+
+/// Issued login state
+struct IssuedState {
+    /// Sealed state
+    sealed: String,
+    /// Client key
+    key: String,
+    /// PKCE code challenge
+    challenge: String,
+}
+
 struct State {
     options: OAuth2Options,
 }
 
 /// OAuth2 auth method
+///
+/// The auth info contains issued `state`, client `key` and PKCE code challenge for each service.
+/// The state is sealed by server secure key and can be used only once until it expired.
+/// The state is bound to client key which is not passed to third service,
+/// so the state can be used only by client which requested it.
+/// The used states is kept in nonce storage (see [`HasNonceStorage`](auth::HasNonceStorage)).
+///
+/// When account is not found the new user will be created.
+/// The account can be linked to existing user which has same email
+/// when `link_email` option enabled.
 #[derive(Clone)]
 pub struct OAuth2Auth(Arc<State>);
 
@@ -32,19 +81,639 @@ impl OAuth2Auth {
     pub fn new(options: OAuth2Options) -> Self {
         OAuth2Auth(Arc::new(State { options }))
     }
+
+    /// Issue sealed state, client key and PKCE code challenge
+    fn create_state<S>(&self, state: &S, name: &str) -> Option<IssuedState>
+    where
+        S: HasSecureKey,
+    {
+        let verifier = encode_config(&random_bytes(32), URL_SAFE_NO_PAD);
+        let challenge = encode_config(&sha256::hash(verifier.as_bytes()), URL_SAFE_NO_PAD);
+        let key = encode_config(&random_bytes(16), URL_SAFE_NO_PAD);
+
+        let login = LoginState {
+            id: encode_config(&random_bytes(16), URL_SAFE_NO_PAD),
+            name: name.into(),
+            verifier,
+            key: client_key_hash(&key),
+            etime: TimeStamp::now() + self.0.options.state_time,
+        };
+
+        (state.as_ref() as &S::SecureKey)
+            .seal_json_b64(&login)
+            .map_err(|error| {
+                error!("Unable to seal OAuth2 state: {}", error);
+            }).ok()
+            .map(|sealed| IssuedState {
+                sealed,
+                key,
+                challenge,
+            })
+    }
+
+    /// Verify state and get PKCE code verifier
+    ///
+    /// Resolves to `None` when state is invalid, expired, used or issued to other client.
+    fn check_state<S>(
+        &self,
+        state: &S,
+        name: &str,
+        sealed: &str,
+        key: &str,
+    ) -> BoxFuture<Option<String>, AuthError>
+    where
+        S: HasSecureKey + HasNonceStorage,
+    {
+        let login: LoginState = match (state.as_ref() as &S::SecureKey).open_json_b64(sealed) {
+            Ok(login) => login,
+            Err(error) => {
+                warn!("Invalid OAuth2 state: {}", error);
+                return Box::new(ok(None));
+            }
+        };
+
+        if login.name != name {
+            warn!("OAuth2 state of other service");
+            return Box::new(ok(None));
+        }
+
+        if login.key != client_key_hash(key) {
+            warn!("OAuth2 state of other client");
+            return Box::new(ok(None));
+        }
+
+        if login.etime <= TimeStamp::now() {
+            warn!("Expired OAuth2 state");
+            return Box::new(ok(None));
+        }
+
+        let LoginState {
+            id,
+            verifier,
+            etime,
+            ..
+        } = login;
+
+        Box::new(
+            (state.as_ref() as &S::NonceStorage)
+                .use_nonce(&format!("oauth2:{}", id), etime)
+                .map_err(|error| {
+                    error!("Unable to use OAuth2 state: {}", error);
+                    AuthError::BackendError
+                }).map(move |fresh| {
+                    if fresh {
+                        Some(verifier)
+                    } else {
+                        warn!("Used OAuth2 state");
+                        None
+                    }
+                }),
+        )
+    }
+
+    /// Fetch account of third service using authorization code
+    ///
+    /// The state will be checked and the account will contain service name and sealed tokens.
+    pub fn fetch_account<S>(
+        &self,
+        state: &S,
+        name: &str,
+        code: &str,
+        state_val: &str,
+        key: &str,
+    ) -> BoxFuture<<S::AccountStorage as IsAccountStorage>::Account, AuthError>
+    where
+        S: HasAccountStorage
+            + HasSecureKey
+            + HasNonceStorage
+            + HasHttpClient
+            + HasOAuth2Providers
+            + Send
+            + Clone
+            + 'static,
+    {
+        let providers: &S::OAuth2Providers = state.as_ref();
+
+        if !providers.has_service(name) {
+            return Box::new(err(AuthError::BadService));
+        }
+
+        let params = if let Some(opts) = self
+            .0
+            .options
+            .services
+            .iter()
+            .find(|opts| opts.name == name)
+        {
+            opts.params.clone()
+        } else {
+            return Box::new(err(AuthError::BadService));
+        };
+
+        let redirect_uri = self.0.options.redirect.to_string() + "/" + name;
+        let nonce = login_nonce(state_val);
+        let code = code.to_string();
+        let state_val = state_val.to_string();
+        let name = name.to_string();
+        let state = state.clone();
+
+        Box::new(
+            self.check_state(&state, &name, &state_val, key)
+                .and_then({
+                    let state = state.clone();
+                    let name = name.clone();
+                    move |code_verifier| {
+                        let code_verifier = match code_verifier {
+                            Some(verifier) => verifier,
+                            None => return Either::A(err(AuthError::BadIdent)),
+                        };
+
+                        let query = AccessTokenRequest {
+                            params: &params,
+                            code: &code,
+                            redirect_uri: &redirect_uri,
+                            grant_type: "authorization_code",
+                            state: &state_val,
+                            code_verifier: &code_verifier,
+                        };
+
+                        Either::B(request_access_token(&state, &name, query))
+                    }
+                }).and_then({
+                    let state = state.clone();
+                    move |response| {
+                        seal_tokens(&state, response.clone()).map(|tokens| (response, tokens))
+                    }
+                }).and_then(move |(response, tokens)| {
+                    (state.as_ref() as &S::OAuth2Providers)
+                        .fetch_token_user_info(&name, &state, &response, &nonce)
+                        .map(move |mut data| {
+                            // add service name to account
+                            data.set_account_service(name.as_str());
+                            // add sealed tokens to account
+                            data.set_account_tokens(Some(tokens));
+                            data
+                        })
+                }),
+        )
+    }
+
+    /// Link account of third service to user
+    ///
+    /// The account which already linked to other user cannot be linked.
+    pub fn link_account<S>(
+        &self,
+        state: &S,
+        user: UserId,
+        name: &str,
+        code: &str,
+        state_val: &str,
+        key: &str,
+    ) -> BoxFuture<<S::AccountStorage as IsAccountStorage>::Account, AuthError>
+    where
+        S: HasAccountStorage
+            + HasSecureKey
+            + HasNonceStorage
+            + HasHttpClient
+            + HasOAuth2Providers
+            + Send
+            + Clone
+            + 'static,
+    {
+        let state = state.clone();
+
+        Box::new(
+            self.fetch_account(&state, name, code, state_val, key)
+                .and_then(move |mut data| {
+                    (state.as_ref() as &S::AccountStorage)
+                        .find_user_account(data.get_account_service(), data.get_account_name())
+                        .map_err(|error| {
+                            error!("Error when finding user account: {}", error);
+                            AuthError::BackendError
+                        }).and_then(move |account| {
+                            if let Some(account) = account {
+                                if account.get_user_id() != user {
+                                    warn!("Account already linked to other user");
+                                    return Either::A(err(AuthError::BadUser));
+                                }
+                                data.set_account_id(account.get_account_id());
+                                let tokens = data.get_account_tokens().cloned().map(|tokens| {
+                                    keep_refresh_token(tokens, account.get_account_tokens())
+                                });
+                                data.set_account_tokens(tokens);
+                            }
+                            data.set_user_id(user);
+                            Either::B(
+                                (state.as_ref() as &S::AccountStorage)
+                                    .put_user_account(data)
+                                    .map_err(|error| {
+                                        error!("Error when putting user account: {}", error);
+                                        AuthError::BackendError
+                                    }),
+                            )
+                        })
+                }),
+        )
+    }
+
+    /// Unlink account of third service from user
+    ///
+    /// The last account cannot be unlinked when user has no other login methods.
+    /// Resolves to `None` when user has no such account.
+    pub fn unlink_account<S>(
+        &self,
+        state: &S,
+        user: UserId,
+        id: AccountId,
+    ) -> BoxFuture<Option<()>, AuthError>
+    where
+        S: HasAccountStorage + HasLoginMethods + Send + Clone + 'static,
+    {
+        let state = state.clone();
+
+        Box::new(
+            (state.as_ref() as &S::AccountStorage)
+                .get_user_accounts(user)
+                .map_err(|error| {
+                    error!("Unable to get user accounts: {}", error);
+                    AuthError::BackendError
+                }).and_then(move |accounts| {
+                    if !accounts.iter().any(|account| account.get_account_id() == id) {
+                        return Either::A(ok(None));
+                    }
+                    Either::B(
+                        if accounts.len() > 1 {
+                            Either::A(ok(true))
+                        } else {
+                            // the last account => user should have other login methods
+                            Either::B(state.has_other_login(user))
+                        }.and_then(move |allowed| {
+                            if !allowed {
+                                warn!("Unable to unlink the last login method");
+                                return Either::A(err(AuthError::Restricted));
+                            }
+                            Either::B(
+                                (state.as_ref() as &S::AccountStorage)
+                                    .del_user_account(id)
+                                    .map_err(|error| {
+                                        error!("Unable to delete user account: {}", error);
+                                        AuthError::BackendError
+                                    }),
+                            )
+                        }),
+                    )
+                }),
+        )
+    }
+
+    /// Get access token of user account on third service
+    ///
+    /// The outdated access token will be refreshed using stored refresh token.
+    /// Use it to call third service API on behalf of user.
+    pub fn get_access_token<S>(
+        &self,
+        state: &S,
+        user: UserId,
+        name: &str,
+    ) -> BoxFuture<String, AuthError>
+    where
+        S: HasAccountStorage
+            + HasSecureKey
+            + HasHttpClient
+            + HasOAuth2Providers
+            + Send
+            + Clone
+            + 'static,
+    {
+        self.access_token(state, user, name, false)
+    }
+
+    /// Call third service API on behalf of user
+    ///
+    /// When the access token is rejected by service (`ThirdError::BadToken`)
+    /// it will be refreshed using stored refresh token and the call will be retried once.
+    ///
+    /// See [`fetch_api_page`](third::fetch_api_page) and [`fetch_api_all`](third::fetch_api_all).
+    pub fn call_third_api<S, F, R>(
+        &self,
+        state: &S,
+        user: UserId,
+        name: &str,
+        call: F,
+    ) -> BoxFuture<R, AuthError>
+    where
+        S: HasAccountStorage
+            + HasSecureKey
+            + HasHttpClient
+            + HasOAuth2Providers
+            + Send
+            + Clone
+            + 'static,
+        F: Fn(&S, String) -> BoxFuture<R, ThirdError> + Send + 'static,
+        R: Send + 'static,
+    {
+        let this = self.clone();
+        let state = state.clone();
+        let name = name.to_string();
+
+        Box::new(
+            self.access_token(&state, user, &name, false)
+                .and_then(move |access_token| {
+                    call(&state, access_token).then(move |result| match result {
+                        Err(ThirdError::BadToken) => {
+                            warn!("Access token rejected, trying to refresh");
+                            Either::A(this.access_token(&state, user, &name, true).and_then(
+                                move |access_token| {
+                                    call(&state, access_token).map_err(third_api_error)
+                                },
+                            ))
+                        }
+                        result => Either::B(result.map_err(third_api_error).into_future()),
+                    })
+                }),
+        )
+    }
+
+    /// Get access token refreshing it when outdated or forced
+    fn access_token<S>(
+        &self,
+        state: &S,
+        user: UserId,
+        name: &str,
+        refresh: bool,
+    ) -> BoxFuture<String, AuthError>
+    where
+        S: HasAccountStorage
+            + HasSecureKey
+            + HasHttpClient
+            + HasOAuth2Providers
+            + Send
+            + Clone
+            + 'static,
+    {
+        let providers: &S::OAuth2Providers = state.as_ref();
+
+        if !providers.has_service(name) {
+            return Box::new(err(AuthError::BadService));
+        }
+
+        let params = if let Some(opts) = self
+            .0
+            .options
+            .services
+            .iter()
+            .find(|opts| opts.name == name)
+        {
+            opts.params.clone()
+        } else {
+            return Box::new(err(AuthError::BadService));
+        };
+
+        let name = name.to_string();
+        let state = state.clone();
+
+        Box::new(
+            (state.as_ref() as &S::AccountStorage)
+                .get_user_accounts(user)
+                .map_err(|error| {
+                    error!("Error when getting user accounts: {}", error);
+                    AuthError::BackendError
+                }).and_then(move |accounts| {
+                    let mut account = if let Some(account) = accounts
+                        .into_iter()
+                        .find(|account| account.get_account_service() == name)
+                    {
+                        account
+                    } else {
+                        return Either::A(err(AuthError::BadUser));
+                    };
+
+                    let tokens = if let Some(tokens) = account.get_account_tokens() {
+                        tokens.clone()
+                    } else {
+                        warn!("No stored tokens of user account");
+                        return Either::A(err(AuthError::BadAuth));
+                    };
+
+                    let key = state.as_ref() as &S::SecureKey;
+
+                    // one minute margin to be able to use token
+                    let valid_time = TimeStamp::now() + TimeStamp::default().with_mins(1);
+
+                    if !refresh && tokens.etime.map(|etime| etime > valid_time).unwrap_or(true) {
+                        return Either::A(result(open_token(key, &tokens.access_token)));
+                    }
+
+                    let refresh_token = match tokens
+                        .refresh_token
+                        .as_ref()
+                        .map(|token| open_token(key, token))
+                    {
+                        Some(Ok(token)) => token,
+                        Some(Err(error)) => return Either::A(err(error)),
+                        None => return Either::A(err(AuthError::Outdated)),
+                    };
+
+                    let query = RefreshTokenRequest {
+                        params: &params,
+                        refresh_token: &refresh_token,
+                        grant_type: "refresh_token",
+                    };
+
+                    Either::B(request_access_token(&state, &name, query).and_then(
+                        move |response| {
+                            let access_token = response.access_token.clone();
+                            let new_tokens = match seal_tokens(&state, response) {
+                                Ok(new_tokens) => keep_refresh_token(new_tokens, Some(&tokens)),
+                                Err(error) => return Either::A(err(error)),
+                            };
+                            account.set_account_tokens(Some(new_tokens));
+
+                            Either::B(
+                                (state.as_ref() as &S::AccountStorage)
+                                    .put_user_account(account)
+                                    .map_err(|error| {
+                                        error!("Error when putting user account: {}", error);
+                                        AuthError::BackendError
+                                    }).map(move |_| access_token),
+                            )
+                        },
+                    ))
+                }),
+        )
+    }
+}
+
+/// Check that user has password or one-time password identity
+///
+/// The user which name is same as email can login using email one-time password.
+/// Use it to implement [`HasLoginMethods`](auth::oauth2::HasLoginMethods).
+pub fn has_own_login<S>(state: &S, user: UserId) -> BoxFuture<bool, AuthError>
+where
+    S: HasUserStorage,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasEmail,
+{
+    Box::new(
+        (state.as_ref() as &S::UserStorage)
+            .get_user_data(user)
+            .map_err(|error| {
+                error!("Unable to get user data: {}", error);
+                AuthError::BackendError
+            }).map(|data| {
+                data.map(|data| {
+                    data.get_password_hash().is_some() || data
+                        .get_email()
+                        .map(|email| email.to_string() == data.get_user_name())
+                        .unwrap_or(false)
+                }).unwrap_or(false)
+            }),
+    )
+}
+
+/// Convert third service API error
+fn third_api_error(error: ThirdError) -> AuthError {
+    error!("Third service API error: {}", error);
+    match error {
+        ThirdError::BackendError => AuthError::BackendError,
+        ThirdError::BadToken => AuthError::Outdated,
+        ThirdError::BadUser => AuthError::BadUser,
+        ThirdError::TooManyRequests(time) => {
+            AuthError::TooManyAttempts((time - TimeStamp::now()).max(TimeStamp::default()))
+        }
+        ThirdError::ServiceError => AuthError::ServiceError,
+    }
+}
+
+/// Get OpenID Connect nonce which bound to sealed state
+fn login_nonce(sealed: &str) -> String {
+    encode_config(&sha256::hash(sealed.as_bytes()), URL_SAFE_NO_PAD)
+}
+
+/// Get hash of client key which stored in state
+fn client_key_hash(key: &str) -> String {
+    encode_config(&sha256::hash(key.as_bytes()), URL_SAFE_NO_PAD)
+}
+
+/// Request access token from third service
+///
+/// The response can be either URL-encoded or JSON.
+fn request_access_token<S, Q>(
+    state: &S,
+    name: &str,
+    query: Q,
+) -> BoxFuture<AccessTokenResponse, AuthError>
+where
+    S: HasHttpClient + HasOAuth2Providers,
+    Q: Serialize,
+{
+    let providers: &S::OAuth2Providers = state.as_ref();
+    let url = match providers.access_token_url(name) {
+        Some(url) => url,
+        None => {
+            error!("Unknown OAuth2 service: {}", name);
+            return Box::new(err(AuthError::BadService));
+        }
+    };
+    let client: &S::HttpClient = state.as_ref();
+
+    use self::request::*;
+
+    Box::new(
+        client
+            .fetch(Method(
+                "POST",
+                Url(
+                    url.as_ref(),
+                    Header(
+                        "Content-Type",
+                        "application/x-www-form-urlencoded",
+                        Header(
+                            "Accept",
+                            "application/x-www-form-urlencoded, application/json",
+                            UrlEncodedBody(query),
+                        ),
+                    ),
+                ),
+            )).map_err(|error| {
+                error!("Access token request error: {}", error);
+                AuthError::ServiceError
+            }).and_then(|RawBody(data): RawBody<Bytes>| {
+                decode_token_response(&data).map_err(|error| {
+                    error!("Access token response error: {}", error);
+                    AuthError::ServiceError
+                })
+            }),
+    )
+}
+
+/// Decode access token response
+fn decode_token_response(data: &[u8]) -> Result<AccessTokenResponse, String> {
+    if data.first() == Some(&b'{') {
+        serde_json::from_slice(data).map_err(|error| error.to_string())
+    } else {
+        serde_qs::from_bytes(data).map_err(|error| error.to_string())
+    }
+}
+
+/// Seal received tokens to store in account
+fn seal_tokens<S>(state: &S, response: AccessTokenResponse) -> Result<AccountTokens, AuthError>
+where
+    S: HasSecureKey,
+{
+    let key = state.as_ref() as &S::SecureKey;
+    let seal = |token: &str| {
+        key.seal_json_b64(token).map_err(|error| {
+            error!("Unable to seal access token: {}", error);
+            AuthError::BackendError
+        })
+    };
+
+    Ok(AccountTokens {
+        access_token: seal(&response.access_token)?,
+        refresh_token: match &response.refresh_token {
+            Some(token) => Some(seal(token)?),
+            None => None,
+        },
+        etime: response
+            .expires_in
+            .map(|secs| TimeStamp::now() + TimeStamp::default().with_secs(secs as i32)),
+    })
+}
+
+/// Open sealed token
+fn open_token<K>(key: &K, token: &str) -> Result<String, AuthError>
+where
+    K: CanDecrypt,
+{
+    key.open_json_b64(token).map_err(|error| {
+        error!("Unable to open access token: {}", error);
+        AuthError::BackendError
+    })
+}
+
+/// Keep previous refresh token when third service does not issue new one
+fn keep_refresh_token(mut tokens: AccountTokens, old: Option<&AccountTokens>) -> AccountTokens {
+    if tokens.refresh_token.is_none() {
+        tokens.refresh_token = old.and_then(|old| old.refresh_token.clone());
+    }
+    tokens
 }
 
 impl<S> IsAuthMethod<S> for OAuth2Auth
 where
     S: HasUserStorage
         + HasAccountStorage
+        + HasSecureKey
+        + HasNonceStorage
         + HasHttpClient
         + HasOAuth2Providers
+        + HasProvisionPolicy
         + Send
         + Clone
         + 'static,
     <S::UserStorage as IsUserStorage>::User:
         CanUpdateFrom<<S::AccountStorage as IsAccountStorage>::Account>,
+    <S::AccountStorage as IsAccountStorage>::Account: HasEmail,
 {
     type AuthInfo = AuthInfo<
         ServiceInfo<
@@ -65,18 +734,24 @@ where
             .services
             .iter()
             .filter(|opts| providers.has_service(&opts.name))
-            .map(|opts| {
-                let url = providers.authorize_url(&opts.name);
-                let scope = providers.authorize_scope(&opts.name);
-                let params = providers.authorize_params(&opts.name);
+            .filter_map(|opts| {
+                let url = providers.authorize_url(&opts.name)?;
+                let scope = providers.authorize_scope(&opts.name)?;
+                let params = providers.authorize_params(&opts.name)?;
+                let issued = self.create_state(state, &opts.name)?;
 
-                ServiceInfo {
+                Some(ServiceInfo {
                     name: opts.name.clone(),
                     url: url.into(),
                     client_id: opts.params.client_id.clone(),
                     scope: scope.into(),
+                    nonce: login_nonce(&issued.sealed),
+                    state: issued.sealed,
+                    key: issued.key,
+                    code_challenge: issued.challenge,
+                    code_challenge_method: "S256",
                     params,
-                }
+                })
             }).collect();
 
         let redirect = self.0.options.redirect.clone();
@@ -91,143 +766,358 @@ where
             name,
             code,
             state: state_val,
+            key,
+            invite,
         }: &Self::UserIdent,
     ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
-        let providers: &S::OAuth2Providers = state.as_ref();
+        let link_email = self.0.options.link_email;
+        let invite = invite.clone();
+        let state = state.clone();
 
-        if !providers.has_service(&name) {
-            return Box::new(err(AuthError::BadService));
+        Box::new(
+            self.fetch_account(&state, name, code, state_val, key)
+                .and_then(move |mut data| {
+                    (state.as_ref() as &S::AccountStorage)
+                        .find_user_account(data.get_account_service(), data.get_account_name())
+                        .map_err(|error| {
+                            error!("Error when finding user account: {}", error);
+                            AuthError::BackendError
+                        }).and_then(move |account| {
+                            if let Some(account) = account {
+                                // found => get user data
+                                data.set_account_id(account.get_account_id());
+                                data.set_user_id(account.get_user_id());
+                                let tokens = data.get_account_tokens().cloned().map(|tokens| {
+                                    keep_refresh_token(tokens, account.get_account_tokens())
+                                });
+                                data.set_account_tokens(tokens);
+                                Either::A(
+                                    (state.as_ref() as &S::UserStorage)
+                                        .get_user_data(account.get_user_id())
+                                        .map_err(|error| {
+                                            error!("Error when getting user data: {}", error);
+                                            AuthError::BackendError
+                                        }).and_then(|user| user.ok_or(AuthError::BadUser))
+                                        .map(move |user| (state, data, user)),
+                                )
+                            } else {
+                                // not found => find user by verified email or create new user
+                                let email = if link_email && data.email_verified() {
+                                    data.get_email().cloned()
+                                } else {
+                                    None
+                                };
+                                Either::B(
+                                    if let Some(email) = email {
+                                        Either::A(
+                                            (state.as_ref() as &S::UserStorage)
+                                                .find_user_by_email(&email),
+                                        )
+                                    } else {
+                                        Either::B(ok(None))
+                                    }.map_err(|error| {
+                                        error!("Error when finding user data: {}", error);
+                                        AuthError::BackendError
+                                    }).and_then(move |user| {
+                                        let user = if let Some(user) = user {
+                                            user
+                                        } else {
+                                            let mut user =
+                                                <S::UserStorage as IsUserStorage>::User::create_new(
+                                                    data.get_account_name().to_string()
+                                                        + "@"
+                                                        + data.get_account_service(),
+                                                );
+                                            state.provision_user(
+                                                &mut user,
+                                                &SignUp {
+                                                    // only verified email is checked by policy
+                                                    email: data
+                                                        .get_email()
+                                                        .filter(|_| data.email_verified()),
+                                                    organizations: data.get_organizations(),
+                                                    invite: invite.as_ref().map(AsRef::as_ref),
+                                                },
+                                            )?;
+                                            user
+                                        };
+                                        Ok((state, data, user))
+                                    }),
+                                )
+                            }
+                        }).and_then(|(state, data, mut user)| {
+                            // update user info from account
+                            // TODO: put only when something changed
+                            user.update_from(&data);
+                            (state.as_ref() as &S::UserStorage)
+                                .put_user_data(user)
+                                .map_err(|error| {
+                                    error!("Error when putting user data: {}", error);
+                                    AuthError::BackendError
+                                }).map(move |user| (state, data, user))
+                        }).and_then(|(state, mut data, user)| {
+                            // save account data
+                            // TODO: put only when something changed
+                            data.set_user_id(user.get_user_id());
+                            (state.as_ref() as &S::AccountStorage)
+                                .put_user_account(data)
+                                .map_err(|error| {
+                                    error!("Error when putting user account: {}", error);
+                                    AuthError::BackendError
+                                }).and_then(move |_| approved_user(user))
+                        })
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use auth::NonceCache;
+    use crypto::{CanKeygen, SecureKey};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use third::{github, recorded::RecordedClient};
+    use user::stub::{AccountData, Accounts};
+
+    struct TestState(SecureKey, NonceCache);
+
+    impl AsRef<SecureKey> for TestState {
+        fn as_ref(&self) -> &SecureKey {
+            &self.0
         }
+    }
 
-        let opts = if let Some(opts) = self
-            .0
-            .options
-            .services
-            .iter()
-            .find(|opts| &opts.name == name)
-        {
-            opts
-        } else {
-            return Box::new(err(AuthError::BadService));
+    impl HasSecureKey for TestState {
+        type SecureKey = SecureKey;
+    }
+
+    impl AsRef<NonceCache> for TestState {
+        fn as_ref(&self) -> &NonceCache {
+            &self.1
+        }
+    }
+
+    impl HasNonceStorage for TestState {
+        type NonceStorage = NonceCache;
+    }
+
+    #[test]
+    fn login_state() {
+        let state = TestState(SecureKey::gen_key(), NonceCache::new());
+        let auth = OAuth2Auth::new(OAuth2Options::default());
+        let check = |auth: &OAuth2Auth, name, sealed, key| {
+            auth.check_state(&state, name, sealed, key).wait().unwrap()
         };
 
-        let redirect_uri = self.0.options.redirect.to_string() + "/" + name;
+        let issued = auth.create_state(&state, "github").unwrap();
+        // state of other service
+        assert_eq!(check(&auth, "google", &issued.sealed, &issued.key), None);
+        // state of other client
+        let other = auth.create_state(&state, "github").unwrap();
+        assert_eq!(check(&auth, "github", &issued.sealed, &other.key), None);
 
-        let url = providers.access_token_url(name);
-        let query = AccessTokenRequest {
-            params: &opts.params,
-            code: code,
-            redirect_uri: &redirect_uri,
-            grant_type: "authorization_code",
-            state: &state_val,
+        let verifier = check(&auth, "github", &issued.sealed, &issued.key).unwrap();
+        assert_eq!(
+            encode_config(&sha256::hash(verifier.as_bytes()), URL_SAFE_NO_PAD),
+            issued.challenge
+        );
+        // reused state
+        assert_eq!(check(&auth, "github", &issued.sealed, &issued.key), None);
+        // used states is shared between method instances
+        let same = OAuth2Auth::new(OAuth2Options::default());
+        assert_eq!(check(&same, "github", &issued.sealed, &issued.key), None);
+        // forged state
+        assert_eq!(check(&auth, "github", "Zm9yZ2Vk", &issued.key), None);
+
+        let mut options = OAuth2Options::default();
+        options.state_time = TimeStamp::default();
+        let auth = OAuth2Auth::new(options);
+        let issued = auth.create_state(&state, "github").unwrap();
+        // expired state
+        assert_eq!(check(&auth, "github", &issued.sealed, &issued.key), None);
+    }
+
+    #[test]
+    fn account_tokens() {
+        let state = TestState(SecureKey::gen_key(), NonceCache::new());
+
+        let old = seal_tokens(
+            &state,
+            AccessTokenResponse {
+                access_token: "access1".into(),
+                refresh_token: Some("refresh1".into()),
+                expires_in: Some(3600),
+                id_token: None,
+            },
+        ).unwrap();
+        assert!(old.etime.unwrap() > TimeStamp::now());
+        assert_eq!(open_token(&state.0, &old.access_token).unwrap(), "access1");
+
+        let new = seal_tokens(
+            &state,
+            AccessTokenResponse {
+                access_token: "access2".into(),
+                refresh_token: None,
+                expires_in: None,
+                id_token: None,
+            },
+        ).unwrap();
+        assert_eq!(new.etime, None);
+
+        // refresh token is not issued again
+        let new = keep_refresh_token(new, Some(&old));
+        assert_eq!(open_token(&state.0, &new.access_token).unwrap(), "access2");
+        assert_eq!(
+            open_token(&state.0, new.refresh_token.as_ref().unwrap()).unwrap(),
+            "refresh1"
+        );
+    }
+
+    #[test]
+    fn token_response() {
+        let response = decode_token_response(b"access_token=abc&expires_in=60").unwrap();
+        assert_eq!(response.access_token, "abc");
+        assert_eq!(response.expires_in, Some(60));
+
+        let response =
+            decode_token_response(br#"{"access_token":"abc","id_token":"a.b.c"}"#).unwrap();
+        assert_eq!(response.access_token, "abc");
+        assert_eq!(response.id_token, Some("a.b.c".into()));
+
+        assert!(decode_token_response(b"error=invalid_grant").is_err());
+    }
+
+    #[derive(Clone)]
+    struct ApiState(Arc<(SecureKey, Accounts, RecordedClient, (github::Service,))>);
+
+    impl AsRef<SecureKey> for ApiState {
+        fn as_ref(&self) -> &SecureKey {
+            &(self.0).0
+        }
+    }
+
+    impl HasSecureKey for ApiState {
+        type SecureKey = SecureKey;
+    }
+
+    impl AsRef<Accounts> for ApiState {
+        fn as_ref(&self) -> &Accounts {
+            &(self.0).1
+        }
+    }
+
+    impl HasAccountStorage for ApiState {
+        type AccountStorage = Accounts;
+    }
+
+    impl AsRef<RecordedClient> for ApiState {
+        fn as_ref(&self) -> &RecordedClient {
+            &(self.0).2
+        }
+    }
+
+    impl HasHttpClient for ApiState {
+        type HttpClient = RecordedClient;
+    }
+
+    impl AsRef<(github::Service,)> for ApiState {
+        fn as_ref(&self) -> &(github::Service,) {
+            &(self.0).3
+        }
+    }
+
+    impl HasOAuth2Providers for ApiState {
+        type OAuth2Providers = (github::Service,);
+    }
+
+    #[test]
+    fn refresh_rejected_token() {
+        let key = SecureKey::gen_key();
+        let seal = |token: &str| key.seal_json_b64(token).unwrap();
+        let account = |user: UserId, refresh_token: Option<String>| {
+            let mut account = AccountData::create_new(user.to_string());
+            account.user = user;
+            account.service = "github".into();
+            account.set_account_tokens(Some(AccountTokens {
+                access_token: seal("access1"),
+                refresh_token,
+                etime: None,
+            }));
+            account
+        };
+        let accounts = Accounts::new()
+            .with_account(account(1, Some(seal("refresh1"))))
+            .with_account(account(2, None));
+        let client = RecordedClient::default().with(
+            "https://github.com/login/oauth/access_token",
+            r#"{"access_token":"access2","token_type":"bearer"}"#,
+        );
+        let state = ApiState(Arc::new((
+            key.clone(),
+            accounts,
+            client,
+            (github::Service::new(Default::default()),),
+        )));
+        let auth = OAuth2Auth::new(
+            serde_json::from_str(
+                r#"{
+                    "services": [{
+                        "name": "github",
+                        "client_id": "client",
+                        "client_secret": "secret"
+                    }],
+                    "redirect": "https://my-site.tld/oauth2"
+                }"#,
+            ).unwrap(),
+        );
+        // the call which accepts only refreshed token
+        let calls = Arc::new(AtomicUsize::new(0));
+        let call = |calls: &Arc<AtomicUsize>| {
+            let calls = calls.clone();
+            move |_: &ApiState, access_token: String| -> BoxFuture<String, ThirdError> {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Box::new(if access_token == "access2" {
+                    ok(access_token)
+                } else {
+                    err(ThirdError::BadToken)
+                })
+            }
         };
 
-        let client: &S::HttpClient = state.as_ref();
+        assert_eq!(
+            auth.call_third_api(&state, 1, "github", call(&calls))
+                .wait()
+                .unwrap(),
+            "access2"
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
 
-        use self::request::*;
+        // refreshed token is stored keeping refresh token
+        let stored = (state.0).1.get_user_accounts(1).wait().unwrap().remove(0);
+        let tokens = stored.get_account_tokens().unwrap();
+        assert_eq!(open_token(&key, &tokens.access_token).unwrap(), "access2");
+        assert_eq!(
+            open_token(&key, tokens.refresh_token.as_ref().unwrap()).unwrap(),
+            "refresh1"
+        );
 
-        let name = name.clone();
-        let state = state.clone();
+        // stored token is used without refresh
+        assert_eq!(
+            auth.call_third_api(&state, 1, "github", call(&calls))
+                .wait()
+                .unwrap(),
+            "access2"
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
 
-        Box::new(
-            client
-                .fetch(Method(
-                    "POST",
-                    Url(
-                        url.as_ref(),
-                        Header(
-                            "Content-Type",
-                            "application/x-www-form-urlencoded",
-                            Header(
-                                "Accept",
-                                "application/x-www-form-urlencoded",
-                                UrlEncodedBody(query),
-                            ),
-                        ),
-                    ),
-                )).map_err(|error| {
-                    error!("Access token request error: {}", error);
-                    AuthError::ServiceError
-                }).map(UrlEncodedBody::into_inner)
-                  .map(AccessTokenResponse::into_access_token)
-                  .and_then(move |access_token| {
-                      (state.as_ref() as &S::OAuth2Providers)
-                          .fetch_user_info(&name, &state, access_token.into())
-                          .and_then(move |mut data: <<S as HasAccountStorage>::AccountStorage as IsAccountStorage>::Account| {
-                              // add service name to account
-                              data.set_account_service(name.as_str());
-                              (state.as_ref() as &S::AccountStorage)
-                                  .find_user_account(&name, data.get_account_name())
-                                  .map_err(|error| {
-                                      error!("Error when finding user account: {}", error);
-                                      AuthError::BackendError
-                                  })
-                                  .and_then(move |account| {
-                                      if let Some(account) = account {
-                                          // found => get user data
-                                          data.set_account_id(account.get_account_id());
-                                          Either::A(
-                                              (state.as_ref() as &S::UserStorage)
-                                                  .get_user_data(account.get_user_id())
-                                                  .map_err(|error| {
-                                                      error!("Error when getting user data: {}", error);
-                                                      AuthError::BackendError
-                                                  })
-                                                  .and_then({
-                                                      let state = state.clone();
-                                                      move |user| {
-                                                          if let Some(mut user) = user {
-                                                              // update user info from account
-                                                              user.update_from(&data);
-                                                              // TODO: put only when something changed
-                                                              Either::A((state.as_ref() as &S::UserStorage)
-                                                                        .put_user_data(user)
-                                                                        .map_err(|error| {
-                                                                            error!("Error when putting user data: {}", error);
-                                                                            AuthError::BackendError
-                                                                        })
-                                                                        .map(move |user| (data, user)))
-                                                          } else {
-                                                              Either::B(err(AuthError::BadUser))
-                                                          }
-                                                      }
-                                                  })
-                                          )
-                                      } else {
-                                          // not found => create user data
-                                          let name = data.get_account_name().to_string() + "@" + &name;
-                                          let mut user = <S::UserStorage as IsUserStorage>::User::create_new(name);
-                                          // fill user info from account
-                                          user.update_from(&data);
-                                          Either::B(
-                                              (state.as_ref() as &S::UserStorage)
-                                                  .put_user_data(user)
-                                                  .map_err(|error| {
-                                                      error!("Error when putting user data: {}", error);
-                                                      AuthError::BackendError
-                                                  })
-                                                  .map(move |user| {
-                                                      // set user id to account data
-                                                      data.set_user_id(user.get_user_id());
-                                                      (data, user)
-                                                  })
-                                          )
-                                      }.and_then(move |(account, user)| {
-                                          // save account data
-                                          // TODO: put only when something changed
-                                          (state.as_ref() as &S::AccountStorage)
-                                              .put_user_account(account)
-                                              .map_err(|error| {
-                                                  error!("Error when putting user account: {}", error);
-                                                  AuthError::BackendError
-                                              })
-                                              .map(move |_| {
-                                                  user
-                                              })
-                                      })
-                                  })
-                          })
-                  })
-        )
+        // no refresh token
+        assert!(
+            match auth.call_third_api(&state, 2, "github", call(&calls)).wait() {
+                Err(AuthError::Outdated) => true,
+                _ => false,
+            }
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
     }
 }
@@ -3,13 +3,48 @@
 ### OAuth2 auth
 
 This method provides OAuth2 authorization using account on third Web-services like **Github**, **Google**, **Facebook**, and etc.
+The services without built-in provider (like in-house SSO servers) can be defined in `provider` field
+of service options and used through [`generic::Service`](third::generic::Service).
+
+The providers can be combined using tuple (fixed at compile time)
+or [`OAuth2Registry`](auth::oauth2::OAuth2Registry) (assembled at runtime).
+
+The auth info contains the `state` and PKCE `code_challenge` (method *S256*) for each service.
+The client should pass both to authorization endpoint and send back the received `code` with same `state`
+and the client `key` which was issued together with state.
+The state is sealed by server secure key, expires after `state_time` and can be used only once
+(the used states is kept in [`IsNonceStorage`](auth::IsNonceStorage)).
+The key should be kept by client and never passed to third service, so the login cannot be completed by other client.
+The `nonce` is bound to state and should be passed to OpenID Connect providers to be checked in ID token.
+
+The received access and refresh tokens are sealed and stored in account when account data supports it.
+Use `OAuth2Auth::get_access_token` to get valid access token for calling third service API on behalf of user.
+The outdated access token will be refreshed automatically.
+Use `OAuth2Auth::call_third_api` to also refresh the access token which was rejected by service and retry the call.
+
+#### Linked accounts
+
+The user can have accounts on several services.
+When account is not found on login the new user will be created
+unless `link_email` option is enabled and some user has same email.
+The new user should satisfy provisioning policy (see [`HasProvisionPolicy`](auth::HasProvisionPolicy)),
+so the client may pass `invite` code with identification data.
+
+Authorized client can link the account of other service to user (*POST /:user/accounts* with [`AccountLink`](auth::method::oauth2::AccountLink)).
+The linked accounts can be listed using *GET /:user/accounts* and unlinked using *DELETE /:user/accounts/:id*.
+The last account cannot be unlinked when user has no other login methods (see [`HasLoginMethods`](auth::oauth2::HasLoginMethods)).
+Only the email which verified by service is used to link account to existing user (see `link_email` option).
 
 */
 
+mod handler;
 mod method;
+mod registry;
 mod traits;
 mod types;
 
+pub use self::handler::*;
 pub use self::method::*;
+pub use self::registry::*;
 pub use self::traits::*;
 pub use self::types::*;
@@ -0,0 +1,252 @@
+use super::{AccessTokenResponse, IsOAuth2Provider, IsOAuth2Providers, OAuth2Options};
+use auth::AuthError;
+use base::BoxFuture;
+use futures::future::err;
+use http::client::HasHttpClient;
+use serde_json::{to_value, Map as JsonMap, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use third::generic;
+use user::{HasEmail, HasFullName, HasImageUrl, HasNickName, IsAccountData};
+
+/// Object-safe form of OAuth2 provider with erased authorize params
+trait IsBoxedProvider<S, X>: Send + Sync {
+    fn authorize_url(&self) -> Cow<str>;
+
+    fn authorize_scope(&self) -> Cow<str>;
+
+    fn authorize_params(&self) -> JsonMap<String, Value>;
+
+    fn access_token_url(&self) -> Cow<str>;
+
+    fn fetch_user_info(&self, state: &S, access_token: Cow<str>) -> BoxFuture<X, AuthError>;
+
+    fn fetch_token_user_info(
+        &self,
+        state: &S,
+        token: &AccessTokenResponse,
+        nonce: &str,
+    ) -> BoxFuture<X, AuthError>;
+}
+
+impl<S, X, P> IsBoxedProvider<S, X> for P
+where
+    X: 'static,
+    P: IsOAuth2Provider<S, X> + Send + Sync,
+{
+    fn authorize_url(&self) -> Cow<str> {
+        IsOAuth2Provider::authorize_url(self)
+    }
+
+    fn authorize_scope(&self) -> Cow<str> {
+        IsOAuth2Provider::authorize_scope(self)
+    }
+
+    fn authorize_params(&self) -> JsonMap<String, Value> {
+        match to_value(IsOAuth2Provider::authorize_params(self)) {
+            Ok(Value::Object(params)) => params,
+            Ok(_) => JsonMap::new(),
+            Err(error) => {
+                error!("Unable to serialize authorize params: {}", error);
+                JsonMap::new()
+            }
+        }
+    }
+
+    fn access_token_url(&self) -> Cow<str> {
+        IsOAuth2Provider::access_token_url(self)
+    }
+
+    fn fetch_user_info(&self, state: &S, access_token: Cow<str>) -> BoxFuture<X, AuthError> {
+        IsOAuth2Provider::fetch_user_info(self, state, access_token)
+    }
+
+    fn fetch_token_user_info(
+        &self,
+        state: &S,
+        token: &AccessTokenResponse,
+        nonce: &str,
+    ) -> BoxFuture<X, AuthError> {
+        IsOAuth2Provider::fetch_token_user_info(self, state, token, nonce)
+    }
+}
+
+/// OAuth2 providers registry
+///
+/// The boxed providers indexed by service name.
+/// Unlike tuples the registry can be assembled at runtime (from config for example).
+/// Unknown services is reported as `AuthError::BadService` instead of panic.
+pub struct OAuth2Registry<S, X>(HashMap<String, Box<IsBoxedProvider<S, X>>>);
+
+impl<S, X> Default for OAuth2Registry<S, X> {
+    fn default() -> Self {
+        OAuth2Registry(HashMap::new())
+    }
+}
+
+impl<S, X> OAuth2Registry<S, X>
+where
+    X: 'static,
+{
+    /// Create empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add provider
+    ///
+    /// The provider with same service name will be replaced.
+    pub fn add<P>(&mut self, provider: P)
+    where
+        P: IsOAuth2Provider<S, X> + Send + Sync + 'static,
+    {
+        let name = provider.service_name().into_owned();
+        if self.0.insert(name, Box::new(provider)).is_some() {
+            warn!("OAuth2 provider replaced");
+        }
+    }
+
+    /// Add provider using builder pattern
+    pub fn with<P>(mut self, provider: P) -> Self
+    where
+        P: IsOAuth2Provider<S, X> + Send + Sync + 'static,
+    {
+        self.add(provider);
+        self
+    }
+
+    /// Add generic providers defined in OAuth2 options
+    ///
+    /// See [`generic`](third::generic).
+    pub fn add_generic(&mut self, options: &OAuth2Options)
+    where
+        S: HasHttpClient,
+        X: IsAccountData + HasNickName + HasFullName + HasEmail + HasImageUrl + Send,
+    {
+        for service in &options.services {
+            if let Some(config) = &service.provider {
+                self.add(generic::Service::new(service.name.as_str(), config.clone()));
+            }
+        }
+    }
+
+    /// Remove provider
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.0.remove(name).is_some()
+    }
+
+    /// Get names of services
+    pub fn services(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
+
+impl<S, X> IsOAuth2Providers<S, X> for OAuth2Registry<S, X>
+where
+    X: Send + 'static,
+{
+    fn has_service(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    fn authorize_url(&self, name: &str) -> Option<Cow<str>> {
+        self.0.get(name).map(|provider| provider.authorize_url())
+    }
+
+    fn authorize_scope(&self, name: &str) -> Option<Cow<str>> {
+        self.0.get(name).map(|provider| provider.authorize_scope())
+    }
+
+    type AuthorizeParams = JsonMap<String, Value>;
+
+    fn authorize_params(&self, name: &str) -> Option<Self::AuthorizeParams> {
+        self.0.get(name).map(|provider| provider.authorize_params())
+    }
+
+    fn access_token_url(&self, name: &str) -> Option<Cow<str>> {
+        self.0.get(name).map(|provider| provider.access_token_url())
+    }
+
+    fn fetch_user_info(
+        &self,
+        name: &str,
+        state: &S,
+        access_token: Cow<str>,
+    ) -> BoxFuture<X, AuthError> {
+        match self.0.get(name) {
+            Some(provider) => provider.fetch_user_info(state, access_token),
+            None => Box::new(err(AuthError::BadService)),
+        }
+    }
+
+    fn fetch_token_user_info(
+        &self,
+        name: &str,
+        state: &S,
+        token: &AccessTokenResponse,
+        nonce: &str,
+    ) -> BoxFuture<X, AuthError> {
+        match self.0.get(name) {
+            Some(provider) => provider.fetch_token_user_info(state, token, nonce),
+            None => Box::new(err(AuthError::BadService)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::Future;
+    use serde_json::from_str;
+    use third::{github, google, recorded::RecordedClient};
+    use user::stub::AccountData;
+
+    #[test]
+    fn registry() {
+        let options: OAuth2Options = from_str(
+            r#"{
+                "services": [{
+                    "name": "sso",
+                    "client_id": "client",
+                    "client_secret": "secret",
+                    "provider": {
+                        "authorize_url": "https://sso.example.com/authorize",
+                        "token_url": "https://sso.example.com/token",
+                        "userinfo_url": "https://sso.example.com/me",
+                        "params": { "realm": "staff" }
+                    }
+                }],
+                "redirect": "https://my-site.tld/oauth2"
+            }"#,
+        ).unwrap();
+
+        let mut registry: OAuth2Registry<RecordedClient, AccountData> = OAuth2Registry::new()
+            .with(github::Service::new(Default::default()))
+            .with(google::Service::new(Default::default()));
+        registry.add_generic(&options);
+
+        assert!(registry.has_service("github"));
+        assert!(registry.has_service("sso"));
+        assert_eq!(
+            registry.access_token_url("sso"),
+            Some("https://sso.example.com/token".into())
+        );
+        assert_eq!(
+            registry.authorize_params("sso").unwrap()["realm"],
+            Value::from("staff")
+        );
+
+        assert!(registry.remove("google"));
+        assert!(!registry.has_service("google"));
+        assert!(registry.authorize_url("google").is_none());
+        assert!(
+            match registry
+                .fetch_user_info("google", &RecordedClient::default(), "token".into())
+                .wait()
+            {
+                Err(AuthError::BadService) => true,
+                _ => false,
+            }
+        );
+    }
+}
@@ -1,10 +1,11 @@
+use super::{AccessTokenResponse, OAuth2Auth};
 use auth::{AuthError, EitherUserIdent};
 use base::BoxFuture;
-use futures::Future;
+use futures::{future::err, Future};
 use serde::Serialize;
 use std::borrow::Cow;
 use third::IsThirdService;
-use user::{HasAccountStorage, IsAccountStorage};
+use user::{HasAccountStorage, IsAccountStorage, UserId};
 
 /// OAuth2 provider interface
 pub trait IsOAuth2Provider<S, X>: IsThirdService<S, X> {
@@ -35,6 +36,21 @@ pub trait IsOAuth2Provider<S, X>: IsThirdService<S, X> {
             AuthError::ServiceError
         }))
     }
+
+    /// Fetch user info using access token response
+    ///
+    /// OpenID Connect providers can get user info from ID token checking `nonce` in it.
+    fn fetch_token_user_info(
+        &self,
+        state: &S,
+        token: &AccessTokenResponse,
+        _nonce: &str,
+    ) -> BoxFuture<X, AuthError>
+    where
+        X: 'static,
+    {
+        self.fetch_user_info(state, token.access_token.as_str().into())
+    }
 }
 
 impl<'a, S, T, X> IsOAuth2Provider<S, X> for &'a T
@@ -58,29 +74,49 @@ where
     fn access_token_url(&self) -> Cow<str> {
         (*self).access_token_url()
     }
+
+    fn fetch_user_info(&self, state: &S, access_token: Cow<str>) -> BoxFuture<X, AuthError>
+    where
+        X: 'static,
+    {
+        (*self).fetch_user_info(state, access_token)
+    }
+
+    fn fetch_token_user_info(
+        &self,
+        state: &S,
+        token: &AccessTokenResponse,
+        nonce: &str,
+    ) -> BoxFuture<X, AuthError>
+    where
+        X: 'static,
+    {
+        (*self).fetch_token_user_info(state, token, nonce)
+    }
 }
 
 /// OAuth2 providers interface
 ///
-/// Helper to combine multiple providers
+/// Helper to combine multiple providers.
+/// The getters returns `None` and the fetchers fails with `AuthError::BadService` for unknown service.
 pub trait IsOAuth2Providers<S, X> {
     /// Check if service exists
     fn has_service(&self, name: &str) -> bool;
 
     /// Authorization endpoint URL
-    fn authorize_url(&self, name: &str) -> Cow<str>;
+    fn authorize_url(&self, name: &str) -> Option<Cow<str>>;
 
     /// Authorization scope
-    fn authorize_scope(&self, name: &str) -> Cow<str>;
+    fn authorize_scope(&self, name: &str) -> Option<Cow<str>>;
 
     /// Extra authorization params
     type AuthorizeParams: Serialize + Send + 'static;
 
     /// Authorizaton parameters
-    fn authorize_params(&self, name: &str) -> Self::AuthorizeParams;
+    fn authorize_params(&self, name: &str) -> Option<Self::AuthorizeParams>;
 
     /// Access token endpoint URL
-    fn access_token_url(&self, name: &str) -> Cow<str>;
+    fn access_token_url(&self, name: &str) -> Option<Cow<str>>;
 
     /// Fetch user info
     fn fetch_user_info(
@@ -89,6 +125,15 @@ pub trait IsOAuth2Providers<S, X> {
         state: &S,
         access_token: Cow<str>,
     ) -> BoxFuture<X, AuthError>;
+
+    /// Fetch user info using access token response
+    fn fetch_token_user_info(
+        &self,
+        name: &str,
+        state: &S,
+        token: &AccessTokenResponse,
+        nonce: &str,
+    ) -> BoxFuture<X, AuthError>;
 }
 
 impl<S, X, A> IsOAuth2Providers<S, X> for (A,)
@@ -100,31 +145,65 @@ where
         self.0.service_name() == name
     }
 
-    fn authorize_url(&self, _name: &str) -> Cow<str> {
-        self.0.authorize_url()
+    fn authorize_url(&self, name: &str) -> Option<Cow<str>> {
+        if self.has_service(name) {
+            Some(self.0.authorize_url())
+        } else {
+            None
+        }
     }
 
-    fn authorize_scope(&self, _name: &str) -> Cow<str> {
-        self.0.authorize_scope()
+    fn authorize_scope(&self, name: &str) -> Option<Cow<str>> {
+        if self.has_service(name) {
+            Some(self.0.authorize_scope())
+        } else {
+            None
+        }
     }
 
     type AuthorizeParams = A::AuthorizeParams;
 
-    fn authorize_params(&self, _name: &str) -> Self::AuthorizeParams {
-        self.0.authorize_params()
+    fn authorize_params(&self, name: &str) -> Option<Self::AuthorizeParams> {
+        if self.has_service(name) {
+            Some(self.0.authorize_params())
+        } else {
+            None
+        }
     }
 
-    fn access_token_url(&self, _name: &str) -> Cow<str> {
-        self.0.access_token_url()
+    fn access_token_url(&self, name: &str) -> Option<Cow<str>> {
+        if self.has_service(name) {
+            Some(self.0.access_token_url())
+        } else {
+            None
+        }
     }
 
     fn fetch_user_info(
         &self,
-        _name: &str,
+        name: &str,
         state: &S,
         access_token: Cow<str>,
     ) -> BoxFuture<X, AuthError> {
-        self.0.fetch_user_info(state, access_token)
+        if self.has_service(name) {
+            self.0.fetch_user_info(state, access_token)
+        } else {
+            Box::new(err(AuthError::BadService))
+        }
+    }
+
+    fn fetch_token_user_info(
+        &self,
+        name: &str,
+        state: &S,
+        token: &AccessTokenResponse,
+        nonce: &str,
+    ) -> BoxFuture<X, AuthError> {
+        if self.has_service(name) {
+            self.0.fetch_token_user_info(state, token, nonce)
+        } else {
+            Box::new(err(AuthError::BadService))
+        }
     }
 }
 
@@ -172,38 +251,45 @@ macro_rules! tuple_providers {
                 false
             }
 
-            fn authorize_url(&self, name: &str) -> Cow<str> {
+            fn authorize_url(&self, name: &str) -> Option<Cow<str>> {
                 $(if self.$index.service_name() == name {
-                    return self.$index.authorize_url();
+                    return Some(self.$index.authorize_url());
                 })+
-                unreachable!();
+                None
             }
 
-            fn authorize_scope(&self, name: &str) -> Cow<str> {
+            fn authorize_scope(&self, name: &str) -> Option<Cow<str>> {
                 $(if self.$index.service_name() == name {
-                    return self.$index.authorize_scope();
+                    return Some(self.$index.authorize_scope());
                 })+
-                unreachable!();
+                None
             }
 
             type AuthorizeParams = authorize_params_type!($($type),+);
 
-            fn authorize_params(&self, name: &str) -> Self::AuthorizeParams {
-                authorize_params!(self, name, $($index),+).unwrap()
+            fn authorize_params(&self, name: &str) -> Option<Self::AuthorizeParams> {
+                authorize_params!(self, name, $($index),+)
             }
 
-            fn access_token_url(&self, name: &str) -> Cow<str> {
+            fn access_token_url(&self, name: &str) -> Option<Cow<str>> {
                 $(if self.$index.service_name() == name {
-                    return self.$index.access_token_url();
+                    return Some(self.$index.access_token_url());
                 })+
-                unreachable!();
+                None
             }
 
             fn fetch_user_info(&self, name: &str, state: &S, access_token: Cow<str>) -> BoxFuture<X, AuthError> {
                 $(if self.$index.service_name() == name {
                     return self.$index.fetch_user_info(state, access_token);
                 })+
-                unreachable!();
+                Box::new(err(AuthError::BadService))
+            }
+
+            fn fetch_token_user_info(&self, name: &str, state: &S, token: &AccessTokenResponse, nonce: &str) -> BoxFuture<X, AuthError> {
+                $(if self.$index.service_name() == name {
+                    return self.$index.fetch_token_user_info(state, token, nonce);
+                })+
+                Box::new(err(AuthError::BadService))
             }
         }
     };
@@ -235,3 +321,22 @@ where
         <<Self as HasAccountStorage>::AccountStorage as IsAccountStorage>::Account,
     >;
 }
+
+/// State which can check login methods of user
+///
+/// The last linked account cannot be unlinked when user has no other login methods.
+/// See [`has_own_login`](auth::oauth2::has_own_login)
+/// and [`has_webauthn_login`](auth::webauthn::has_webauthn_login) helpers.
+pub trait HasLoginMethods {
+    /// Check that user has login methods other than linked accounts
+    ///
+    /// The password, WebAuthn credentials and one-time password identities should be counted.
+    fn has_other_login(&self, user: UserId) -> BoxFuture<bool, AuthError>;
+}
+
+/// State which has OAuth2 auth method
+pub trait HasOAuth2
+where
+    Self: AsRef<OAuth2Auth>,
+{
+}
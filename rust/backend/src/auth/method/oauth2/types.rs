@@ -1,4 +1,8 @@
+use auth::IsThrottleIdent;
+use base::TimeStamp;
+use user::{AccountId, UserId};
 use serde_with::rust::display_fromstr;
+use third::generic;
 use url::Url;
 
 /// OAuth2 auth options
@@ -11,6 +15,23 @@ pub struct OAuth2Options {
     /// The service name will be added to this URI to get `redirect_uri` parameter.
     #[serde(with = "display_fromstr")]
     pub redirect: Url,
+    /// State life time in milliseconds
+    ///
+    /// The login should be completed during this time.
+    #[serde(default = "default_state_time")]
+    pub state_time: TimeStamp,
+    /// Link new account to existing user by email
+    ///
+    /// Only the email which verified by service is used
+    /// (see [`IsAccountData::email_verified`](user::IsAccountData::email_verified))
+    /// and only the user which email is verified too can be found
+    /// (see [`IsUserStorage::find_user_by_email`](user::IsUserStorage::find_user_by_email)).
+    #[serde(default)]
+    pub link_email: bool,
+}
+
+fn default_state_time() -> TimeStamp {
+    TimeStamp::default().with_mins(10)
 }
 
 impl Default for OAuth2Options {
@@ -18,10 +39,19 @@ impl Default for OAuth2Options {
         Self {
             services: Vec::new(),
             redirect: "https://my-site.tld/oauth2".parse().unwrap(),
+            state_time: default_state_time(),
+            link_email: false,
         }
     }
 }
 
+/// OAuth2 accounts arguments (or predicate)
+#[derive(Debug)]
+pub struct OAuth2Arg {
+    /// Accounts owner
+    pub user: UserId,
+}
+
 /// OAuth2 client options
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientOptions {
@@ -31,6 +61,12 @@ pub struct ClientOptions {
     /// Client params
     #[serde(flatten)]
     pub params: ClientParams,
+
+    /// Generic provider config
+    ///
+    /// Used to define provider without code (see [`generic`](third::generic)).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<generic::Config>,
 }
 
 /// OAuth2 client parameters
@@ -79,13 +115,35 @@ pub struct ServiceInfo<Params> {
     /// Scope
     pub scope: String,
 
+    /// State string
+    ///
+    /// The state is issued by server per login attempt and should be passed back unchanged.
+    pub state: String,
+
+    /// Client key
+    ///
+    /// The key is bound to state and should be kept by client and sent back with code.
+    /// Unlike state it should not be passed to authorization endpoint.
+    pub key: String,
+
+    /// PKCE code challenge
+    pub code_challenge: String,
+
+    /// PKCE code challenge method
+    pub code_challenge_method: &'static str,
+
+    /// OpenID Connect nonce
+    ///
+    /// The nonce is bound to state and will be checked in ID token by OpenID Connect providers.
+    pub nonce: String,
+
     /// Extra params
     #[serde(flatten)]
     pub params: Params,
 }
 
 /// OAuth2 auth user identification
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub enum UserIdent {
     #[serde(rename = "oauth2")]
     OAuth2 {
@@ -98,11 +156,60 @@ pub enum UserIdent {
 
         /// State string
         ///
-        /// Some services need this.
+        /// The state which issued by server in auth info.
         state: String,
+
+        /// Client key
+        ///
+        /// The key which issued by server in auth info together with state.
+        key: String,
+
+        /// Invite code
+        ///
+        /// Used on sign-up of new user (see [`ProvisionOptions`](auth::ProvisionOptions)).
+        #[serde(default)]
+        invite: Option<String>,
     },
 }
 
+/// Link account request
+///
+/// The same data as in user identification.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccountLink {
+    /// Service name
+    pub name: String,
+
+    /// Authorization code
+    pub code: String,
+
+    /// State string
+    pub state: String,
+
+    /// Client key
+    pub key: String,
+}
+
+/// Linked account information
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountInfo {
+    /// Account identifier
+    pub id: AccountId,
+
+    /// Service name
+    pub service: String,
+
+    /// Account name on service
+    pub name: String,
+}
+
+impl IsThrottleIdent for UserIdent {
+    fn throttle_key(&self) -> Option<String> {
+        // user is unknown until code exchange
+        None
+    }
+}
+
 /// OAuth2 access token request params
 #[derive(Debug, Clone, Serialize)]
 pub struct AccessTokenRequest<'a> {
@@ -127,6 +234,25 @@ pub struct AccessTokenRequest<'a> {
     ///
     /// Some services need this.
     pub state: &'a str,
+
+    /// PKCE code verifier
+    pub code_verifier: &'a str,
+}
+
+/// OAuth2 refresh token request params
+#[derive(Debug, Clone, Serialize)]
+pub struct RefreshTokenRequest<'a> {
+    /// Provider params
+    #[serde(flatten)]
+    pub params: &'a ClientParams,
+
+    /// Refresh token
+    pub refresh_token: &'a str,
+
+    /// Grant type
+    ///
+    /// Should be set to "refresh_token".
+    pub grant_type: &'a str,
 }
 
 /// OAuth2 access token response params
@@ -142,6 +268,11 @@ pub struct AccessTokenResponse {
     ///
     /// The remaining lifetime of the access token in seconds.
     pub expires_in: Option<u32>,
+
+    /// ID token
+    ///
+    /// OpenID Connect providers issue it.
+    pub id_token: Option<String>,
 }
 
 impl AccessTokenResponse {
@@ -0,0 +1,161 @@
+#[cfg(feature = "send_mail")]
+use super::IsMagicLinkStorage;
+use super::{AuthToken, IsOTPassStorage};
+use base::{BoxFuture, IsBackend, TimeStamp};
+use futures::future::result;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind};
+use std::sync::{Arc, Mutex};
+
+/// In-memory one-time password tokens
+///
+/// Expired tokens is purged on adding new tokens.
+/// This storage cannot be shared between server instances.
+#[derive(Clone)]
+pub struct OTPassCache {
+    tokens: Arc<Mutex<HashMap<String, AuthToken>>>,
+}
+
+impl OTPassCache {
+    /// Create tokens cache
+    pub fn new() -> Self {
+        Self {
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl IsBackend for OTPassCache {
+    type Error = IoError;
+}
+
+impl IsOTPassStorage for OTPassCache {
+    fn add_token(
+        &self,
+        key: &str,
+        token: AuthToken,
+        dead: TimeStamp,
+    ) -> BoxFuture<bool, Self::Error> {
+        Box::new(result(
+            self.tokens
+                .lock()
+                .map(|mut tokens| {
+                    // clear dead tokens
+                    tokens.retain(|_, token| token.ctime > dead);
+                    if tokens.contains_key(key) {
+                        false
+                    } else {
+                        tokens.insert(key.into(), token);
+                        true
+                    }
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn get_token(&self, key: &str, dead: TimeStamp) -> BoxFuture<Option<AuthToken>, Self::Error> {
+        Box::new(result(
+            self.tokens
+                .lock()
+                .map(|tokens| {
+                    tokens
+                        .get(key)
+                        .filter(|token| token.ctime > dead)
+                        .cloned()
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn add_retry(&self, key: &str) -> BoxFuture<Option<usize>, Self::Error> {
+        Box::new(result(
+            self.tokens
+                .lock()
+                .map(|mut tokens| {
+                    tokens.get_mut(key).map(|token| {
+                        token.retry += 1;
+                        token.retry
+                    })
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn del_token(&self, key: &str) -> BoxFuture<Option<()>, Self::Error> {
+        Box::new(result(
+            self.tokens
+                .lock()
+                .map(|mut tokens| tokens.remove(key).map(|_| ()))
+                .map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+}
+
+/// In-memory magic links data
+///
+/// Outdated records is purged on adding new records.
+/// This storage cannot be shared between server instances.
+#[cfg(feature = "send_mail")]
+#[derive(Clone)]
+pub struct MagicLinkCache {
+    /// Last sending time by address
+    sent: Arc<Mutex<HashMap<String, TimeStamp>>>,
+    /// Expiration time of used tokens by identifier
+    used: Arc<Mutex<HashMap<String, TimeStamp>>>,
+}
+
+#[cfg(feature = "send_mail")]
+impl MagicLinkCache {
+    /// Create magic links cache
+    pub fn new() -> Self {
+        Self {
+            sent: Arc::new(Mutex::new(HashMap::new())),
+            used: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+#[cfg(feature = "send_mail")]
+impl IsBackend for MagicLinkCache {
+    type Error = IoError;
+}
+
+#[cfg(feature = "send_mail")]
+impl IsMagicLinkStorage for MagicLinkCache {
+    fn add_sent(
+        &self,
+        email: &str,
+        time: TimeStamp,
+        dead: TimeStamp,
+    ) -> BoxFuture<bool, Self::Error> {
+        Box::new(result(
+            self.sent
+                .lock()
+                .map(|mut sent| {
+                    // clear outdated records
+                    sent.retain(|_, time| *time > dead);
+                    if sent.contains_key(email) {
+                        false
+                    } else {
+                        sent.insert(email.into(), time);
+                        true
+                    }
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn add_used(&self, id: &str, etime: TimeStamp) -> BoxFuture<bool, Self::Error> {
+        let at = TimeStamp::now();
+        Box::new(result(
+            self.used
+                .lock()
+                .map(|mut used| {
+                    // clear expired tokens
+                    used.retain(|_, etime| *etime > at);
+                    if used.contains_key(id) {
+                        false
+                    } else {
+                        used.insert(id.into(), etime);
+                        true
+                    }
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+}
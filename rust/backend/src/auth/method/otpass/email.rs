@@ -56,6 +56,14 @@ pub trait IsEmailOTPassFormatter<S> {
                 .into()
         }
     }
+
+    /// Create message
+    fn message(&self, state: &S, ident: &EmailUserIdent, password: &str) -> MailMessage {
+        MailMessage::create()
+            .to(Mailbox::new(Some(ident.name.clone()), ident.email.clone()))
+            .subject(self.subject(state, ident, password))
+            .mime_body(self.body(state, ident, password))
+    }
 }
 
 /// Built-in email formatter
@@ -80,6 +88,10 @@ impl IsOTPassIdent for EmailUserIdent {
     fn get_user_name(&self) -> Cow<str> {
         self.email.to_string().into()
     }
+
+    fn get_email(&self) -> Option<&MailAddress> {
+        Some(&self.email)
+    }
 }
 
 /// One-time password sender which uses email
@@ -109,14 +121,9 @@ where
         ident: &Self::UserIdent,
         password: &str,
     ) -> BoxFuture<(), AuthError> {
-        let message = MailMessage::create()
-            .to(Mailbox::new(Some(ident.name.clone()), ident.email.clone()))
-            .subject(self.0.subject(state, ident, password))
-            .mime_body(self.0.body(state, ident, password));
-
         Box::new(
             (state.as_ref() as &S::Mailer)
-                .send_mail(message)
+                .send_mail(self.0.message(state, ident, password))
                 .map_err(|_| AuthError::BackendError)
                 .and_then(|_| Err(AuthError::NeedRetry)),
         )
@@ -0,0 +1,383 @@
+use super::{
+    check_user, find_or_create_user, EmailUserIdent, HasMagicLinkStorage,
+    IsEmailOTPassFormatter, IsMagicLinkStorage,
+};
+use auth::{AuthContext, AuthError, HasProvisionPolicy, IsAuthMethod, IsThrottleIdent};
+use base::{serde_extra::base64, BoxFuture, CanUpdateFrom, TimeStamp};
+use base64lib::{encode_config, URL_SAFE_NO_PAD};
+use crypto::{random_bytes, CanDecrypt, CanEncrypt, HasSecureKey, PublicKey};
+use futures::{
+    future::{err, lazy, ok, Either},
+    Future,
+};
+use mail::{HasMailer, IsMailer};
+use std::sync::Arc;
+use url::Url;
+use user::{HasUserStorage, IsUserStorage};
+
+/// Magic link auth options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MagicLinkOptions {
+    /// Link base URL
+    ///
+    /// Usually this is a page of client app which completes login.
+    /// The token will be added to it as `token` query parameter.
+    pub base_url: String,
+
+    /// Link life time in milliseconds
+    #[serde(default = "default_link_time")]
+    pub link_time: TimeStamp,
+
+    /// Minimum interval between links to same address in milliseconds
+    #[serde(default = "default_resend_time")]
+    pub resend_time: TimeStamp,
+}
+
+fn default_link_time() -> TimeStamp {
+    TimeStamp::default().with_mins(15)
+}
+
+fn default_resend_time() -> TimeStamp {
+    TimeStamp::default().with_mins(1)
+}
+
+impl MagicLinkOptions {
+    /// Create options using link base URL
+    pub fn new<S: Into<String>>(base_url: S) -> Self {
+        Self {
+            base_url: base_url.into(),
+            link_time: default_link_time(),
+            resend_time: default_resend_time(),
+        }
+    }
+}
+
+/// Magic link token
+///
+/// The data which sealed by server secure key.
+#[derive(Debug, Serialize, Deserialize)]
+struct MagicLinkToken {
+    /// Unique token identifier
+    id: String,
+    /// Recipient
+    ident: EmailUserIdent,
+    /// Invite code
+    #[serde(default)]
+    invite: Option<String>,
+    /// Public key of client which requested link
+    #[serde(with = "base64")]
+    pbkey: PublicKey,
+    /// Expiration time
+    etime: TimeStamp,
+}
+
+/// Magic link auth info
+#[derive(Debug, Serialize)]
+pub struct MagicLinkAuthInfo {
+    pub magic: MagicLinkInfo,
+}
+
+/// Magic link sender info
+#[derive(Debug, Serialize)]
+pub struct MagicLinkInfo {
+    email: bool,
+}
+
+/// Magic link auth user identification
+#[derive(Debug, Clone, Deserialize)]
+pub enum MagicLinkIdent {
+    /// Request link using email
+    #[serde(rename = "magic")]
+    Request {
+        #[serde(flatten)]
+        ident: EmailUserIdent,
+        /// Invite code
+        #[serde(default)]
+        invite: Option<String>,
+    },
+    /// Complete login using token from link
+    #[serde(rename = "magic_token")]
+    Token { token: String },
+}
+
+impl IsThrottleIdent for MagicLinkIdent {
+    fn throttle_key(&self) -> Option<String> {
+        match self {
+            MagicLinkIdent::Request { ident, .. } => Some(ident.email.to_string()),
+            // token is sealed so it can not be guessed
+            MagicLinkIdent::Token { .. } => None,
+        }
+    }
+}
+
+struct State<F> {
+    options: MagicLinkOptions,
+    formatter: F,
+}
+
+/// Magic link auth method
+///
+/// Emails the signed single-use link which completes login.
+/// The link is bound to public key of client which requested it,
+/// so the login can be completed only by same client.
+///
+/// The formatter gets link instead of password.
+/// The sending times and used tokens is kept in state storage (see [`HasMagicLinkStorage`]).
+#[derive(Clone)]
+pub struct MagicLinkAuth<F>(Arc<State<F>>);
+
+impl<F> MagicLinkAuth<F> {
+    /// Create magic link auth method instance
+    pub fn new(formatter: F, options: MagicLinkOptions) -> Self {
+        MagicLinkAuth(Arc::new(State { options, formatter }))
+    }
+
+    /// Add link sending
+    ///
+    /// The sending is added when the returned future is polled.
+    fn can_send<S>(
+        &self,
+        state: &S,
+        ident: &EmailUserIdent,
+    ) -> BoxFuture<bool, AuthError>
+    where
+        S: HasMagicLinkStorage + Send + Clone + 'static,
+    {
+        let resend_time = self.0.options.resend_time;
+        let email = ident.email.to_string();
+        let state = state.clone();
+
+        Box::new(lazy(move || {
+            let at = TimeStamp::now();
+
+            (state.as_ref() as &S::MagicLinkStorage)
+                .add_sent(&email, at, at - resend_time)
+                .map_err(|error| {
+                    error!("Unable to add magic link sending: {}", error);
+                    AuthError::BackendError
+                })
+        }))
+    }
+
+    fn use_token<S>(
+        &self,
+        state: &S,
+        token: &MagicLinkToken,
+    ) -> BoxFuture<bool, AuthError>
+    where
+        S: HasMagicLinkStorage,
+    {
+        if token.etime <= TimeStamp::now() {
+            return Box::new(ok(false));
+        }
+
+        Box::new(
+            (state.as_ref() as &S::MagicLinkStorage)
+                .add_used(&token.id, token.etime)
+                .map_err(|error| {
+                    error!("Unable to add used magic link token: {}", error);
+                    AuthError::BackendError
+                }),
+        )
+    }
+
+    fn create_link<S>(
+        &self,
+        state: &S,
+        ident: &EmailUserIdent,
+        invite: &Option<String>,
+        pbkey: PublicKey,
+    ) -> Option<String>
+    where
+        S: HasSecureKey,
+    {
+        let token = MagicLinkToken {
+            id: encode_config(&random_bytes(16), URL_SAFE_NO_PAD),
+            ident: ident.clone(),
+            invite: invite.clone(),
+            pbkey,
+            etime: TimeStamp::now() + self.0.options.link_time,
+        };
+
+        let token = (state.as_ref() as &S::SecureKey)
+            .seal_json_b64(&token)
+            .map_err(|error| {
+                error!("Unable to seal magic link token: {}", error);
+            }).ok()?;
+
+        let mut url = Url::parse(&self.0.options.base_url)
+            .map_err(|error| {
+                error!("Invalid magic link base URL: {}", error);
+            }).ok()?;
+        url.query_pairs_mut().append_pair("token", &token);
+
+        Some(url.to_string())
+    }
+}
+
+impl<S, F> IsAuthMethod<S> for MagicLinkAuth<F>
+where
+    S: HasUserStorage
+        + HasSecureKey
+        + HasMailer
+        + HasMagicLinkStorage
+        + HasProvisionPolicy
+        + Send
+        + Clone
+        + 'static,
+    F: IsEmailOTPassFormatter<S>,
+    <S::UserStorage as IsUserStorage>::User: CanUpdateFrom<EmailUserIdent>,
+{
+    type AuthInfo = MagicLinkAuthInfo;
+    type UserIdent = MagicLinkIdent;
+
+    fn get_auth_info(&self, _state: &S) -> Self::AuthInfo {
+        MagicLinkAuthInfo {
+            magic: MagicLinkInfo { email: true },
+        }
+    }
+
+    fn try_user_auth(
+        &self,
+        state: &S,
+        ident: &Self::UserIdent,
+    ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
+        self.try_user_auth_from(state, ident, &AuthContext::default())
+    }
+
+    fn try_user_auth_from(
+        &self,
+        state: &S,
+        ident: &Self::UserIdent,
+        ctx: &AuthContext,
+    ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
+        let pbkey = match ctx.pbkey {
+            Some(pbkey) => pbkey,
+            None => {
+                error!("Magic link auth requires client public key");
+                return Box::new(err(AuthError::BadIdent));
+            }
+        };
+
+        match ident {
+            MagicLinkIdent::Request { ident, invite } => {
+                let link = match self.create_link(state, ident, invite, pbkey) {
+                    Some(link) => link,
+                    None => return Box::new(err(AuthError::BackendError)),
+                };
+                let message = self.0.formatter.message(state, ident, &link);
+                let sending = self.can_send(state, ident);
+                let state = state.clone();
+
+                Box::new(
+                    check_user(&state, ident, invite.as_ref().map(AsRef::as_ref))
+                        .and_then(move |_| sending)
+                        .and_then(move |sent| {
+                            if !sent {
+                                warn!("Magic link already sent");
+                                return Either::A(err(AuthError::BadIdent));
+                            }
+
+                            Either::B(
+                                (state.as_ref() as &S::Mailer)
+                                    .send_mail(message)
+                                    .map_err(|error| {
+                                        error!("Unable to send magic link: {}", error);
+                                        AuthError::BackendError
+                                    }).and_then(|_| Err(AuthError::NeedRetry)),
+                            )
+                        }),
+                )
+            }
+            MagicLinkIdent::Token { token } => {
+                let token: MagicLinkToken =
+                    match (state.as_ref() as &S::SecureKey).open_json_b64(token) {
+                        Ok(token) => token,
+                        Err(error) => {
+                            warn!("Invalid magic link token: {}", error);
+                            return Box::new(err(AuthError::BadIdent));
+                        }
+                    };
+
+                if token.pbkey != pbkey {
+                    warn!("Magic link token of other client");
+                    return Box::new(err(AuthError::BadIdent));
+                }
+
+                let state = state.clone();
+
+                Box::new(self.use_token(&state, &token).and_then(move |used| {
+                    if !used {
+                        warn!("Expired or used magic link token");
+                        return Either::A(err(AuthError::BadIdent));
+                    }
+
+                    Either::B(find_or_create_user(
+                        &state,
+                        &token.ident,
+                        token.invite.as_ref().map(AsRef::as_ref),
+                    ))
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use auth::otpass::MagicLinkCache;
+    use sodiumoxide::crypto::box_::gen_keypair;
+
+    #[derive(Clone)]
+    struct State(MagicLinkCache);
+
+    impl AsRef<MagicLinkCache> for State {
+        fn as_ref(&self) -> &MagicLinkCache {
+            &self.0
+        }
+    }
+
+    impl HasMagicLinkStorage for State {
+        type MagicLinkStorage = MagicLinkCache;
+    }
+
+    #[test]
+    fn single_use() {
+        let auth = MagicLinkAuth::new((), MagicLinkOptions::new("https://localhost/login"));
+        let state = State(MagicLinkCache::new());
+        let ident = EmailUserIdent {
+            email: "user@example.com".parse().unwrap(),
+            name: "User".into(),
+        };
+
+        assert!(auth.can_send(&state, &ident).wait().unwrap());
+        // resend too early
+        assert!(!auth.can_send(&state, &ident).wait().unwrap());
+
+        let token = |etime| MagicLinkToken {
+            id: "id".into(),
+            ident: ident.clone(),
+            invite: None,
+            pbkey: gen_keypair().0,
+            etime,
+        };
+
+        let now = TimeStamp::now();
+        let use_token = |etime| auth.use_token(&state, &token(etime)).wait().unwrap();
+        assert!(!use_token(now - TimeStamp::default().with_secs(1)));
+        assert!(use_token(now + TimeStamp::default().with_mins(1)));
+        // reused token
+        assert!(!use_token(now + TimeStamp::default().with_mins(1)));
+
+        // storage is shared between method instances
+        let other = MagicLinkAuth::new((), MagicLinkOptions::new("https://localhost/login"));
+        assert!(!other.can_send(&state, &ident).wait().unwrap());
+        assert!(
+            !other
+                .use_token(&state, &token(now + TimeStamp::default().with_mins(1)))
+                .wait()
+                .unwrap()
+        );
+    }
+}
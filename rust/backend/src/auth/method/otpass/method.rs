@@ -1,113 +1,127 @@
-use auth::{AuthError, IsAuthMethod};
+use auth::{approved_user, AuthError, HasProvisionPolicy, IsAuthMethod, SignUp};
 use base::{BoxFuture, CanUpdateFrom, TimeStamp};
 use futures::{
     future::{err, ok, Either},
     Future,
 };
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::marker::PhantomData;
+use std::sync::Arc;
 use user::{HasUserStorage, IsUserData, IsUserStorage};
 
-use super::{AuthInfo, AuthToken, IsOTPassIdent, IsOTPassSender, OTPassOptions, UserIdent};
-
-type Tokens<I> = HashMap<I, AuthToken>;
+use super::{
+    AuthInfo, AuthToken, HasOTPassStorage, IsOTPassIdent, IsOTPassSender, IsOTPassStorage,
+    OTPassOptions, UserIdent,
+};
 
-struct State<P, I> {
+struct State<P> {
     options: OTPassOptions,
     sender: P,
-    tokens: RwLock<Tokens<I>>,
 }
 
 /// One-Time password auth method
-#[derive(Clone)]
-pub struct OTPassAuth<S, P>(Arc<State<P, P::UserIdent>>)
+///
+/// The pending tokens is kept in state storage (see [`HasOTPassStorage`]).
+pub struct OTPassAuth<S, P>(Arc<State<P>>, PhantomData<S>)
 where
     P: IsOTPassSender<S>;
 
+impl<S, P> Clone for OTPassAuth<S, P>
+where
+    P: IsOTPassSender<S>,
+{
+    fn clone(&self) -> Self {
+        OTPassAuth(self.0.clone(), PhantomData)
+    }
+}
+
 impl<S, P> OTPassAuth<S, P>
 where
+    S: HasOTPassStorage + Clone,
     P: IsOTPassSender<S>,
 {
     /// Create one-time password auth method instance
     pub fn new(sender: P, options: OTPassOptions) -> Self {
-        OTPassAuth(Arc::new(State {
-            options,
-            sender,
-            tokens: RwLock::new(HashMap::new()),
-        }))
-    }
-
-    fn clear_tokens(&self, at: TimeStamp) {
-        // clear dead tokens
-        let dt = at - self.0.options.dead_time;
-        self.0
-            .tokens
-            .write()
-            .unwrap()
-            .retain(|_, token| token.ctime > dt);
+        OTPassAuth(Arc::new(State { options, sender }), PhantomData)
     }
 
-    fn create_token(&self, _state: &S, ident: &P::UserIdent) -> Option<String> {
+    fn create_token(
+        &self,
+        state: &S,
+        ident: &P::UserIdent,
+    ) -> impl Future<Item = Option<String>, Error = AuthError> {
         let at = TimeStamp::now();
-        self.clear_tokens(at);
-
-        {
-            let tokens = self.0.tokens.read().unwrap();
-            if tokens.contains_key(&ident) {
-                return None;
-            }
-        }
-
-        let mut tokens = self.0.tokens.write().unwrap();
         let token = AuthToken::new(at, &self.0.options);
         let pass = token.pass.clone();
 
-        tokens.insert(ident.clone(), token);
-
-        Some(pass)
+        (state.as_ref() as &S::OTPassStorage)
+            .add_token(
+                &ident.get_user_name(),
+                token,
+                at - self.0.options.dead_time,
+            ).map_err(|error| {
+                error!("Unable to add OTP token: {}", error);
+                AuthError::BackendError
+            }).map(move |added| if added { Some(pass) } else { None })
     }
 
-    fn verify_token(&self, _state: &S, ident: &P::UserIdent, pass: &String) -> bool {
-        let at = TimeStamp::now();
-        self.clear_tokens(at);
-
-        let mut no_retry = false;
-        let mut is_match = false;
-
-        {
-            let tokens = self.0.tokens.read().unwrap();
-            // get token
-            if let Some(token) = tokens.get(&ident) {
-                // check password
-                if &token.pass == pass {
-                    is_match = true;
-                }
-                // check retries limit
-                if token.retry >= self.0.options.retry_lim {
-                    no_retry = true;
-                }
-            } else {
-                return false;
-            }
-        }
+    fn verify_token(
+        &self,
+        state: &S,
+        ident: &P::UserIdent,
+        pass: &str,
+    ) -> impl Future<Item = bool, Error = AuthError> {
+        let dead = TimeStamp::now() - self.0.options.dead_time;
+        let retry_lim = self.0.options.retry_lim;
+        let key = ident.get_user_name().to_string();
+        let pass = pass.to_string();
+        let state = state.clone();
 
-        let mut tokens = self.0.tokens.write().unwrap();
+        // count attempt before checking password
+        (state.as_ref() as &S::OTPassStorage)
+            .add_retry(&key)
+            .and_then({
+                let state = state.clone();
+                let key = key.clone();
+                move |retry| match retry {
+                    Some(retry) => Either::A(
+                        (state.as_ref() as &S::OTPassStorage)
+                            .get_token(&key, dead)
+                            .map(move |token| token.map(|token| (token, retry))),
+                    ),
+                    None => Either::B(ok(None)),
+                }
+            }).and_then(move |token| {
+                let storage = state.as_ref() as &S::OTPassStorage;
+                let (token, retry) = match token {
+                    Some(token) => token,
+                    None => return Either::A(ok(false)),
+                };
 
-        if is_match || no_retry {
-            tokens.remove(&ident);
-        } else {
-            let token = tokens.get_mut(&ident).unwrap();
-            token.retry += 1;
-        }
+                // check retries limit
+                let is_match = retry <= retry_lim + 1 && token.pass == pass;
+                let no_retry = retry > retry_lim;
 
-        is_match
+                if is_match || no_retry {
+                    // the token can be used only once
+                    Either::B(
+                        storage
+                            .del_token(&key)
+                            .map(move |deleted| is_match && deleted.is_some()),
+                    )
+                } else {
+                    Either::A(ok(false))
+                }
+            }).map_err(|error| {
+                error!("OTP storage error: {}", error);
+                AuthError::BackendError
+            })
     }
 }
 
 impl<S, P> IsAuthMethod<S> for OTPassAuth<S, P>
 where
-    S: HasUserStorage + Send + Clone + 'static,
-    P: IsOTPassSender<S>,
+    S: HasUserStorage + HasOTPassStorage + HasProvisionPolicy + Send + Clone + 'static,
+    P: IsOTPassSender<S> + Send + Sync + 'static,
     <S::UserStorage as IsUserStorage>::User: CanUpdateFrom<P::UserIdent>,
 {
     type AuthInfo = AuthInfo<P::AuthInfo>;
@@ -122,46 +136,313 @@ where
     fn try_user_auth(
         &self,
         state: &S,
-        UserIdent::OTPass { ident, pass }: &Self::UserIdent,
+        UserIdent::OTPass {
+            ident,
+            pass,
+            invite,
+        }: &Self::UserIdent,
     ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
         if let Some(pass) = pass {
-            if self.verify_token(state, ident, pass) {
-                let name = ident.get_user_name().to_string();
+            Box::new(self.verify_token(state, ident, pass).and_then({
+                let state = state.clone();
                 let ident = ident.clone();
-                Box::new(
-                    (state.as_ref() as &S::UserStorage)
-                        .find_user_data(&name)
-                        .and_then({
-                            let state = state.clone();
-                            move |user| {
-                                if let Some(user) = user {
-                                    Either::A(ok(user))
-                                } else {
-                                    let mut user =
-                                        <S::UserStorage as IsUserStorage>::User::create_new(name);
-                                    // update info
-                                    user.update_from(&ident);
-                                    Either::B(
-                                        (state.as_ref() as &S::UserStorage).put_user_data(user),
-                                    )
-                                }
-                            }
-                        }).map_err(|_| AuthError::BackendError),
-                )
-            } else {
-                Box::new(err(AuthError::BadIdent))
-            }
+                let invite = invite.clone();
+                move |valid| {
+                    if valid {
+                        Either::A(find_or_create_user(
+                            &state,
+                            &ident,
+                            invite.as_ref().map(AsRef::as_ref),
+                        ))
+                    } else {
+                        Either::B(err(AuthError::BadIdent))
+                    }
+                }
+            }))
         } else {
-            if let Some(password) = self.create_token(state, ident) {
-                Box::new(
-                    self.0
-                        .sender
-                        .send_password(state, ident, &password)
-                        .and_then(|_| err(AuthError::NeedRetry)),
-                )
-            } else {
-                Box::new(err(AuthError::BadIdent))
+            let this = self.clone();
+            let state = state.clone();
+            let ident = ident.clone();
+
+            Box::new(
+                check_user(&state, &ident, invite.as_ref().map(AsRef::as_ref))
+                    .and_then({
+                        let this = this.clone();
+                        let state = state.clone();
+                        let ident = ident.clone();
+                        move |_| this.create_token(&state, &ident)
+                    }).and_then(move |password| {
+                        if let Some(password) = password {
+                            Either::A(
+                                this.0
+                                    .sender
+                                    .send_password(&state, &ident, &password)
+                                    .and_then(|_| err(AuthError::NeedRetry)),
+                            )
+                        } else {
+                            Either::B(err(AuthError::BadIdent))
+                        }
+                    }),
+            )
+        }
+    }
+}
+
+/// Check user by one-time password ident before sending password
+///
+/// The existing user should be approved and the new user should satisfy provisioning policy.
+pub fn check_user<S, I>(state: &S, ident: &I, invite: Option<&str>) -> BoxFuture<(), AuthError>
+where
+    S: HasUserStorage + HasProvisionPolicy + Send + Clone + 'static,
+    I: IsOTPassIdent,
+{
+    let email = ident.get_email().cloned();
+    let invite = invite.map(String::from);
+    let state = state.clone();
+
+    Box::new(
+        (state.as_ref() as &S::UserStorage)
+            .find_user_data(ident.get_user_name().as_ref())
+            .map_err(|error| {
+                error!("Unable to find user data: {}", error);
+                AuthError::BackendError
+            }).and_then(move |user| {
+                if let Some(user) = user {
+                    approved_user(user).map(|_| ())
+                } else {
+                    state.check_sign_up(&SignUp {
+                        email: email.as_ref(),
+                        organizations: &[],
+                        invite: invite.as_ref().map(AsRef::as_ref),
+                    })
+                }
+            }),
+    )
+}
+
+/// Find user by one-time password ident or create new one
+///
+/// The new user should satisfy provisioning policy.
+pub fn find_or_create_user<S, I>(
+    state: &S,
+    ident: &I,
+    invite: Option<&str>,
+) -> impl Future<Item = <S::UserStorage as IsUserStorage>::User, Error = AuthError>
+where
+    S: HasUserStorage + HasProvisionPolicy + Clone,
+    I: IsOTPassIdent + Clone,
+    <S::UserStorage as IsUserStorage>::User: CanUpdateFrom<I>,
+{
+    let name = ident.get_user_name().to_string();
+    let ident = ident.clone();
+    let invite = invite.map(String::from);
+
+    (state.as_ref() as &S::UserStorage)
+        .find_user_data(&name)
+        .map_err(|error| {
+            error!("Unable to find user data: {}", error);
+            AuthError::BackendError
+        }).and_then({
+            let state = state.clone();
+            move |user| {
+                if let Some(user) = user {
+                    Either::A(ok(user))
+                } else {
+                    let mut user = <S::UserStorage as IsUserStorage>::User::create_new(name);
+                    if let Err(error) = state.provision_user(
+                        &mut user,
+                        &SignUp {
+                            email: ident.get_email(),
+                            organizations: &[],
+                            invite: invite.as_ref().map(AsRef::as_ref),
+                        },
+                    ) {
+                        return Either::B(Either::A(err(error)));
+                    }
+                    // update info
+                    user.update_from(&ident);
+                    Either::B(Either::B(
+                        (state.as_ref() as &S::UserStorage)
+                            .put_user_data(user)
+                            .map_err(|error| {
+                                error!("Unable to put user data: {}", error);
+                                AuthError::BackendError
+                            }),
+                    ))
+                }
             }
+        }).and_then(approved_user)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use auth::{method::otpass::OTPassCache, ProvisionOptions};
+    use std::borrow::Cow;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use user::stub::{UserData, Users};
+
+    #[derive(Clone)]
+    struct TestState(OTPassCache, Users, Arc<ProvisionOptions>);
+
+    impl AsRef<OTPassCache> for TestState {
+        fn as_ref(&self) -> &OTPassCache {
+            &self.0
+        }
+    }
+
+    impl HasOTPassStorage for TestState {
+        type OTPassStorage = OTPassCache;
+    }
+
+    impl AsRef<Users> for TestState {
+        fn as_ref(&self) -> &Users {
+            &self.1
+        }
+    }
+
+    impl HasUserStorage for TestState {
+        type UserStorage = Users;
+    }
+
+    impl AsRef<ProvisionOptions> for TestState {
+        fn as_ref(&self) -> &ProvisionOptions {
+            &self.2
+        }
+    }
+
+    impl HasProvisionPolicy for TestState {}
+
+    #[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize)]
+    struct TestIdent(String);
+
+    impl IsOTPassIdent for TestIdent {
+        fn get_user_name(&self) -> Cow<str> {
+            (&self.0).into()
+        }
+    }
+
+    impl CanUpdateFrom<TestIdent> for UserData {
+        fn update_from(&mut self, _ident: &TestIdent) {}
+    }
+
+    /// Sender which counts sent passwords
+    struct TestSender(AtomicUsize);
+
+    impl IsOTPassSender<TestState> for TestSender {
+        type AuthInfo = ();
+        type UserIdent = TestIdent;
+
+        fn sender_info(&self) -> Self::AuthInfo {}
+
+        fn send_password(
+            &self,
+            _state: &TestState,
+            _ident: &Self::UserIdent,
+            _password: &str,
+        ) -> BoxFuture<(), AuthError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Box::new(ok(()))
+        }
+    }
+
+    fn auth(options: OTPassOptions) -> (OTPassAuth<TestState, TestSender>, TestState) {
+        (
+            OTPassAuth::new(TestSender(AtomicUsize::new(0)), options),
+            TestState(
+                OTPassCache::new(),
+                Users::new().with_user(UserData::new(1, "user")),
+                Arc::new(ProvisionOptions::default()),
+            ),
+        )
+    }
+
+    #[test]
+    fn single_use() {
+        let (auth, state) = auth(OTPassOptions::default());
+        let ident = TestIdent("user".into());
+
+        let pass = auth.create_token(&state, &ident).wait().unwrap().unwrap();
+        // token already sent
+        assert_eq!(auth.create_token(&state, &ident).wait().unwrap(), None);
+
+        assert!(auth.verify_token(&state, &ident, &pass).wait().unwrap());
+        assert!(!auth.verify_token(&state, &ident, &pass).wait().unwrap());
+    }
+
+    #[test]
+    fn dead_time() {
+        let mut options = OTPassOptions::default();
+        options.dead_time = TimeStamp::default();
+        let (auth, state) = auth(options);
+        let ident = TestIdent("user".into());
+
+        let pass = auth.create_token(&state, &ident).wait().unwrap().unwrap();
+        assert!(!auth.verify_token(&state, &ident, &pass).wait().unwrap());
+        // dead token is replaced by new one
+        assert!(auth.create_token(&state, &ident).wait().unwrap().is_some());
+    }
+
+    #[test]
+    fn retry_lim() {
+        let mut options = OTPassOptions::default();
+        options.retry_lim = 2;
+        let (auth, state) = auth(options);
+        let ident = TestIdent("user".into());
+
+        let pass = auth.create_token(&state, &ident).wait().unwrap().unwrap();
+        let bad = format!("{}_", pass);
+
+        assert!(!auth.verify_token(&state, &ident, &bad).wait().unwrap());
+        assert!(!auth.verify_token(&state, &ident, &bad).wait().unwrap());
+        // retries exhausted so token is dropped
+        assert!(!auth.verify_token(&state, &ident, &bad).wait().unwrap());
+        assert!(!auth.verify_token(&state, &ident, &pass).wait().unwrap());
+
+        // attempts which is counted concurrently exhaust retries too
+        let pass = auth.create_token(&state, &ident).wait().unwrap().unwrap();
+        for _ in 0..3 {
+            state.0.add_retry("user").wait().unwrap();
         }
+        assert!(!auth.verify_token(&state, &ident, &pass).wait().unwrap());
+    }
+
+    #[test]
+    fn sign_up_policy() {
+        let (auth, mut state) = auth(OTPassOptions::default());
+        let mut provision = ProvisionOptions::default();
+        provision.invite_codes = vec!["code".into()];
+        state.2 = Arc::new(provision);
+
+        let request = |name: &str, invite: Option<&str>| {
+            match auth
+                .try_user_auth(
+                    &state,
+                    &UserIdent::OTPass {
+                        ident: TestIdent(name.into()),
+                        pass: None,
+                        invite: invite.map(String::from),
+                    },
+                ).wait()
+            {
+                Err(AuthError::NeedRetry) => true,
+                Err(AuthError::Restricted) => false,
+                _ => unreachable!(),
+            }
+        };
+        let sent = || (auth.0).sender.0.load(Ordering::SeqCst);
+
+        // new user without invite code
+        assert!(!request("other", None));
+        assert!(!request("other", Some("wrong")));
+        // password is not sent
+        assert_eq!(sent(), 0);
+
+        assert!(request("other", Some("code")));
+        assert_eq!(sent(), 1);
+
+        // existing user
+        assert!(request("user", None));
+        assert_eq!(sent(), 2);
     }
 }
@@ -4,22 +4,48 @@
 
 This method provides one-time password auth using *email*, *mobile phone* and etc.
 
+The pending passwords is kept by [`IsOTPassStorage`](auth::otpass::IsOTPassStorage) backend.
+The [`OTPassCache`](auth::otpass::OTPassCache) is a default in-memory storage.
+
+The user will be created on first login when provisioning policy allows it
+(see [`HasProvisionPolicy`](auth::HasProvisionPolicy)).
+The policy is checked before sending password or link, so the disallowed users receives nothing.
+The client may pass `invite` code with identification data.
+
+### Magic link auth
+
+The [`MagicLinkAuth`](auth::otpass::MagicLinkAuth) is a variant of email auth which sends single-use link instead of code.
+
+1. Client requests link (`magic: { email, name, invite }`) and gets *NeedRetry*
+2. User opens link which leads to client app page (see [`MagicLinkOptions`](auth::otpass::MagicLinkOptions))
+3. Client sends token from link (`magic_token: { token }`) with same public key
+
+The link token is sealed using server secure key and bound to public key of client which requested it.
+The sending times and used tokens is kept by [`IsMagicLinkStorage`](auth::otpass::IsMagicLinkStorage) backend,
+the [`MagicLinkCache`](auth::otpass::MagicLinkCache) is a default in-memory storage.
+
 */
 
+mod cache;
 mod method;
 mod traits;
 mod types;
 
 #[cfg(feature = "send_mail")]
 mod email;
+#[cfg(feature = "send_mail")]
+mod magic;
 #[cfg(feature = "send_sms")]
 mod phone;
 
+pub use self::cache::*;
 pub use self::method::*;
 pub use self::traits::*;
 pub use self::types::*;
 
 #[cfg(feature = "send_mail")]
 pub use self::email::*;
+#[cfg(feature = "send_mail")]
+pub use self::magic::*;
 #[cfg(feature = "send_sms")]
 pub use self::phone::*;
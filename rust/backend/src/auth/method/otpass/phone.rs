@@ -0,0 +1,80 @@
+use super::{IsOTPassIdent, IsOTPassSender};
+use auth::AuthError;
+use base::BoxFuture;
+use futures::Future;
+use sms::{HasSmsGateway, IsSmsGateway, PhoneNumber, SmsMessage};
+use std::borrow::Cow;
+
+/// SMS message creating
+pub trait IsPhoneOTPassFormatter<S> {
+    /// Create message text
+    fn text(&self, _state: &S, _ident: &PhoneUserIdent, password: &str) -> String {
+        format!("Your authentication code: {}", password)
+    }
+}
+
+/// Built-in SMS formatter
+pub struct PhoneOTPassFormatter;
+
+impl<S> IsPhoneOTPassFormatter<S> for PhoneOTPassFormatter {}
+
+/// Phone-based OTP auth info
+#[derive(Debug, Serialize)]
+pub struct PhoneAuthInfo {
+    phone: bool,
+}
+
+/// Phone-based OTP user ident
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub struct PhoneUserIdent {
+    pub phone: PhoneNumber,
+}
+
+impl IsOTPassIdent for PhoneUserIdent {
+    fn get_user_name(&self) -> Cow<str> {
+        self.phone.as_str().into()
+    }
+}
+
+/// One-time password sender which uses SMS
+pub struct PhoneOTPass<F>(F);
+
+impl<F> PhoneOTPass<F> {
+    pub fn new(formatter: F) -> Self {
+        PhoneOTPass(formatter)
+    }
+}
+
+impl<S, F> IsOTPassSender<S> for PhoneOTPass<F>
+where
+    S: HasSmsGateway,
+    F: IsPhoneOTPassFormatter<S>,
+{
+    type AuthInfo = PhoneAuthInfo;
+    type UserIdent = PhoneUserIdent;
+
+    fn sender_info(&self) -> Self::AuthInfo {
+        PhoneAuthInfo { phone: true }
+    }
+
+    fn send_password(
+        &self,
+        state: &S,
+        ident: &Self::UserIdent,
+        password: &str,
+    ) -> BoxFuture<(), AuthError> {
+        let message = SmsMessage {
+            to: ident.phone.clone(),
+            text: self.0.text(state, ident, password),
+        };
+
+        Box::new(
+            (state.as_ref() as &S::SmsGateway)
+                .send_sms(message)
+                .map_err(|error| {
+                    error!("Unable to send SMS: {}", error);
+                    AuthError::BackendError
+                }).and_then(|_| Err(AuthError::NeedRetry)),
+        )
+    }
+}
@@ -2,17 +2,25 @@ use auth::{
     method::{BothAuthInfo, EitherUserIdent},
     AuthError,
 };
-use base::BoxFuture;
+use base::{BoxFuture, IsBackend, TimeStamp};
+use mail::MailAddress;
 use serde::{de::DeserializeOwned, Serialize};
 use std::borrow::Cow;
 use std::hash::Hash;
 
+use super::AuthToken;
+
 /// One-time password auth user identification data
 ///
 /// This is a user identification data such as email address, phone number and etc.
 pub trait IsOTPassIdent {
     /// Get user name from identification data
     fn get_user_name(&self) -> Cow<str>;
+
+    /// Get email from identification data
+    fn get_email(&self) -> Option<&MailAddress> {
+        None
+    }
 }
 
 /// One-time password auth code sender
@@ -43,6 +51,79 @@ pub trait IsOTPassSender<S> {
     ) -> BoxFuture<(), AuthError>;
 }
 
+/// One-time password tokens storage
+///
+/// The tokens is identified by user name of identification data.
+/// Shared storage allows to use one-time passwords with several server instances.
+pub trait IsOTPassStorage: IsBackend {
+    /// Add new token
+    ///
+    /// The tokens which created before `dead` time is expired and may be purged.
+    /// The token should not be added when alive token with same key exists,
+    /// in that case `false` should be returned.
+    fn add_token(
+        &self,
+        key: &str,
+        token: AuthToken,
+        dead: TimeStamp,
+    ) -> BoxFuture<bool, Self::Error>;
+
+    /// Get alive token
+    fn get_token(&self, key: &str, dead: TimeStamp) -> BoxFuture<Option<AuthToken>, Self::Error>;
+
+    /// Count verification attempt
+    ///
+    /// The counter should be incremented atomically, because the attempt is counted before
+    /// the password is checked, so concurrent checks cannot exceed the retries limit.
+    /// Returns the number of attempts including this one or `None` when token is missing.
+    fn add_retry(&self, key: &str) -> BoxFuture<Option<usize>, Self::Error>;
+
+    /// Delete token
+    ///
+    /// Returns `None` when token is already deleted.
+    fn del_token(&self, key: &str) -> BoxFuture<Option<()>, Self::Error>;
+}
+
+/// State has access to one-time password tokens
+pub trait HasOTPassStorage
+where
+    Self: AsRef<<Self as HasOTPassStorage>::OTPassStorage>,
+{
+    /// Tokens storage type
+    type OTPassStorage: IsOTPassStorage;
+}
+
+/// Magic links storage
+///
+/// Keeps the sending times of links and the identifiers of used tokens.
+/// Shared storage allows to use magic links with several server instances.
+#[cfg(feature = "send_mail")]
+pub trait IsMagicLinkStorage: IsBackend {
+    /// Mark link as sent to address
+    ///
+    /// The marks which created before `dead` time is expired and may be purged.
+    /// The mark should not be added when alive mark with same address exists,
+    /// in that case `false` should be returned.
+    fn add_sent(&self, email: &str, time: TimeStamp, dead: TimeStamp)
+        -> BoxFuture<bool, Self::Error>;
+
+    /// Mark token as used
+    ///
+    /// The mark should be kept until token expiration time.
+    /// Returns `false` when token is already used.
+    fn add_used(&self, id: &str, etime: TimeStamp) -> BoxFuture<bool, Self::Error>;
+}
+
+/// State has access to magic links storage
+#[cfg(feature = "send_mail")]
+pub trait HasMagicLinkStorage
+where
+    Self: AsRef<<Self as HasMagicLinkStorage>::MagicLinkStorage>,
+{
+    /// Magic links storage type
+    type MagicLinkStorage: IsMagicLinkStorage;
+}
+
 impl<A, B> IsOTPassIdent for EitherUserIdent<A, B>
 where
     A: IsOTPassIdent,
@@ -54,6 +135,13 @@ where
             EitherUserIdent::B(b) => b.get_user_name(),
         }
     }
+
+    fn get_email(&self) -> Option<&MailAddress> {
+        match self {
+            EitherUserIdent::A(a) => a.get_email(),
+            EitherUserIdent::B(b) => b.get_email(),
+        }
+    }
 }
 
 macro_rules! auth_info_type {
@@ -1,3 +1,5 @@
+use super::IsOTPassIdent;
+use auth::IsThrottleIdent;
 use base::TimeStamp;
 use std::borrow::Cow;
 use std::ops::RangeInclusive;
@@ -34,24 +36,34 @@ pub struct AuthInfo<I> {
 }
 
 /// One-time password auth user identification
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub enum UserIdent<I> {
     #[serde(rename = "otpass")]
     OTPass {
         #[serde(flatten)]
         ident: I,
         pass: Option<String>,
+        #[serde(default)]
+        invite: Option<String>,
     },
 }
 
+impl<I: IsOTPassIdent> IsThrottleIdent for UserIdent<I> {
+    fn throttle_key(&self) -> Option<String> {
+        match self {
+            UserIdent::OTPass { ident, .. } => Some(ident.get_user_name().into()),
+        }
+    }
+}
+
 /// One-time password auth token
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthToken {
     /// Generated password
     pub pass: String,
     /// Token creating time
     pub ctime: TimeStamp,
-    /// Auth retries
+    /// Verification attempts
     pub retry: usize,
 }
 
@@ -0,0 +1,497 @@
+/*!
+
+### Auth throttling
+
+This wrapper protects any auth method from brute-force attacks.
+
+It counts failed attempts per user name and per client IP address
+and applies exponential backoff when the number of failures exceeds the limit.
+While backoff is active any attempt is rejected with [`AuthError::TooManyAttempts`](auth::AuthError::TooManyAttempts).
+
+Each attempt is counted as failure in advance together with checking backoff,
+so the concurrent attempts cannot pass the check before failures is counted.
+The successful attempts is uncounted then.
+
+The same counters is used by handlers which check user secrets outside of auth methods
+(like current password on password change), see [`HasThrottleOptions`](auth::HasThrottleOptions).
+
+*/
+
+use auth::{AuthContext, AuthError, IsAuthMethod};
+use base::{BoxFuture, IsBackend, TimeStamp};
+use futures::{
+    future::{err, join_all, ok, result, Either},
+    Future,
+};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{Error as IoError, ErrorKind};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use user::{HasUserStorage, IsUserStorage};
+
+/// Throttling options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrottleOptions {
+    /// Number of allowed failures before backoff
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    /// Initial backoff delay in milliseconds
+    ///
+    /// The delay doubles on each next failure.
+    #[serde(default = "default_base_delay")]
+    pub base_delay: TimeStamp,
+    /// Max backoff delay in milliseconds
+    ///
+    /// The temporary lockout time.
+    #[serde(default = "default_max_delay")]
+    pub max_delay: TimeStamp,
+    /// Reset time in milliseconds
+    ///
+    /// The failures will be forgotten after this interval since last failure.
+    #[serde(default = "default_reset_time")]
+    pub reset_time: TimeStamp,
+}
+
+fn default_max_failures() -> u32 {
+    5
+}
+
+fn default_base_delay() -> TimeStamp {
+    TimeStamp::default().with_secs(1)
+}
+
+fn default_max_delay() -> TimeStamp {
+    TimeStamp::default().with_mins(15)
+}
+
+fn default_reset_time() -> TimeStamp {
+    TimeStamp::default().with_hours(1)
+}
+
+impl Default for ThrottleOptions {
+    fn default() -> Self {
+        Self {
+            max_failures: default_max_failures(),
+            base_delay: default_base_delay(),
+            max_delay: default_max_delay(),
+            reset_time: default_reset_time(),
+        }
+    }
+}
+
+impl ThrottleOptions {
+    /// Get the time until which attempts are rejected
+    pub fn locked_until(&self, counter: &ThrottleCounter) -> Option<TimeStamp> {
+        if counter.count <= self.max_failures {
+            return None;
+        }
+        let power = (counter.count - self.max_failures - 1).min(30);
+        let delay: i64 = self.base_delay.into();
+        let max_delay: i64 = self.max_delay.into();
+        let delay = delay.saturating_mul(1 << power).min(max_delay);
+        Some(counter.last + TimeStamp::from(delay))
+    }
+}
+
+/// Failed attempts counter
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ThrottleCounter {
+    /// Number of failures
+    pub count: u32,
+    /// Last failure time
+    pub last: TimeStamp,
+}
+
+/// Failed attempts counters storage
+pub trait IsThrottleStorage: IsBackend {
+    /// Get counter by key
+    fn get_counter(&self, key: &str) -> BoxFuture<Option<ThrottleCounter>, Self::Error>;
+
+    /// Count failure
+    ///
+    /// The counter which last failure is older than `reset_time` should be restarted.
+    fn add_failure(
+        &self,
+        key: &str,
+        reset_time: TimeStamp,
+    ) -> BoxFuture<ThrottleCounter, Self::Error>;
+
+    /// Reserve attempt
+    ///
+    /// Checks that counter isn't locked and counts failure in advance in single atomic operation.
+    /// Returns the time until which counter is locked, or `None` when attempt is counted.
+    /// The counter which last failure is older than `reset_time` should be restarted.
+    fn reserve_attempt(
+        &self,
+        key: &str,
+        options: &ThrottleOptions,
+    ) -> BoxFuture<Option<TimeStamp>, Self::Error>;
+
+    /// Cancel reserved attempt which isn't failed
+    fn cancel_attempt(&self, key: &str) -> BoxFuture<(), Self::Error>;
+
+    /// Delete counter by key
+    fn del_counter(&self, key: &str) -> BoxFuture<(), Self::Error>;
+}
+
+/// State has access to failed attempts counters
+pub trait HasThrottleStorage
+where
+    Self: AsRef<<Self as HasThrottleStorage>::ThrottleStorage>,
+{
+    /// Counters storage type
+    type ThrottleStorage: IsThrottleStorage;
+}
+
+/// State has throttling options
+///
+/// The options is used by handlers which throttle attempts without auth method.
+pub trait HasThrottleOptions
+where
+    Self: AsRef<ThrottleOptions>,
+{
+}
+
+/// User identification which can be throttled
+pub trait IsThrottleIdent {
+    /// Get user name to count failures
+    ///
+    /// When it is `None` only client address failures will be counted.
+    fn throttle_key(&self) -> Option<String>;
+}
+
+impl<A, B> IsThrottleIdent for super::EitherUserIdent<A, B>
+where
+    A: IsThrottleIdent,
+    B: IsThrottleIdent,
+{
+    fn throttle_key(&self) -> Option<String> {
+        match self {
+            super::EitherUserIdent::A(a) => a.throttle_key(),
+            super::EitherUserIdent::B(b) => b.throttle_key(),
+        }
+    }
+}
+
+/// In-memory failed attempts counters
+#[derive(Clone)]
+pub struct ThrottleCache {
+    counters: Arc<Mutex<HashMap<String, ThrottleCounter>>>,
+}
+
+impl ThrottleCache {
+    /// Create counters cache
+    pub fn new() -> Self {
+        Self {
+            counters: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl IsBackend for ThrottleCache {
+    type Error = IoError;
+}
+
+impl IsThrottleStorage for ThrottleCache {
+    fn get_counter(&self, key: &str) -> BoxFuture<Option<ThrottleCounter>, Self::Error> {
+        Box::new(result(
+            self.counters
+                .lock()
+                .map(|counters| counters.get(key).cloned())
+                .map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn add_failure(
+        &self,
+        key: &str,
+        reset_time: TimeStamp,
+    ) -> BoxFuture<ThrottleCounter, Self::Error> {
+        Box::new(result(
+            self.counters
+                .lock()
+                .map(|mut counters| {
+                    let now = TimeStamp::now();
+                    counters.retain(|_, counter| counter.last + reset_time > now);
+                    let counter = counters.entry(key.into()).or_insert(ThrottleCounter {
+                        count: 0,
+                        last: now,
+                    });
+                    counter.count += 1;
+                    counter.last = now;
+                    *counter
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn reserve_attempt(
+        &self,
+        key: &str,
+        options: &ThrottleOptions,
+    ) -> BoxFuture<Option<TimeStamp>, Self::Error> {
+        Box::new(result(
+            self.counters
+                .lock()
+                .map(|mut counters| {
+                    let now = TimeStamp::now();
+                    counters.retain(|_, counter| counter.last + options.reset_time > now);
+                    let counter = counters.entry(key.into()).or_insert(ThrottleCounter {
+                        count: 0,
+                        last: now,
+                    });
+                    match options.locked_until(counter) {
+                        Some(until) if until > now => Some(until),
+                        _ => {
+                            counter.count += 1;
+                            counter.last = now;
+                            None
+                        }
+                    }
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn cancel_attempt(&self, key: &str) -> BoxFuture<(), Self::Error> {
+        Box::new(result(
+            self.counters
+                .lock()
+                .map(|mut counters| {
+                    if let Some(counter) = counters.get_mut(key) {
+                        counter.count = counter.count.saturating_sub(1);
+                    }
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+
+    fn del_counter(&self, key: &str) -> BoxFuture<(), Self::Error> {
+        Box::new(result(
+            self.counters
+                .lock()
+                .map(|mut counters| {
+                    counters.remove(key);
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+}
+
+/// Throttled auth method
+#[derive(Clone)]
+pub struct Throttled<M> {
+    method: M,
+    options: Arc<ThrottleOptions>,
+}
+
+impl<M> Throttled<M> {
+    /// Wrap auth method with throttling
+    pub fn new(method: M, options: ThrottleOptions) -> Self {
+        Self {
+            method,
+            options: Arc::new(options),
+        }
+    }
+}
+
+fn throttle_keys(name: Option<String>, addr: Option<IpAddr>) -> Vec<String> {
+    name.map(|name| format!("user:{}", name))
+        .into_iter()
+        .chain(addr.map(|addr| format!("addr:{}", addr)))
+        .collect()
+}
+
+fn storage_error<E: Display>(error: E) -> AuthError {
+    error!("Throttle storage error: {}", error);
+    AuthError::BackendError
+}
+
+/// Reserve attempt using each of counters
+///
+/// The attempt is counted as failure in advance, so it should be canceled
+/// using [`cancel_throttle_attempt`](auth::cancel_throttle_attempt) when it isn't failed.
+/// The attempt is rejected while any of counters is locked.
+pub fn reserve_throttle_attempt<S>(
+    state: &S,
+    options: &ThrottleOptions,
+    keys: &[String],
+) -> impl Future<Item = (), Error = AuthError>
+where
+    S: HasThrottleStorage + Clone,
+{
+    let state = state.clone();
+    let keys = keys.to_vec();
+
+    join_all(
+        keys.iter()
+            .map(|key| (state.as_ref() as &S::ThrottleStorage).reserve_attempt(key, options))
+            .collect::<Vec<_>>(),
+    ).map_err(storage_error)
+    .and_then(move |locks| {
+        let until = locks.iter().filter_map(|until| *until).max();
+        if let Some(until) = until {
+            warn!("Too many auth attempts");
+            // uncount attempt for the counters which isn't locked
+            let reserved = keys
+                .into_iter()
+                .zip(locks)
+                .filter(|(_, until)| until.is_none())
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>();
+            Either::A(
+                cancel_throttle_attempt(&state, &reserved).and_then(move |_| {
+                    err(AuthError::TooManyAttempts(until - TimeStamp::now()))
+                }),
+            )
+        } else {
+            Either::B(ok(()))
+        }
+    })
+}
+
+/// Cancel reserved attempt using each of counters
+pub fn cancel_throttle_attempt<S>(state: &S, keys: &[String]) -> BoxFuture<(), AuthError>
+where
+    S: HasThrottleStorage,
+{
+    Box::new(
+        join_all(
+            keys.iter()
+                .map(|key| (state.as_ref() as &S::ThrottleStorage).cancel_attempt(key))
+                .collect::<Vec<_>>(),
+        ).map_err(storage_error)
+        .map(|_| ()),
+    )
+}
+
+/// Forget failed attempts after successful one
+pub fn del_throttle_counter<S>(state: &S, key: &str) -> BoxFuture<(), AuthError>
+where
+    S: HasThrottleStorage,
+{
+    Box::new(
+        (state.as_ref() as &S::ThrottleStorage)
+            .del_counter(key)
+            .map_err(storage_error),
+    )
+}
+
+impl<S, M> IsAuthMethod<S> for Throttled<M>
+where
+    S: HasUserStorage + HasThrottleStorage + Send + Clone + 'static,
+    M: IsAuthMethod<S> + Clone + Send + 'static,
+    M::UserIdent: IsThrottleIdent + Clone,
+    <S::UserStorage as IsUserStorage>::User: Send + 'static,
+{
+    type AuthInfo = M::AuthInfo;
+    type UserIdent = M::UserIdent;
+
+    fn get_auth_info(&self, state: &S) -> Self::AuthInfo {
+        self.method.get_auth_info(state)
+    }
+
+    fn get_auth_info_from(&self, state: &S, ctx: &AuthContext) -> Self::AuthInfo {
+        self.method.get_auth_info_from(state, ctx)
+    }
+
+    fn try_user_auth(
+        &self,
+        state: &S,
+        ident: &Self::UserIdent,
+    ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
+        self.try_user_auth_from(state, ident, &AuthContext::default())
+    }
+
+    fn try_user_auth_from(
+        &self,
+        state: &S,
+        ident: &Self::UserIdent,
+        ctx: &AuthContext,
+    ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
+        let name = ident.throttle_key();
+        // the user counter is the first one
+        let has_user = name.is_some();
+        let keys = throttle_keys(name, ctx.addr);
+        let options = self.options.clone();
+
+        // the auth attempt is started only when it is reserved
+        let attempt = {
+            let state = state.clone();
+            let method = self.method.clone();
+            let ident = ident.clone();
+            let ctx = ctx.clone();
+            move |_| method.try_user_auth_from(&state, &ident, &ctx).then(Ok)
+        };
+
+        Box::new(
+            reserve_throttle_attempt(state, &options, &keys)
+                .and_then(attempt)
+                .and_then({
+                    let state = state.clone();
+                    move |res| match res {
+                        // the failure is already counted
+                        Err(AuthError::BadIdent) => Either::A(err(AuthError::BadIdent)),
+                        Ok(user) => Either::B(Either::A(if has_user {
+                            let (user_key, other_keys) = keys.split_at(1);
+                            let other_keys = other_keys.to_vec();
+                            Either::A(
+                                del_throttle_counter(&state, user_key[0].as_str())
+                                    .join(cancel_throttle_attempt(&state, &other_keys))
+                                    .map(move |_| user),
+                            )
+                        } else {
+                            Either::B(cancel_throttle_attempt(&state, &keys).map(move |_| user))
+                        })),
+                        Err(error) => Either::B(Either::B(
+                            cancel_throttle_attempt(&state, &keys).and_then(move |_| err(error)),
+                        )),
+                    }
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let options = ThrottleOptions::default();
+        let last = TimeStamp::from(1_000_000i64);
+        let counter = |count| ThrottleCounter { count, last };
+
+        assert_eq!(options.locked_until(&counter(5)), None);
+        assert_eq!(
+            options.locked_until(&counter(6)),
+            Some(last + TimeStamp::default().with_secs(1))
+        );
+        assert_eq!(
+            options.locked_until(&counter(8)),
+            Some(last + TimeStamp::default().with_secs(4))
+        );
+        assert_eq!(
+            options.locked_until(&counter(100)),
+            Some(last + TimeStamp::default().with_mins(15))
+        );
+    }
+
+    #[test]
+    fn reserve_attempt() {
+        let cache = ThrottleCache::new();
+        let options = ThrottleOptions::default();
+        let reserve = |key| cache.reserve_attempt(key, &options).wait().unwrap();
+
+        // the pending attempts is counted in advance
+        for _ in 0..options.max_failures + 1 {
+            assert_eq!(reserve("user:a"), None);
+        }
+        assert!(reserve("user:a").is_some());
+
+        // the canceled attempts isn't counted
+        for _ in 0..options.max_failures + 1 {
+            assert_eq!(reserve("addr:b"), None);
+            cache.cancel_attempt("addr:b").wait().unwrap();
+        }
+        assert_eq!(reserve("addr:b"), None);
+        assert_eq!(cache.get_counter("addr:b").wait().unwrap().unwrap().count, 1);
+    }
+}
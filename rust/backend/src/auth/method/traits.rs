@@ -1,8 +1,19 @@
 use auth::AuthError;
-use base::BoxFuture;
+use base::{BoxFuture, CanUpdateFrom};
+use crypto::PublicKey;
 use serde::{de::DeserializeOwned, Serialize};
+use std::net::IpAddr;
 use user::{HasUserStorage, IsUserStorage};
 
+/// Client context of auth request
+#[derive(Debug, Clone, Default)]
+pub struct AuthContext {
+    /// Client address
+    pub addr: Option<IpAddr>,
+    /// Client public key
+    pub pbkey: Option<PublicKey>,
+}
+
 /// Authentication method interface
 pub trait IsAuthMethod<S>
 where
@@ -17,12 +28,33 @@ where
     /// Auth method may provide some data to client
     fn get_auth_info(&self, state: &S) -> Self::AuthInfo;
 
+    /// Auth method may provide some data to client using client context
+    ///
+    /// By default the context is ignored.
+    /// It is useful for methods which wraps other methods or keeps some state per client.
+    fn get_auth_info_from(&self, state: &S, _ctx: &AuthContext) -> Self::AuthInfo {
+        self.get_auth_info(state)
+    }
+
     /// Auth method should made some checks itself
     fn try_user_auth(
         &self,
         state: &S,
         ident: &Self::UserIdent,
     ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError>;
+
+    /// Auth method should made some checks itself using client context
+    ///
+    /// By default the context is ignored.
+    /// It is useful for methods which wraps other methods or binds credentials to client.
+    fn try_user_auth_from(
+        &self,
+        state: &S,
+        ident: &Self::UserIdent,
+        _ctx: &AuthContext,
+    ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
+        self.try_user_auth(state, ident)
+    }
 }
 
 /// Access to auth method
@@ -57,6 +89,18 @@ pub enum EitherUserIdent<A, B> {
     B(B),
 }
 
+impl<T, A, B> CanUpdateFrom<EitherUserIdent<A, B>> for T
+where
+    T: CanUpdateFrom<A> + CanUpdateFrom<B>,
+{
+    fn update_from(&mut self, ident: &EitherUserIdent<A, B>) {
+        match ident {
+            EitherUserIdent::A(a) => self.update_from(a),
+            EitherUserIdent::B(b) => self.update_from(b),
+        }
+    }
+}
+
 macro_rules! auth_info_type {
     ($a:ident, $b:ident) => {
         BothAuthInfo<$a::AuthInfo, $b::AuthInfo>
@@ -84,6 +128,21 @@ macro_rules! get_auth_info {
     };
 }
 
+macro_rules! get_auth_info_from {
+    ($self:expr, $state:expr, $ctx:expr, $i:tt, $j:tt) => {
+        BothAuthInfo::new(
+            $self.$i.get_auth_info_from($state, $ctx),
+            $self.$j.get_auth_info_from($state, $ctx),
+        )
+    };
+    ($self:expr, $state:expr, $ctx:expr, $i:tt, $($j:tt),+) => {
+        BothAuthInfo::new(
+            $self.$i.get_auth_info_from($state, $ctx),
+            get_auth_info_from!($self, $state, $ctx, $($j),+),
+        )
+    };
+}
+
 macro_rules! try_user_auth {
     ($self:expr, $state:expr, $ident:expr, $i:tt, $j:tt) => {
         match $ident {
@@ -99,6 +158,21 @@ macro_rules! try_user_auth {
     };
 }
 
+macro_rules! try_user_auth_from {
+    ($self:expr, $state:expr, $ident:expr, $ctx:expr, $i:tt, $j:tt) => {
+        match $ident {
+            EitherUserIdent::A(a) => $self.$i.try_user_auth_from($state, a, $ctx),
+            EitherUserIdent::B(b) => $self.$j.try_user_auth_from($state, b, $ctx),
+        }
+    };
+    ($self:expr, $state:expr, $ident:expr, $ctx:expr, $i:tt, $($j:tt),+) => {
+        match $ident {
+            EitherUserIdent::A(a) => $self.$i.try_user_auth_from($state, a, $ctx),
+            EitherUserIdent::B(ident) => try_user_auth_from!($self, $state, ident, $ctx, $($j),+),
+        }
+    };
+}
+
 macro_rules! tuple_method {
     (($($type:ident),+) => ($($id:tt),+)) => {
         impl<S, $($type),+> IsAuthMethod<S> for ($($type),+)
@@ -113,6 +187,10 @@ macro_rules! tuple_method {
                 get_auth_info!(self, state, $($id),+)
             }
 
+            fn get_auth_info_from(&self, state: &S, ctx: &AuthContext) -> Self::AuthInfo {
+                get_auth_info_from!(self, state, ctx, $($id),+)
+            }
+
             fn try_user_auth(
                 &self,
                 state: &S,
@@ -120,6 +198,15 @@ macro_rules! tuple_method {
             ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
                 try_user_auth!(self, state, ident, $($id),+)
             }
+
+            fn try_user_auth_from(
+                &self,
+                state: &S,
+                ident: &Self::UserIdent,
+                ctx: &AuthContext,
+            ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
+                try_user_auth_from!(self, state, ident, ctx, $($id),+)
+            }
         }
     };
 }
@@ -0,0 +1,154 @@
+use super::WebAuthnError;
+
+const MAX_DEPTH: usize = 16;
+
+/// Minimal CBOR value
+///
+/// Only types which is used in WebAuthn structures are supported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CborValue {
+    Int(i64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<CborValue>),
+    Map(Vec<(CborValue, CborValue)>),
+    Bool(bool),
+    Null,
+}
+
+impl CborValue {
+    /// Get map value by integer key
+    pub fn get_int(&self, key: i64) -> Option<&CborValue> {
+        self.get(&CborValue::Int(key))
+    }
+
+    /// Get map value by text key
+    pub fn get_text(&self, key: &str) -> Option<&CborValue> {
+        self.get(&CborValue::Text(key.into()))
+    }
+
+    fn get(&self, key: &CborValue) -> Option<&CborValue> {
+        if let CborValue::Map(pairs) = self {
+            pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+        } else {
+            None
+        }
+    }
+
+    /// Get integer
+    pub fn as_int(&self) -> Option<i64> {
+        if let CborValue::Int(value) = self {
+            Some(*value)
+        } else {
+            None
+        }
+    }
+
+    /// Get byte string
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        if let CborValue::Bytes(value) = self {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Get text string
+    pub fn as_text(&self) -> Option<&str> {
+        if let CborValue::Text(value) = self {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decode single CBOR item
+///
+/// Returns decoded value and the rest of data.
+pub fn cbor_decode(data: &[u8]) -> Result<(CborValue, &[u8]), WebAuthnError> {
+    decode_item(data, 0)
+}
+
+fn decode_item(data: &[u8], depth: usize) -> Result<(CborValue, &[u8]), WebAuthnError> {
+    if depth > MAX_DEPTH {
+        return Err(WebAuthnError::BadData);
+    }
+
+    let (head, data) = data.split_first().ok_or(WebAuthnError::BadData)?;
+    let major = head >> 5;
+    let info = head & 0x1f;
+
+    let (arg, mut data) = match info {
+        0..=23 => (u64::from(info), data),
+        24..=27 => {
+            let size = 1 << (info - 24);
+            if data.len() < size {
+                return Err(WebAuthnError::BadData);
+            }
+            let (arg, rest) = data.split_at(size);
+            (
+                arg.iter().fold(0u64, |arg, byte| arg << 8 | u64::from(*byte)),
+                rest,
+            )
+        }
+        // indefinite length items is not supported
+        _ => return Err(WebAuthnError::BadData),
+    };
+
+    let value = match major {
+        0 if arg <= i64::max_value() as u64 => CborValue::Int(arg as i64),
+        1 if arg <= i64::max_value() as u64 => CborValue::Int(-1 - arg as i64),
+        2 | 3 => {
+            if arg > data.len() as u64 {
+                return Err(WebAuthnError::BadData);
+            }
+            let (bytes, rest) = data.split_at(arg as usize);
+            data = rest;
+            if major == 2 {
+                CborValue::Bytes(bytes.into())
+            } else {
+                CborValue::Text(
+                    String::from_utf8(bytes.into()).map_err(|_| WebAuthnError::BadData)?,
+                )
+            }
+        }
+        4 => {
+            // each item takes at least one byte
+            if arg > data.len() as u64 {
+                return Err(WebAuthnError::BadData);
+            }
+            let mut items = Vec::with_capacity(arg as usize);
+            for _ in 0..arg {
+                let (item, rest) = decode_item(data, depth + 1)?;
+                items.push(item);
+                data = rest;
+            }
+            CborValue::Array(items)
+        }
+        5 => {
+            if arg > data.len() as u64 {
+                return Err(WebAuthnError::BadData);
+            }
+            let mut pairs = Vec::with_capacity(arg as usize);
+            for _ in 0..arg {
+                let (key, rest) = decode_item(data, depth + 1)?;
+                let (value, rest) = decode_item(rest, depth + 1)?;
+                pairs.push((key, value));
+                data = rest;
+            }
+            CborValue::Map(pairs)
+        }
+        // tags is ignored
+        6 => return decode_item(data, depth + 1),
+        7 if info < 24 => match info {
+            20 => CborValue::Bool(false),
+            21 => CborValue::Bool(true),
+            22 | 23 => CborValue::Null,
+            _ => return Err(WebAuthnError::BadData),
+        },
+        _ => return Err(WebAuthnError::BadData),
+    };
+
+    Ok((value, data))
+}
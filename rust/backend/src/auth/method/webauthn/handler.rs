@@ -0,0 +1,259 @@
+use super::{
+    decode_b64url, CredentialInfo, HasWebAuthn, HasWebAuthnStorage, IsWebAuthnStorage,
+    WebAuthnArg, WebAuthnAuth, WebAuthnRegister,
+};
+use access::{audit_access_to, Grant, HasAccess, HasAuditSink};
+use auth::{
+    AuthError, HasSessionOptions, HasSessionStorage, HasTokenStorage, HasUserAuth, IsTokenAuth,
+};
+use crypto::HasSecretKey;
+use filters::{x_client_addr, HasClientAddrOptions};
+use futures::{
+    future::{err, Either},
+    Future,
+};
+use user::{HasUserStorage, IsUserData, IsUserStorage};
+use warp::{Filter, Rejection, Reply};
+use x_auth;
+
+/// Handle get user WebAuthn credentials
+pub fn get_user_webauthn<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasWebAuthnStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<WebAuthnArg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::get2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("webauthn"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &WebAuthnArg { user }, &Grant::Read)
+                    .map(move |_| user)
+            }
+        }).and_then(move |user| {
+            (state.as_ref() as &S::WebAuthnStorage)
+                .get_user_credentials(user)
+                .map_err(|error| {
+                    error!("Unable to get user credentials: {}", error);
+                    warp::reject::custom(AuthError::BackendError)
+                }).map(|creds| {
+                    warp::reply::json(&creds.iter().map(CredentialInfo::from).collect::<Vec<_>>())
+                })
+        }).recover(AuthError::recover)
+}
+
+/// Handle start WebAuthn credential registration
+///
+/// Returns options for `navigator.credentials.create()`.
+pub fn add_user_webauthn<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasWebAuthn
+        + HasWebAuthnStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<WebAuthnArg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::post2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("webauthn"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &WebAuthnArg { user }, &Grant::Create)
+                    .map(move |_| user)
+            }
+        }).and_then(move |user| {
+            let state = state.clone();
+            (state.as_ref() as &S::UserStorage)
+                .get_user_data(user)
+                .map_err(|error| {
+                    error!("Unable to get user data: {}", error);
+                    AuthError::BackendError
+                }).and_then(|data| data.ok_or(AuthError::BadUser))
+                .and_then(move |data| {
+                    (state.as_ref() as &S::WebAuthnStorage)
+                        .get_user_credentials(user)
+                        .map_err(|error| {
+                            error!("Unable to get user credentials: {}", error);
+                            AuthError::BackendError
+                        }).map(move |creds| {
+                            warp::reply::json(&(state.as_ref() as &WebAuthnAuth)
+                                .start_registration(user, data.get_user_name(), &creds))
+                        })
+                }).map_err(warp::reject::custom)
+        }).recover(AuthError::recover)
+}
+
+/// Handle finish WebAuthn credential registration
+///
+/// Verifies the response of authenticator and stores new credential.
+pub fn put_user_webauthn<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasWebAuthn
+        + HasWebAuthnStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<WebAuthnArg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::put2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("webauthn"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &WebAuthnArg { user }, &Grant::Update)
+                    .map(move |_| user)
+            }
+        }).and(warp::body::json())
+        .and_then(move |user, req: WebAuthnRegister| {
+            let state = state.clone();
+            (state.as_ref() as &S::WebAuthnStorage)
+                .get_user_credentials(user)
+                .map_err(|error| {
+                    error!("Unable to get user credentials: {}", error);
+                    AuthError::BackendError
+                }).and_then(move |creds| {
+                    let cred = match (state.as_ref() as &WebAuthnAuth)
+                        .finish_registration(user, &req, &creds)
+                    {
+                        Ok(cred) => cred,
+                        Err(error) => {
+                            warn!("Invalid WebAuthn registration: {}", error);
+                            return Either::A(err(AuthError::BadIdent));
+                        }
+                    };
+                    let info = CredentialInfo::from(&cred);
+                    Either::B(
+                        (state.as_ref() as &S::WebAuthnStorage)
+                            .put_user_credential(cred)
+                            .map_err(|error| {
+                                error!("Unable to put user credential: {}", error);
+                                AuthError::BackendError
+                            }).map(move |_| warp::reply::json(&info)),
+                    )
+                }).map_err(warp::reject::custom)
+        }).recover(AuthError::recover)
+}
+
+/// Handle delete user WebAuthn credential
+pub fn del_user_webauthn<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasWebAuthnStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<WebAuthnArg, Grant> + IsTokenAuth<S>,
+{
+    let state = state.clone();
+
+    warp::delete2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("webauthn"))
+        .and(warp::path::param()) // credential id
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, id: String, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &WebAuthnArg { user }, &Grant::Delete)
+                    .map(move |_| (user, id))
+            }
+        }).and_then(move |(user, id): (_, String)| {
+            let id = match decode_b64url(&id) {
+                Ok(id) => id,
+                Err(_) => return Either::A(err(warp::reject::not_found())),
+            };
+            Either::B(
+                (state.as_ref() as &S::WebAuthnStorage)
+                    .del_user_credential(user, &id)
+                    .map_err(|error| {
+                        error!("Unable to delete user credential: {}", error);
+                        warp::reject::custom(AuthError::BackendError)
+                    }).and_then(|res| {
+                        res.map(|_| warp::reply())
+                            .ok_or_else(warp::reject::not_found)
+                    }),
+            )
+        }).recover(AuthError::recover)
+}
+
+/// Scope with user WebAuthn credentials handlers
+pub fn webauthn_scope<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasWebAuthn
+        + HasWebAuthnStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<WebAuthnArg, Grant> + IsTokenAuth<S>,
+{
+    get_user_webauthn(state)
+        .or(add_user_webauthn(state))
+        .or(put_user_webauthn(state))
+        .or(del_user_webauthn(state))
+}
@@ -0,0 +1,640 @@
+use super::{
+    cbor_decode, decode_b64url, signed_data, AssertionInfo, AuthData, AuthInfo, CborValue,
+    ClientData, CreationInfo, HasWebAuthnStorage, IsWebAuthnStorage, RelyingParty, UserEntity,
+    UserIdent, WebAuthnCredential, WebAuthnError, WebAuthnOptions, WebAuthnRegister,
+    COSE_ALG_EDDSA, COSE_ALG_ES256,
+};
+use auth::{approved_user, AuthContext, AuthError, IsAuthMethod};
+use base::{BoxFuture, TimeStamp};
+use base64lib::{encode_config, URL_SAFE_NO_PAD};
+use crypto::random_bytes;
+use futures::{
+    future::{err, result},
+    Future,
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::from_utf8;
+use std::sync::{Arc, RwLock};
+use user::{HasUserStorage, IsUserStorage, UserId};
+
+struct Challenge {
+    ctime: TimeStamp,
+    /// Registering user or none for assertion
+    user: Option<UserId>,
+    /// Client address of assertion
+    addr: Option<IpAddr>,
+}
+
+struct State {
+    options: WebAuthnOptions,
+    challenges: RwLock<HashMap<String, Challenge>>,
+}
+
+/// Verified assertion which awaits credential check
+struct Assertion {
+    user: UserId,
+    id: Vec<u8>,
+    data: Vec<u8>,
+    signature: Vec<u8>,
+    count: u32,
+}
+
+/// WebAuthn auth method
+#[derive(Clone)]
+pub struct WebAuthnAuth(Arc<State>);
+
+impl WebAuthnAuth {
+    /// Create WebAuthn auth method instance
+    pub fn new(options: WebAuthnOptions) -> Self {
+        WebAuthnAuth(Arc::new(State {
+            options,
+            challenges: RwLock::new(HashMap::new()),
+        }))
+    }
+
+    /// Get options
+    pub fn options(&self) -> &WebAuthnOptions {
+        &self.0.options
+    }
+
+    fn user_verification(&self) -> String {
+        if self.0.options.user_verification {
+            "required"
+        } else {
+            "preferred"
+        }.into()
+    }
+
+    fn create_challenge(&self, user: Option<UserId>, addr: Option<IpAddr>) -> String {
+        let options = &self.0.options;
+        let at = TimeStamp::now();
+        let dt = at - options.timeout;
+        let challenge = encode_config(&random_bytes(32), URL_SAFE_NO_PAD);
+
+        let mut challenges = self.0.challenges.write().unwrap();
+        // clear dead challenges
+        challenges.retain(|_, challenge| challenge.ctime > dt);
+        // the owner of challenges is registering user or client of assertion
+        if user.is_some() || addr.is_some() {
+            forget_oldest(&mut challenges, options.max_client_challenges, |challenge| {
+                challenge.user == user && challenge.addr == addr
+            });
+        }
+        forget_oldest(&mut challenges, options.max_challenges, |_| true);
+        challenges.insert(
+            challenge.clone(),
+            Challenge {
+                ctime: at,
+                user,
+                addr,
+            },
+        );
+
+        challenge
+    }
+
+    fn take_challenge(&self, challenge: &str, user: Option<UserId>) -> bool {
+        let dt = TimeStamp::now() - self.0.options.timeout;
+
+        self.0
+            .challenges
+            .write()
+            .unwrap()
+            .remove(challenge)
+            .map(|challenge| challenge.ctime > dt && challenge.user == user)
+            .unwrap_or(false)
+    }
+
+    /// Start registration ceremony
+    ///
+    /// Creates options for `navigator.credentials.create()`.
+    pub fn start_registration(
+        &self,
+        user: UserId,
+        name: &str,
+        creds: &[WebAuthnCredential],
+    ) -> CreationInfo {
+        let options = &self.0.options;
+
+        CreationInfo {
+            rp: RelyingParty {
+                id: options.rp_id.clone(),
+                name: options.rp_name.clone(),
+            },
+            user: UserEntity {
+                id: encode_config(user.to_string().as_bytes(), URL_SAFE_NO_PAD),
+                name: name.into(),
+            },
+            challenge: self.create_challenge(Some(user), None),
+            algs: vec![COSE_ALG_ES256, COSE_ALG_EDDSA],
+            timeout: options.timeout,
+            exclude: creds
+                .iter()
+                .map(|cred| encode_config(&cred.id, URL_SAFE_NO_PAD))
+                .collect(),
+            user_verification: self.user_verification(),
+        }
+    }
+
+    /// Finish registration ceremony
+    ///
+    /// Verifies the response of authenticator and creates new credential.
+    /// The attestation statement is not verified, so authenticator model is not trusted.
+    /// The credentials which user already has cannot be registered again.
+    pub fn finish_registration(
+        &self,
+        user: UserId,
+        req: &WebAuthnRegister,
+        creds: &[WebAuthnCredential],
+    ) -> Result<WebAuthnCredential, WebAuthnError> {
+        let options = &self.0.options;
+
+        let client_data = decode_b64url(&req.client_data)?;
+        let client_data = ClientData::parse(&client_data, "webauthn.create", options)?;
+
+        if !self.take_challenge(&client_data.challenge, Some(user)) {
+            return Err(WebAuthnError::BadChallenge);
+        }
+
+        let attestation = decode_b64url(&req.attestation)?;
+        let (attestation, _) = cbor_decode(&attestation)?;
+        let auth_data = attestation
+            .get_text("authData")
+            .and_then(CborValue::as_bytes)
+            .ok_or(WebAuthnError::BadData)?;
+        let auth_data = AuthData::parse(auth_data, options)?;
+        let (id, key) = auth_data.cred.ok_or(WebAuthnError::BadKey)?;
+
+        if creds.iter().any(|cred| cred.id == id) {
+            return Err(WebAuthnError::DupCredential);
+        }
+
+        Ok(WebAuthnCredential {
+            id,
+            user,
+            name: req.name.clone().unwrap_or_else(|| "Passkey".into()),
+            key,
+            count: auth_data.count,
+            ctime: TimeStamp::now(),
+        })
+    }
+
+    fn check_assertion(&self, ident: &UserIdent) -> Result<Assertion, WebAuthnError> {
+        let options = &self.0.options;
+
+        let UserIdent::WebAuthn {
+            id,
+            user_handle,
+            client_data,
+            auth_data,
+            signature,
+        } = ident;
+
+        let client_data = decode_b64url(client_data)?;
+        let challenge = ClientData::parse(&client_data, "webauthn.get", options)?.challenge;
+
+        if !self.take_challenge(&challenge, None) {
+            return Err(WebAuthnError::BadChallenge);
+        }
+
+        let auth_data = decode_b64url(auth_data)?;
+        let count = AuthData::parse(&auth_data, options)?.count;
+
+        let user = decode_b64url(user_handle)?;
+        let user = from_utf8(&user)
+            .ok()
+            .and_then(|user| user.parse().ok())
+            .ok_or(WebAuthnError::BadCredential)?;
+
+        Ok(Assertion {
+            user,
+            id: decode_b64url(id)?,
+            data: signed_data(&auth_data, &client_data),
+            signature: decode_b64url(signature)?,
+            count,
+        })
+    }
+}
+
+/// Forget the oldest challenges to keep the number of matched ones less than limit
+fn forget_oldest<F>(challenges: &mut HashMap<String, Challenge>, limit: usize, matches: F)
+where
+    F: Fn(&Challenge) -> bool,
+{
+    let mut matched = challenges
+        .iter()
+        .filter(|(_, challenge)| matches(challenge))
+        .map(|(key, challenge)| (challenge.ctime, key.clone()))
+        .collect::<Vec<_>>();
+
+    let limit = limit.max(1);
+    if matched.len() < limit {
+        return;
+    }
+
+    let excess = matched.len() - limit + 1;
+    matched.sort();
+    for (_, key) in matched.into_iter().take(excess) {
+        challenges.remove(&key);
+    }
+}
+
+/// Check that user has WebAuthn credentials
+///
+/// Use it to implement [`HasLoginMethods`](auth::oauth2::HasLoginMethods).
+pub fn has_webauthn_login<S>(state: &S, user: UserId) -> BoxFuture<bool, AuthError>
+where
+    S: HasWebAuthnStorage,
+{
+    Box::new(
+        (state.as_ref() as &S::WebAuthnStorage)
+            .get_user_credentials(user)
+            .map_err(|error| {
+                error!("Unable to get user credentials: {}", error);
+                AuthError::BackendError
+            }).map(|creds| !creds.is_empty()),
+    )
+}
+
+impl<S> IsAuthMethod<S> for WebAuthnAuth
+where
+    S: HasUserStorage + HasWebAuthnStorage + Send + Clone + 'static,
+{
+    type AuthInfo = AuthInfo;
+    type UserIdent = UserIdent;
+
+    fn get_auth_info(&self, state: &S) -> Self::AuthInfo {
+        self.get_auth_info_from(state, &AuthContext::default())
+    }
+
+    fn get_auth_info_from(&self, _state: &S, ctx: &AuthContext) -> Self::AuthInfo {
+        let options = &self.0.options;
+
+        AuthInfo {
+            webauthn: AssertionInfo {
+                rp_id: options.rp_id.clone(),
+                challenge: self.create_challenge(None, ctx.addr),
+                timeout: options.timeout,
+                user_verification: self.user_verification(),
+            },
+        }
+    }
+
+    fn try_user_auth(
+        &self,
+        state: &S,
+        ident: &Self::UserIdent,
+    ) -> BoxFuture<<S::UserStorage as IsUserStorage>::User, AuthError> {
+        let Assertion {
+            user,
+            id,
+            data,
+            signature,
+            count,
+        } = match self.check_assertion(ident) {
+            Ok(assertion) => assertion,
+            Err(error) => {
+                warn!("Invalid WebAuthn assertion: {}", error);
+                return Box::new(err(AuthError::BadIdent));
+            }
+        };
+
+        let state = state.clone();
+
+        Box::new(
+            (state.as_ref() as &S::WebAuthnStorage)
+                .get_user_credentials(user)
+                .map_err(|error| {
+                    error!("Unable to get user credentials: {}", error);
+                    AuthError::BackendError
+                }).and_then(move |creds| {
+                    result(
+                        creds
+                            .into_iter()
+                            .find(|cred| cred.id == id)
+                            .ok_or(WebAuthnError::BadCredential)
+                            .and_then(|mut cred| {
+                                if !cred.key.verify(&data, &signature) {
+                                    return Err(WebAuthnError::BadSignature);
+                                }
+                                // authenticators which does not support counter always sends zero
+                                if (cred.count != 0 || count != 0) && count <= cred.count {
+                                    return Err(WebAuthnError::BadCounter);
+                                }
+                                cred.count = count;
+                                Ok(cred)
+                            }).map_err(|error| {
+                                warn!("Invalid WebAuthn assertion: {}", error);
+                                AuthError::BadIdent
+                            }),
+                    )
+                }).and_then({
+                    let state = state.clone();
+                    move |cred| {
+                        (state.as_ref() as &S::WebAuthnStorage)
+                            .put_user_credential(cred)
+                            .map_err(|error| {
+                                error!("Unable to put user credential: {}", error);
+                                AuthError::BackendError
+                            })
+                    }
+                }).and_then(move |_| {
+                    (state.as_ref() as &S::UserStorage)
+                        .get_user_data(user)
+                        .map_err(|error| {
+                            error!("Unable to get user data: {}", error);
+                            AuthError::BackendError
+                        }).and_then(|user| user.ok_or(AuthError::BadIdent))
+                        .and_then(approved_user)
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use openssl::{
+        bn::{BigNum, BigNumContext},
+        ec::{EcGroup, EcKey},
+        ecdsa::EcdsaSig,
+        nid::Nid,
+    };
+    use sodiumoxide::crypto::{hash::sha256, sign::ed25519};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    fn cbor_head(major: u8, arg: usize) -> Vec<u8> {
+        let major = major << 5;
+        if arg < 24 {
+            vec![major | arg as u8]
+        } else if arg < 0x100 {
+            vec![major | 24, arg as u8]
+        } else {
+            vec![major | 25, (arg >> 8) as u8, arg as u8]
+        }
+    }
+
+    fn cbor_int(value: i64) -> Vec<u8> {
+        if value < 0 {
+            cbor_head(1, (-1 - value) as usize)
+        } else {
+            cbor_head(0, value as usize)
+        }
+    }
+
+    fn cbor_bytes(value: &[u8]) -> Vec<u8> {
+        let mut data = cbor_head(2, value.len());
+        data.extend_from_slice(value);
+        data
+    }
+
+    fn cbor_text(value: &str) -> Vec<u8> {
+        let mut data = cbor_head(3, value.len());
+        data.extend_from_slice(value.as_bytes());
+        data
+    }
+
+    fn cbor_map(pairs: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+        let mut data = cbor_head(5, pairs.len());
+        for (key, value) in pairs {
+            data.extend_from_slice(key);
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    fn b64(data: &[u8]) -> String {
+        encode_config(data, URL_SAFE_NO_PAD)
+    }
+
+    trait Authenticator {
+        fn cose_key(&self) -> Vec<u8>;
+        fn sign(&self, data: &[u8]) -> Vec<u8>;
+    }
+
+    struct ES256Key(EcKey<::openssl::pkey::Private>);
+
+    impl ES256Key {
+        fn new() -> Self {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            ES256Key(EcKey::generate(&group).unwrap())
+        }
+    }
+
+    impl Authenticator for ES256Key {
+        fn cose_key(&self) -> Vec<u8> {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let mut ctx = BigNumContext::new().unwrap();
+            let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+            self.0
+                .public_key()
+                .affine_coordinates_gfp(&group, &mut x, &mut y, &mut ctx)
+                .unwrap();
+            cbor_map(&[
+                (cbor_int(1), cbor_int(2)),
+                (cbor_int(3), cbor_int(COSE_ALG_ES256)),
+                (cbor_int(-1), cbor_int(1)),
+                (cbor_int(-2), cbor_bytes(&x.to_vec_padded(32).unwrap())),
+                (cbor_int(-3), cbor_bytes(&y.to_vec_padded(32).unwrap())),
+            ])
+        }
+
+        fn sign(&self, data: &[u8]) -> Vec<u8> {
+            EcdsaSig::sign(&sha256::hash(data)[..], &self.0)
+                .unwrap()
+                .to_der()
+                .unwrap()
+        }
+    }
+
+    struct EdDSAKey(ed25519::PublicKey, ed25519::SecretKey);
+
+    impl EdDSAKey {
+        fn new() -> Self {
+            let (pk, sk) = ed25519::gen_keypair();
+            EdDSAKey(pk, sk)
+        }
+    }
+
+    impl Authenticator for EdDSAKey {
+        fn cose_key(&self) -> Vec<u8> {
+            cbor_map(&[
+                (cbor_int(1), cbor_int(1)),
+                (cbor_int(3), cbor_int(COSE_ALG_EDDSA)),
+                (cbor_int(-1), cbor_int(6)),
+                (cbor_int(-2), cbor_bytes(&(self.0).0)),
+            ])
+        }
+
+        fn sign(&self, data: &[u8]) -> Vec<u8> {
+            ed25519::sign_detached(data, &self.1).0.to_vec()
+        }
+    }
+
+    fn client_data(kind: &str, challenge: &str) -> Vec<u8> {
+        format!(
+            r#"{{"type":"{}","challenge":"{}","origin":"https://localhost","crossOrigin":false}}"#,
+            kind, challenge
+        ).into_bytes()
+    }
+
+    fn auth_data(flags: u8, count: u32) -> Vec<u8> {
+        let mut data = sha256::hash(b"localhost")[..].to_vec();
+        data.push(flags);
+        data.extend_from_slice(&[
+            (count >> 24) as u8,
+            (count >> 16) as u8,
+            (count >> 8) as u8,
+            count as u8,
+        ]);
+        data
+    }
+
+    fn register<A: Authenticator>(
+        auth: &WebAuthnAuth,
+        key: &A,
+        id: &[u8],
+        creds: &[WebAuthnCredential],
+    ) -> Result<WebAuthnCredential, WebAuthnError> {
+        let info = auth.start_registration(1, "user", &[]);
+
+        let mut data = auth_data(0x41, 0);
+        data.extend_from_slice(&[0; 16]); // aaguid
+        data.extend_from_slice(&[0, id.len() as u8]);
+        data.extend_from_slice(id);
+        data.extend_from_slice(&key.cose_key());
+
+        let attestation = cbor_map(&[
+            (cbor_text("fmt"), cbor_text("none")),
+            (cbor_text("attStmt"), cbor_map(&[])),
+            (cbor_text("authData"), cbor_bytes(&data)),
+        ]);
+
+        auth.finish_registration(
+            1,
+            &WebAuthnRegister {
+                name: None,
+                client_data: b64(&client_data("webauthn.create", &info.challenge)),
+                attestation: b64(&attestation),
+            },
+            creds,
+        )
+    }
+
+    fn assert<A: Authenticator>(auth: &WebAuthnAuth, key: &A, id: &[u8], count: u32) -> UserIdent {
+        let challenge = auth.create_challenge(None, None);
+        let client_data = client_data("webauthn.get", &challenge);
+        let auth_data = auth_data(0x01, count);
+
+        UserIdent::WebAuthn {
+            id: b64(id),
+            user_handle: b64(b"1"),
+            signature: b64(&key.sign(&signed_data(&auth_data, &client_data))),
+            client_data: b64(&client_data),
+            auth_data: b64(&auth_data),
+        }
+    }
+
+    fn ceremony<A: Authenticator>(key: A) {
+        let auth = WebAuthnAuth::new(WebAuthnOptions::new("localhost"));
+
+        let cred = register(&auth, &key, b"cred", &[]).unwrap();
+        assert_eq!(cred.id, b"cred".to_vec());
+        assert_eq!(cred.user, 1);
+
+        // the same credential cannot be registered again
+        assert_eq!(
+            register(&auth, &key, b"cred", &[cred.clone()]).err(),
+            Some(WebAuthnError::DupCredential)
+        );
+
+        let ident = assert(&auth, &key, b"cred", 1);
+        let assertion = auth.check_assertion(&ident).unwrap();
+        assert_eq!(assertion.user, 1);
+        assert_eq!(assertion.id, cred.id);
+        assert_eq!(assertion.count, 1);
+        assert!(cred.key.verify(&assertion.data, &assertion.signature));
+
+        // challenge is used only once
+        assert_eq!(
+            auth.check_assertion(&ident).err(),
+            Some(WebAuthnError::BadChallenge)
+        );
+
+        // signature of other data
+        let other = auth.check_assertion(&assert(&auth, &key, b"cred", 2)).unwrap();
+        assert!(!cred.key.verify(&other.data, &assertion.signature));
+    }
+
+    #[test]
+    fn es256_ceremony() {
+        ceremony(ES256Key::new());
+    }
+
+    #[test]
+    fn eddsa_ceremony() {
+        ceremony(EdDSAKey::new());
+    }
+
+    #[test]
+    fn foreign_origin() {
+        let auth = WebAuthnAuth::new(WebAuthnOptions::new("localhost"));
+        let challenge = auth.create_challenge(None, None);
+        let client_data = br#"{"type":"webauthn.get","challenge":"","origin":"https://evil.com"}"#;
+        let ident = UserIdent::WebAuthn {
+            id: b64(b"cred"),
+            user_handle: b64(b"1"),
+            signature: String::new(),
+            client_data: b64(client_data),
+            auth_data: b64(&auth_data(0x01, 1)),
+        };
+        assert_eq!(
+            auth.check_assertion(&ident).err(),
+            Some(WebAuthnError::BadOrigin)
+        );
+        // challenge is still alive
+        assert!(auth.take_challenge(&challenge, None));
+    }
+
+    #[test]
+    fn challenges_limit() {
+        let mut options = WebAuthnOptions::new("localhost");
+        options.max_challenges = 3;
+        let auth = WebAuthnAuth::new(options);
+
+        let first = auth.create_challenge(None, None);
+        sleep(Duration::from_millis(2));
+        for _ in 0..5 {
+            auth.create_challenge(None, None);
+        }
+        let last = auth.create_challenge(None, None);
+
+        assert_eq!(auth.0.challenges.read().unwrap().len(), 3);
+        assert!(auth.take_challenge(&last, None));
+        assert!(!auth.take_challenge(&first, None));
+    }
+
+    #[test]
+    fn client_challenges_limit() {
+        let mut options = WebAuthnOptions::new("localhost");
+        options.max_client_challenges = 2;
+        let auth = WebAuthnAuth::new(options);
+        let addr = "192.0.2.7".parse().ok();
+
+        let other = auth.create_challenge(None, "198.51.100.2".parse().ok());
+        let user = auth.create_challenge(Some(1), None);
+        sleep(Duration::from_millis(2));
+        // flooding client evicts own challenges only
+        for _ in 0..10 {
+            auth.create_challenge(None, addr);
+        }
+        let last = auth.create_challenge(None, addr);
+
+        assert_eq!(auth.0.challenges.read().unwrap().len(), 4);
+        assert!(auth.take_challenge(&last, None));
+        assert!(auth.take_challenge(&other, None));
+        assert!(auth.take_challenge(&user, Some(1)));
+    }
+}
@@ -0,0 +1,45 @@
+/*!
+
+### WebAuthn auth
+
+This method provides passwordless auth using platform authenticators and security keys (passkeys)
+according to [Web Authentication](https://www.w3.org/TR/webauthn/).
+
+Supported public key algorithms is ES256 and EdDSA. The attestation statement is not verified.
+
+#### Registration
+
+1. Authorized client requests registration options (*POST /:user/webauthn*) and gets [`CreationInfo`](auth::method::webauthn::CreationInfo)
+2. Client calls `navigator.credentials.create()` using received options
+3. Client sends response of authenticator (*PUT /:user/webauthn* with [`WebAuthnRegister`](auth::method::webauthn::WebAuthnRegister))
+4. Server verifies response and stores credential
+
+Registered credentials can be listed using *GET /:user/webauthn* and deleted using *DELETE /:user/webauthn/:id*.
+
+#### Authorization
+
+1. Server data: `webauthn: { rp_id, challenge, timeout, user_verification }`
+2. User identification data (the response of `navigator.credentials.get()`):
+  * Credential identifier (`id: string`)
+  * User handle (`user_handle: string`)
+  * Client data JSON (`client_data: string`)
+  * Authenticator data (`auth_data: string`)
+  * Signature (`signature: string`)
+
+All binary data is encoded using base64url without padding.
+
+*/
+
+mod cbor;
+mod handler;
+mod method;
+mod traits;
+mod types;
+mod verify;
+
+pub use self::cbor::*;
+pub use self::handler::*;
+pub use self::method::*;
+pub use self::traits::*;
+pub use self::types::*;
+pub use self::verify::*;
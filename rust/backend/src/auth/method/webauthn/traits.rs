@@ -0,0 +1,36 @@
+use super::{WebAuthnAuth, WebAuthnCredential};
+use base::{BoxFuture, IsBackend};
+use user::UserId;
+
+/// WebAuthn credentials storage
+pub trait IsWebAuthnStorage: IsBackend {
+    /// Get all credentials of user
+    fn get_user_credentials(
+        &self,
+        user: UserId,
+    ) -> BoxFuture<Vec<WebAuthnCredential>, Self::Error>;
+
+    /// Put new or update existing credential
+    fn put_user_credential(&self, cred: WebAuthnCredential) -> BoxFuture<(), Self::Error>;
+
+    /// Delete credential of user by identifier
+    fn del_user_credential(&self, user: UserId, id: &[u8]) -> BoxFuture<Option<()>, Self::Error>;
+}
+
+/// State has access to WebAuthn credentials
+pub trait HasWebAuthnStorage
+where
+    Self: AsRef<<Self as HasWebAuthnStorage>::WebAuthnStorage>,
+{
+    /// Credentials storage type
+    type WebAuthnStorage: IsWebAuthnStorage;
+}
+
+/// State has access to WebAuthn auth method
+///
+/// The registration handlers uses it to share challenges with auth method.
+pub trait HasWebAuthn
+where
+    Self: AsRef<WebAuthnAuth>,
+{
+}
@@ -0,0 +1,307 @@
+use auth::IsThrottleIdent;
+use base::{serde_extra::base64, TimeStamp};
+use base64lib::{encode_config, URL_SAFE_NO_PAD};
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use user::UserId;
+
+/// WebAuthn arguments (or predicate)
+#[derive(Debug)]
+pub struct WebAuthnArg {
+    /// Credentials owner
+    pub user: UserId,
+}
+
+/// WebAuthn auth options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebAuthnOptions {
+    /// Relying party identifier
+    ///
+    /// Usually this is a domain name of site.
+    pub rp_id: String,
+    /// Relying party name which authenticators shows
+    pub rp_name: String,
+    /// Allowed origins of client
+    ///
+    /// Like `https://example.com`.
+    pub origins: Vec<String>,
+    /// Ceremony timeout in milliseconds
+    ///
+    /// The challenges which not used during this interval will be forgotten.
+    #[serde(default = "default_timeout")]
+    pub timeout: TimeStamp,
+    /// Max number of pending challenges
+    ///
+    /// The oldest challenges will be forgotten when this limit is reached.
+    #[serde(default = "default_max_challenges")]
+    pub max_challenges: usize,
+    /// Max number of pending challenges per user or client address
+    ///
+    /// The oldest challenges of same user or client will be forgotten when this limit is reached,
+    /// so the single client cannot flood challenges of others.
+    /// The assertion challenges of clients with unknown address is limited by `max_challenges` only.
+    #[serde(default = "default_max_client_challenges")]
+    pub max_client_challenges: usize,
+    /// Require user verification (PIN, biometry and etc.)
+    #[serde(default)]
+    pub user_verification: bool,
+}
+
+fn default_timeout() -> TimeStamp {
+    TimeStamp::default().with_mins(2)
+}
+
+fn default_max_challenges() -> usize {
+    10000
+}
+
+fn default_max_client_challenges() -> usize {
+    10
+}
+
+impl WebAuthnOptions {
+    /// Create options using relying party domain
+    ///
+    /// The origin will be set to `https://` + domain.
+    pub fn new<S: Into<String>>(rp_id: S) -> Self {
+        let rp_id = rp_id.into();
+        Self {
+            rp_name: rp_id.clone(),
+            origins: vec![format!("https://{}", rp_id)],
+            rp_id,
+            timeout: default_timeout(),
+            max_challenges: default_max_challenges(),
+            max_client_challenges: default_max_client_challenges(),
+            user_verification: false,
+        }
+    }
+}
+
+/// Credential public key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "alg")]
+pub enum CoseKey {
+    /// ECDSA using P-256 curve and SHA-256
+    #[serde(rename = "ES256")]
+    ES256 {
+        #[serde(with = "base64")]
+        x: Vec<u8>,
+        #[serde(with = "base64")]
+        y: Vec<u8>,
+    },
+    /// EdDSA using Ed25519 curve
+    #[serde(rename = "EdDSA")]
+    EdDSA {
+        #[serde(with = "base64")]
+        x: Vec<u8>,
+    },
+}
+
+/// User WebAuthn credential
+///
+/// The data which stored on server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebAuthnCredential {
+    /// Credential identifier
+    #[serde(with = "base64")]
+    pub id: Vec<u8>,
+
+    /// Credential owner
+    pub user: UserId,
+
+    /// Human-readable name of credential
+    pub name: String,
+
+    /// Credential public key
+    pub key: CoseKey,
+
+    /// Last signature counter
+    pub count: u32,
+
+    /// Registration time
+    pub ctime: TimeStamp,
+}
+
+/// User WebAuthn credential info
+///
+/// The data which shows to client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CredentialInfo {
+    /// Credential identifier (base64url)
+    pub id: String,
+
+    /// Human-readable name of credential
+    pub name: String,
+
+    /// Registration time
+    pub ctime: TimeStamp,
+}
+
+impl<'a> From<&'a WebAuthnCredential> for CredentialInfo {
+    fn from(cred: &'a WebAuthnCredential) -> Self {
+        Self {
+            id: encode_config(&cred.id, URL_SAFE_NO_PAD),
+            name: cred.name.clone(),
+            ctime: cred.ctime,
+        }
+    }
+}
+
+/// WebAuthn auth method information
+///
+/// It contains the options for assertion (`navigator.credentials.get()`).
+#[derive(Debug, Serialize)]
+pub struct AuthInfo {
+    pub webauthn: AssertionInfo,
+}
+
+/// Assertion options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssertionInfo {
+    /// Relying party identifier
+    pub rp_id: String,
+
+    /// Fresh challenge (base64url)
+    pub challenge: String,
+
+    /// Ceremony timeout in milliseconds
+    pub timeout: TimeStamp,
+
+    /// User verification requirement
+    pub user_verification: String,
+}
+
+/// Registration options
+///
+/// The options for `navigator.credentials.create()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreationInfo {
+    /// Relying party
+    pub rp: RelyingParty,
+
+    /// User entity
+    pub user: UserEntity,
+
+    /// Fresh challenge (base64url)
+    pub challenge: String,
+
+    /// Supported public key algorithms (COSE identifiers)
+    pub algs: Vec<i64>,
+
+    /// Ceremony timeout in milliseconds
+    pub timeout: TimeStamp,
+
+    /// Already registered credentials (base64url)
+    pub exclude: Vec<String>,
+
+    /// User verification requirement
+    pub user_verification: String,
+}
+
+/// Relying party entity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+/// User entity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserEntity {
+    /// User handle (base64url)
+    ///
+    /// It contains decimal user identifier.
+    pub id: String,
+    pub name: String,
+}
+
+/// Registration request
+///
+/// The response of authenticator which client sends to server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebAuthnRegister {
+    /// Human-readable name of credential
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Client data JSON (base64url)
+    pub client_data: String,
+
+    /// Attestation object (base64url)
+    pub attestation: String,
+}
+
+/// WebAuthn auth user identification
+///
+/// The assertion response of authenticator.
+#[derive(Debug, Clone, Deserialize)]
+pub enum UserIdent {
+    #[serde(rename = "webauthn")]
+    WebAuthn {
+        /// Credential identifier (base64url)
+        id: String,
+        /// User handle (base64url)
+        user_handle: String,
+        /// Client data JSON (base64url)
+        client_data: String,
+        /// Authenticator data (base64url)
+        auth_data: String,
+        /// Signature (base64url)
+        signature: String,
+    },
+}
+
+impl IsThrottleIdent for UserIdent {
+    fn throttle_key(&self) -> Option<String> {
+        // signature can not be guessed and challenge is used only once
+        None
+    }
+}
+
+/// WebAuthn verification error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebAuthnError {
+    /// Malformed data
+    BadData,
+    /// Unexpected ceremony type
+    BadType,
+    /// Unknown or expired challenge
+    BadChallenge,
+    /// Disallowed origin
+    BadOrigin,
+    /// Relying party mismatch
+    BadRelyingParty,
+    /// User presence or verification missing
+    BadFlags,
+    /// Unsupported or missing public key
+    BadKey,
+    /// Unknown credential
+    BadCredential,
+    /// Credential already registered
+    DupCredential,
+    /// Invalid signature
+    BadSignature,
+    /// Signature counter not increased (possibly cloned authenticator)
+    BadCounter,
+}
+
+impl Error for WebAuthnError {}
+
+impl Display for WebAuthnError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        use self::WebAuthnError::*;
+        f.write_str(match self {
+            BadData => "Malformed data",
+            BadType => "Unexpected ceremony type",
+            BadChallenge => "Unknown or expired challenge",
+            BadOrigin => "Disallowed origin",
+            BadRelyingParty => "Relying party mismatch",
+            BadFlags => "User presence or verification missing",
+            BadKey => "Unsupported public key",
+            BadCredential => "Unknown credential",
+            DupCredential => "Credential already registered",
+            BadSignature => "Invalid signature",
+            BadCounter => "Signature counter not increased",
+        })
+    }
+}
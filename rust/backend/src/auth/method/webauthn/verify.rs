@@ -0,0 +1,170 @@
+use super::{cbor_decode, CborValue, CoseKey, WebAuthnError, WebAuthnOptions};
+use base64lib::{decode_config, URL_SAFE_NO_PAD};
+use openssl::{
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    nid::Nid,
+};
+use serde_json;
+use sodiumoxide::crypto::{hash::sha256, sign::ed25519};
+
+/// User present flag
+const FLAG_UP: u8 = 0x01;
+/// User verified flag
+const FLAG_UV: u8 = 0x04;
+/// Attested credential data included flag
+const FLAG_AT: u8 = 0x40;
+
+/// COSE algorithm identifier of ES256
+pub const COSE_ALG_ES256: i64 = -7;
+/// COSE algorithm identifier of EdDSA
+pub const COSE_ALG_EDDSA: i64 = -8;
+
+/// Collected client data
+#[derive(Debug, Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub challenge: String,
+    pub origin: String,
+}
+
+impl ClientData {
+    /// Parse client data JSON and check ceremony type and origin
+    pub fn parse(
+        data: &[u8],
+        kind: &str,
+        options: &WebAuthnOptions,
+    ) -> Result<Self, WebAuthnError> {
+        let data: ClientData = serde_json::from_slice(data).map_err(|_| WebAuthnError::BadData)?;
+        if data.kind != kind {
+            return Err(WebAuthnError::BadType);
+        }
+        if !options.origins.iter().any(|origin| origin == &data.origin) {
+            return Err(WebAuthnError::BadOrigin);
+        }
+        Ok(data)
+    }
+}
+
+/// Parsed authenticator data
+#[derive(Debug)]
+pub struct AuthData {
+    /// Signature counter
+    pub count: u32,
+    /// Attested credential identifier and public key
+    pub cred: Option<(Vec<u8>, CoseKey)>,
+}
+
+impl AuthData {
+    /// Parse authenticator data and check relying party and flags
+    pub fn parse(data: &[u8], options: &WebAuthnOptions) -> Result<Self, WebAuthnError> {
+        if data.len() < 37 {
+            return Err(WebAuthnError::BadData);
+        }
+
+        if data[..32] != sha256::hash(options.rp_id.as_bytes())[..] {
+            return Err(WebAuthnError::BadRelyingParty);
+        }
+
+        let flags = data[32];
+        if flags & FLAG_UP == 0 || (options.user_verification && flags & FLAG_UV == 0) {
+            return Err(WebAuthnError::BadFlags);
+        }
+
+        let count = data[33..37]
+            .iter()
+            .fold(0u32, |count, byte| count << 8 | u32::from(*byte));
+
+        let cred = if flags & FLAG_AT != 0 {
+            // aaguid (16) + credential id length (2)
+            let data = &data[37..];
+            if data.len() < 18 {
+                return Err(WebAuthnError::BadData);
+            }
+            let length = (data[16] as usize) << 8 | data[17] as usize;
+            let data = &data[18..];
+            if data.len() < length {
+                return Err(WebAuthnError::BadData);
+            }
+            let (id, data) = data.split_at(length);
+            let (key, _) = cbor_decode(data)?;
+            Some((id.into(), CoseKey::parse(&key)?))
+        } else {
+            None
+        };
+
+        Ok(Self { count, cred })
+    }
+}
+
+impl CoseKey {
+    /// Parse COSE key
+    pub fn parse(key: &CborValue) -> Result<Self, WebAuthnError> {
+        let bytes = |label| {
+            key.get_int(label)
+                .and_then(CborValue::as_bytes)
+                .filter(|bytes| bytes.len() == 32)
+                .map(Vec::from)
+                .ok_or(WebAuthnError::BadKey)
+        };
+
+        match (
+            key.get_int(1).and_then(CborValue::as_int), // kty
+            key.get_int(3).and_then(CborValue::as_int), // alg
+            key.get_int(-1).and_then(CborValue::as_int), // crv
+        ) {
+            // EC2, P-256
+            (Some(2), Some(COSE_ALG_ES256), Some(1)) => Ok(CoseKey::ES256 {
+                x: bytes(-2)?,
+                y: bytes(-3)?,
+            }),
+            // OKP, Ed25519
+            (Some(1), Some(COSE_ALG_EDDSA), Some(6)) => Ok(CoseKey::EdDSA { x: bytes(-2)? }),
+            _ => Err(WebAuthnError::BadKey),
+        }
+    }
+
+    /// Verify signature of data
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        match self {
+            CoseKey::ES256 { x, y } => verify_es256(x, y, data, signature).unwrap_or(false),
+            CoseKey::EdDSA { x } => {
+                if let (Some(key), Some(signature)) = (
+                    ed25519::PublicKey::from_slice(x),
+                    ed25519::Signature::from_slice(signature),
+                ) {
+                    ed25519::verify_detached(&signature, data, &key)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+fn verify_es256(
+    x: &[u8],
+    y: &[u8],
+    data: &[u8],
+    signature: &[u8],
+) -> Result<bool, ::openssl::error::ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let (x, y) = (BigNum::from_slice(x)?, BigNum::from_slice(y)?);
+    let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)?;
+    EcdsaSig::from_der(signature)?.verify(&sha256::hash(data)[..], &key)
+}
+
+/// Get signed data of assertion
+pub fn signed_data(auth_data: &[u8], client_data: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(auth_data.len() + sha256::DIGESTBYTES);
+    data.extend_from_slice(auth_data);
+    data.extend_from_slice(&sha256::hash(client_data)[..]);
+    data
+}
+
+/// Decode base64url string
+pub fn decode_b64url(data: &str) -> Result<Vec<u8>, WebAuthnError> {
+    decode_config(data, URL_SAFE_NO_PAD).map_err(|_| WebAuthnError::BadData)
+}
@@ -8,23 +8,28 @@
    * Server timestamp ([`ctime`](auth::AuthInfo::ctime): *number* unix-time in microseconds)
    * Server public key ([`pbkey`](auth::AuthInfo::pbkey): *string* base64 sealed-box key)
    * Authentication methods ([`authm`](auth::AuthInfo::authm): *{\[method_name: string\]: [`MethodInfo`](auth::AuthInfo::MethodInfo)}*)
-2. Client synchronize self clock with server
+2. Client synchronize self clock with server (optionally using time endpoint which returns [`TimeInfo`](auth::TimeInfo))
 3. Client creates authentication request ([`AuthRequest`](auth::AuthRequest)) using:
    * Client time stamp ([`ctime`](auth::AuthRequest::ctime): *number* unix-time microseconds)
+   * Unique random nonce ([`nonce`](auth::AuthRequest::nonce): *string*) which is optional by default
    * Client public key ([`pbkey`](auth::AuthRequest::pbkey): *string* base64 sealed-box key)
    * User identification data ([`ident`](auth::AuthRequest::ident): *Value*) which depends from authentication method
+   * Optional device label ([`device`](auth::AuthRequest::device): *string*) to help user recognize session
 4. Client encrypts authentication request using server public key and sends to server as request body
 5. Server receives request body and decrypts authentication data using server secret key
 6. Server verifies authentication request in a next way:
-   1. Checks time stamp synchronization (absolute delta between server time less than three seconds)
-   2. Checks user identification in a way depending from authentication method
-   3. Checks that session for this user with same timestamp doesn't already created
+   1. Checks time stamp synchronization (absolute delta between server time less than [`clock_skew`](auth::SessionOptions::clock_skew), three seconds by default)
+   2. Checks that nonce wasn't used before (see [`IsNonceStorage`](auth::IsNonceStorage))
+   3. Checks user identification in a way depending from authentication method
+   4. Checks that session for this user with same timestamp doesn't already created
+   5. When user has second factor enabled responds with [`FactorRequired`](auth::FactorRequired) which includes sealed ticket, and the client completes authentication by sending [`FactorRequest`](auth::FactorRequest) with ticket and code (see [`totp`](auth::totp))
 7. Server creates session data ([`SessionData`](auth::SessionData)) which includes:
    * Client public key ([`pbkey`](auth::SessionData::pbkey): *binary*)
    * Unique session token ([`token`](auth::SessionData::pbkey): *binary*)
    * Session serial ([`serno`](auth::SessionData::serno): *number*) with 1 as initial value which increments on each authorized request
    * Session create time ([`ctime`](auth::SessionData::ctime): *number*) which contains client time stamp
    * Session access time ([`atime`](auth::SessionData::atime): *number*)
+   * Client metadata ([`meta`](auth::SessionData::meta)) which includes IP address, user agent and device label
 8. Server creates authentication response ([`AuthResponse`](auth::AuthResponse)) which includes:
    * User id ([`user`](auth::AuthResponse::user): *number*)
    * Session id ([`sess`](auth::AuthResponse::sess): *number*)
@@ -45,24 +50,47 @@
 3. Server receives *X-Auth* header and decrypts authentication token using server secret key
 4. Server verifies authentication data in a next way:
    1. Gets session data ([`SessionData`](auth::SessionData)) by session and user identifiers
-   2. Checks access time ([`atime`](auth::SessionData::ctime)) to prevent use of outdated sessions
+   2. Checks access time ([`atime`](auth::SessionData::atime)) and creation time ([`ctime`](auth::SessionData::ctime)) to prevent use of outdated sessions (see [`SessionOptions`](auth::SessionOptions))
    3. Checks equality of received session token ([`token`](auth::AuthData::token)) and stored session token ([`token`](auth::SessionData::token))
-   4. Checks equality of received serial ([`serno`](auth::AuthData::serno)) and stored serial ([`serno`](auth::SessionData::serno))
-5. Server increments and stores new serial in session data ([`serno`](auth::SessionData::serno)
+   4. Checks that received serial ([`serno`](auth::AuthData::serno)) is within serials window around stored serial ([`serno`](auth::SessionData::serno)) and wasn't used before ([`seen`](auth::SessionData::seen))
+5. Server atomically stores new serials state in session data ([`serno`](auth::SessionData::serno), [`seen`](auth::SessionData::seen))
 6. Server gets user data ([`HasUserData::UserData`](auth::HasUserData::UserData))
 7. Server creates user auth ([`HasUserAuth::AuthData`](auth::HasUserAuth::AuthData)) with application-dependent session and user info and returns back to the application
 
+Expired sessions is periodically deleted from storage by [`session_sweeper`](auth::session_sweeper) task.
+
+### API tokens
+
+1. Authorized client requests new token ([`TokenRequest`](auth::TokenRequest)) which includes:
+   * Token name ([`name`](auth::TokenRequest::name): *string*) to identify it in token list
+   * Token scope ([`scope`](auth::TokenRequest::scope): *Value*) which depends from application
+   * Optional time to live ([`ttl`](auth::TokenRequest::ttl): *number* milliseconds)
+2. Server creates token data ([`BaseTokenData`](auth::BaseTokenData)) and returns bearer token ([`TokenResponse`](auth::TokenResponse)) to client
+3. Client sends bearer token with requests as *Authorization: Bearer* header
+4. Server verifies bearer token in a next way:
+   1. Gets token data by user and token identifiers
+   2. Checks equality of received token and stored token
+   3. Checks expiration time ([`etime`](auth::BaseTokenData::etime)) to prevent use of outdated tokens
+5. Server creates user auth restricted by token scope using [`IsUserAuth::new_token_auth`](auth::IsUserAuth::new_token_auth)
+
 */
 
 mod error;
 mod handler;
 mod method;
+mod nonce;
+mod provision;
 pub mod stub;
+mod sweeper;
 mod traits;
+pub mod totp;
 mod types;
 
 pub use self::error::*;
 pub use self::handler::*;
 pub use self::method::*;
+pub use self::nonce::*;
+pub use self::provision::*;
+pub use self::sweeper::*;
 pub use self::traits::*;
 pub use self::types::*;
@@ -0,0 +1,84 @@
+use super::IsNonceStorage;
+use base::{BoxFuture, IsBackend, TimeStamp};
+use futures::future::result;
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind};
+use std::sync::{Arc, Mutex};
+
+/// In-memory used nonces cache
+///
+/// Expired nonces is purged on each access.
+#[derive(Clone)]
+pub struct NonceCache {
+    nonces: Arc<Mutex<HashMap<String, TimeStamp>>>,
+}
+
+impl NonceCache {
+    /// Create nonces cache
+    pub fn new() -> Self {
+        Self {
+            nonces: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl IsBackend for NonceCache {
+    type Error = IoError;
+}
+
+impl IsNonceStorage for NonceCache {
+    fn use_nonce(&self, nonce: &str, etime: TimeStamp) -> BoxFuture<bool, Self::Error> {
+        Box::new(result(
+            self.nonces
+                .lock()
+                .map(|mut nonces| {
+                    let now = TimeStamp::now();
+                    nonces.retain(|_, etime| *etime > now);
+                    if nonces.contains_key(nonce) {
+                        false
+                    } else {
+                        nonces.insert(nonce.into(), etime);
+                        true
+                    }
+                }).map_err(|_| IoError::new(ErrorKind::Other, "Poisoned lock")),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::Future;
+
+    #[test]
+    fn replay() {
+        let cache = NonceCache::new();
+        let etime = TimeStamp::now().with_mins(1);
+
+        assert_eq!(cache.use_nonce("a", etime).wait().unwrap(), true);
+        assert_eq!(cache.use_nonce("a", etime).wait().unwrap(), false);
+        assert_eq!(cache.use_nonce("b", etime).wait().unwrap(), true);
+        assert_eq!(cache.clone().use_nonce("b", etime).wait().unwrap(), false);
+    }
+
+    #[test]
+    fn expiry() {
+        let cache = NonceCache::new();
+        let etime = TimeStamp::now().with_mins(1);
+
+        assert_eq!(cache.use_nonce("a", etime).wait().unwrap(), true);
+        assert_eq!(
+            cache
+                .use_nonce("b", TimeStamp::now().with_msecs(-1))
+                .wait()
+                .unwrap(),
+            true
+        );
+
+        // expired nonce is purged and can be used again
+        assert_eq!(cache.use_nonce("b", etime).wait().unwrap(), true);
+        assert_eq!(cache.use_nonce("b", etime).wait().unwrap(), false);
+        assert_eq!(cache.use_nonce("a", etime).wait().unwrap(), false);
+        assert_eq!(cache.nonces.lock().unwrap().len(), 2);
+    }
+}
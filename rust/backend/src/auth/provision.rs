@@ -0,0 +1,178 @@
+use super::AuthError;
+use mail::MailAddress;
+use user::IsUserData;
+
+/// User provisioning options
+///
+/// Applies to users which created on first login by external auth methods
+/// (like OAuth2 or one-time password) and to open sign-up with password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProvisionOptions {
+    /// Allow sign-up of new users
+    #[serde(default = "default_sign_up")]
+    pub sign_up: bool,
+
+    /// Allowed email domains
+    ///
+    /// Any domain is allowed when empty.
+    /// The emails of third service accounts are checked only when verified by service.
+    #[serde(default)]
+    pub email_domains: Vec<String>,
+
+    /// Allowed organizations (Github organizations for example)
+    ///
+    /// Any organization is allowed when empty.
+    #[serde(default)]
+    pub organizations: Vec<String>,
+
+    /// Invite codes
+    ///
+    /// One of codes is required to sign-up when not empty.
+    #[serde(default)]
+    pub invite_codes: Vec<String>,
+
+    /// New users should be approved before login
+    #[serde(default)]
+    pub approval: bool,
+}
+
+fn default_sign_up() -> bool {
+    true
+}
+
+impl Default for ProvisionOptions {
+    fn default() -> Self {
+        Self {
+            sign_up: default_sign_up(),
+            email_domains: Vec::new(),
+            organizations: Vec::new(),
+            invite_codes: Vec::new(),
+            approval: false,
+        }
+    }
+}
+
+/// Sign-up request info
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignUp<'a> {
+    /// User email address
+    pub email: Option<&'a MailAddress>,
+    /// User organizations
+    pub organizations: &'a [String],
+    /// Invite code
+    pub invite: Option<&'a str>,
+}
+
+impl ProvisionOptions {
+    /// Check sign-up against options
+    pub fn check(&self, sign_up: &SignUp) -> Result<(), AuthError> {
+        if !self.sign_up {
+            warn!("Sign-up disabled");
+            return Err(AuthError::Restricted);
+        }
+
+        if !self.email_domains.is_empty() && !sign_up.email.map_or(false, |email| {
+            self.email_domains
+                .iter()
+                .any(|domain| domain.eq_ignore_ascii_case(&email.domain))
+        }) {
+            warn!("Sign-up with disallowed email domain");
+            return Err(AuthError::Restricted);
+        }
+
+        if !self.organizations.is_empty() && !sign_up.organizations.iter().any(|org| {
+            self.organizations
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(org))
+        }) {
+            warn!("Sign-up outside of allowed organizations");
+            return Err(AuthError::Restricted);
+        }
+
+        if !self.invite_codes.is_empty() && !sign_up
+            .invite
+            .map_or(false, |invite| self.invite_codes.iter().any(|code| code == invite))
+        {
+            warn!("Sign-up without valid invite code");
+            return Err(AuthError::Restricted);
+        }
+
+        Ok(())
+    }
+}
+
+/// State has user provisioning policy
+///
+/// The policy is evaluated before new user will be stored.
+pub trait HasProvisionPolicy
+where
+    Self: AsRef<ProvisionOptions>,
+{
+    /// Check sign-up of new user
+    ///
+    /// Override it to implement custom policy.
+    fn check_sign_up(&self, sign_up: &SignUp) -> Result<(), AuthError> {
+        (self.as_ref() as &ProvisionOptions).check(sign_up)
+    }
+
+    /// Prepare new user
+    ///
+    /// Checks sign-up and marks user as pending when approval is required.
+    fn provision_user<U: IsUserData>(
+        &self,
+        user: &mut U,
+        sign_up: &SignUp,
+    ) -> Result<(), AuthError> {
+        self.check_sign_up(sign_up)?;
+        if (self.as_ref() as &ProvisionOptions).approval {
+            user.set_approved(false);
+        }
+        Ok(())
+    }
+}
+
+/// Pass approved user only
+pub fn approved_user<U: IsUserData>(user: U) -> Result<U, AuthError> {
+    if user.is_approved() {
+        Ok(user)
+    } else {
+        warn!("User is not approved yet");
+        Err(AuthError::Restricted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn is_restricted(result: Result<(), AuthError>) -> bool {
+        match result {
+            Err(AuthError::Restricted) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn sign_up() {
+        let email = "user@Example.com".parse().unwrap();
+        let orgs = vec!["literium".to_string()];
+        let sign_up = SignUp {
+            email: Some(&email),
+            organizations: &orgs,
+            invite: Some("secret"),
+        };
+
+        let mut options = ProvisionOptions::default();
+        assert!(options.check(&sign_up).is_ok());
+        assert!(options.check(&SignUp::default()).is_ok());
+
+        options.email_domains = vec!["example.com".into()];
+        options.organizations = vec!["Literium".into()];
+        options.invite_codes = vec!["secret".into()];
+        assert!(options.check(&sign_up).is_ok());
+        assert!(is_restricted(options.check(&SignUp::default())));
+
+        options.sign_up = false;
+        assert!(is_restricted(options.check(&sign_up)));
+    }
+}
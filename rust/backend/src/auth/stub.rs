@@ -7,13 +7,21 @@ This module provides dummy implementation of session storage backend for example
 */
 
 use super::{
-    BaseSessionData, HasSessionStorage, IsSessionData, IsSessionStorage, IsUserAuth, SessionArg,
-    SessionId,
+    totp::TotpArg, BaseSessionData, BaseTokenData, HasSessionStorage, HasTokenStorage,
+    IsSessionData, IsSessionStorage, IsTokenAuth, IsTokenData, IsTokenStorage, IsUserAuth,
+    SessionArg, SessionId, TokenArg, TokenId,
 };
-use access::{Grant, HasAccess};
+#[cfg(feature = "oauth2_auth")]
+use super::method::oauth2::OAuth2Arg;
+#[cfg(feature = "webauthn_auth")]
+use super::method::webauthn::{IsWebAuthnStorage, WebAuthnArg, WebAuthnCredential};
+use access::{AuditQuery, AuditSubject, Grant, HasAccess, HasPerm, IsAuditSubject, Scoped};
 use base::{BoxFuture, DummyError, IsBackend, TimeStamp};
 use futures::future::result;
-use std::sync::{Arc, RwLock};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, RwLock,
+};
 use user::{HasUserStorage, IsUserData, IsUserStorage, UserArg, UserId};
 
 pub type SessionData = BaseSessionData;
@@ -108,6 +116,30 @@ impl IsSessionStorage for Sessions {
         ))
     }
 
+    fn cas_user_session(
+        &self,
+        prev: &SessionData,
+        session: Self::Session,
+    ) -> BoxFuture<Option<Self::Session>, Self::Error> {
+        Box::new(result(
+            self.sessions
+                .write()
+                .map(|mut sessions| {
+                    sessions
+                        .iter_mut()
+                        .find(|data| data.user == session.user && data.sess == session.sess)
+                        .and_then(|data| {
+                            if data.serno == prev.serno && data.seen == prev.seen {
+                                *data = session.clone();
+                                Some(session)
+                            } else {
+                                None
+                            }
+                        })
+                }).map_err(|_| DummyError),
+        ))
+    }
+
     fn del_user_session(
         &self,
         user: UserId,
@@ -152,12 +184,270 @@ impl IsSessionStorage for Sessions {
                 .map_err(|_| DummyError),
         ))
     }
+
+    fn del_outdated_sessions(
+        &self,
+        atime: TimeStamp,
+        ctime: TimeStamp,
+    ) -> BoxFuture<(), Self::Error> {
+        Box::new(result(
+            self.sessions
+                .write()
+                .map(|mut sessions| {
+                    sessions.retain(|data| data.atime >= atime && data.ctime >= ctime)
+                }).map_err(|_| DummyError),
+        ))
+    }
+}
+
+/// Token data type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenData {
+    #[serde(flatten)]
+    pub base: BaseTokenData,
+    pub scope: Vec<Grant>,
+}
+
+impl From<(BaseTokenData, Vec<Grant>)> for TokenData {
+    fn from((base, scope): (BaseTokenData, Vec<Grant>)) -> Self {
+        Self { base, scope }
+    }
+}
+
+impl IsTokenData for TokenData {
+    type Scope = Vec<Grant>;
+
+    fn token_data(&self) -> &BaseTokenData {
+        &self.base
+    }
+
+    fn token_data_mut(&mut self) -> &mut BaseTokenData {
+        &mut self.base
+    }
+
+    fn token_scope(&self) -> &Self::Scope {
+        &self.scope
+    }
+}
+
+/// Dummy tokens backend
+#[derive(Clone)]
+pub struct Tokens {
+    tokens: Arc<RwLock<Vec<TokenData>>>,
+    // ids is never reused after deletion
+    last_id: Arc<AtomicUsize>,
+}
+
+impl Tokens {
+    /// Create tokens backend
+    pub fn new() -> Self {
+        Self {
+            tokens: Arc::new(RwLock::new(Vec::new())),
+            last_id: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Add token
+    pub fn with_token(self, token: TokenData) -> Self {
+        let id = token.base.id as usize;
+        if id > self.last_id.load(Ordering::SeqCst) {
+            self.last_id.store(id, Ordering::SeqCst);
+        }
+        self.tokens.write().unwrap().push(token);
+        self
+    }
+}
+
+impl IsBackend for Tokens {
+    type Error = DummyError;
+}
+
+impl IsTokenStorage for Tokens {
+    type Token = TokenData;
+
+    fn get_user_token(
+        &self,
+        user: UserId,
+        id: TokenId,
+    ) -> BoxFuture<Option<Self::Token>, Self::Error> {
+        Box::new(result(
+            self.tokens
+                .read()
+                .map(|tokens| {
+                    tokens
+                        .iter()
+                        .find(|data| data.base.user == user && data.base.id == id)
+                        .cloned()
+                }).map_err(|_| DummyError),
+        ))
+    }
+
+    fn put_user_token(&self, mut token: Self::Token) -> BoxFuture<Self::Token, Self::Error> {
+        Box::new(result(
+            self.tokens
+                .write()
+                .map(|mut tokens| {
+                    if let Some(index) = tokens.iter().position(|data| {
+                        data.base.user == token.base.user && data.base.id == token.base.id
+                    }) {
+                        tokens[index] = token.clone();
+                    } else {
+                        token.base.id = self.last_id.fetch_add(1, Ordering::SeqCst) as u32 + 1;
+                        tokens.push(token.clone());
+                    }
+                    token
+                }).map_err(|_| DummyError),
+        ))
+    }
+
+    fn del_user_token(&self, user: UserId, id: TokenId) -> BoxFuture<Option<()>, Self::Error> {
+        Box::new(result(
+            self.tokens
+                .write()
+                .map(|mut tokens| {
+                    if let Some(index) = tokens
+                        .iter()
+                        .position(|data| data.base.user == user && data.base.id == id)
+                    {
+                        tokens.remove(index);
+                        Some(())
+                    } else {
+                        None
+                    }
+                }).map_err(|_| DummyError),
+        ))
+    }
+
+    fn get_user_tokens(&self, user: UserId) -> BoxFuture<Vec<Self::Token>, Self::Error> {
+        Box::new(result(
+            self.tokens
+                .read()
+                .map(|tokens| {
+                    tokens
+                        .iter()
+                        .filter(|data| data.base.user == user)
+                        .cloned()
+                        .collect()
+                }).map_err(|_| DummyError),
+        ))
+    }
+}
+
+/// Dummy WebAuthn credentials backend
+#[cfg(feature = "webauthn_auth")]
+#[derive(Clone)]
+pub struct Credentials {
+    creds: Arc<RwLock<Vec<WebAuthnCredential>>>,
+}
+
+#[cfg(feature = "webauthn_auth")]
+impl Credentials {
+    /// Create credentials backend
+    pub fn new() -> Self {
+        Self {
+            creds: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+}
+
+#[cfg(feature = "webauthn_auth")]
+impl IsBackend for Credentials {
+    type Error = DummyError;
+}
+
+#[cfg(feature = "webauthn_auth")]
+impl IsWebAuthnStorage for Credentials {
+    fn get_user_credentials(
+        &self,
+        user: UserId,
+    ) -> BoxFuture<Vec<WebAuthnCredential>, Self::Error> {
+        Box::new(result(
+            self.creds
+                .read()
+                .map(|creds| {
+                    creds
+                        .iter()
+                        .filter(|cred| cred.user == user)
+                        .cloned()
+                        .collect()
+                }).map_err(|_| DummyError),
+        ))
+    }
+
+    fn put_user_credential(&self, cred: WebAuthnCredential) -> BoxFuture<(), Self::Error> {
+        Box::new(result(
+            self.creds
+                .write()
+                .map(|mut creds| {
+                    if let Some(index) = creds
+                        .iter()
+                        .position(|data| data.user == cred.user && data.id == cred.id)
+                    {
+                        creds[index] = cred;
+                    } else {
+                        creds.push(cred);
+                    }
+                }).map_err(|_| DummyError),
+        ))
+    }
+
+    fn del_user_credential(&self, user: UserId, id: &[u8]) -> BoxFuture<Option<()>, Self::Error> {
+        Box::new(result(
+            self.creds
+                .write()
+                .map(|mut creds| {
+                    if let Some(index) = creds
+                        .iter()
+                        .position(|data| data.user == user && data.id == id)
+                    {
+                        creds.swap_remove(index);
+                        Some(())
+                    } else {
+                        None
+                    }
+                }).map_err(|_| DummyError),
+        ))
+    }
 }
 
 pub struct UserAuth {
     pub user: UserId,
     pub sess: SessionId,
     pub name: String,
+    /// Effective grants of user on own resources
+    ///
+    /// It is restricted by scope in case of API token.
+    pub grants: Scoped<Vec<Grant>, Vec<Grant>>,
+}
+
+impl UserAuth {
+    /// The grants which user has on own resources
+    fn user_grants() -> Scoped<Vec<Grant>, Vec<Grant>> {
+        Scoped::new(vec![
+            Grant::Read,
+            Grant::Create,
+            Grant::Update,
+            Grant::Delete,
+            Grant::Manage,
+        ])
+    }
+
+    fn in_scope(&self, grant: &Grant) -> bool {
+        self.grants.has_perm(*grant)
+    }
+
+    fn is_session(&self) -> bool {
+        self.grants.scope.is_none()
+    }
+}
+
+impl IsAuditSubject for UserAuth {
+    fn audit_subject(&self) -> AuditSubject {
+        AuditSubject {
+            user: self.user,
+            sess: self.sess,
+        }
+    }
 }
 
 impl<S> IsUserAuth<S> for UserAuth
@@ -172,6 +462,7 @@ where
             user: user.get_user_id(),
             sess: session.session_data().sess,
             name: user.get_user_name().into(),
+            grants: Self::user_grants(),
         }
     }
 
@@ -180,6 +471,25 @@ where
             user: 0,
             sess: 0,
             name: "guest".into(),
+            grants: Self::user_grants(),
+        })
+    }
+}
+
+impl<S> IsTokenAuth<S> for UserAuth
+where
+    S: HasTokenStorage + HasUserStorage,
+    <S::TokenStorage as IsTokenStorage>::Token: IsTokenData<Scope = Vec<Grant>>,
+{
+    fn new_token_auth(
+        token: &<<S as HasTokenStorage>::TokenStorage as IsTokenStorage>::Token,
+        user: &<<S as HasUserStorage>::UserStorage as IsUserStorage>::User,
+    ) -> Option<Self> {
+        Some(UserAuth {
+            user: user.get_user_id(),
+            sess: 0,
+            name: user.get_user_name().into(),
+            grants: Self::user_grants().with_scope(token.token_scope().clone()),
         })
     }
 }
@@ -196,7 +506,57 @@ impl HasAccess<SessionArg, Grant> for UserAuth {
     fn has_access_to(&self, session: &SessionArg, grant: &Grant) -> bool {
         match grant {
             // Only owner can read and drop sessions
-            Grant::Read | Grant::Delete => self.user == session.user,
+            Grant::Read | Grant::Delete => self.user == session.user && self.in_scope(grant),
+            _ => false,
+        }
+    }
+}
+
+impl HasAccess<TokenArg, Grant> for UserAuth {
+    fn has_access_to(&self, token: &TokenArg, grant: &Grant) -> bool {
+        match grant {
+            // Only owner can manage tokens using interactive session
+            Grant::Create | Grant::Read | Grant::Delete => {
+                self.user == token.user && self.is_session()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl HasAccess<TotpArg, Grant> for UserAuth {
+    fn has_access_to(&self, totp: &TotpArg, grant: &Grant) -> bool {
+        match grant {
+            // Only owner can manage second factor using interactive session
+            Grant::Create | Grant::Read | Grant::Update | Grant::Delete => {
+                self.user == totp.user && self.is_session()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "webauthn_auth")]
+impl HasAccess<WebAuthnArg, Grant> for UserAuth {
+    fn has_access_to(&self, webauthn: &WebAuthnArg, grant: &Grant) -> bool {
+        match grant {
+            // Only owner can manage credentials using interactive session
+            Grant::Create | Grant::Read | Grant::Update | Grant::Delete => {
+                self.user == webauthn.user && self.is_session()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "oauth2_auth")]
+impl HasAccess<OAuth2Arg, Grant> for UserAuth {
+    fn has_access_to(&self, accounts: &OAuth2Arg, grant: &Grant) -> bool {
+        match grant {
+            // Only owner can manage linked accounts using interactive session
+            Grant::Create | Grant::Read | Grant::Delete => {
+                self.user == accounts.user && self.is_session()
+            }
             _ => false,
         }
     }
@@ -205,7 +565,17 @@ impl HasAccess<SessionArg, Grant> for UserAuth {
 impl HasAccess<UserArg, Grant> for UserAuth {
     fn has_access_to(&self, user: &UserArg, grant: &Grant) -> bool {
         match grant {
-            Grant::Read | Grant::Update => self.user == user.user,
+            Grant::Read | Grant::Update => self.user == user.user && self.in_scope(grant),
+            _ => false,
+        }
+    }
+}
+
+impl HasAccess<AuditQuery, Grant> for UserAuth {
+    fn has_access_to(&self, query: &AuditQuery, grant: &Grant) -> bool {
+        match grant {
+            // Only owner can read own audit records
+            Grant::Read => query.user == Some(self.user) && self.in_scope(grant),
             _ => false,
         }
     }
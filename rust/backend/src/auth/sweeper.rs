@@ -0,0 +1,30 @@
+use super::{HasSessionOptions, HasSessionStorage, IsSessionStorage, SessionOptions};
+use base::TimeStamp;
+use futures::{Future, Stream};
+use tokio::timer::Interval;
+
+/// Run expired sessions sweeper
+///
+/// This task periodically deletes sessions which outlived idle time or life time.
+pub fn session_sweeper<S>(state: &S) -> impl Future<Item = (), Error = ()> + Send + 'static
+where
+    S: HasSessionStorage + HasSessionOptions + Send + Sync + Clone + 'static,
+{
+    let state = state.clone();
+    let poll_time = (state.as_ref() as &SessionOptions).poll_time;
+    Interval::new_interval(poll_time.into())
+        .map_err(|error| {
+            error!("Timer error: {}", error);
+        }).for_each(move |_| {
+            let options = state.as_ref() as &SessionOptions;
+            let curr_time = TimeStamp::now();
+            (state.as_ref() as &S::SessionStorage)
+                .del_outdated_sessions(curr_time - options.idle_time, curr_time - options.life_time)
+                .then(|res| {
+                    if let Err(error) = res {
+                        error!("Unable to delete outdated sessions: {}", error);
+                    }
+                    Ok(())
+                })
+        })
+}
@@ -0,0 +1,174 @@
+use sha1::{Digest, Sha1};
+
+const HMAC_BLOCK_SIZE: usize = 64;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+fn hmac_sha1(key: &[u8], msg: &[u8]) -> Vec<u8> {
+    let mut block = [0u8; HMAC_BLOCK_SIZE];
+
+    if key.len() > HMAC_BLOCK_SIZE {
+        let hash = Sha1::digest(key);
+        block[..hash.len()].copy_from_slice(&hash);
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let ipad: Vec<u8> = block.iter().map(|byte| byte ^ 0x36).collect();
+    let opad: Vec<u8> = block.iter().map(|byte| byte ^ 0x5c).collect();
+
+    let mut inner = Sha1::default();
+    inner.input(&ipad);
+    inner.input(msg);
+
+    let mut outer = Sha1::default();
+    outer.input(&opad);
+    outer.input(&inner.result());
+
+    outer.result().to_vec()
+}
+
+/// Generate HMAC-based one-time password (RFC 4226)
+///
+/// ```
+/// use literium::auth::totp::hotp_code;
+///
+/// let secret = b"12345678901234567890";
+///
+/// assert_eq!(hotp_code(secret, 0, 6), 755224);
+/// assert_eq!(hotp_code(secret, 9, 6), 520489);
+/// // time step of 59 seconds with 30 seconds period (RFC 6238)
+/// assert_eq!(hotp_code(secret, 1, 8), 94287082);
+/// ```
+pub fn hotp_code(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut msg = [0u8; 8];
+    for (index, byte) in msg.iter_mut().enumerate() {
+        *byte = (counter >> ((7 - index) * 8)) as u8;
+    }
+
+    let hash = hmac_sha1(secret, &msg);
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+
+    let code = (u32::from(hash[offset]) & 0x7f) << 24
+        | u32::from(hash[offset + 1]) << 16
+        | u32::from(hash[offset + 2]) << 8
+        | u32::from(hash[offset + 3]);
+
+    code % 10u32.pow(digits.min(9))
+}
+
+/// Format one-time password with leading zeros
+pub fn format_code(code: u32, digits: u32) -> String {
+    format!("{:01$}", code, digits as usize)
+}
+
+/// Encode binary data using base32 alphabet without padding (RFC 4648)
+///
+/// ```
+/// use literium::auth::totp::base32_encode;
+///
+/// assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+/// assert_eq!(base32_encode(b"12345678901234567890"), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+/// ```
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in data {
+        buffer = (buffer << 8 | u32::from(*byte)) & 0xffff;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[(buffer >> bits & 0x1f) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[(buffer << (5 - bits) & 0x1f) as usize] as char);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use auth::totp::TotpOptions;
+    use base::TimeStamp;
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn hmac() {
+        // RFC 2202
+        assert_eq!(
+            hex(&hmac_sha1(&[0x0b; 20], b"Hi There")),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+        assert_eq!(
+            hex(&hmac_sha1(b"Jefe", b"what do ya want for nothing?")),
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        );
+        assert_eq!(
+            hex(&hmac_sha1(
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "aa4ae5e15272d00e95705637ce8a3b55ed402112"
+        );
+    }
+
+    #[test]
+    fn hotp() {
+        // RFC 4226 Appendix D
+        let secret = b"12345678901234567890";
+        let codes = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+
+        for (counter, code) in codes.iter().enumerate() {
+            assert_eq!(hotp_code(secret, counter as u64, 6), *code);
+        }
+    }
+
+    #[test]
+    fn totp() {
+        // RFC 6238 Appendix B (SHA1)
+        let secret = b"12345678901234567890";
+        let options = TotpOptions::default();
+        let codes = [
+            (59i64, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+
+        for (time, code) in codes.iter() {
+            let step = options.time_step(TimeStamp::from(time * 1000));
+            assert_eq!(format_code(hotp_code(secret, step, 8), 8), *code);
+        }
+    }
+
+    #[test]
+    fn base32() {
+        // RFC 4648 without padding
+        let vectors = [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+
+        for (data, encoded) in vectors.iter() {
+            assert_eq!(base32_encode(data.as_bytes()), *encoded);
+        }
+    }
+}
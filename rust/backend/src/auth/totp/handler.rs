@@ -0,0 +1,610 @@
+use super::{
+    base32_encode, totp_throttle_key, HasTotpData, HasTotpOptions, TotpArg, TotpConfirm, TotpData,
+    TotpEnroll, TotpIdent, TotpInfo, TotpOptions, TotpRecovery,
+};
+use access::{audit_access_to, Grant, HasAccess, HasAuditSink};
+use auth::{
+    cancel_throttle_attempt, del_throttle_counter, reserve_throttle_attempt, AuthError,
+    HasSessionOptions, HasSessionStorage, HasThrottleStorage, HasTokenStorage, HasUserAuth,
+    IsTokenAuth,
+};
+use base::TimeStamp;
+use crypto::HasSecretKey;
+use filters::{x_client_addr, HasClientAddrOptions};
+use futures::{
+    future::{err, Either},
+    Future,
+};
+use user::{HasUserStorage, IsUserData, IsUserStorage, UserId};
+use warp::{Filter, Rejection, Reply};
+use x_auth;
+
+/// Handle get user TOTP info
+pub fn get_user_totp<S>(state: &S) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<TotpArg, Grant> + IsTokenAuth<S>,
+    <S::UserStorage as IsUserStorage>::User: HasTotpData,
+{
+    let state = state.clone();
+
+    warp::get2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("totp"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &TotpArg { user }, &Grant::Read)
+                    .map(move |_| user)
+            }
+        }).and_then(move |user| {
+            (state.as_ref() as &S::UserStorage)
+                .get_user_data(user)
+                .map_err(|error| {
+                    error!("Unable to get user data: {}", error);
+                    warp::reject::custom(AuthError::BackendError)
+                }).and_then(|user| {
+                    user.map(|user| warp::reply::json(&TotpInfo::from(user.get_totp_data())))
+                        .ok_or_else(|| warp::reject::custom(AuthError::BadUser))
+                })
+        }).recover(AuthError::recover)
+}
+
+/// Handle start TOTP enrollment
+///
+/// The new secret will be generated but not enabled until confirmation.
+pub fn add_user_totp<S>(state: &S) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasTotpOptions
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<TotpArg, Grant> + IsTokenAuth<S>,
+    <S::UserStorage as IsUserStorage>::User: HasTotpData,
+{
+    let state = state.clone();
+
+    warp::post2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("totp"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &TotpArg { user }, &Grant::Create)
+                    .map(move |_| user)
+            }
+        }).and_then(move |user| {
+            let options = (state.as_ref() as &TotpOptions).clone();
+            update_user_totp(&state, user, move |user| {
+                if user.has_totp_enabled() {
+                    warn!("TOTP already enabled");
+                    return Err(AuthError::Restricted);
+                }
+                let data = TotpData::new(&options);
+                let enroll = TotpEnroll {
+                    secret: base32_encode(&data.secret),
+                    uri: data.key_uri(&options, user.get_user_name()),
+                };
+                user.set_totp_data(Some(data));
+                Ok(enroll)
+            }).map(|enroll| warp::reply::json(&enroll))
+            .map_err(warp::reject::custom)
+        }).recover(AuthError::recover)
+}
+
+/// Handle confirm TOTP enrollment
+///
+/// The second factor will be enabled and new recovery codes will be generated.
+/// The wrong codes is throttled like second factor on auth.
+pub fn put_user_totp<S>(state: &S) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasTotpOptions
+        + HasThrottleStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<TotpArg, Grant> + IsTokenAuth<S>,
+    <S::UserStorage as IsUserStorage>::User: HasTotpData,
+{
+    let state = state.clone();
+
+    warp::put2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("totp"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &TotpArg { user }, &Grant::Update)
+                    .map(move |_| user)
+            }
+        }).and(warp::body::json())
+        .and_then(move |user, req: TotpConfirm| {
+            let options = (state.as_ref() as &TotpOptions).clone();
+            verify_user_totp(&state, user, move |user| {
+                let mut data = user.get_totp_data().cloned().ok_or(AuthError::BadIdent)?;
+                if !data.verify_code(&options, &req.code, TimeStamp::now()) {
+                    warn!("Invalid TOTP code");
+                    return Err(AuthError::BadIdent);
+                }
+                data.enabled = true;
+                let codes = data.gen_recovery_codes(&options);
+                user.set_totp_data(Some(data));
+                Ok(TotpRecovery { codes })
+            }).map(|recovery| warp::reply::json(&recovery))
+            .map_err(warp::reject::custom)
+        }).recover(AuthError::recover)
+}
+
+/// Handle disable TOTP
+///
+/// The enabled second factor requires current code or recovery code
+/// so the stolen session alone is not enough to disable it.
+/// The wrong codes is throttled like second factor on auth.
+pub fn del_user_totp<S>(state: &S) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasTotpOptions
+        + HasThrottleStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<TotpArg, Grant> + IsTokenAuth<S>,
+    <S::UserStorage as IsUserStorage>::User: HasTotpData,
+{
+    let state = state.clone();
+
+    warp::delete2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("totp"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &TotpArg { user }, &Grant::Delete)
+                    .map(move |_| user)
+            }
+        }).and(warp::body::json())
+        .and_then(move |user, req: TotpIdent| {
+            let options = (state.as_ref() as &TotpOptions).clone();
+            verify_user_totp(&state, user, move |user| {
+                if let Some(mut data) = user.get_totp_data().filter(|data| data.enabled).cloned() {
+                    let valid = match &req {
+                        TotpIdent::Totp { code } => {
+                            data.verify_code(&options, code, TimeStamp::now())
+                        }
+                        TotpIdent::Recovery { code } => data.use_recovery_code(code),
+                    };
+                    if !valid {
+                        warn!("Invalid second factor");
+                        return Err(AuthError::BadIdent);
+                    }
+                }
+                user.set_totp_data(None);
+                Ok(())
+            }).map(|_| warp::reply())
+            .map_err(warp::reject::custom)
+        }).recover(AuthError::recover)
+}
+
+/// Update user TOTP data which requires second factor check
+///
+/// The failed checks is throttled using the same counter as second factor on auth.
+fn verify_user_totp<S, F, R>(
+    state: &S,
+    user: UserId,
+    update: F,
+) -> impl Future<Item = R, Error = AuthError>
+where
+    S: HasUserStorage + HasThrottleStorage + HasTotpOptions + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasTotpData,
+    F: FnOnce(&mut <S::UserStorage as IsUserStorage>::User) -> Result<R, AuthError>,
+{
+    let state = state.clone();
+    let options = (state.as_ref() as &TotpOptions).throttle_options();
+    let keys = vec![totp_throttle_key(user)];
+
+    reserve_throttle_attempt(&state, &options, &keys)
+        .and_then({
+            let state = state.clone();
+            move |_| update_user_totp(&state, user, update).then(Ok)
+        }).and_then(move |res| match res {
+            // the failure is already counted
+            Err(AuthError::BadIdent) => Either::A(err(AuthError::BadIdent)),
+            Ok(res) => Either::B(Either::A(
+                del_throttle_counter(&state, &keys[0]).map(move |_| res),
+            )),
+            Err(error) => Either::B(Either::B(
+                cancel_throttle_attempt(&state, &keys).and_then(move |_| err(error)),
+            )),
+        })
+}
+
+fn update_user_totp<S, F, R>(
+    state: &S,
+    user: UserId,
+    update: F,
+) -> impl Future<Item = R, Error = AuthError>
+where
+    S: HasUserStorage + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasTotpData,
+    F: FnOnce(&mut <S::UserStorage as IsUserStorage>::User) -> Result<R, AuthError>,
+{
+    let state = state.clone();
+
+    (state.as_ref() as &S::UserStorage)
+        .get_user_data(user)
+        .map_err(|error| {
+            error!("Unable to get user data: {}", error);
+            AuthError::BackendError
+        }).and_then(move |user| {
+            let mut user = match user {
+                Some(user) => user,
+                None => return Either::A(err(AuthError::BadUser)),
+            };
+            let res = match update(&mut user) {
+                Ok(res) => res,
+                Err(error) => return Either::A(err(error)),
+            };
+            Either::B(
+                (state.as_ref() as &S::UserStorage)
+                    .put_user_data(user)
+                    .map_err(|error| {
+                        error!("Unable to put user data: {}", error);
+                        AuthError::BackendError
+                    }).map(move |_| res),
+            )
+        })
+}
+
+/// Scope with user TOTP handlers
+pub fn totp_scope<S>(state: &S) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasUserStorage
+        + HasSessionStorage
+        + HasTotpOptions
+        + HasThrottleStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<TotpArg, Grant> + IsTokenAuth<S>,
+    <S::UserStorage as IsUserStorage>::User: HasTotpData,
+{
+    get_user_totp(state)
+        .or(add_user_totp(state))
+        .or(put_user_totp(state))
+        .or(del_user_totp(state))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use access::{HasAuditSink, MemoryAudit};
+    use auth::{
+        stub::{SessionData, Sessions, Tokens, UserAuth},
+        totp::{format_code, hotp_code},
+        AuthData, HasSessionOptions, HasTokenStorage, IsSessionStorage, IsThrottleStorage,
+        SessionOptions, ThrottleCache,
+    };
+    use crypto::{CanEncrypt, CryptoKeys, PublicKey};
+    use filters::ClientAddrOptions;
+    use httplib::StatusCode;
+    use serde_json::from_slice;
+    use std::sync::Arc;
+    use user::stub::{UserData, Users};
+    use warp::test::request;
+
+    #[derive(Clone)]
+    struct State {
+        keys: Arc<CryptoKeys>,
+        users: Users,
+        sessions: Sessions,
+        session_options: Arc<SessionOptions>,
+        tokens: Tokens,
+        audit: MemoryAudit,
+        totp_options: Arc<TotpOptions>,
+        client_addr: Arc<ClientAddrOptions>,
+        throttle: ThrottleCache,
+    }
+
+    impl AsRef<ClientAddrOptions> for State {
+        fn as_ref(&self) -> &ClientAddrOptions {
+            &self.client_addr
+        }
+    }
+
+    impl HasClientAddrOptions for State {}
+
+    impl AsRef<CryptoKeys> for State {
+        fn as_ref(&self) -> &CryptoKeys {
+            &self.keys
+        }
+    }
+
+    impl HasSecretKey for State {
+        type SecretKey = CryptoKeys;
+    }
+
+    impl AsRef<Users> for State {
+        fn as_ref(&self) -> &Users {
+            &self.users
+        }
+    }
+
+    impl HasUserStorage for State {
+        type UserStorage = Users;
+    }
+
+    impl AsRef<Sessions> for State {
+        fn as_ref(&self) -> &Sessions {
+            &self.sessions
+        }
+    }
+
+    impl HasSessionStorage for State {
+        type SessionStorage = Sessions;
+    }
+
+    impl AsRef<SessionOptions> for State {
+        fn as_ref(&self) -> &SessionOptions {
+            &self.session_options
+        }
+    }
+
+    impl HasSessionOptions for State {}
+
+    impl AsRef<Tokens> for State {
+        fn as_ref(&self) -> &Tokens {
+            &self.tokens
+        }
+    }
+
+    impl HasTokenStorage for State {
+        type TokenStorage = Tokens;
+    }
+
+    impl AsRef<MemoryAudit> for State {
+        fn as_ref(&self) -> &MemoryAudit {
+            &self.audit
+        }
+    }
+
+    impl HasAuditSink for State {
+        type AuditSink = MemoryAudit;
+    }
+
+    impl HasUserAuth for State {
+        type UserAuth = UserAuth;
+    }
+
+    impl AsRef<TotpOptions> for State {
+        fn as_ref(&self) -> &TotpOptions {
+            &self.totp_options
+        }
+    }
+
+    impl HasTotpOptions for State {}
+
+    impl AsRef<ThrottleCache> for State {
+        fn as_ref(&self) -> &ThrottleCache {
+            &self.throttle
+        }
+    }
+
+    impl HasThrottleStorage for State {
+        type ThrottleStorage = ThrottleCache;
+    }
+
+    #[test]
+    fn enroll_and_disable() {
+        let client_keys = CryptoKeys::default();
+        let sessions = Sessions::new();
+        let session = sessions
+            .put_user_session(SessionData::new(
+                1,
+                (client_keys.as_ref() as &PublicKey).clone(),
+            )).wait()
+            .unwrap();
+
+        let state = State {
+            keys: Arc::new(CryptoKeys::default()),
+            users: Users::new().with_user(UserData::new(1, "yumi")),
+            sessions,
+            session_options: Arc::new(SessionOptions::default()),
+            client_addr: Arc::new(ClientAddrOptions::default()),
+            tokens: Tokens::new(),
+            audit: MemoryAudit::new(),
+            totp_options: Arc::new(TotpOptions::default()),
+            throttle: ThrottleCache::new(),
+        };
+
+        let mut serno = session.serno;
+        let mut auth_header = || {
+            let auth = AuthData {
+                user: session.user,
+                sess: session.sess,
+                token: session.token.clone(),
+                serno,
+            };
+            serno += 1;
+            state.keys.seal_json_b64(&auth).unwrap()
+        };
+
+        let app = totp_scope(&state);
+
+        let totp_data = || {
+            state
+                .users
+                .get_user_data(1)
+                .wait()
+                .unwrap()
+                .unwrap()
+                .totp
+                .unwrap()
+        };
+        let current_code = || {
+            let step = state.totp_options.time_step(TimeStamp::now());
+            format_code(hotp_code(&totp_data().secret, step, 6), 6)
+        };
+
+        // enroll
+        let res = request()
+            .method("POST")
+            .path("/1/totp")
+            .header("x-auth", auth_header())
+            .reply(&app);
+        assert_eq!(res.status(), StatusCode::OK);
+        let enroll: TotpEnroll = from_slice(res.body()).unwrap();
+        assert_eq!(enroll.secret, base32_encode(&totp_data().secret));
+        assert!(!totp_data().enabled);
+
+        // confirm with invalid code
+        let res = request()
+            .method("PUT")
+            .path("/1/totp")
+            .header("x-auth", auth_header())
+            .json(&TotpConfirm {
+                code: "000000".into(),
+            }).reply(&app);
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(!totp_data().enabled);
+
+        // confirm
+        let res = request()
+            .method("PUT")
+            .path("/1/totp")
+            .header("x-auth", auth_header())
+            .json(&TotpConfirm {
+                code: current_code(),
+            }).reply(&app);
+        assert_eq!(res.status(), StatusCode::OK);
+        let recovery: TotpRecovery = from_slice(res.body()).unwrap();
+        assert_eq!(recovery.codes.len(), 10);
+        assert!(totp_data().enabled);
+
+        let res = request()
+            .method("GET")
+            .path("/1/totp")
+            .header("x-auth", auth_header())
+            .reply(&app);
+        let info: TotpInfo = from_slice(res.body()).unwrap();
+        assert!(info.enabled);
+        assert_eq!(info.recovery, 10);
+
+        // enrollment is not allowed when enabled
+        let res = request()
+            .method("POST")
+            .path("/1/totp")
+            .header("x-auth", auth_header())
+            .reply(&app);
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // recovery code can be used only once
+        let mut data = totp_data();
+        assert!(data.use_recovery_code(&recovery.codes[3]));
+        assert!(!data.use_recovery_code(&recovery.codes[3]));
+        assert_eq!(data.recovery.len(), 9);
+
+        // disable without code
+        let res = request()
+            .method("DELETE")
+            .path("/1/totp")
+            .header("x-auth", auth_header())
+            .reply(&app);
+        assert!(!res.status().is_success());
+        assert!(totp_data().enabled);
+
+        // disable with invalid code
+        let res = request()
+            .method("DELETE")
+            .path("/1/totp")
+            .header("x-auth", auth_header())
+            .json(&TotpIdent::Recovery {
+                code: "0123456789".into(),
+            }).reply(&app);
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(totp_data().enabled);
+
+        // invalid codes is throttled
+        let mut disable = |code: &str| {
+            request()
+                .method("DELETE")
+                .path("/1/totp")
+                .header("x-auth", auth_header())
+                .json(&TotpIdent::Recovery { code: code.into() })
+                .reply(&app)
+                .status()
+        };
+        for _ in 1..state.totp_options.max_failures {
+            assert_eq!(disable("0123456789"), StatusCode::FORBIDDEN);
+        }
+        assert_eq!(disable(&recovery.codes[0]), StatusCode::TOO_MANY_REQUESTS);
+        assert!(totp_data().enabled);
+        state.throttle.del_counter("totp:1").wait().unwrap();
+
+        // disable with recovery code
+        let res = request()
+            .method("DELETE")
+            .path("/1/totp")
+            .header("x-auth", auth_header())
+            .json(&TotpIdent::Recovery {
+                code: recovery.codes[0].clone(),
+            }).reply(&app);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(
+            state
+                .users
+                .get_user_data(1)
+                .wait()
+                .unwrap()
+                .unwrap()
+                .totp
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,37 @@
+/*!
+
+### TOTP second factor
+
+This module implements time-based one-time passwords ([RFC 6238](https://tools.ietf.org/html/rfc6238))
+which is compatible with common authenticator apps.
+
+#### Enrollment
+
+1. Authorized client requests new secret (*POST /:user/totp*) and gets [`TotpEnroll`](auth::totp::TotpEnroll) with base32 secret and `otpauth://` key URI
+2. User adds key to authenticator app (usually by scanning QR-code of key URI)
+3. Client confirms enrollment by sending current code (*PUT /:user/totp* with [`TotpConfirm`](auth::totp::TotpConfirm))
+4. Server enables second factor and returns recovery codes ([`TotpRecovery`](auth::totp::TotpRecovery)) which is shown only once
+
+Next confirmation regenerates recovery codes. Second factor can be disabled using *DELETE /:user/totp*
+with current code or recovery code ([`TotpIdent`](auth::totp::TotpIdent)).
+
+#### Authorization
+
+When user has second factor enabled the auth request ends with [`FactorRequired`](auth::FactorRequired) instead of [`AuthResponse`](auth::AuthResponse).
+The client should send [`FactorRequest`](auth::FactorRequest) with received ticket and code from authenticator app or recovery code
+to *POST /factor* and gets [`AuthResponse`](auth::AuthResponse) as usual.
+
+The failed second factor checks is counted per user (see [`TotpOptions::throttle_options`](auth::totp::TotpOptions::throttle_options)),
+so the same limit applies to auth, confirmation and disabling.
+
+*/
+
+mod code;
+mod handler;
+mod traits;
+mod types;
+
+pub use self::code::*;
+pub use self::handler::*;
+pub use self::traits::*;
+pub use self::types::*;
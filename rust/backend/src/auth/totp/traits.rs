@@ -0,0 +1,21 @@
+use super::{TotpData, TotpOptions};
+
+/// User data which has TOTP second factor
+pub trait HasTotpData {
+    fn get_totp_data(&self) -> Option<&TotpData>;
+    fn set_totp_data(&mut self, new: Option<TotpData>);
+
+    /// Check that second factor is enabled
+    fn has_totp_enabled(&self) -> bool {
+        self.get_totp_data()
+            .map(|data| data.enabled)
+            .unwrap_or(false)
+    }
+}
+
+/// State has TOTP options
+pub trait HasTotpOptions
+where
+    Self: AsRef<TotpOptions>,
+{
+}
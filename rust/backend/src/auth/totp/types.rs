@@ -0,0 +1,301 @@
+use super::{base32_encode, format_code, hotp_code};
+use auth::ThrottleOptions;
+use base::{serde_extra::base64, TimeStamp};
+use crypto::random_bytes;
+use sodiumoxide::{crypto::hash::sha256, utils::memcmp};
+use url::Url;
+use user::{gen_password, UserId, ARABIC_NUMBERS_AND_LATIN_LOWER_LETTERS};
+
+/// TOTP arguments (or predicate)
+#[derive(Debug)]
+pub struct TotpArg {
+    /// Second factor owner
+    pub user: UserId,
+}
+
+/// TOTP second factor options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpOptions {
+    /// Issuer name which authenticator apps shows
+    #[serde(default = "default_issuer")]
+    pub issuer: String,
+    /// Number of code digits
+    #[serde(default = "default_digits")]
+    pub digits: u32,
+    /// Time step in milliseconds
+    #[serde(default = "default_period")]
+    pub period: TimeStamp,
+    /// Number of time steps ahead or behind of current which will be accepted
+    #[serde(default = "default_drift")]
+    pub drift: u32,
+    /// Secret size in bytes
+    #[serde(default = "default_secret_size")]
+    pub secret_size: usize,
+    /// Number of recovery codes
+    #[serde(default = "default_recovery_codes")]
+    pub recovery_codes: usize,
+    /// Second factor ticket life time in milliseconds
+    ///
+    /// The client should pass second factor during this interval after first factor.
+    #[serde(default = "default_ticket_time")]
+    pub ticket_time: TimeStamp,
+    /// Number of allowed failures per ticket life time
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+}
+
+fn default_issuer() -> String {
+    "Literium".into()
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_period() -> TimeStamp {
+    TimeStamp::default().with_secs(30)
+}
+
+fn default_drift() -> u32 {
+    1
+}
+
+fn default_secret_size() -> usize {
+    20
+}
+
+fn default_recovery_codes() -> usize {
+    10
+}
+
+fn default_ticket_time() -> TimeStamp {
+    TimeStamp::default().with_mins(5)
+}
+
+fn default_max_failures() -> u32 {
+    5
+}
+
+impl Default for TotpOptions {
+    fn default() -> Self {
+        Self {
+            issuer: default_issuer(),
+            digits: default_digits(),
+            period: default_period(),
+            drift: default_drift(),
+            secret_size: default_secret_size(),
+            recovery_codes: default_recovery_codes(),
+            ticket_time: default_ticket_time(),
+            max_failures: default_max_failures(),
+        }
+    }
+}
+
+impl TotpOptions {
+    /// Get time step number
+    pub fn time_step(&self, time: TimeStamp) -> u64 {
+        let time: i64 = time.into();
+        let period: i64 = self.period.into();
+        (time / period.max(1)).max(0) as u64
+    }
+
+    /// Get throttling options of second factor checks
+    ///
+    /// The checks is locked during ticket life time after `max_failures` failures.
+    pub fn throttle_options(&self) -> ThrottleOptions {
+        ThrottleOptions {
+            max_failures: self.max_failures.saturating_sub(1),
+            base_delay: self.ticket_time,
+            max_delay: self.ticket_time,
+            reset_time: self.ticket_time,
+        }
+    }
+}
+
+/// Get throttle counter key of second factor checks
+///
+/// The same counter is used on auth and on changing second factor.
+pub fn totp_throttle_key(user: UserId) -> String {
+    format!("totp:{}", user)
+}
+
+/// User TOTP data
+///
+/// The data which stored with user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpData {
+    /// Shared secret
+    #[serde(with = "base64")]
+    pub secret: Vec<u8>,
+
+    /// Second factor is confirmed and enabled
+    pub enabled: bool,
+
+    /// Last accepted time step
+    ///
+    /// Codes of this and earlier steps will be rejected.
+    #[serde(default)]
+    pub last: u64,
+
+    /// Hashes of unused recovery codes
+    #[serde(default)]
+    pub recovery: Vec<Vec<u8>>,
+}
+
+impl TotpData {
+    /// Create new not yet enabled data with random secret
+    pub fn new(options: &TotpOptions) -> Self {
+        Self {
+            secret: random_bytes(options.secret_size),
+            enabled: false,
+            last: 0,
+            recovery: Vec::new(),
+        }
+    }
+
+    /// Get key URI for authenticator apps
+    ///
+    /// See [Key Uri Format](https://github.com/google/google-authenticator/wiki/Key-Uri-Format).
+    pub fn key_uri(&self, options: &TotpOptions, account: &str) -> String {
+        let period: i64 = options.period.into();
+        let mut uri = Url::parse("otpauth://totp/").unwrap();
+        uri.set_path(&format!("{}:{}", options.issuer, account));
+        uri.query_pairs_mut()
+            .append_pair("secret", &base32_encode(&self.secret))
+            .append_pair("issuer", &options.issuer)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &options.digits.to_string())
+            .append_pair("period", &(period / 1000).to_string());
+        uri.to_string()
+    }
+
+    /// Verify code within drift window
+    ///
+    /// The accepted time step will be remembered to prevent code reuse.
+    ///
+    /// ```
+    /// use literium::auth::totp::{format_code, hotp_code, TotpData, TotpOptions};
+    /// use literium::base::TimeStamp;
+    ///
+    /// let options = TotpOptions::default();
+    /// let mut data = TotpData::new(&options);
+    /// let time = TimeStamp::from(1_000_000_000i64);
+    /// let step = options.time_step(time);
+    /// let code = |step| format_code(hotp_code(&data.secret, step, 6), 6);
+    /// let (prev, next) = (code(step - 1), code(step + 1));
+    ///
+    /// assert_eq!(data.verify_code(&options, &prev, time), true);
+    /// // reused code
+    /// assert_eq!(data.verify_code(&options, &prev, time), false);
+    /// assert_eq!(data.verify_code(&options, &next, time), true);
+    /// ```
+    pub fn verify_code(&mut self, options: &TotpOptions, code: &str, time: TimeStamp) -> bool {
+        let step = options.time_step(time);
+        let drift = u64::from(options.drift);
+        let code = code.trim();
+
+        for step in step.saturating_sub(drift)..=step + drift {
+            if step <= self.last {
+                continue;
+            }
+            let valid = format_code(
+                hotp_code(&self.secret, step, options.digits),
+                options.digits,
+            );
+            if memcmp(valid.as_bytes(), code.as_bytes()) {
+                self.last = step;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Generate new recovery codes
+    ///
+    /// The previous codes will be replaced.
+    /// Only hashes of codes will be stored.
+    /// The codes is random enough, so fast hash is used instead of password hash.
+    pub fn gen_recovery_codes(&mut self, options: &TotpOptions) -> Vec<String> {
+        let codes: Vec<String> = (0..options.recovery_codes)
+            .map(|_| gen_password(10, &ARABIC_NUMBERS_AND_LATIN_LOWER_LETTERS))
+            .collect();
+        self.recovery = codes.iter().map(|code| recovery_hash(code)).collect();
+        codes
+    }
+
+    /// Verify and consume recovery code
+    pub fn use_recovery_code(&mut self, code: &str) -> bool {
+        let hash = recovery_hash(code.trim());
+        if let Some(index) = self
+            .recovery
+            .iter()
+            .position(|other| memcmp(&hash, other))
+        {
+            self.recovery.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn recovery_hash(code: &str) -> Vec<u8> {
+    sha256::hash(code.as_bytes()).as_ref().to_vec()
+}
+
+/// TOTP second factor info
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpInfo {
+    /// Second factor is enabled
+    pub enabled: bool,
+
+    /// Number of unused recovery codes
+    pub recovery: usize,
+}
+
+impl<'a> From<Option<&'a TotpData>> for TotpInfo {
+    fn from(data: Option<&'a TotpData>) -> Self {
+        Self {
+            enabled: data.map(|data| data.enabled).unwrap_or(false),
+            recovery: data.map(|data| data.recovery.len()).unwrap_or(0),
+        }
+    }
+}
+
+/// TOTP enrollment response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpEnroll {
+    /// Shared secret in base32 encoding
+    pub secret: String,
+
+    /// Key URI (`otpauth://`) for authenticator apps
+    pub uri: String,
+}
+
+/// TOTP confirmation request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpConfirm {
+    /// Current code from authenticator app
+    pub code: String,
+}
+
+/// TOTP recovery codes response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpRecovery {
+    /// Recovery codes
+    ///
+    /// Each code can be used only once instead of authenticator app code.
+    pub codes: Vec<String>,
+}
+
+/// TOTP second factor user identification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TotpIdent {
+    /// Code from authenticator app
+    #[serde(rename = "totp")]
+    Totp { code: String },
+    /// Recovery code
+    #[serde(rename = "recovery")]
+    Recovery { code: String },
+}
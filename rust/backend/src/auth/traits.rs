@@ -1,5 +1,7 @@
-use super::{BaseSessionData, SessionId};
+use super::{BaseSessionData, BaseTokenData, SessionId, SessionOptions, TokenId};
+use access::IsAuditSubject;
 use base::{BoxFuture, IsBackend, TimeStamp};
+use serde::{de::DeserializeOwned, Serialize};
 use user::{HasUserStorage, IsUserStorage, UserId};
 
 /// Access to session data
@@ -43,6 +45,17 @@ pub trait IsSessionStorage: IsBackend {
     /// Save modified user session data
     fn put_user_session(&self, session: Self::Session) -> BoxFuture<Self::Session, Self::Error>;
 
+    /// Save modified user session data atomically
+    ///
+    /// The session should be stored only when stored session has same request serials state
+    /// ([`serno`](BaseSessionData::serno) and [`seen`](BaseSessionData::seen)) as `prev`.
+    /// Returns `None` when stored session was modified concurrently.
+    fn cas_user_session(
+        &self,
+        prev: &BaseSessionData,
+        session: Self::Session,
+    ) -> BoxFuture<Option<Self::Session>, Self::Error>;
+
     /// Delete user session by identifier
     fn del_user_session(
         &self,
@@ -55,6 +68,15 @@ pub trait IsSessionStorage: IsBackend {
 
     /// Drop all user sessions
     fn del_user_sessions(&self, user: UserId) -> BoxFuture<(), Self::Error>;
+
+    /// Drop all expired sessions
+    ///
+    /// The sessions which last accessed before `atime` or created before `ctime` should be deleted.
+    fn del_outdated_sessions(
+        &self,
+        atime: TimeStamp,
+        ctime: TimeStamp,
+    ) -> BoxFuture<(), Self::Error>;
 }
 
 /// State has access to user sessions
@@ -66,10 +88,84 @@ where
     type SessionStorage: IsSessionStorage;
 }
 
+/// Access to used nonces
+pub trait IsNonceStorage: IsBackend {
+    /// Mark nonce as used
+    ///
+    /// The nonce should be kept until expiration time.
+    /// Returns `false` when nonce already used.
+    fn use_nonce(&self, nonce: &str, etime: TimeStamp) -> BoxFuture<bool, Self::Error>;
+}
+
+/// State has access to used nonces
+pub trait HasNonceStorage
+where
+    Self: AsRef<<Self as HasNonceStorage>::NonceStorage>,
+{
+    /// Used nonces accessor
+    type NonceStorage: IsNonceStorage;
+}
+
+/// State has user sessions options
+pub trait HasSessionOptions
+where
+    Self: AsRef<SessionOptions>,
+{
+}
+
+/// Access to API token data
+pub trait IsTokenData {
+    /// Permissions scope type
+    ///
+    /// Usually this is a set of permissions or roles which token allows.
+    type Scope: Serialize + DeserializeOwned + Clone + Send + 'static;
+
+    /// Get immutable token data
+    fn token_data(&self) -> &BaseTokenData;
+
+    /// Get mutable token data
+    fn token_data_mut(&mut self) -> &mut BaseTokenData;
+
+    /// Get permissions scope of token
+    fn token_scope(&self) -> &Self::Scope;
+}
+
+/// Access to user API tokens
+pub trait IsTokenStorage: IsBackend {
+    /// Token data type
+    type Token: IsTokenData + Send + 'static;
+
+    /// Get user token data by identifier
+    fn get_user_token(
+        &self,
+        user: UserId,
+        token: TokenId,
+    ) -> BoxFuture<Option<Self::Token>, Self::Error>;
+
+    /// Save modified user token data
+    fn put_user_token(&self, token: Self::Token) -> BoxFuture<Self::Token, Self::Error>;
+
+    /// Delete user token by identifier
+    fn del_user_token(&self, user: UserId, token: TokenId)
+        -> BoxFuture<Option<()>, Self::Error>;
+
+    /// Get all user tokens
+    fn get_user_tokens(&self, user: UserId) -> BoxFuture<Vec<Self::Token>, Self::Error>;
+}
+
+/// State has access to user API tokens
+pub trait HasTokenStorage
+where
+    Self: AsRef<<Self as HasTokenStorage>::TokenStorage>,
+{
+    /// User token accessor
+    type TokenStorage: IsTokenStorage;
+}
+
 /// Server-side user auth data
 pub trait IsUserAuth<S>
 where
-    Self: Send + Sized,
+    Self: IsAuditSubject + Send + Sized,
     S: HasSessionStorage + HasUserStorage,
 {
     /// Create auth data from session and user data
@@ -87,6 +183,26 @@ where
     fn new_none_auth() -> Option<Self>;
 }
 
+/// Server-side user auth data of API token
+pub trait IsTokenAuth<S>
+where
+    Self: Sized,
+    S: HasTokenStorage + HasUserStorage,
+{
+    /// Create auth data from API token and user data
+    ///
+    /// This should returns user auth when `Authorization: Bearer` header is present and has valid token.
+    /// The resulting auth should grant only the permissions which both token scope and user roles have
+    /// (see [`Scoped`](access::Scoped)).
+    /// If this method returns `None` then `x_auth()` rejects with `FORBIDDEN` error.
+    fn new_token_auth(
+        _token: &<<S as HasTokenStorage>::TokenStorage as IsTokenStorage>::Token,
+        _user: &<<S as HasUserStorage>::UserStorage as IsUserStorage>::User,
+    ) -> Option<Self> {
+        None
+    }
+}
+
 /// State has server-side user auth data
 pub trait HasUserAuth
 where
@@ -1,11 +1,20 @@
 use base::{serde_extra::base64, TimeStamp};
+use base64lib::{decode_config, encode_config, URL_SAFE_NO_PAD};
 use crypto::{random_bytes, PublicKey};
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::utils::memcmp;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::time::Duration;
 use user::UserId;
 
 /// Unique session indentifier
 pub type SessionId = u32;
 
+/// Unique API token identifier
+pub type TokenId = u32;
+
 /// Session arguments (or predicate)
 #[derive(Debug)]
 pub struct SessionArg {
@@ -13,6 +22,81 @@ pub struct SessionArg {
     pub user: UserId,
 }
 
+/// Token arguments (or predicate)
+#[derive(Debug)]
+pub struct TokenArg {
+    /// Token owner
+    pub user: UserId,
+}
+
+/// User sessions options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionOptions {
+    /// Idle time in milliseconds
+    ///
+    /// Sessions which not accessed during this interval will be expired.
+    #[serde(default = "default_idle_time")]
+    pub idle_time: TimeStamp,
+    /// Life time in milliseconds
+    ///
+    /// Sessions will be expired after this interval since creation regardless of activity.
+    #[serde(default = "default_life_time")]
+    pub life_time: TimeStamp,
+    /// Poll time in milliseconds
+    ///
+    /// Expired sessions will be deleted each poll time.
+    #[serde(default = "default_poll_time")]
+    pub poll_time: TimeStamp,
+    /// Request serials window
+    ///
+    /// The number of serials ahead or behind of expected which will be accepted (up to 64).
+    #[serde(default = "default_serial_window")]
+    pub serial_window: u32,
+    /// Clock skew tolerance in milliseconds
+    ///
+    /// The max absolute delta between client and server time on authorization.
+    #[serde(default = "default_clock_skew")]
+    pub clock_skew: TimeStamp,
+    /// Require nonce in auth requests
+    ///
+    /// Enable it to keep replay protection when clock skew tolerance is wide.
+    #[serde(default)]
+    pub require_nonce: bool,
+}
+
+fn default_idle_time() -> TimeStamp {
+    TimeStamp::default().with_days(5)
+}
+
+fn default_life_time() -> TimeStamp {
+    TimeStamp::default().with_days(30)
+}
+
+fn default_poll_time() -> TimeStamp {
+    TimeStamp::default().with_hours(1)
+}
+
+fn default_serial_window() -> u32 {
+    16
+}
+
+fn default_clock_skew() -> TimeStamp {
+    TimeStamp::default().with_secs(3)
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        SessionOptions {
+            idle_time: default_idle_time(),
+            life_time: default_life_time(),
+            poll_time: default_poll_time(),
+            serial_window: default_serial_window(),
+            clock_skew: default_clock_skew(),
+            require_nonce: false,
+        }
+    }
+}
+
 /// Public server data
 ///
 /// The data which server publishes for clients,
@@ -43,6 +127,30 @@ impl<MethodInfo> AuthInfo<MethodInfo> {
     }
 }
 
+/// Server time info
+///
+/// The response of time synchronization endpoint.
+///
+/// Client can estimate round-trip time as `now - ctime`
+/// and clock offset as `stime - (ctime + now) / 2`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeInfo {
+    /// Client time from request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctime: Option<TimeStamp>,
+
+    /// Current time of server
+    pub stime: TimeStamp,
+}
+
+/// Server time request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeRequest {
+    /// Current time of client
+    #[serde(default)]
+    pub ctime: Option<TimeStamp>,
+}
+
 /// Authentication request
 ///
 /// The request which client sends to server to do authentication.
@@ -55,6 +163,18 @@ pub struct AuthRequest<UserIdent> {
     #[serde(with = "base64")]
     pub pbkey: PublicKey,
 
+    /// Client device label
+    ///
+    /// Optional human-readable name of device to recognize session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+
+    /// Unique request nonce
+    ///
+    /// The random string which protects from replaying auth requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+
     /// Method-specific identification data
     ///
     /// This field must be represented as an internally tagged enum (`#[serde(tag = "authm")]`),
@@ -86,6 +206,69 @@ pub struct AuthResponse<UserInfo> {
     pub extra: UserInfo,
 }
 
+/// Authentication result
+///
+/// The response which server sends to client on success first factor authentication.
+/// It is either [`AuthResponse`](auth::AuthResponse) or [`FactorRequired`](auth::FactorRequired)
+/// when user has second factor enabled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AuthResult<UserInfo> {
+    /// Session created
+    Done(AuthResponse<UserInfo>),
+    /// Second factor required
+    Factor(FactorRequired),
+}
+
+/// Second factor required response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FactorRequired {
+    /// Second factor name
+    pub factor: String,
+
+    /// Opaque ticket which should be passed with second factor
+    pub ticket: String,
+
+    /// Ticket expiration time
+    pub etime: TimeStamp,
+}
+
+/// Second factor request
+///
+/// The request which client sends to server to complete authentication.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FactorRequest<FactorIdent> {
+    /// Ticket from [`FactorRequired`](auth::FactorRequired)
+    pub ticket: String,
+
+    /// Factor-specific identification data
+    #[serde(flatten)]
+    pub ident: FactorIdent,
+}
+
+/// Second factor ticket
+///
+/// The state of authentication between first and second factors.
+/// It is sealed using server secure key and passed to client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FactorTicket {
+    /// Unique user identifier
+    pub user: UserId,
+
+    /// Client public key
+    #[serde(with = "base64")]
+    pub pbkey: PublicKey,
+
+    /// Client time of auth request
+    pub ctime: TimeStamp,
+
+    /// Client metadata
+    pub meta: SessionMeta,
+
+    /// Ticket expiration time
+    pub etime: TimeStamp,
+}
+
 /// User session data
 ///
 /// The data which stored on server
@@ -103,14 +286,24 @@ pub struct BaseSessionData {
     /// Unique session token
     pub token: Vec<u8>,
 
-    /// Last request serial number
+    /// Next expected request serial number
     pub serno: u32,
 
+    /// Already used request serials before next expected
+    ///
+    /// The bit N is set when serial `serno - 1 - N` was used.
+    #[serde(default)]
+    pub seen: u64,
+
     /// Session creation time
     pub ctime: TimeStamp,
 
     /// Last access time
     pub atime: TimeStamp,
+
+    /// Client metadata
+    #[serde(flatten)]
+    pub meta: SessionMeta,
 }
 
 impl BaseSessionData {
@@ -125,23 +318,107 @@ impl BaseSessionData {
             pbkey,
             token: random_bytes(20),
             serno: 1,
+            seen: 0,
             ctime: now,
             atime: now,
+            meta: SessionMeta::default(),
         }
     }
 
+    /// Set client metadata
+    pub fn with_meta(mut self, meta: SessionMeta) -> Self {
+        self.meta = meta;
+        self
+    }
+
     /// Check session outdating
     pub fn valid(&self, ttl: &Duration) -> bool {
         self.atime + *ttl > TimeStamp::now()
     }
 
+    /// Check session expiration using idle time and absolute life time
+    pub fn alive(&self, options: &SessionOptions) -> bool {
+        let now = TimeStamp::now();
+        self.atime + options.idle_time > now && self.ctime + options.life_time > now
+    }
+
+    /// Accept request serial number
+    ///
+    /// The serials within `window` ahead of expected are accepted and not yet used serials
+    /// within `window` behind of expected are accepted too, so concurrent requests can
+    /// arrive out of order. Reused or too far serials are rejected.
+    ///
+    /// ```
+    /// use literium::{auth::BaseSessionData, crypto::{CanKeygen, PublicKey, SecretKey}};
+    ///
+    /// let (pbkey, _) = <(PublicKey, SecretKey)>::gen_key();
+    /// let mut session = BaseSessionData::new(1, pbkey);
+    ///
+    /// assert_eq!(session.accept_serno(1, 4), true);
+    /// // concurrent requests arrives out of order
+    /// assert_eq!(session.accept_serno(3, 4), true);
+    /// assert_eq!(session.accept_serno(2, 4), true);
+    /// // reused serial
+    /// assert_eq!(session.accept_serno(2, 4), false);
+    /// // too far ahead
+    /// assert_eq!(session.accept_serno(8, 4), false);
+    /// assert_eq!(session.accept_serno(7, 4), true);
+    /// // too far behind
+    /// assert_eq!(session.accept_serno(4, 4), true);
+    /// assert_eq!(session.accept_serno(3, 4), false);
+    /// ```
+    pub fn accept_serno(&mut self, serno: u32, window: u32) -> bool {
+        let window = window.max(1).min(64);
+        if serno >= self.serno {
+            let shift = serno - self.serno + 1;
+            if shift > window {
+                return false;
+            }
+            self.seen = if shift < 64 {
+                (self.seen << shift) | 1
+            } else {
+                1
+            };
+            self.serno = serno + 1;
+            true
+        } else {
+            let index = self.serno - 1 - serno;
+            if index >= window || self.seen & (1 << index) != 0 {
+                return false;
+            }
+            self.seen |= 1 << index;
+            true
+        }
+    }
+
     /// Refresh existing session data
     pub fn renew(&mut self) {
-        self.serno += 1;
         self.atime = TimeStamp::now();
     }
 }
 
+/// User session metadata
+///
+/// The client info which helps user to recognize session.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionMeta {
+    /// Client IP address when session was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addr: Option<IpAddr>,
+
+    /// Client user agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+
+    /// Client device label
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+
+    /// Client IP address of last request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_addr: Option<IpAddr>,
+}
+
 /// User session info
 ///
 /// The data which shows to client
@@ -158,6 +435,10 @@ pub struct SessionInfo {
 
     /// Last access time
     pub atime: TimeStamp,
+
+    /// Client metadata
+    #[serde(flatten)]
+    pub meta: SessionMeta,
 }
 
 impl<'a> From<&'a BaseSessionData> for SessionInfo {
@@ -167,6 +448,7 @@ impl<'a> From<&'a BaseSessionData> for SessionInfo {
             sess,
             ctime,
             atime,
+            meta,
             ..
         }: &'a BaseSessionData,
     ) -> Self {
@@ -175,6 +457,7 @@ impl<'a> From<&'a BaseSessionData> for SessionInfo {
             sess: *sess,
             ctime: *ctime,
             atime: *atime,
+            meta: meta.clone(),
         }
     }
 }
@@ -200,8 +483,229 @@ pub struct AuthData {
 }
 
 impl PartialEq<BaseSessionData> for AuthData {
-    /// Validate
+    /// Validate session token
+    ///
+    /// The request serial should be checked separately using [`BaseSessionData::accept_serno`].
     fn eq(&self, other: &BaseSessionData) -> bool {
-        self.serno == other.serno && self.token == other.token
+        self.token == other.token
+    }
+}
+
+/// API token data
+///
+/// The data which stored on server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaseTokenData {
+    /// Unique user identifier
+    pub user: UserId,
+
+    /// Token identifier (unique for user)
+    pub id: TokenId,
+
+    /// Human-readable token name
+    pub name: String,
+
+    /// Hash of unique token secret
+    ///
+    /// The secret itself is shown to client only once and never stored.
+    pub token: Vec<u8>,
+
+    /// Token creation time
+    pub ctime: TimeStamp,
+
+    /// Last access time
+    pub atime: TimeStamp,
+
+    /// Expiration time
+    ///
+    /// The token never expires when it is missing.
+    pub etime: Option<TimeStamp>,
+}
+
+impl BaseTokenData {
+    /// Create new token data
+    ///
+    /// Create new token data using user identifier, name, secret and optional life time
+    pub fn new<S: Into<String>>(
+        user: UserId,
+        name: S,
+        secret: &[u8],
+        ttl: Option<TimeStamp>,
+    ) -> Self {
+        let now = TimeStamp::now();
+        Self {
+            user,
+            id: Default::default(),
+            name: name.into(),
+            token: token_hash(secret),
+            ctime: now,
+            atime: now,
+            etime: ttl.map(|ttl| now + ttl),
+        }
+    }
+
+    /// Check token outdating
+    pub fn valid(&self) -> bool {
+        self.etime
+            .map(|etime| etime > TimeStamp::now())
+            .unwrap_or(true)
+    }
+
+    /// Refresh access time
+    pub fn renew(&mut self) {
+        self.atime = TimeStamp::now();
+    }
+}
+
+/// Generate new token secret
+pub fn token_secret() -> Vec<u8> {
+    random_bytes(32)
+}
+
+fn token_hash(secret: &[u8]) -> Vec<u8> {
+    sha256::hash(secret).as_ref().to_vec()
+}
+
+/// API token info
+///
+/// The data which shows to client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenInfo<Scope> {
+    /// Token identifier unique for user
+    pub id: TokenId,
+
+    /// Human-readable token name
+    pub name: String,
+
+    /// Permissions scope
+    pub scope: Scope,
+
+    /// Token creation time
+    pub ctime: TimeStamp,
+
+    /// Last access time
+    pub atime: TimeStamp,
+
+    /// Expiration time
+    pub etime: Option<TimeStamp>,
+}
+
+impl<'a, Scope: Clone> From<(&'a BaseTokenData, &'a Scope)> for TokenInfo<Scope> {
+    fn from(
+        (
+            BaseTokenData {
+                id,
+                name,
+                ctime,
+                atime,
+                etime,
+                ..
+            },
+            scope,
+        ): (&'a BaseTokenData, &'a Scope),
+    ) -> Self {
+        Self {
+            id: *id,
+            name: name.clone(),
+            scope: scope.clone(),
+            ctime: *ctime,
+            atime: *atime,
+            etime: *etime,
+        }
+    }
+}
+
+/// API token request
+///
+/// The request which client sends to server to create new token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenRequest<Scope> {
+    /// Human-readable token name
+    pub name: String,
+
+    /// Permissions scope
+    ///
+    /// The token will have only permissions which both user roles and this scope have.
+    pub scope: Scope,
+
+    /// Life time in milliseconds
+    #[serde(default)]
+    pub ttl: Option<TimeStamp>,
+}
+
+/// API token response
+///
+/// The response which server sends to client on success token creation.
+/// Note that the token string is shown only once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenResponse {
+    /// Token identifier unique for user
+    pub id: TokenId,
+
+    /// Bearer token string
+    pub token: String,
+}
+
+/// Bearer token
+///
+/// The data which client sends in `Authorization: Bearer` header
+/// to do authorized requests using API token.
+///
+/// It represented as `<user>.<id>.<base64url-encoded secret>` string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BearerToken {
+    /// Unique user identifier
+    pub user: UserId,
+
+    /// Token identifier (unique for user)
+    pub id: TokenId,
+
+    /// Unique token secret
+    pub token: Vec<u8>,
+}
+
+impl BearerToken {
+    /// Create bearer token using stored token data and its secret
+    pub fn new(data: &BaseTokenData, secret: Vec<u8>) -> Self {
+        Self {
+            user: data.user,
+            id: data.id,
+            token: secret,
+        }
+    }
+}
+
+impl PartialEq<BaseTokenData> for BearerToken {
+    /// Validate
+    ///
+    /// The hash of secret is compared in constant time.
+    fn eq(&self, other: &BaseTokenData) -> bool {
+        self.user == other.user
+            && self.id == other.id
+            && memcmp(&token_hash(&self.token), &other.token)
+    }
+}
+
+impl Display for BearerToken {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "{}.{}.{}",
+            self.user,
+            self.id,
+            encode_config(&self.token, URL_SAFE_NO_PAD)
+        )
+    }
+}
+
+impl FromStr for BearerToken {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, '.');
+        let user = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        let id = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        let token = decode_config(parts.next().ok_or(())?, URL_SAFE_NO_PAD).map_err(|_| ())?;
+        Ok(Self { user, id, token })
     }
 }
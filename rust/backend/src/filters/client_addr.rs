@@ -0,0 +1,117 @@
+use base::XForwardedFor;
+use std::net::IpAddr;
+use warp::{any, header, Filter, Rejection};
+
+/// Client address options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientAddrOptions {
+    /// Number of trusted reverse proxies in front of server
+    ///
+    /// Each proxy appends address of its peer to *X-Forwarded-For* header,
+    /// so only the entries which added by trusted proxies can be used.
+    /// When it is zero the proxy headers is ignored at all.
+    #[serde(default = "default_proxies")]
+    pub proxies: usize,
+}
+
+fn default_proxies() -> usize {
+    1
+}
+
+impl Default for ClientAddrOptions {
+    fn default() -> Self {
+        Self {
+            proxies: default_proxies(),
+        }
+    }
+}
+
+/// State has client address options
+pub trait HasClientAddrOptions
+where
+    Self: AsRef<ClientAddrOptions>,
+{
+}
+
+/** Extract client IP address
+
+The address is taken from *X-Forwarded-For* header or from *X-Real-IP* header,
+so the server should be placed behind a trusted reverse proxy which sets it.
+
+The client controls the leading entries of *X-Forwarded-For* header,
+so the right-most entry which is not added by trusted proxies is used
+(see [`ClientAddrOptions`](filters::ClientAddrOptions)).
+
+Note that the address of peer is not available here, so the address is missing without proxy headers.
+
+*/
+pub fn x_client_addr<S>(
+    state: &S,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Rejection> + Clone
+where
+    S: HasClientAddrOptions,
+{
+    let proxies = (state.as_ref() as &ClientAddrOptions).proxies;
+
+    header("x-forwarded-for")
+        .map(move |addrs: XForwardedFor| client_addr(addrs.as_ref(), proxies))
+        .or(header("x-real-ip").map(move |addr: IpAddr| Some(addr).filter(|_| proxies > 0)))
+        .unify()
+        .or(any().map(|| None))
+        .unify()
+}
+
+/// Get the right-most untrusted address
+///
+/// The last entry is added by nearest proxy, the previous one by next proxy and so on.
+fn client_addr(addrs: &[IpAddr], proxies: usize) -> Option<IpAddr> {
+    if proxies == 0 {
+        return None;
+    }
+    addrs.get(addrs.len().saturating_sub(proxies)).cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use warp::test::request;
+
+    struct State(ClientAddrOptions);
+
+    impl AsRef<ClientAddrOptions> for State {
+        fn as_ref(&self) -> &ClientAddrOptions {
+            &self.0
+        }
+    }
+
+    impl HasClientAddrOptions for State {}
+
+    fn addr(proxies: usize, headers: &[(&str, &str)]) -> Option<IpAddr> {
+        let mut req = request();
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.filter(&x_client_addr(&State(ClientAddrOptions { proxies })))
+            .unwrap()
+    }
+
+    #[test]
+    fn forwarded_for() {
+        let forwarded = [("x-forwarded-for", "10.0.0.1, 192.0.2.7, 198.51.100.2")];
+
+        // spoofed first entry is ignored
+        assert_eq!(addr(1, &forwarded), Some("198.51.100.2".parse().unwrap()));
+        assert_eq!(addr(2, &forwarded), Some("192.0.2.7".parse().unwrap()));
+        // all entries is added by trusted proxies
+        assert_eq!(addr(5, &forwarded), Some("10.0.0.1".parse().unwrap()));
+        // no trusted proxies
+        assert_eq!(addr(0, &forwarded), None);
+
+        assert_eq!(
+            addr(1, &[("x-real-ip", "192.0.2.7")]),
+            Some("192.0.2.7".parse().unwrap())
+        );
+        assert_eq!(addr(0, &[("x-real-ip", "192.0.2.7")]), None);
+        assert_eq!(addr(1, &[]), None);
+    }
+}
@@ -10,17 +10,25 @@ The filters which widely used by literium web-framework.
 * Using BASE64 encoded sealed JSON authorization
 * Check image data from request body and get data stream
 * Check file content from request body and get data stream
+* Extracting client IP address from proxy headers
+* Extracting client metadata for sessions
 
 */
 
+mod client_addr;
 mod image_file;
 mod magic_file;
 #[cfg(feature = "auth")]
 mod sealed_auth;
 mod sealed_json;
+#[cfg(feature = "auth")]
+mod session_meta;
 
+pub use self::client_addr::*;
 pub use self::image_file::*;
 pub use self::magic_file::*;
 #[cfg(feature = "auth")]
 pub use self::sealed_auth::*;
 pub use self::sealed_json::*;
+#[cfg(feature = "auth")]
+pub use self::session_meta::*;
@@ -1,13 +1,17 @@
 use futures::{
-    future::{err, result, Either},
+    future::{err, loop_fn, ok, result, Either, Loop},
     Future,
 };
 
+use super::{x_client_addr, HasClientAddrOptions};
+use access::{audit_denial, AuditDecision, AuditRecord, AuditSubject, HasAuditSink};
 use auth::{
-    AuthData, AuthError, HasSessionStorage, HasUserAuth, IsSessionData, IsSessionStorage,
-    IsUserAuth,
+    AuthData, AuthError, BearerToken, HasSessionOptions, HasSessionStorage, HasTokenStorage,
+    HasUserAuth, IsSessionData, IsSessionStorage, IsTokenAuth, IsTokenData, IsTokenStorage,
+    IsUserAuth, SessionOptions, TokenArg,
 };
 use crypto::{CanDecrypt, HasSecretKey};
+use std::net::IpAddr;
 use user::{HasUserStorage, IsUserStorage};
 use warp::{any, header, reject::custom, Filter, Rejection};
 
@@ -15,7 +19,13 @@ use warp::{any, header, reject::custom, Filter, Rejection};
 
 Requests should contain BASE64 encoded sealed JSON auth data in `X-Auth` header.
 
-This function extracts auth data, checks session and returns user data which can be used to check permissions and etc.
+Alternatively requests may contain API token in `Authorization: Bearer <token>` header.
+The other authorization schemes is ignored, so such requests is handled as unauthorized.
+
+This function extracts auth data, checks session (or token) and returns user data which can be used to check permissions and etc.
+
+Besides sessions the state should provide session options, client address options, API tokens storage and audit sink,
+and user auth should support creating from API token ([`IsTokenAuth`](auth::IsTokenAuth)).
 
 ```
 extern crate futures;
@@ -30,15 +40,18 @@ extern crate pretty_env_logger;
 
 use futures::Future;
 use literium::{
+    access::{HasAuditSink, MemoryAudit},
     user::{
         stub::{UserData, Users},
         HasUserStorage,
     },
     auth::{
-        stub::{Sessions, UserAuth, SessionData},
-        AuthData, HasUserAuth, HasSessionStorage, IsSessionStorage,
+        stub::{Sessions, Tokens, UserAuth, SessionData},
+        AuthData, HasUserAuth, HasSessionOptions, HasSessionStorage, HasTokenStorage,
+        IsSessionStorage, SessionOptions,
     },
     x_auth,
+    filters::{ClientAddrOptions, HasClientAddrOptions},
     crypto::{
         CanEncrypt, CryptoKeys, HasSecretKey, PublicKey,
     },
@@ -51,6 +64,10 @@ pub struct State {
     config: Arc<CryptoKeys>,
     users: Users,
     sessions: Sessions,
+    session_options: Arc<SessionOptions>,
+    tokens: Tokens,
+    audit: MemoryAudit,
+    client_addr: Arc<ClientAddrOptions>,
 }
 
 impl AsRef<CryptoKeys> for State {
@@ -83,6 +100,42 @@ impl HasSessionStorage for State {
     type SessionStorage = Sessions;
 }
 
+impl AsRef<SessionOptions> for State {
+    fn as_ref(&self) -> &SessionOptions {
+        &self.session_options
+    }
+}
+
+impl HasSessionOptions for State {}
+
+impl AsRef<ClientAddrOptions> for State {
+    fn as_ref(&self) -> &ClientAddrOptions {
+        &self.client_addr
+    }
+}
+
+impl HasClientAddrOptions for State {}
+
+impl AsRef<Tokens> for State {
+    fn as_ref(&self) -> &Tokens {
+        &self.tokens
+    }
+}
+
+impl HasTokenStorage for State {
+    type TokenStorage = Tokens;
+}
+
+impl AsRef<MemoryAudit> for State {
+    fn as_ref(&self) -> &MemoryAudit {
+        &self.audit
+    }
+}
+
+impl HasAuditSink for State {
+    type AuditSink = MemoryAudit;
+}
+
 impl HasUserAuth for State {
     type UserAuth = UserAuth;
 }
@@ -114,6 +167,10 @@ fn main() {
         config: Arc::new(server_keys),
         users,
         sessions,
+        session_options: Arc::new(SessionOptions::default()),
+        tokens: Tokens::new(),
+        audit: MemoryAudit::new(),
+        client_addr: Arc::new(ClientAddrOptions::default()),
     };
 
     let auth_data = AuthData {
@@ -155,29 +212,55 @@ fn main() {
 */
 pub fn x_auth<S>(state: &S) -> impl Filter<Extract = (S::UserAuth,), Error = Rejection> + Clone
 where
-    S: HasSecretKey + HasUserStorage + HasSessionStorage + HasUserAuth + Send + Clone,
+    S: HasSecretKey
+        + HasUserStorage
+        + HasSessionStorage
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasUserAuth
+        + HasClientAddrOptions
+        + Send
+        + Clone,
+    S::UserAuth: IsTokenAuth<S>,
 {
     let state = state.clone();
     header("x-auth")
         .map(|auth: String| Some(auth))
         .or(any().map(|| None))
         .unify()
-        .and_then(move |auth: Option<String>| {
-            auth.map(|auth| Either::A(proc_user_auth(&state, auth)))
-                .unwrap_or_else(|| {
-                    Either::B(result(
-                        S::UserAuth::new_none_auth().ok_or_else(|| AuthError::MissingAuth),
-                    ))
-                }).map_err(custom)
+        .and(
+            header("authorization")
+                .map(|auth: String| bearer_token(&auth))
+                .or(any().map(|| None))
+                .unify(),
+        ).and(x_client_addr(&state))
+        .and_then(move |auth: Option<String>, bearer: Option<String>, addr| {
+            if let Some(auth) = auth {
+                Either::A(Either::A(proc_user_auth(&state, addr, auth)))
+            } else if let Some(bearer) = bearer {
+                Either::A(Either::B(proc_token_auth(&state, addr, bearer)))
+            } else {
+                Either::B(result(
+                    S::UserAuth::new_none_auth().ok_or_else(|| AuthError::MissingAuth),
+                ))
+            }.map_err(custom)
         })
 }
 
 fn proc_user_auth<S>(
     state: &S,
+    addr: Option<IpAddr>,
     auth: String,
 ) -> impl Future<Item = S::UserAuth, Error = AuthError> + Send
 where
-    S: HasSecretKey + HasUserStorage + HasSessionStorage + HasUserAuth + Send + Clone,
+    S: HasSecretKey
+        + HasUserStorage
+        + HasSessionStorage
+        + HasSessionOptions
+        + HasUserAuth
+        + Send
+        + Clone,
 {
     let state = state.clone();
     result(
@@ -192,42 +275,7 @@ where
         move |data: AuthData| {
             debug!("Received auth data: {:?}", data);
 
-            (state.as_ref() as &S::SessionStorage)
-                .get_user_session(data.user, data.sess)
-                .map_err(|error| {
-                    error!("Error when getting user session: {}", error);
-                    AuthError::BackendError
-                }).map(
-                    move |session: Option<<S::SessionStorage as IsSessionStorage>::Session>| {
-                        (data, session)
-                    },
-                )
-        }
-    }).and_then({
-        let state = state.clone();
-        move |(data, session)| {
-            let mut session = if let Some(session) = session {
-                session
-            } else {
-                error!("Lost session");
-                return Either::A(err(AuthError::LostSession));
-            };
-            //if !session.valid(Duration::from_secs(5 * 24 * 60 * 60)) {
-            //
-            //}
-            if &data != session.session_data() {
-                error!("Bad session");
-                return Either::A(err(AuthError::BadSession));
-            }
-            session.session_data_mut().renew();
-            Either::B(
-                (state.as_ref() as &S::SessionStorage)
-                    .put_user_session(session)
-                    .map_err(|error| {
-                        error!("Backend error: {}", error);
-                        AuthError::BackendError
-                    }),
-            )
+            proc_user_session(&state, addr, data)
         }
     }).and_then({
         move |session: <S::SessionStorage as IsSessionStorage>::Session| {
@@ -242,3 +290,174 @@ where
         }
     })
 }
+
+/// Max number of attempts to update session concurrently
+const SESSION_UPDATE_ATTEMPTS: usize = 4;
+
+fn proc_user_session<S>(
+    state: &S,
+    addr: Option<IpAddr>,
+    data: AuthData,
+) -> impl Future<Item = <S::SessionStorage as IsSessionStorage>::Session, Error = AuthError> + Send
+where
+    S: HasSessionStorage + HasSessionOptions + Send + Clone,
+{
+    let state = state.clone();
+    loop_fn(1, move |attempt| {
+        let state = state.clone();
+        let data = data.clone();
+        (state.as_ref() as &S::SessionStorage)
+            .get_user_session(data.user, data.sess)
+            .map_err(|error| {
+                error!("Error when getting user session: {}", error);
+                AuthError::BackendError
+            }).and_then(
+                move |session: Option<<S::SessionStorage as IsSessionStorage>::Session>| {
+                    let mut session = if let Some(session) = session {
+                        session
+                    } else {
+                        error!("Lost session");
+                        return Either::A(err(AuthError::LostSession));
+                    };
+                    let options = state.as_ref() as &SessionOptions;
+                    if !session.session_data().alive(options) {
+                        error!("Outdated session");
+                        return Either::A(err(AuthError::Outdated));
+                    }
+                    if &data != session.session_data() {
+                        error!("Bad session");
+                        return Either::A(err(AuthError::BadSession));
+                    }
+                    let prev = session.session_data().clone();
+                    if !session
+                        .session_data_mut()
+                        .accept_serno(data.serno, options.serial_window)
+                    {
+                        error!("Bad request serial");
+                        return Either::A(err(AuthError::BadSession));
+                    }
+                    session.session_data_mut().renew();
+                    if addr.is_some() {
+                        session.session_data_mut().meta.last_addr = addr;
+                    }
+                    Either::B(
+                        (state.as_ref() as &S::SessionStorage)
+                            .cas_user_session(&prev, session)
+                            .map_err(|error| {
+                                error!("Backend error: {}", error);
+                                AuthError::BackendError
+                            }).and_then(move |session| match session {
+                                Some(session) => Ok(Loop::Break(session)),
+                                None if attempt < SESSION_UPDATE_ATTEMPTS => {
+                                    debug!("Session modified concurrently, retrying");
+                                    Ok(Loop::Continue(attempt + 1))
+                                }
+                                None => {
+                                    error!("Unable to update session concurrently");
+                                    Err(AuthError::BackendError)
+                                }
+                            }),
+                    )
+                },
+            )
+    })
+}
+
+/// Get token from `Authorization` header when scheme is `Bearer`
+///
+/// The other schemes (like `Basic` which may be added by proxy) is ignored.
+fn bearer_token(auth: &str) -> Option<String> {
+    let mut parts = auth.trim().splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(kind), Some(token)) if kind.eq_ignore_ascii_case("bearer") => {
+            Some(token.trim().into())
+        }
+        _ => None,
+    }
+}
+
+fn proc_token_auth<S>(
+    state: &S,
+    addr: Option<IpAddr>,
+    auth: String,
+) -> impl Future<Item = S::UserAuth, Error = AuthError> + Send
+where
+    S: HasUserStorage + HasTokenStorage + HasAuditSink + HasUserAuth + Send + Clone,
+    S::UserAuth: IsTokenAuth<S>,
+{
+    let state = state.clone();
+    result(auth.parse().map_err(|_| {
+        error!("Error when parsing bearer token");
+        AuthError::BadAuth
+    })).and_then({
+            let state = state.clone();
+            move |bearer: BearerToken| {
+                debug!("Received bearer token: {}.{}", bearer.user, bearer.id);
+
+                (state.as_ref() as &S::TokenStorage)
+                    .get_user_token(bearer.user, bearer.id)
+                    .map_err(|error| {
+                        error!("Error when getting user token: {}", error);
+                        AuthError::BackendError
+                    }).map(
+                        move |token: Option<<S::TokenStorage as IsTokenStorage>::Token>| {
+                            (bearer, token)
+                        },
+                    )
+            }
+        }).and_then({
+            let state = state.clone();
+            move |(bearer, token)| {
+                let mut token = if let Some(token) = token {
+                    token
+                } else {
+                    error!("Lost token");
+                    return Either::A(err(AuthError::BadAuth));
+                };
+                if &bearer != token.token_data() {
+                    error!("Bad token");
+                    return Either::A(err(AuthError::BadAuth));
+                }
+                if !token.token_data().valid() {
+                    error!("Outdated token");
+                    return Either::A(err(AuthError::Outdated));
+                }
+                token.token_data_mut().renew();
+                Either::B(
+                    (state.as_ref() as &S::TokenStorage)
+                        .put_user_token(token)
+                        .map_err(|error| {
+                            error!("Backend error: {}", error);
+                            AuthError::BackendError
+                        }),
+                )
+            }
+        }).and_then({
+            let state = state.clone();
+            move |token: <S::TokenStorage as IsTokenStorage>::Token| {
+                (state.as_ref() as &S::UserStorage)
+                    .get_user_data(token.token_data().user)
+                    .map_err(|_| AuthError::BackendError)
+                    .map(move |user| (token, user))
+            }
+        }).and_then(move |(token, user)| {
+            let user: <S::UserStorage as IsUserStorage>::User = if let Some(user) = user {
+                user
+            } else {
+                return Either::A(err(AuthError::BadUser));
+            };
+            if let Some(auth) = S::UserAuth::new_token_auth(&token, &user) {
+                return Either::A(ok(auth));
+            }
+            warn!("Restricted token: {}.{}", token.token_data().user, token.token_data().id);
+            let subject = AuditSubject {
+                user: token.token_data().user,
+                sess: 0,
+            };
+            let record = AuditRecord::new(subject, AuditDecision::Deny)
+                .with_object(&TokenArg { user: subject.user })
+                .with_reason(AuthError::Restricted)
+                .with_addr(addr);
+            Either::B(audit_denial(&state, record).then(|_| Err(AuthError::Restricted)))
+        })
+}
@@ -0,0 +1,31 @@
+use super::{x_client_addr, HasClientAddrOptions};
+use auth::SessionMeta;
+use warp::{any, header, Filter, Rejection};
+
+/** Extract client metadata for session
+
+The metadata includes client IP address and user agent.
+
+It can be used by [`HasFilter<SessionArg>`](base::HasFilter) implementation to get client info
+for application-specific session data.
+
+*/
+pub fn x_session_meta<S>(
+    state: &S,
+) -> impl Filter<Extract = (SessionMeta,), Error = Rejection> + Clone
+where
+    S: HasClientAddrOptions,
+{
+    x_client_addr(state)
+        .and(
+            header("user-agent")
+                .map(|agent: String| Some(agent))
+                .or(any().map(|| None))
+                .unify(),
+        ).map(|addr, agent| SessionMeta {
+            addr,
+            agent,
+            device: None,
+            last_addr: addr,
+        })
+}
@@ -1,6 +1,8 @@
 #[macro_use]
 extern crate log;
 extern crate base64 as base64lib;
+#[cfg(feature = "bcrypt_hash")]
+extern crate bcrypt;
 extern crate bytes;
 extern crate serde;
 extern crate time;
@@ -21,9 +23,13 @@ extern crate mime;
 extern crate native_tls;
 #[cfg(feature = "send_mail")]
 extern crate new_tokio_smtp;
+#[cfg(any(feature = "webauthn_auth", feature = "oidc_auth"))]
+extern crate openssl;
 extern crate serde_json;
 extern crate serde_qs;
 extern crate serde_with;
+#[cfg(feature = "auth")]
+extern crate sha1;
 extern crate sodiumoxide;
 extern crate tokio;
 extern crate toml;
@@ -48,6 +54,8 @@ pub mod http;
 #[cfg(feature = "send_mail")]
 pub mod mail;
 pub mod reply;
+#[cfg(feature = "send_sms")]
+pub mod sms;
 pub mod third;
 pub mod user;
 
@@ -0,0 +1,122 @@
+use super::{HasSmsGateway, HttpSmsConfig, IsSmsGateway, PhoneNumber, SmsError, SmsMessage};
+use base::BoxFuture;
+use futures::{
+    future::{err, ok},
+    Future,
+};
+use http::{
+    client::{HttpClientError, IntoHttpRequest, IsHttpClient},
+    request::{Header, JsonBody, Method, NoError, Url},
+    HttpRequest, HttpRequestBuilder,
+};
+use serde_json as json;
+use std::sync::Arc;
+
+/// Send SMS message
+pub fn send_sms<State>(state: State, message: SmsMessage) -> BoxFuture<(), SmsError>
+where
+    State: HasSmsGateway,
+{
+    state.as_ref().send_sms(message)
+}
+
+#[derive(Serialize)]
+struct HttpSmsRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<&'a str>,
+    to: &'a PhoneNumber,
+    text: &'a str,
+}
+
+/// Generic HTTP SMS gateway
+///
+/// It posts messages to configured URL using HTTP client.
+#[derive(Clone)]
+pub struct HttpSmsGateway<C> {
+    client: C,
+    config: Arc<HttpSmsConfig>,
+}
+
+impl<C> HttpSmsGateway<C> {
+    /// Create HTTP gateway using client and configuration
+    pub fn new(client: C, config: &HttpSmsConfig) -> Result<Self, SmsError> {
+        if !config.url.starts_with("https://") && !config.url.starts_with("http://") {
+            return Err(SmsError::BadConfig(format!("Invalid URL: {}", config.url)));
+        }
+
+        Ok(HttpSmsGateway {
+            client,
+            config: Arc::new(config.clone()),
+        })
+    }
+
+    fn make_request(&self, message: &SmsMessage) -> Result<HttpRequest, SmsError> {
+        let config = &self.config;
+        let body = JsonBody(HttpSmsRequest {
+            from: config.from.as_ref().map(String::as_str),
+            to: &message.to,
+            text: &message.text,
+        });
+        let body = Header("Content-Type", "application/json", body);
+
+        let request: Result<_, HttpClientError<json::Error, NoError>> =
+            if let Some(token) = &config.token {
+                Method(
+                    "POST",
+                    Url(
+                        config.url.as_str(),
+                        Header("Authorization", format!("Bearer {}", token), body),
+                    ),
+                ).into_request(HttpRequestBuilder::new())
+            } else {
+                Method("POST", Url(config.url.as_str(), body))
+                    .into_request(HttpRequestBuilder::new())
+            };
+
+        request.map_err(|error| {
+            error!("Unable to create SMS request: {}", error);
+            SmsError::BadMessage
+        })
+    }
+}
+
+impl<C> IsSmsGateway for HttpSmsGateway<C>
+where
+    C: IsHttpClient,
+{
+    fn send_sms(&self, message: SmsMessage) -> BoxFuture<(), SmsError> {
+        let request = match self.make_request(&message) {
+            Ok(request) => request,
+            Err(error) => return Box::new(err(error)),
+        };
+
+        Box::new(
+            self.client
+                .send_request(request)
+                .map_err(|error| {
+                    error!("Unable to send SMS request: {}", error);
+                    SmsError::GatewayError
+                }).and_then(|response| {
+                    if response.status().is_success() {
+                        Ok(())
+                    } else {
+                        error!("SMS gateway responds with status: {}", response.status());
+                        Err(SmsError::GatewayError)
+                    }
+                }),
+        )
+    }
+}
+
+/// Logging SMS gateway
+///
+/// This gateway only writes messages to log. Use it for development and testing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSmsGateway;
+
+impl IsSmsGateway for LogSmsGateway {
+    fn send_sms(&self, message: SmsMessage) -> BoxFuture<(), SmsError> {
+        info!("SMS to {}: {}", message.to, message.text);
+        Box::new(ok(()))
+    }
+}
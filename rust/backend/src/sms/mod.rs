@@ -0,0 +1,19 @@
+/*!
+
+## SMS messaging functions
+
+This feature (`send_sms`) is optional and can be disabled.
+
+The messages is sent through pluggable gateways ([`IsSmsGateway`](sms::IsSmsGateway)).
+The generic [`HttpSmsGateway`](sms::HttpSmsGateway) posts messages as JSON to any compatible service
+and the [`LogSmsGateway`](sms::LogSmsGateway) only writes messages to log.
+
+*/
+
+mod gateway;
+mod traits;
+mod types;
+
+pub use self::gateway::*;
+pub use self::traits::*;
+pub use self::types::*;
@@ -0,0 +1,23 @@
+use super::{SmsError, SmsMessage};
+use base::BoxFuture;
+use futures::future;
+
+/// The generic SMS gateway interface
+pub trait IsSmsGateway {
+    fn send_sms(&self, message: SmsMessage) -> BoxFuture<(), SmsError>;
+}
+
+/// Dummy gateway
+impl IsSmsGateway for () {
+    fn send_sms(&self, _message: SmsMessage) -> BoxFuture<(), SmsError> {
+        Box::new(future::ok(()))
+    }
+}
+
+/// Backend has SMS sending features
+pub trait HasSmsGateway
+where
+    Self: AsRef<<Self as HasSmsGateway>::SmsGateway>,
+{
+    type SmsGateway: IsSmsGateway;
+}
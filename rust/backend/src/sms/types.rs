@@ -0,0 +1,166 @@
+use serde::{
+    de::{self, Deserializer, Visitor},
+    Deserialize, Serialize, Serializer,
+};
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+/// Phone number in E.164 format
+///
+/// The separators like spaces, dashes, dots and parentheses will be removed.
+///
+/// ```
+/// use literium::sms::PhoneNumber;
+///
+/// let phone: PhoneNumber = "+1 (202) 555-0143".parse().unwrap();
+/// assert_eq!(phone.to_string(), "+12025550143");
+///
+/// assert!("12025550143".parse::<PhoneNumber>().is_err());
+/// assert!("+0123456789".parse::<PhoneNumber>().is_err());
+/// assert!("+1202555014300000".parse::<PhoneNumber>().is_err());
+/// ```
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct PhoneNumber(String);
+
+impl PhoneNumber {
+    /// Minimum number of digits
+    pub const MIN_DIGITS: usize = 7;
+    /// Maximum number of digits
+    pub const MAX_DIGITS: usize = 15;
+
+    /// Get number as string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Invalid phone number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhoneNumberError;
+
+impl Error for PhoneNumberError {}
+
+impl Display for PhoneNumberError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("Invalid phone number (E.164 expected)")
+    }
+}
+
+impl FromStr for PhoneNumber {
+    type Err = PhoneNumberError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let digits: String = src
+            .trim()
+            .chars()
+            .filter(|c| !(c.is_whitespace() || "-.()".contains(*c)))
+            .collect();
+
+        if !digits.starts_with('+') {
+            return Err(PhoneNumberError);
+        }
+
+        let digits = &digits[1..];
+
+        if digits.len() < Self::MIN_DIGITS
+            || digits.len() > Self::MAX_DIGITS
+            || digits.starts_with('0')
+            || !digits.bytes().all(|c| c.is_ascii_digit())
+        {
+            return Err(PhoneNumberError);
+        }
+
+        Ok(PhoneNumber(format!("+{}", digits)))
+    }
+}
+
+impl Display for PhoneNumber {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for PhoneNumber {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+struct PhoneNumberVisitor;
+
+impl<'de> Visitor<'de> for PhoneNumberVisitor {
+    type Value = PhoneNumber;
+
+    fn expecting(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter.write_str("phone number in E.164 format like +12025550143")
+    }
+
+    fn visit_str<E>(self, src: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        src.parse().map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for PhoneNumber {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PhoneNumberVisitor)
+    }
+}
+
+/// SMS message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmsMessage {
+    /// Recipient phone number
+    pub to: PhoneNumber,
+    /// Message text
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub enum SmsError {
+    BadConfig(String),
+    BadMessage,
+    GatewayError,
+}
+
+impl Error for SmsError {}
+
+impl Display for SmsError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        use self::SmsError::*;
+        match self {
+            BadConfig(error) => write!(f, "Invalid SMS gateway config: {}", error),
+            BadMessage => f.write_str("Bad SMS message"),
+            GatewayError => f.write_str("SMS gateway error"),
+        }
+    }
+}
+
+/// HTTP SMS gateway configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpSmsConfig {
+    /// Gateway endpoint URL
+    ///
+    /// The messages will be posted to it as JSON objects like
+    /// `{ "from": "Sender", "to": "+12025550143", "text": "..." }`.
+    pub url: String,
+
+    /// Bearer token for *Authorization* header
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// Sender name or number
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+}
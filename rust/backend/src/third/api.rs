@@ -0,0 +1,332 @@
+use super::{ThirdApiParams, ThirdError};
+use base::{BoxFuture, TimeStamp};
+use futures::{
+    future::{err, loop_fn, Either, Loop},
+    Future, Stream,
+};
+use http::{
+    client::{HasHttpClient, HttpClientError, IntoHttpRequest, IsHttpClient},
+    request::{Header, Method, NoBody, NoError, UrlWithQuery},
+    HttpRequestBuilder, StatusCode,
+};
+use httplib::HeaderMap;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// The way to pass access token to third service API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiAuth {
+    /// Using `Authorization: Bearer` header
+    Bearer,
+    /// Using `access_token` query param
+    Query,
+}
+
+/// Query params of API request
+pub type ApiQuery = BTreeMap<&'static str, String>;
+
+/// Rate limit state of third service API
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimit {
+    /// Max number of requests in window
+    pub limit: Option<u32>,
+    /// Remaining number of requests in window
+    pub remaining: Option<u32>,
+    /// Time when window will be reset
+    pub reset: Option<TimeStamp>,
+}
+
+impl RateLimit {
+    /// Get rate limit from `X-RateLimit-*` response headers
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        fn get<T: FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+            headers.get(name)?.to_str().ok()?.trim().parse().ok()
+        }
+
+        RateLimit {
+            limit: get(headers, "x-ratelimit-limit"),
+            remaining: get(headers, "x-ratelimit-remaining"),
+            // reset time in unix seconds
+            reset: get::<i64>(headers, "x-ratelimit-reset").map(|secs| TimeStamp::from(secs * 1000)),
+        }
+    }
+
+    /// No more requests allowed until reset
+    pub fn is_exceeded(&self) -> bool {
+        self.remaining == Some(0)
+    }
+}
+
+/// Page of API call results
+#[derive(Debug)]
+pub struct ApiPage<T> {
+    /// Result items
+    pub items: Vec<T>,
+    /// The cursor of next page if any
+    pub next: Option<String>,
+    /// Rate limit state after call
+    pub rate_limit: RateLimit,
+}
+
+/// Typed paginated third service API call
+///
+/// The pages is addressed by service-specific cursors (page number, offset, token ...etc).
+pub trait IsThirdApiCall: Clone + Send + 'static {
+    /// Result item
+    type Item: Send + 'static;
+
+    /// Response data
+    type Data: DeserializeOwned + Send + 'static;
+
+    /// The way to pass access token
+    fn auth(&self) -> ApiAuth {
+        ApiAuth::Bearer
+    }
+
+    /// Endpoint URL
+    fn url(&self) -> Cow<str>;
+
+    /// Request query params
+    ///
+    /// The cursor is `None` for first page.
+    fn query(&self, cursor: Option<&str>) -> ApiQuery;
+
+    /// Extract items and next page cursor from response
+    fn page(
+        &self,
+        data: Self::Data,
+        headers: &HeaderMap,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<Self::Item>, Option<String>), ThirdError>;
+}
+
+/// Fetch single page of API call results
+///
+/// The rejected access token (`401 Unauthorized`) is reported as `ThirdError::BadToken`
+/// so the caller can refresh it and retry. The exceeded rate limit is reported as
+/// `ThirdError::TooManyRequests`.
+pub fn fetch_api_page<S, C>(
+    state: &S,
+    call: &C,
+    access_token: &str,
+    cursor: Option<&str>,
+) -> BoxFuture<ApiPage<C::Item>, ThirdError>
+where
+    S: HasHttpClient,
+    C: IsThirdApiCall,
+{
+    let client: &S::HttpClient = state.as_ref();
+    let url = call.url().into_owned();
+    let query = call.query(cursor);
+    let accept = Header("Accept", "application/json", NoBody);
+
+    let request: Result<_, HttpClientError<NoError, NoError>> = match call.auth() {
+        ApiAuth::Bearer => Method(
+            "GET",
+            UrlWithQuery(
+                url,
+                query,
+                Header("Authorization", format!("Bearer {}", access_token), accept),
+            ),
+        ).into_request(HttpRequestBuilder::new()),
+        ApiAuth::Query => Method(
+            "GET",
+            UrlWithQuery(url, ThirdApiParams::new(access_token).with(query), accept),
+        ).into_request(HttpRequestBuilder::new()),
+    };
+
+    let request = match request {
+        Ok(request) => request,
+        Err(error) => {
+            error!("Unable to create API request: {}", error);
+            return Box::new(err(ThirdError::BackendError));
+        }
+    };
+
+    let call = call.clone();
+    let cursor = cursor.map(String::from);
+
+    Box::new(
+        client
+            .send_request(request)
+            .map_err(|error| {
+                error!("Error when calling API: {}", error);
+                ThirdError::ServiceError
+            }).and_then(move |response| {
+                let (parts, body) = response.into_parts();
+                let rate_limit = RateLimit::from_headers(&parts.headers);
+
+                if parts.status == StatusCode::UNAUTHORIZED {
+                    warn!("Access token rejected by API");
+                    return Either::A(err(ThirdError::BadToken));
+                }
+
+                if parts.status == StatusCode::TOO_MANY_REQUESTS
+                    || parts.status == StatusCode::FORBIDDEN && rate_limit.is_exceeded()
+                {
+                    warn!("API rate limit exceeded");
+                    return Either::A(err(ThirdError::TooManyRequests(
+                        rate_limit.reset.unwrap_or_else(TimeStamp::now),
+                    )));
+                }
+
+                if !parts.status.is_success() {
+                    error!("API call failed with status: {}", parts.status);
+                    return Either::A(err(ThirdError::ServiceError));
+                }
+
+                Either::B(
+                    body.concat2()
+                        .map_err(|error| {
+                            error!("Error when receiving API response: {}", error);
+                            ThirdError::ServiceError
+                        }).and_then(move |data| {
+                            let data = serde_json::from_slice(&data.into_bytes()).map_err(
+                                |error| {
+                                    error!("Unable to decode API response: {}", error);
+                                    ThirdError::ServiceError
+                                },
+                            )?;
+                            let (items, next) = call.page(
+                                data,
+                                &parts.headers,
+                                cursor.as_ref().map(String::as_str),
+                            )?;
+                            Ok(ApiPage {
+                                items,
+                                next,
+                                rate_limit,
+                            })
+                        }),
+                )
+            }),
+    )
+}
+
+/// Fetch all results of API call
+///
+/// The pages is fetched sequentially until last page or `max_pages` reached.
+pub fn fetch_api_all<S, C>(
+    state: &S,
+    call: C,
+    access_token: String,
+    max_pages: usize,
+) -> BoxFuture<Vec<C::Item>, ThirdError>
+where
+    S: HasHttpClient + Clone + Send + 'static,
+    C: IsThirdApiCall,
+{
+    let state = state.clone();
+
+    Box::new(loop_fn(
+        (Vec::new(), None, 1),
+        move |(mut items, cursor, pages): (Vec<C::Item>, Option<String>, usize)| {
+            fetch_api_page(
+                &state,
+                &call,
+                &access_token,
+                cursor.as_ref().map(String::as_str),
+            ).map(move |page| {
+                items.extend(page.items);
+                match page.next {
+                    Some(next) if pages < max_pages => Loop::Continue((items, Some(next), pages + 1)),
+                    _ => Loop::Break(items),
+                }
+            })
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use third::{github, recorded::RecordedClient};
+
+    #[test]
+    fn pagination() {
+        let client = RecordedClient::default()
+            .with_reply(
+                "https://api.github.com/user/emails?per_page=100",
+                200,
+                &[
+                    (
+                        "Link",
+                        r#"<https://api.github.com/user/emails?per_page=100&page=2>; rel="next", <https://api.github.com/user/emails?per_page=100&page=2>; rel="last""#,
+                    ),
+                    ("X-RateLimit-Limit", "5000"),
+                    ("X-RateLimit-Remaining", "4999"),
+                    ("X-RateLimit-Reset", "1546300800"),
+                ],
+                r#"[{ "email": "octocat@github.com", "verified": true, "primary": true }]"#,
+            ).with(
+                "https://api.github.com/user/emails?page=2&per_page=100",
+                r#"[{ "email": "octocat@example.com", "verified": false, "primary": false }]"#,
+            );
+
+        let call = github::UserEmails::default();
+
+        let page = fetch_api_page(&client, &call, "token", None).wait().unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.next, Some("2".into()));
+        assert_eq!(page.rate_limit.limit, Some(5000));
+        assert_eq!(page.rate_limit.remaining, Some(4999));
+        assert_eq!(
+            page.rate_limit.reset,
+            Some(TimeStamp::from(1546300800000i64))
+        );
+
+        let emails = fetch_api_all(&client, call.clone(), "token".into(), 10)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            emails
+                .iter()
+                .map(|email| email.email.as_str())
+                .collect::<Vec<_>>(),
+            vec!["octocat@github.com", "octocat@example.com"]
+        );
+
+        let emails = fetch_api_all(&client, call, "token".into(), 1).wait().unwrap();
+        assert_eq!(emails.len(), 1);
+    }
+
+    #[test]
+    fn errors() {
+        let client = RecordedClient::default()
+            .with_reply(
+                "https://api.github.com/user/emails",
+                401,
+                &[],
+                r#"{ "message": "Bad credentials" }"#,
+            ).with_reply(
+                "https://api.github.com/user/orgs",
+                403,
+                &[
+                    ("X-RateLimit-Remaining", "0"),
+                    ("X-RateLimit-Reset", "1546300800"),
+                ],
+                r#"{ "message": "API rate limit exceeded" }"#,
+            );
+
+        let service = github::Service::new(Default::default());
+
+        assert!(
+            match fetch_api_page(&client, &service.user_emails(), "token", None).wait() {
+                Err(ThirdError::BadToken) => true,
+                _ => false,
+            }
+        );
+
+        assert!(
+            match fetch_api_page(&client, &service.user_organizations(), "token", None).wait() {
+                Err(ThirdError::TooManyRequests(reset)) => {
+                    reset == TimeStamp::from(1546300800000i64)
+                }
+                _ => false,
+            }
+        );
+    }
+}
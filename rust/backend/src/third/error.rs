@@ -1,3 +1,4 @@
+use base::TimeStamp;
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
@@ -12,6 +13,10 @@ pub enum ThirdError {
     BadToken,
     /// Invalid user (blocked or blacklisted)
     BadUser,
+    /// API rate limit exceeded
+    ///
+    /// Contains the time after which requests may be retried.
+    TooManyRequests(TimeStamp),
 }
 
 impl Error for ThirdError {}
@@ -24,6 +29,7 @@ impl Display for ThirdError {
             ServiceError => f.write_str("Service error"),
             BadToken => f.write_str("Bad token"),
             BadUser => f.write_str("Bad user"),
+            TooManyRequests(_) => f.write_str("Too many requests"),
         }
     }
 }
@@ -2,13 +2,18 @@
 
 ## Third-party services integration
 
+Besides user profiles the services provides typed API calls (see [`IsThirdApiCall`]).
+The results is fetched page by page using [`fetch_api_page`] or all at once using [`fetch_api_all`].
+
 */
 
+mod api;
 mod error;
 mod service;
 mod traits;
 mod types;
 
+pub use self::api::*;
 pub use self::error::*;
 pub use self::service::*;
 pub use self::traits::*;
@@ -0,0 +1,249 @@
+/*!
+
+## Discord service integration and OAuth2 provider
+
+See Discord OAuth2 docs at https://discordapp.com/developers/docs/topics/oauth2.
+
+*/
+use auth::oauth2::IsOAuth2Provider;
+use base::{serde_extra::is_default, wrappers::DisplayIter, BoxFuture};
+use futures::Future;
+use http::{
+    client::{HasHttpClient, IsHttpClient},
+    request,
+};
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use third::{endpoint_url, IsThirdService, ThirdError};
+use user::{HasEmail, HasImageUrl, HasLocale, HasNickName, IsAccountData};
+
+/// Discord config
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Scope to use
+    #[serde(default = "default_scope")]
+    pub scope: Vec<Scope>,
+
+    /// Always prompt for authorization
+    #[serde(default)]
+    pub consent: bool,
+
+    /// Base URL to use instead of default
+    ///
+    /// See [`endpoint_url`](third::endpoint_url).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+}
+
+fn default_scope() -> Vec<Scope> {
+    vec![Scope::Identify, Scope::Email]
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scope: default_scope(),
+            consent: false,
+            base_url: None,
+        }
+    }
+}
+
+/// Discord scope
+///
+/// TODO: Add other scopes
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Scope {
+    /// Access to user profile without email (`identify`)
+    #[serde(rename = "identify")]
+    Identify,
+    /// Access to user email (`email`)
+    #[serde(rename = "email")]
+    Email,
+    /// Access to user guilds (`guilds`)
+    #[serde(rename = "guilds")]
+    Guilds,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        use self::Scope::*;
+        f.write_str(match self {
+            Identify => "identify",
+            Email => "email",
+            Guilds => "guilds",
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct AuthorizeParams {
+    #[serde(skip_serializing_if = "is_default")]
+    prompt: &'static str,
+}
+
+/// Discord service
+pub struct Service(Config);
+
+impl Service {
+    pub fn new(config: Config) -> Self {
+        Service(config)
+    }
+}
+
+#[derive(Deserialize)]
+struct UserProfile {
+    id: String,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    discriminator: String,
+    #[serde(default)]
+    avatar: Option<String>,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    verified: bool,
+    #[serde(default)]
+    locale: Option<String>,
+}
+
+impl<S, A> IsThirdService<S, A> for Service
+where
+    S: HasHttpClient,
+    A: IsAccountData + HasNickName + HasEmail + HasImageUrl + HasLocale + 'static,
+{
+    fn service_name(&self) -> Cow<str> {
+        "discord".into()
+    }
+
+    fn get_user_profile(&self, state: &S, access_token: Cow<str>) -> BoxFuture<A, ThirdError> {
+        use self::request::*;
+
+        let client: &S::HttpClient = state.as_ref();
+
+        Box::new(
+            client
+                .fetch(Method(
+                    "GET",
+                    Url(
+                        endpoint_url(&self.0.base_url, "https://discordapp.com/api/users/@me")
+                            .as_ref(),
+                        Header(
+                            "Authorization",
+                            format!("Bearer {}", access_token),
+                            Header("Accept", "application/json", NoBody),
+                        ),
+                    ),
+                )).map_err(|error| {
+                    error!("Error when fetching user profile: {}", error);
+                    ThirdError::ServiceError
+                }).map(JsonBody::into_inner)
+                .map(|data: UserProfile| {
+                    let mut account = A::create_new(data.id.clone());
+
+                    account.set_nick_name(Some(
+                        if data.discriminator.is_empty() || data.discriminator == "0" {
+                            data.username
+                        } else {
+                            format!("{}#{}", data.username, data.discriminator)
+                        },
+                    ));
+
+                    // the unverified email cannot be trusted
+                    if data.verified {
+                        if let Some(email) = data.email.and_then(|email| email.parse().ok()) {
+                            account.set_email(Some(email));
+                            account.set_email_verified(true);
+                        }
+                    }
+
+                    if let Some(avatar) = data.avatar {
+                        let avatar_url = format!(
+                            "https://cdn.discordapp.com/avatars/{}/{}.png",
+                            data.id, avatar
+                        );
+                        if let Ok(url) = avatar_url.parse() {
+                            account.set_image_url(Some(url));
+                        }
+                    }
+
+                    account.set_locale(data.locale.filter(|s| !s.is_empty()));
+
+                    account
+                }),
+        )
+    }
+}
+
+impl<S, A> IsOAuth2Provider<S, A> for Service
+where
+    A: IsAccountData + HasNickName + HasEmail + HasImageUrl + HasLocale + 'static,
+    S: HasHttpClient,
+{
+    type AuthorizeParams = AuthorizeParams;
+
+    fn authorize_url(&self) -> Cow<str> {
+        endpoint_url(&self.0.base_url, "https://discordapp.com/api/oauth2/authorize")
+    }
+
+    fn authorize_scope(&self) -> Cow<str> {
+        DisplayIter::wrap(&self.0.scope)
+            .separator(" ")
+            .to_string()
+            .into()
+    }
+
+    fn authorize_params(&self) -> Self::AuthorizeParams {
+        AuthorizeParams {
+            prompt: if self.0.consent { "consent" } else { "" },
+        }
+    }
+
+    fn access_token_url(&self) -> Cow<str> {
+        endpoint_url(&self.0.base_url, "https://discordapp.com/api/oauth2/token")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use third::service::recorded::RecordedClient;
+    use user::stub::AccountData;
+
+    #[test]
+    fn user_profile() {
+        let client = RecordedClient::default().with(
+            "https://discordapp.com/api/users/@me",
+            r#"{
+                "id": "80351110224678912",
+                "username": "Nelly",
+                "discriminator": "1337",
+                "avatar": "8342729096ea3675442027381ff50dfe",
+                "verified": false,
+                "email": "nelly@discordapp.com",
+                "locale": "en-US",
+                "mfa_enabled": false
+            }"#,
+        );
+
+        let service = Service::new(Config::default());
+
+        let account: AccountData = service
+            .get_user_profile(&client, "token".into())
+            .wait()
+            .unwrap();
+        assert_eq!(account.name, "80351110224678912");
+        assert_eq!(account.nick_name, Some("Nelly#1337".into()));
+        // unverified email
+        assert_eq!(account.email, None);
+        assert_eq!(
+            account.image_url.map(|url| url.into_inner().into_string()),
+            Some(
+                "https://cdn.discordapp.com/avatars/80351110224678912/8342729096ea3675442027381ff50dfe.png"
+                    .into()
+            )
+        );
+        assert_eq!(account.locale, Some("en-US".into()));
+    }
+}
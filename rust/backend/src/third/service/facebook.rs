@@ -0,0 +1,369 @@
+/*!
+
+## Facebook service integration and OAuth2 provider
+
+See Facebook Login docs at https://developers.facebook.com/docs/facebook-login/manually-build-a-login-flow.
+
+*/
+use auth::oauth2::IsOAuth2Provider;
+use base::{serde_extra::is_default, wrappers::DisplayIter, BoxFuture, TimeStamp};
+use futures::Future;
+use http::{
+    client::{HasHttpClient, IsHttpClient},
+    request,
+};
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use third::{endpoint_url, IsThirdService, ThirdApiParams, ThirdError};
+use user::{
+    HasBirthDate, HasEmail, HasFamilyName, HasFullName, HasGender, HasGivenName, HasHomeUrl,
+    HasImageUrl, HasLocale, HasMiddleName, IsAccountData,
+};
+
+/// Facebook config
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Scope to use
+    #[serde(default = "default_scope")]
+    pub scope: Vec<Scope>,
+
+    /// Graph API version to use
+    #[serde(default = "default_version")]
+    pub version: String,
+
+    /// Re-request declined permissions
+    #[serde(default)]
+    pub rerequest: bool,
+
+    /// Base URL to use instead of default
+    ///
+    /// See [`endpoint_url`](third::endpoint_url).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+}
+
+fn default_scope() -> Vec<Scope> {
+    vec![Scope::PublicProfile, Scope::Email]
+}
+
+fn default_version() -> String {
+    "v3.2".into()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scope: default_scope(),
+            version: default_version(),
+            rerequest: false,
+            base_url: None,
+        }
+    }
+}
+
+/// Facebook scope
+///
+/// TODO: Add other permissions
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Scope {
+    /// Access to public profile (`public_profile`)
+    #[serde(rename = "public_profile")]
+    PublicProfile,
+    /// Access to primary email (`email`)
+    #[serde(rename = "email")]
+    Email,
+    /// Access to birthday (`user_birthday`)
+    #[serde(rename = "user_birthday")]
+    UserBirthday,
+    /// Access to gender (`user_gender`)
+    #[serde(rename = "user_gender")]
+    UserGender,
+    /// Access to profile link (`user_link`)
+    #[serde(rename = "user_link")]
+    UserLink,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        use self::Scope::*;
+        f.write_str(match self {
+            PublicProfile => "public_profile",
+            Email => "email",
+            UserBirthday => "user_birthday",
+            UserGender => "user_gender",
+            UserLink => "user_link",
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct AuthorizeParams {
+    #[serde(skip_serializing_if = "is_default")]
+    auth_type: &'static str,
+}
+
+/// Facebook service
+pub struct Service(Config);
+
+impl Service {
+    pub fn new(config: Config) -> Self {
+        Service(config)
+    }
+}
+
+#[derive(Serialize)]
+struct UserGetRequest<'a> {
+    /// Requested fields
+    fields: &'a str,
+}
+
+#[derive(Deserialize)]
+struct UserProfile {
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    first_name: String,
+    #[serde(default)]
+    middle_name: String,
+    #[serde(default)]
+    last_name: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    gender: String,
+    /// Birthday in `MM/DD/YYYY` format
+    #[serde(default)]
+    birthday: String,
+    #[serde(default)]
+    link: String,
+    #[serde(default)]
+    locale: String,
+    #[serde(default)]
+    picture: Option<Picture>,
+}
+
+#[derive(Deserialize)]
+struct Picture {
+    data: PictureData,
+}
+
+#[derive(Deserialize)]
+struct PictureData {
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    is_silhouette: bool,
+}
+
+impl<S, A> IsThirdService<S, A> for Service
+where
+    S: HasHttpClient,
+    A: IsAccountData
+        + HasFullName
+        + HasGivenName
+        + HasMiddleName
+        + HasFamilyName
+        + HasGender
+        + HasBirthDate
+        + HasLocale
+        + HasEmail
+        + HasImageUrl
+        + HasHomeUrl
+        + 'static,
+{
+    fn service_name(&self) -> Cow<str> {
+        "facebook".into()
+    }
+
+    fn get_user_profile(&self, state: &S, access_token: Cow<str>) -> BoxFuture<A, ThirdError> {
+        use self::request::*;
+
+        let client: &S::HttpClient = state.as_ref();
+
+        Box::new(
+            client
+                .fetch(Method(
+                    "GET",
+                    UrlWithQuery(
+                        endpoint_url(
+                            &self.0.base_url,
+                            &format!("https://graph.facebook.com/{}/me", self.0.version),
+                        ).into_owned(),
+                        ThirdApiParams::new(&access_token).with(UserGetRequest {
+                            fields: "id,name,first_name,middle_name,last_name,email,gender,birthday,link,locale,picture",
+                        }),
+                        Header("Accept", "application/json", NoBody),
+                    ),
+                )).map_err(|error| {
+                    error!("Error when fetching user profile: {}", error);
+                    ThirdError::ServiceError
+                }).map(JsonBody::into_inner)
+                .map(|data: UserProfile| {
+                    let mut account = A::create_new(data.id);
+
+                    if !data.name.is_empty() {
+                        account.set_full_name(Some(data.name));
+                    }
+
+                    if !data.first_name.is_empty() {
+                        account.set_given_name(Some(data.first_name));
+                    }
+
+                    if !data.middle_name.is_empty() {
+                        account.set_middle_name(Some(data.middle_name));
+                    }
+
+                    if !data.last_name.is_empty() {
+                        account.set_family_name(Some(data.last_name));
+                    }
+
+                    if !data.gender.is_empty() {
+                        if let Ok(gender) = data.gender.parse() {
+                            account.set_gender(Some(gender));
+                        }
+                    }
+
+                    // the year may be omitted due to privacy settings
+                    if !data.birthday.is_empty() {
+                        if let Ok(date) = TimeStamp::parse(&data.birthday, &"%m/%d/%Y") {
+                            account.set_birth_date(Some(date));
+                        }
+                    }
+
+                    if !data.locale.is_empty() {
+                        account.set_locale(Some(data.locale));
+                    }
+
+                    if !data.email.is_empty() {
+                        if let Ok(email) = data.email.parse() {
+                            account.set_email(Some(email));
+                        }
+                    }
+
+                    if let Some(picture) = data.picture {
+                        if !picture.data.is_silhouette && !picture.data.url.is_empty() {
+                            if let Ok(url) = picture.data.url.parse() {
+                                account.set_image_url(Some(url));
+                            }
+                        }
+                    }
+
+                    if !data.link.is_empty() {
+                        if let Ok(url) = data.link.parse() {
+                            account.set_home_url(Some(url));
+                        }
+                    }
+
+                    account
+                }),
+        )
+    }
+}
+
+impl<S, A> IsOAuth2Provider<S, A> for Service
+where
+    A: IsAccountData
+        + HasFullName
+        + HasGivenName
+        + HasMiddleName
+        + HasFamilyName
+        + HasGender
+        + HasBirthDate
+        + HasLocale
+        + HasEmail
+        + HasImageUrl
+        + HasHomeUrl
+        + 'static,
+    S: HasHttpClient,
+{
+    type AuthorizeParams = AuthorizeParams;
+
+    fn authorize_url(&self) -> Cow<str> {
+        endpoint_url(
+            &self.0.base_url,
+            &format!("https://www.facebook.com/{}/dialog/oauth", self.0.version),
+        ).into_owned()
+        .into()
+    }
+
+    fn authorize_scope(&self) -> Cow<str> {
+        DisplayIter::wrap(&self.0.scope)
+            .separator(",")
+            .to_string()
+            .into()
+    }
+
+    fn authorize_params(&self) -> Self::AuthorizeParams {
+        AuthorizeParams {
+            auth_type: if self.0.rerequest { "rerequest" } else { "" },
+        }
+    }
+
+    fn access_token_url(&self) -> Cow<str> {
+        endpoint_url(
+            &self.0.base_url,
+            &format!(
+                "https://graph.facebook.com/{}/oauth/access_token",
+                self.0.version
+            ),
+        ).into_owned()
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use third::service::recorded::RecordedClient;
+    use user::{stub::AccountData, Gender};
+
+    #[test]
+    fn user_profile() {
+        let client = RecordedClient::default().with(
+            "https://graph.facebook.com/v3.2/me",
+            r#"{
+                "id": "10102345678901234",
+                "name": "Mary Ann Smith",
+                "first_name": "Mary",
+                "middle_name": "Ann",
+                "last_name": "Smith",
+                "email": "mary@example.com",
+                "gender": "female",
+                "birthday": "08/31/1990",
+                "locale": "en_US",
+                "picture": {
+                    "data": {
+                        "height": 50,
+                        "is_silhouette": false,
+                        "url": "https://platform-lookaside.fbsbx.com/platform/profilepic/?asid=10102345678901234",
+                        "width": 50
+                    }
+                }
+            }"#,
+        );
+
+        let service = Service::new(Config::default());
+        assert_eq!(
+            IsOAuth2Provider::<RecordedClient, AccountData>::authorize_scope(&service),
+            "public_profile,email"
+        );
+
+        let account: AccountData = service
+            .get_user_profile(&client, "token".into())
+            .wait()
+            .unwrap();
+        assert_eq!(account.name, "10102345678901234");
+        assert_eq!(account.full_name, Some("Mary Ann Smith".into()));
+        assert_eq!(account.middle_name, Some("Ann".into()));
+        assert_eq!(account.family_name, Some("Smith".into()));
+        assert_eq!(account.email, Some("mary@example.com".parse().unwrap()));
+        assert!(match account.gender {
+            Some(Gender::Female) => true,
+            _ => false,
+        });
+        assert!(account.birth_date.is_some());
+        assert!(account.image_url.is_some());
+        assert!(account.home_url.is_none());
+    }
+}
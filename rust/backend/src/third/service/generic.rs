@@ -0,0 +1,312 @@
+/*!
+
+## Generic OAuth2 provider defined by config
+
+This provider allows to use any OAuth2 service (like in-house SSO server) without writing code.
+The endpoints, scope, extra authorize params and user info fields are set in config.
+The config usually is a part of service options in [`OAuth2Options`](auth::oauth2::OAuth2Options).
+
+The user info fields is selected using JSON pointers (see [RFC6901](https://tools.ietf.org/html/rfc6901)).
+
+*/
+use auth::oauth2::{IsOAuth2Provider, OAuth2Options};
+use base::BoxFuture;
+use futures::Future;
+use http::{
+    client::{HasHttpClient, IsHttpClient},
+    request,
+};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use third::{IsThirdService, ThirdError};
+use user::{HasEmail, HasFullName, HasImageUrl, HasNickName, IsAccountData};
+
+/// Generic provider config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Authorization endpoint URL
+    pub authorize_url: String,
+
+    /// Access token endpoint URL
+    pub token_url: String,
+
+    /// User info endpoint URL
+    pub userinfo_url: String,
+
+    /// Scope to use
+    #[serde(default)]
+    pub scope: Vec<String>,
+
+    /// Extra authorize params
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+
+    /// User info fields
+    #[serde(default)]
+    pub fields: Fields,
+}
+
+/// User info fields mapping
+///
+/// Each field is a JSON pointer to value in user info.
+/// The `id` field is required, the other fields is optional.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fields {
+    /// Unique account identifier
+    #[serde(default = "default_id")]
+    pub id: String,
+
+    /// Login (or nick name)
+    #[serde(default = "default_login")]
+    pub login: String,
+
+    /// Email address
+    #[serde(default = "default_email")]
+    pub email: String,
+
+    /// Full name
+    #[serde(default = "default_name")]
+    pub name: String,
+
+    /// Avatar image URL
+    #[serde(default = "default_avatar")]
+    pub avatar: String,
+}
+
+fn default_id() -> String {
+    "/id".into()
+}
+
+fn default_login() -> String {
+    "/login".into()
+}
+
+fn default_email() -> String {
+    "/email".into()
+}
+
+fn default_name() -> String {
+    "/name".into()
+}
+
+fn default_avatar() -> String {
+    "/avatar_url".into()
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Self {
+            id: default_id(),
+            login: default_login(),
+            email: default_email(),
+            name: default_name(),
+            avatar: default_avatar(),
+        }
+    }
+}
+
+impl Fields {
+    /// Get field value as string
+    ///
+    /// The numbers is converted to strings, the empty strings is ignored.
+    fn get<'a>(&self, data: &'a Value, pointer: &str) -> Option<Cow<'a, str>> {
+        match data.pointer(pointer)? {
+            Value::String(value) if !value.is_empty() => Some(value.as_str().into()),
+            Value::Number(value) => Some(value.to_string().into()),
+            _ => None,
+        }
+    }
+}
+
+/// Generic service
+pub struct Service {
+    name: String,
+    config: Config,
+}
+
+impl Service {
+    /// Create service using name and config
+    pub fn new<S: Into<String>>(name: S, config: Config) -> Self {
+        Service {
+            name: name.into(),
+            config,
+        }
+    }
+
+    /// Create service using provider config from OAuth2 options
+    pub fn from_options(options: &OAuth2Options, name: &str) -> Option<Self> {
+        options
+            .services
+            .iter()
+            .find(|service| service.name == name)
+            .and_then(|service| service.provider.clone())
+            .map(|config| Service::new(name, config))
+    }
+}
+
+impl<S, A> IsThirdService<S, A> for Service
+where
+    S: HasHttpClient,
+    A: IsAccountData + HasNickName + HasFullName + HasEmail + HasImageUrl + Send + 'static,
+{
+    fn service_name(&self) -> Cow<str> {
+        self.name.as_str().into()
+    }
+
+    fn get_user_profile(&self, state: &S, access_token: Cow<str>) -> BoxFuture<A, ThirdError> {
+        use self::request::*;
+
+        let client: &S::HttpClient = state.as_ref();
+        let fields = self.config.fields.clone();
+
+        Box::new(
+            client
+                .fetch(Method(
+                    "GET",
+                    Url(
+                        self.config.userinfo_url.as_str(),
+                        Header(
+                            "Authorization",
+                            format!("Bearer {}", access_token),
+                            Header("Accept", "application/json", NoBody),
+                        ),
+                    ),
+                )).map_err(|error| {
+                    error!("Error when fetching user profile: {}", error);
+                    ThirdError::ServiceError
+                }).map(JsonBody::into_inner)
+                .and_then(move |data: Value| {
+                    let mut account = match fields.get(&data, &fields.id) {
+                        Some(id) => A::create_new(id),
+                        None => {
+                            error!("Missing account id in user info");
+                            return Err(ThirdError::ServiceError);
+                        }
+                    };
+
+                    account.set_nick_name(fields.get(&data, &fields.login));
+                    account.set_full_name(fields.get(&data, &fields.name));
+
+                    if let Some(email) = fields
+                        .get(&data, &fields.email)
+                        .and_then(|email| email.parse().ok())
+                    {
+                        account.set_email(Some(email));
+                    }
+
+                    if let Some(url) = fields
+                        .get(&data, &fields.avatar)
+                        .and_then(|url| url.parse().ok())
+                    {
+                        account.set_image_url(Some(url));
+                    }
+
+                    Ok(account)
+                }),
+        )
+    }
+}
+
+impl<S, A> IsOAuth2Provider<S, A> for Service
+where
+    A: IsAccountData + HasNickName + HasFullName + HasEmail + HasImageUrl + Send + 'static,
+    S: HasHttpClient,
+{
+    type AuthorizeParams = BTreeMap<String, String>;
+
+    fn authorize_url(&self) -> Cow<str> {
+        self.config.authorize_url.as_str().into()
+    }
+
+    fn authorize_scope(&self) -> Cow<str> {
+        self.config.scope.join(" ").into()
+    }
+
+    fn authorize_params(&self) -> Self::AuthorizeParams {
+        self.config.params.clone()
+    }
+
+    fn access_token_url(&self) -> Cow<str> {
+        self.config.token_url.as_str().into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::from_str;
+    use third::service::recorded::RecordedClient;
+    use user::stub::AccountData;
+
+    #[test]
+    fn user_profile() {
+        let options: OAuth2Options = from_str(
+            r#"{
+                "services": [{
+                    "name": "sso",
+                    "client_id": "client",
+                    "client_secret": "secret",
+                    "provider": {
+                        "authorize_url": "https://sso.example.com/authorize",
+                        "token_url": "https://sso.example.com/token",
+                        "userinfo_url": "https://sso.example.com/me",
+                        "scope": ["profile", "mail"],
+                        "params": { "realm": "staff" },
+                        "fields": {
+                            "id": "/user/uid",
+                            "login": "/user/account",
+                            "email": "/user/contacts/0/mail",
+                            "name": "/user/display_name"
+                        }
+                    }
+                }],
+                "redirect": "https://my-site.tld/oauth2"
+            }"#,
+        ).unwrap();
+
+        let service = Service::from_options(&options, "sso").unwrap();
+        assert!(Service::from_options(&options, "other").is_none());
+        assert_eq!(
+            IsOAuth2Provider::<RecordedClient, AccountData>::authorize_scope(&service),
+            "profile mail"
+        );
+        assert_eq!(
+            IsOAuth2Provider::<RecordedClient, AccountData>::authorize_params(&service)["realm"],
+            "staff"
+        );
+
+        let client = RecordedClient::default().with(
+            "https://sso.example.com/me",
+            r#"{
+                "user": {
+                    "uid": 42,
+                    "account": "jdoe",
+                    "display_name": "John Doe",
+                    "contacts": [{ "mail": "jdoe@example.com" }]
+                }
+            }"#,
+        );
+
+        let account: AccountData = service
+            .get_user_profile(&client, "token".into())
+            .wait()
+            .unwrap();
+        assert_eq!(account.name, "42");
+        assert_eq!(account.nick_name, Some("jdoe".into()));
+        assert_eq!(account.full_name, Some("John Doe".into()));
+        assert_eq!(account.email, Some("jdoe@example.com".parse().unwrap()));
+        assert!(account.image_url.is_none());
+
+        // missing id
+        let client = RecordedClient::default().with("https://sso.example.com/me", "{}");
+        assert!(
+            IsThirdService::<RecordedClient, AccountData>::get_user_profile(
+                &service,
+                &client,
+                "token".into()
+            ).wait()
+            .is_err()
+        );
+    }
+}
@@ -7,14 +7,20 @@ See Github OAuth2 docs at https://developer.github.com/apps/building-oauth-apps/
 */
 use auth::oauth2::IsOAuth2Provider;
 use base::{serde_extra::timestamp, wrappers::DisplayIter, BoxFuture, TimeStamp};
-use futures::Future;
+use futures::{
+    future::{ok, Either},
+    Future,
+};
 use http::{
     client::{HasHttpClient, IsHttpClient},
     request,
 };
+use httplib::HeaderMap;
 use std::borrow::Cow;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use third::{IsThirdService, ThirdApiParams, ThirdError};
+use third::{
+    endpoint_url, ApiQuery, IsThirdApiCall, IsThirdService, ThirdApiParams, ThirdError,
+};
 use user::{
     HasAbout, HasCompany, HasCreateDate, HasEmail, HasFullName, HasHomeUrl, HasImageUrl,
     HasLocation, HasNickName, IsAccountData,
@@ -30,6 +36,18 @@ pub struct Config {
     /// Allow signup
     #[serde(default = "default_allow_signup")]
     pub allow_signup: bool,
+
+    /// Fetch user organizations
+    ///
+    /// The private memberships are available with `read:org` scope only.
+    #[serde(default)]
+    pub organizations: bool,
+
+    /// Base URL to use instead of default
+    ///
+    /// See [`endpoint_url`](third::endpoint_url).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
 }
 
 fn default_scope() -> Vec<Scope> {
@@ -49,6 +67,8 @@ impl Default for Config {
         Self {
             scope: default_scope(),
             allow_signup: default_allow_signup(),
+            organizations: false,
+            base_url: None,
         }
     }
 }
@@ -72,6 +92,9 @@ pub enum Scope {
     /// Grants access to follow or unfollow other users (`user:follow`)
     #[serde(rename = "user:follow")]
     UserFollow,
+    /// Grants read access to a user's organizations membership (`read:org`)
+    #[serde(rename = "read:org")]
+    ReadOrg,
 }
 
 impl Display for Scope {
@@ -82,6 +105,7 @@ impl Display for Scope {
             ReadUser => "read:user",
             UserEmail => "user:email",
             UserFollow => "user:follow",
+            ReadOrg => "read:org",
         })
     }
 }
@@ -99,6 +123,33 @@ impl Service {
     pub fn new(config: Config) -> Self {
         Service(config)
     }
+
+    /// User email addresses API call
+    pub fn user_emails(&self) -> UserEmails {
+        UserEmails {
+            base_url: self.0.base_url.clone(),
+        }
+    }
+
+    /// User organizations API call
+    pub fn user_organizations(&self) -> UserOrganizations {
+        UserOrganizations {
+            base_url: self.0.base_url.clone(),
+        }
+    }
+
+    /// User teams API call
+    pub fn user_teams(&self) -> UserTeams {
+        UserTeams {
+            base_url: self.0.base_url.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct UserOrganization {
+    #[serde(default)]
+    login: String,
 }
 
 #[derive(Deserialize)]
@@ -141,6 +192,7 @@ where
         + HasHomeUrl
         + HasCompany
         + HasAbout
+        + Send
         + 'static,
 {
     fn service_name(&self) -> Cow<str> {
@@ -152,12 +204,34 @@ where
 
         let client: &S::HttpClient = state.as_ref();
 
+        let organizations = if self.0.organizations {
+            Either::A(
+                client
+                    .fetch(Method(
+                        "GET",
+                        UrlWithQuery(
+                            endpoint_url(&self.0.base_url, "https://api.github.com/user/orgs"),
+                            ThirdApiParams::new(&access_token),
+                            Header("Accept", "application/json", NoBody),
+                        ),
+                    )).map_err(|error| {
+                        error!("Error when fetching user organizations: {}", error);
+                        ThirdError::ServiceError
+                    }).map(JsonBody::into_inner)
+                    .map(|data: Vec<UserOrganization>| {
+                        data.into_iter().map(|org| org.login).collect()
+                    }),
+            )
+        } else {
+            Either::B(ok(Vec::new()))
+        };
+
         Box::new(
             client
                 .fetch(Method(
                     "GET",
                     UrlWithQuery(
-                        "https://api.github.com/user",
+                        endpoint_url(&self.0.base_url, "https://api.github.com/user"),
                         ThirdApiParams::new(&access_token),
                         Header("Accept", "application/json", NoBody),
                     ),
@@ -210,12 +284,156 @@ where
                         account.set_about(Some(data.bio));
                     }
 
+                    account
+                }).join(organizations)
+                .map(|(mut account, organizations)| {
+                    account.set_organizations(organizations);
                     account
                 }),
         )
     }
 }
 
+/// User email addresses API call
+///
+/// Requires `user:email` scope.
+#[derive(Clone, Default)]
+pub struct UserEmails {
+    base_url: Option<String>,
+}
+
+/// User organizations API call
+///
+/// The private memberships are available with `read:org` scope only.
+#[derive(Clone, Default)]
+pub struct UserOrganizations {
+    base_url: Option<String>,
+}
+
+/// User teams API call
+///
+/// Requires `read:org` scope.
+#[derive(Clone, Default)]
+pub struct UserTeams {
+    base_url: Option<String>,
+}
+
+/// User email address
+#[derive(Debug, Deserialize)]
+pub struct Email {
+    pub email: String,
+    #[serde(default)]
+    pub primary: bool,
+    #[serde(default)]
+    pub verified: bool,
+}
+
+/// Organization
+#[derive(Debug, Deserialize)]
+pub struct Organization {
+    pub id: u64,
+    pub login: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Team
+#[derive(Debug, Deserialize)]
+pub struct Team {
+    pub id: u64,
+    pub name: String,
+    pub slug: String,
+    pub organization: Organization,
+}
+
+/// Query of page
+fn page_query(cursor: Option<&str>) -> ApiQuery {
+    let mut query = ApiQuery::new();
+    query.insert("per_page", "100".into());
+    if let Some(page) = cursor {
+        query.insert("page", page.into());
+    }
+    query
+}
+
+/// Get number of next page from `Link` header
+fn next_page(headers: &HeaderMap) -> Option<String> {
+    let links = headers.get("link")?.to_str().ok()?;
+    let link = links.split(',').find(|link| link.contains("rel=\"next\""))?;
+    let url = link.split(';').next()?.trim().trim_matches(|c| c == '<' || c == '>');
+    let query = url.splitn(2, '?').nth(1)?;
+    query
+        .split('&')
+        .find(|param| param.starts_with("page="))
+        .map(|param| param["page=".len()..].into())
+}
+
+impl IsThirdApiCall for UserEmails {
+    type Item = Email;
+    type Data = Vec<Email>;
+
+    fn url(&self) -> Cow<str> {
+        endpoint_url(&self.base_url, "https://api.github.com/user/emails")
+    }
+
+    fn query(&self, cursor: Option<&str>) -> ApiQuery {
+        page_query(cursor)
+    }
+
+    fn page(
+        &self,
+        data: Self::Data,
+        headers: &HeaderMap,
+        _cursor: Option<&str>,
+    ) -> Result<(Vec<Self::Item>, Option<String>), ThirdError> {
+        Ok((data, next_page(headers)))
+    }
+}
+
+impl IsThirdApiCall for UserOrganizations {
+    type Item = Organization;
+    type Data = Vec<Organization>;
+
+    fn url(&self) -> Cow<str> {
+        endpoint_url(&self.base_url, "https://api.github.com/user/orgs")
+    }
+
+    fn query(&self, cursor: Option<&str>) -> ApiQuery {
+        page_query(cursor)
+    }
+
+    fn page(
+        &self,
+        data: Self::Data,
+        headers: &HeaderMap,
+        _cursor: Option<&str>,
+    ) -> Result<(Vec<Self::Item>, Option<String>), ThirdError> {
+        Ok((data, next_page(headers)))
+    }
+}
+
+impl IsThirdApiCall for UserTeams {
+    type Item = Team;
+    type Data = Vec<Team>;
+
+    fn url(&self) -> Cow<str> {
+        endpoint_url(&self.base_url, "https://api.github.com/user/teams")
+    }
+
+    fn query(&self, cursor: Option<&str>) -> ApiQuery {
+        page_query(cursor)
+    }
+
+    fn page(
+        &self,
+        data: Self::Data,
+        headers: &HeaderMap,
+        _cursor: Option<&str>,
+    ) -> Result<(Vec<Self::Item>, Option<String>), ThirdError> {
+        Ok((data, next_page(headers)))
+    }
+}
+
 impl<S, A> IsOAuth2Provider<S, A> for Service
 where
     A: IsAccountData
@@ -228,13 +446,14 @@ where
         + HasHomeUrl
         + HasCompany
         + HasAbout
+        + Send
         + 'static,
     S: HasHttpClient,
 {
     type AuthorizeParams = AuthorizeParams;
 
     fn authorize_url(&self) -> Cow<str> {
-        "https://github.com/login/oauth/authorize".into()
+        endpoint_url(&self.0.base_url, "https://github.com/login/oauth/authorize")
     }
 
     fn authorize_scope(&self) -> Cow<str> {
@@ -250,7 +469,7 @@ where
         }
     }
 
-    fn access_token_url(&self) -> Cow<'static, str> {
-        "https://github.com/login/oauth/access_token".into()
+    fn access_token_url(&self) -> Cow<str> {
+        endpoint_url(&self.0.base_url, "https://github.com/login/oauth/access_token")
     }
 }
@@ -0,0 +1,336 @@
+/*!
+
+## GitLab service integration and OAuth2 provider
+
+See GitLab OAuth2 docs at https://docs.gitlab.com/ee/api/oauth2.html.
+
+The self-hosted instances is supported using `base_url` config option.
+
+*/
+use auth::oauth2::IsOAuth2Provider;
+use base::{wrappers::DisplayIter, BoxFuture, EmptyMap, TimeStamp};
+use futures::Future;
+use http::{
+    client::{HasHttpClient, IsHttpClient},
+    request,
+};
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use third::{IsThirdService, ThirdError};
+use user::{
+    HasAbout, HasCompany, HasCreateDate, HasEmail, HasFullName, HasHomeUrl, HasImageUrl,
+    HasLocation, HasNickName, HasPosition, IsAccountData,
+};
+
+/// GitLab config
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Base URL of instance
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+
+    /// Scope to use
+    #[serde(default = "default_scope")]
+    pub scope: Vec<Scope>,
+}
+
+fn default_base_url() -> String {
+    "https://gitlab.com".into()
+}
+
+fn default_scope() -> Vec<Scope> {
+    vec![Scope::ReadUser]
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            base_url: default_base_url(),
+            scope: default_scope(),
+        }
+    }
+}
+
+/// GitLab scope
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Scope {
+    /// Grants read access to authenticated user profile (`read_user`)
+    #[serde(rename = "read_user")]
+    ReadUser,
+    /// Grants full access to API (`api`)
+    #[serde(rename = "api")]
+    Api,
+    /// Grants OpenID Connect authentication (`openid`)
+    #[serde(rename = "openid")]
+    OpenId,
+    /// Grants read access to user profile using OpenID Connect (`profile`)
+    #[serde(rename = "profile")]
+    Profile,
+    /// Grants read access to user primary email using OpenID Connect (`email`)
+    #[serde(rename = "email")]
+    Email,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        use self::Scope::*;
+        f.write_str(match self {
+            ReadUser => "read_user",
+            Api => "api",
+            OpenId => "openid",
+            Profile => "profile",
+            Email => "email",
+        })
+    }
+}
+
+/// GitLab service
+pub struct Service(Config);
+
+impl Service {
+    pub fn new(config: Config) -> Self {
+        Service(config)
+    }
+
+    fn url(&self, path: &str) -> String {
+        self.0.base_url.trim_end_matches('/').to_string() + path
+    }
+}
+
+#[derive(Deserialize)]
+struct UserProfile {
+    id: u64,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    avatar_url: Option<String>,
+    #[serde(default)]
+    web_url: String,
+    #[serde(default)]
+    website_url: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    public_email: Option<String>,
+    #[serde(default)]
+    location: Option<String>,
+    #[serde(default)]
+    bio: Option<String>,
+    #[serde(default)]
+    organization: Option<String>,
+    #[serde(default)]
+    job_title: String,
+    #[serde(default)]
+    created_at: String,
+}
+
+/// Parse ISO8601 time ignoring fractional seconds
+fn parse_date(src: &str) -> Option<TimeStamp> {
+    let src = match src.find('.') {
+        Some(pos) => {
+            let end = src[pos + 1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(src.len(), |len| pos + 1 + len);
+            format!("{}{}", &src[..pos], &src[end..])
+        }
+        None => src.into(),
+    };
+    TimeStamp::parse(&src, &TimeStamp::ISO8601).ok()
+}
+
+impl<S, A> IsThirdService<S, A> for Service
+where
+    S: HasHttpClient,
+    A: IsAccountData
+        + HasNickName
+        + HasFullName
+        + HasCreateDate
+        + HasLocation
+        + HasEmail
+        + HasImageUrl
+        + HasHomeUrl
+        + HasCompany
+        + HasPosition
+        + HasAbout
+        + 'static,
+{
+    fn service_name(&self) -> Cow<str> {
+        "gitlab".into()
+    }
+
+    fn get_user_profile(&self, state: &S, access_token: Cow<str>) -> BoxFuture<A, ThirdError> {
+        use self::request::*;
+
+        let client: &S::HttpClient = state.as_ref();
+
+        Box::new(
+            client
+                .fetch(Method(
+                    "GET",
+                    Url(
+                        self.url("/api/v4/user").as_str(),
+                        Header(
+                            "Authorization",
+                            format!("Bearer {}", access_token),
+                            Header("Accept", "application/json", NoBody),
+                        ),
+                    ),
+                )).map_err(|error| {
+                    error!("Error when fetching user profile: {}", error);
+                    ThirdError::ServiceError
+                }).map(JsonBody::into_inner)
+                .map(|data: UserProfile| {
+                    let mut account = A::create_new(data.id.to_string());
+
+                    account.set_nick_name(Some(data.username));
+
+                    if !data.name.is_empty() {
+                        account.set_full_name(Some(data.name));
+                    }
+
+                    if let Some(date) = parse_date(&data.created_at) {
+                        account.set_create_date(Some(date));
+                    }
+
+                    if let Some(location) = data.location.filter(|s| !s.is_empty()) {
+                        account.set_location(Some(location));
+                    }
+
+                    // the private email is available with `read_user` scope only
+                    let email = if !data.email.is_empty() {
+                        Some(data.email)
+                    } else {
+                        data.public_email
+                    };
+                    if let Some(email) = email.and_then(|email| email.parse().ok()) {
+                        account.set_email(Some(email));
+                    }
+
+                    if let Some(url) = data.avatar_url.and_then(|url| url.parse().ok()) {
+                        account.set_image_url(Some(url));
+                    }
+
+                    if !data.website_url.is_empty() {
+                        if let Ok(url) = data.website_url.parse() {
+                            account.set_home_url(Some(url));
+                        }
+                    } else if !data.web_url.is_empty() {
+                        if let Ok(url) = data.web_url.parse() {
+                            account.set_home_url(Some(url));
+                        }
+                    }
+
+                    if let Some(company) = data.organization.filter(|s| !s.is_empty()) {
+                        account.set_company(Some(company));
+                    }
+
+                    if !data.job_title.is_empty() {
+                        account.set_position(Some(data.job_title));
+                    }
+
+                    if let Some(bio) = data.bio.filter(|s| !s.is_empty()) {
+                        account.set_about(Some(bio));
+                    }
+
+                    account
+                }),
+        )
+    }
+}
+
+impl<S, A> IsOAuth2Provider<S, A> for Service
+where
+    A: IsAccountData
+        + HasNickName
+        + HasFullName
+        + HasCreateDate
+        + HasLocation
+        + HasEmail
+        + HasImageUrl
+        + HasHomeUrl
+        + HasCompany
+        + HasPosition
+        + HasAbout
+        + 'static,
+    S: HasHttpClient,
+{
+    type AuthorizeParams = EmptyMap;
+
+    fn authorize_url(&self) -> Cow<str> {
+        self.url("/oauth/authorize").into()
+    }
+
+    fn authorize_scope(&self) -> Cow<str> {
+        DisplayIter::wrap(&self.0.scope)
+            .separator(" ")
+            .to_string()
+            .into()
+    }
+
+    fn authorize_params(&self) -> Self::AuthorizeParams {
+        EmptyMap
+    }
+
+    fn access_token_url(&self) -> Cow<str> {
+        self.url("/oauth/token").into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use third::service::recorded::RecordedClient;
+    use user::stub::AccountData;
+
+    #[test]
+    fn user_profile() {
+        let client = RecordedClient::default().with(
+            "https://git.example.com/api/v4/user",
+            r#"{
+                "id": 1,
+                "username": "john_smith",
+                "email": "john@example.com",
+                "name": "John Smith",
+                "state": "active",
+                "avatar_url": "https://git.example.com/uploads/user/avatar/1/index.jpg",
+                "web_url": "https://git.example.com/john_smith",
+                "created_at": "2012-05-23T08:00:58.367Z",
+                "bio": "",
+                "location": null,
+                "public_email": "john@example.com",
+                "website_url": "",
+                "organization": "Example",
+                "job_title": "Developer"
+            }"#,
+        );
+
+        let service = Service::new(Config {
+            base_url: "https://git.example.com/".into(),
+            ..Config::default()
+        });
+        assert_eq!(
+            IsOAuth2Provider::<RecordedClient, AccountData>::authorize_url(&service),
+            "https://git.example.com/oauth/authorize"
+        );
+
+        let account: AccountData = service
+            .get_user_profile(&client, "token".into())
+            .wait()
+            .unwrap();
+        assert_eq!(account.name, "1");
+        assert_eq!(account.nick_name, Some("john_smith".into()));
+        assert_eq!(account.full_name, Some("John Smith".into()));
+        assert_eq!(account.email, Some("john@example.com".parse().unwrap()));
+        assert_eq!(
+            account.home_url.map(|url| url.into_inner().into_string()),
+            Some("https://git.example.com/john_smith".into())
+        );
+        assert_eq!(account.company, Some("Example".into()));
+        assert_eq!(account.position, Some("Developer".into()));
+        assert_eq!(account.location, None);
+        assert_eq!(account.about, None);
+        assert!(account.create_date.is_some());
+    }
+}
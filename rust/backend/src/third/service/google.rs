@@ -13,9 +13,10 @@ use http::{
     client::{HasHttpClient, IsHttpClient},
     request,
 };
+use httplib::HeaderMap;
 use std::borrow::Cow;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use third::{IsThirdService, ThirdApiParams, ThirdError};
+use third::{endpoint_url, ApiQuery, IsThirdApiCall, IsThirdService, ThirdApiParams, ThirdError};
 use user::{
     HasEmail, HasFamilyName, HasFullName, HasGender, HasGivenName, HasHomeUrl, HasImageUrl,
     HasLocale, IsAccountData,
@@ -39,6 +40,12 @@ pub struct Config {
     /// Prompts to use
     #[serde(default)]
     pub prompt: Vec<Prompt>,
+
+    /// Base URL to use instead of default
+    ///
+    /// See [`endpoint_url`](third::endpoint_url).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
 }
 
 fn default_scopes() -> Vec<Scope> {
@@ -52,6 +59,7 @@ impl Default for Config {
             access_type: AccessType::default(),
             include_granted_scopes: false,
             prompt: Vec::new(),
+            base_url: None,
         }
     }
 }
@@ -81,6 +89,11 @@ pub enum Scope {
     /// `https://www.googleapis.com/auth/userinfo.profile`
     #[serde(rename = "userinfo.profile")]
     UserInfoProfile,
+    /// See your contacts
+    ///
+    /// `https://www.googleapis.com/auth/contacts.readonly`
+    #[serde(rename = "contacts.readonly")]
+    ContactsReadOnly,
 }
 
 impl Display for Scope {
@@ -91,6 +104,7 @@ impl Display for Scope {
             PlusMe => "https://www.googleapis.com/auth/plus.me",
             UserInfoEmail => "https://www.googleapis.com/auth/userinfo.email",
             UserInfoProfile => "https://www.googleapis.com/auth/userinfo.profile",
+            ContactsReadOnly => "https://www.googleapis.com/auth/contacts.readonly",
         })
     }
 }
@@ -160,6 +174,13 @@ impl Service {
     pub fn new(config: Config) -> Self {
         Service(config)
     }
+
+    /// User contacts API call
+    pub fn contacts(&self) -> Contacts {
+        Contacts {
+            base_url: self.0.base_url.clone(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -168,6 +189,8 @@ struct UserProfile {
     #[serde(default)]
     email: String,
     #[serde(default)]
+    verified_email: bool,
+    #[serde(default)]
     name: String,
     #[serde(default)]
     given_name: String,
@@ -211,7 +234,10 @@ where
                 .fetch(Method(
                     "GET",
                     UrlWithQuery(
-                        "https://www.googleapis.com/oauth2/v2/userinfo",
+                        endpoint_url(
+                            &self.0.base_url,
+                            "https://www.googleapis.com/oauth2/v2/userinfo",
+                        ),
                         ThirdApiParams::new(&access_token),
                         Header("Accept", "application/json", NoBody),
                     ),
@@ -247,6 +273,7 @@ where
                     if !data.email.is_empty() {
                         if let Ok(email) = data.email.parse() {
                             account.set_email(Some(email));
+                            account.set_email_verified(data.verified_email);
                         }
                     }
 
@@ -268,6 +295,103 @@ where
     }
 }
 
+/// User contacts API call
+///
+/// Requires `contacts.readonly` scope.
+#[derive(Clone, Default)]
+pub struct Contacts {
+    base_url: Option<String>,
+}
+
+/// User contact
+#[derive(Debug)]
+pub struct Contact {
+    /// Resource name like `people/c123`
+    pub id: String,
+    pub name: Option<String>,
+    pub emails: Vec<String>,
+}
+
+/// Contacts API response
+#[derive(Deserialize)]
+pub struct ContactsResponse {
+    #[serde(default)]
+    connections: Vec<Person>,
+    #[serde(default, rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Person {
+    #[serde(rename = "resourceName")]
+    resource_name: String,
+    #[serde(default)]
+    names: Vec<PersonName>,
+    #[serde(default, rename = "emailAddresses")]
+    email_addresses: Vec<PersonEmail>,
+}
+
+#[derive(Deserialize)]
+struct PersonName {
+    #[serde(default, rename = "displayName")]
+    display_name: String,
+}
+
+#[derive(Deserialize)]
+struct PersonEmail {
+    #[serde(default)]
+    value: String,
+}
+
+impl IsThirdApiCall for Contacts {
+    type Item = Contact;
+    type Data = ContactsResponse;
+
+    fn url(&self) -> Cow<str> {
+        endpoint_url(
+            &self.base_url,
+            "https://people.googleapis.com/v1/people/me/connections",
+        )
+    }
+
+    fn query(&self, cursor: Option<&str>) -> ApiQuery {
+        let mut query = ApiQuery::new();
+        query.insert("personFields", "names,emailAddresses".into());
+        query.insert("pageSize", "1000".into());
+        if let Some(token) = cursor {
+            query.insert("pageToken", token.into());
+        }
+        query
+    }
+
+    fn page(
+        &self,
+        data: Self::Data,
+        _headers: &HeaderMap,
+        _cursor: Option<&str>,
+    ) -> Result<(Vec<Self::Item>, Option<String>), ThirdError> {
+        Ok((
+            data.connections
+                .into_iter()
+                .map(|person| Contact {
+                    id: person.resource_name,
+                    name: person
+                        .names
+                        .into_iter()
+                        .map(|name| name.display_name)
+                        .find(|name| !name.is_empty()),
+                    emails: person
+                        .email_addresses
+                        .into_iter()
+                        .map(|email| email.value)
+                        .filter(|email| !email.is_empty())
+                        .collect(),
+                }).collect(),
+            data.next_page_token.filter(|token| !token.is_empty()),
+        ))
+    }
+}
+
 impl<S, A> IsOAuth2Provider<S, A> for Service
 where
     A: IsAccountData
@@ -285,7 +409,7 @@ where
     type AuthorizeParams = AuthorizeParams;
 
     fn authorize_url(&self) -> Cow<str> {
-        "https://accounts.google.com/o/oauth2/v2/auth".into()
+        endpoint_url(&self.0.base_url, "https://accounts.google.com/o/oauth2/v2/auth")
     }
 
     fn authorize_scope(&self) -> Cow<str> {
@@ -304,6 +428,66 @@ where
     }
 
     fn access_token_url(&self) -> Cow<str> {
-        "https://www.googleapis.com/oauth2/v4/token".into()
+        endpoint_url(&self.0.base_url, "https://www.googleapis.com/oauth2/v4/token")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use third::{fetch_api_all, fetch_api_page, service::recorded::RecordedClient};
+
+    #[test]
+    fn contacts() {
+        let client = RecordedClient::default()
+            .with(
+                "https://people.googleapis.com/v1/people/me/connections?pageSize=1000&personFields=names%2CemailAddresses",
+                r#"{
+                    "connections": [
+                        {
+                            "resourceName": "people/c1",
+                            "names": [{ "displayName": "" }, { "displayName": "Alice" }],
+                            "emailAddresses": [{ "value": "alice@example.com" }, { "value": "" }]
+                        },
+                        { "resourceName": "people/c2" }
+                    ],
+                    "nextPageToken": "next",
+                    "totalPeople": 3
+                }"#,
+            ).with(
+                "https://people.googleapis.com/v1/people/me/connections?pageSize=1000&pageToken=next&personFields=names%2CemailAddresses",
+                r#"{
+                    "connections": [
+                        {
+                            "resourceName": "people/c3",
+                            "names": [{ "displayName": "Bob" }]
+                        }
+                    ],
+                    "totalPeople": 3
+                }"#,
+            );
+
+        let call = Service::new(Config::default()).contacts();
+
+        let page = fetch_api_page(&client, &call, "token", None).wait().unwrap();
+        assert_eq!(page.next, Some("next".into()));
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.items[0].id, "people/c1");
+        assert_eq!(page.items[0].name, Some("Alice".into()));
+        assert_eq!(page.items[0].emails, vec!["alice@example.com".to_string()]);
+        assert_eq!(page.items[1].id, "people/c2");
+        assert_eq!(page.items[1].name, None);
+        assert!(page.items[1].emails.is_empty());
+
+        let contacts = fetch_api_all(&client, call, "token".into(), 10)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            contacts
+                .iter()
+                .map(|contact| contact.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["people/c1", "people/c2", "people/c3"]
+        );
     }
 }
@@ -0,0 +1,270 @@
+/*!
+
+## Mail.ru service integration and OAuth2 provider
+
+See Mail.ru OAuth2 docs at https://oauth.mail.ru/docs.
+
+*/
+use auth::oauth2::IsOAuth2Provider;
+use base::{wrappers::DisplayIter, BoxFuture, EmptyMap, TimeStamp};
+use futures::Future;
+use http::{
+    client::{HasHttpClient, IsHttpClient},
+    request,
+};
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use third::{endpoint_url, IsThirdService, ThirdApiParams, ThirdError};
+use user::{
+    HasBirthDate, HasEmail, HasFamilyName, HasFullName, HasGender, HasGivenName, HasImageUrl,
+    HasLocale, HasNickName, IsAccountData,
+};
+
+/// Mail.ru config
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Scope to use
+    #[serde(default = "default_scope")]
+    pub scope: Vec<Scope>,
+
+    /// Base URL to use instead of default
+    ///
+    /// See [`endpoint_url`](third::endpoint_url).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+}
+
+fn default_scope() -> Vec<Scope> {
+    vec![Scope::UserInfo]
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scope: default_scope(),
+            base_url: None,
+        }
+    }
+}
+
+/// Mail.ru scope
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Scope {
+    /// Access to user profile and email (`userinfo`)
+    #[serde(rename = "userinfo")]
+    UserInfo,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        use self::Scope::*;
+        f.write_str(match self {
+            UserInfo => "userinfo",
+        })
+    }
+}
+
+/// Mail.ru service
+pub struct Service(Config);
+
+impl Service {
+    pub fn new(config: Config) -> Self {
+        Service(config)
+    }
+}
+
+#[derive(Deserialize)]
+struct UserProfile {
+    id: String,
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    first_name: String,
+    #[serde(default)]
+    last_name: String,
+    #[serde(default)]
+    nickname: String,
+    #[serde(default)]
+    gender: String,
+    /// Birthday in `DD.MM.YYYY` format
+    #[serde(default)]
+    birthday: String,
+    #[serde(default)]
+    image: String,
+    #[serde(default)]
+    locale: String,
+}
+
+impl<S, A> IsThirdService<S, A> for Service
+where
+    S: HasHttpClient,
+    A: IsAccountData
+        + HasNickName
+        + HasFullName
+        + HasGivenName
+        + HasFamilyName
+        + HasGender
+        + HasBirthDate
+        + HasLocale
+        + HasEmail
+        + HasImageUrl
+        + 'static,
+{
+    fn service_name(&self) -> Cow<str> {
+        "mailru".into()
+    }
+
+    fn get_user_profile(&self, state: &S, access_token: Cow<str>) -> BoxFuture<A, ThirdError> {
+        use self::request::*;
+
+        let client: &S::HttpClient = state.as_ref();
+
+        Box::new(
+            client
+                .fetch(Method(
+                    "GET",
+                    UrlWithQuery(
+                        endpoint_url(&self.0.base_url, "https://oauth.mail.ru/userinfo"),
+                        ThirdApiParams::new(&access_token),
+                        Header("Accept", "application/json", NoBody),
+                    ),
+                )).map_err(|error| {
+                    error!("Error when fetching user profile: {}", error);
+                    ThirdError::ServiceError
+                }).map(JsonBody::into_inner)
+                .map(|data: UserProfile| {
+                    let mut account = A::create_new(data.id);
+
+                    if !data.nickname.is_empty() {
+                        account.set_nick_name(Some(data.nickname));
+                    }
+
+                    if !data.name.is_empty() {
+                        account.set_full_name(Some(data.name));
+                    }
+
+                    if !data.first_name.is_empty() {
+                        account.set_given_name(Some(data.first_name));
+                    }
+
+                    if !data.last_name.is_empty() {
+                        account.set_family_name(Some(data.last_name));
+                    }
+
+                    if !data.gender.is_empty() {
+                        if let Ok(gender) = data.gender.parse() {
+                            account.set_gender(Some(gender));
+                        }
+                    }
+
+                    if !data.birthday.is_empty() {
+                        if let Ok(date) = TimeStamp::parse(&data.birthday, &"%d.%m.%Y") {
+                            account.set_birth_date(Some(date));
+                        }
+                    }
+
+                    if !data.locale.is_empty() {
+                        account.set_locale(Some(data.locale));
+                    }
+
+                    if !data.email.is_empty() {
+                        if let Ok(email) = data.email.parse() {
+                            account.set_email(Some(email));
+                        }
+                    }
+
+                    if !data.image.is_empty() {
+                        if let Ok(url) = data.image.parse() {
+                            account.set_image_url(Some(url));
+                        }
+                    }
+
+                    account
+                }),
+        )
+    }
+}
+
+impl<S, A> IsOAuth2Provider<S, A> for Service
+where
+    A: IsAccountData
+        + HasNickName
+        + HasFullName
+        + HasGivenName
+        + HasFamilyName
+        + HasGender
+        + HasBirthDate
+        + HasLocale
+        + HasEmail
+        + HasImageUrl
+        + 'static,
+    S: HasHttpClient,
+{
+    type AuthorizeParams = EmptyMap;
+
+    fn authorize_url(&self) -> Cow<str> {
+        endpoint_url(&self.0.base_url, "https://oauth.mail.ru/login")
+    }
+
+    fn authorize_scope(&self) -> Cow<str> {
+        DisplayIter::wrap(&self.0.scope)
+            .separator(" ")
+            .to_string()
+            .into()
+    }
+
+    fn authorize_params(&self) -> Self::AuthorizeParams {
+        EmptyMap
+    }
+
+    fn access_token_url(&self) -> Cow<str> {
+        endpoint_url(&self.0.base_url, "https://oauth.mail.ru/token")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use third::service::recorded::RecordedClient;
+    use user::{stub::AccountData, Gender};
+
+    #[test]
+    fn user_profile() {
+        let client = RecordedClient::default().with(
+            "https://oauth.mail.ru/userinfo",
+            r#"{
+                "id": "1234567890",
+                "client_id": "client",
+                "gender": "m",
+                "name": "Ivan Petrov",
+                "nickname": "ivan",
+                "locale": "ru_RU",
+                "first_name": "Ivan",
+                "last_name": "Petrov",
+                "email": "ivan@mail.ru",
+                "birthday": "01.02.1985",
+                "image": "https://filin.mail.ru/pic?d=abc&width=180&height=180"
+            }"#,
+        );
+
+        let service = Service::new(Config::default());
+
+        let account: AccountData = service
+            .get_user_profile(&client, "token".into())
+            .wait()
+            .unwrap();
+        assert_eq!(account.name, "1234567890");
+        assert_eq!(account.nick_name, Some("ivan".into()));
+        assert_eq!(account.given_name, Some("Ivan".into()));
+        assert_eq!(account.family_name, Some("Petrov".into()));
+        assert_eq!(account.email, Some("ivan@mail.ru".parse().unwrap()));
+        assert!(match account.gender {
+            Some(Gender::Male) => true,
+            _ => false,
+        });
+        assert!(account.birth_date.is_some());
+        assert!(account.image_url.is_some());
+    }
+}
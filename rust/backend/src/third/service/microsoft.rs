@@ -0,0 +1,309 @@
+/*!
+
+## Microsoft identity platform integration and OAuth2 provider
+
+See Microsoft identity platform docs at https://docs.microsoft.com/en-us/azure/active-directory/develop/v2-oauth2-auth-code-flow.
+
+The `tenant` config option selects the audience:
+*common* for both personal and work accounts,
+*organizations* for work accounts only,
+*consumers* for personal accounts only,
+or the tenant identifier (or domain) for accounts of single organization.
+
+*/
+use auth::oauth2::IsOAuth2Provider;
+use base::{serde_extra::is_default, wrappers::DisplayIter, BoxFuture};
+use futures::Future;
+use http::{
+    client::{HasHttpClient, IsHttpClient},
+    request,
+};
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use third::{endpoint_url, IsThirdService, ThirdError};
+use user::{
+    HasEmail, HasFamilyName, HasFullName, HasGivenName, HasLocale, HasLocation, HasPosition,
+    IsAccountData,
+};
+
+/// Microsoft config
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Tenant
+    #[serde(default = "default_tenant")]
+    pub tenant: String,
+
+    /// Scope to use
+    #[serde(default = "default_scope")]
+    pub scope: Vec<Scope>,
+
+    /// Prompt to use
+    #[serde(default)]
+    pub prompt: Option<Prompt>,
+
+    /// Base URL to use instead of default
+    ///
+    /// See [`endpoint_url`](third::endpoint_url).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+}
+
+fn default_tenant() -> String {
+    "common".into()
+}
+
+fn default_scope() -> Vec<Scope> {
+    vec![Scope::OpenId, Scope::Profile, Scope::Email, Scope::UserRead]
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tenant: default_tenant(),
+            scope: default_scope(),
+            prompt: None,
+            base_url: None,
+        }
+    }
+}
+
+/// Microsoft scope
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Scope {
+    /// Sign in using OpenID Connect (`openid`)
+    #[serde(rename = "openid")]
+    OpenId,
+    /// Access to basic profile (`profile`)
+    #[serde(rename = "profile")]
+    Profile,
+    /// Access to primary email (`email`)
+    #[serde(rename = "email")]
+    Email,
+    /// Issue refresh token (`offline_access`)
+    #[serde(rename = "offline_access")]
+    OfflineAccess,
+    /// Read user profile using Graph API (`User.Read`)
+    #[serde(rename = "User.Read")]
+    UserRead,
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        use self::Scope::*;
+        f.write_str(match self {
+            OpenId => "openid",
+            Profile => "profile",
+            Email => "email",
+            OfflineAccess => "offline_access",
+            UserRead => "User.Read",
+        })
+    }
+}
+
+/// Microsoft prompt
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Prompt {
+    #[serde(rename = "login")]
+    Login,
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "consent")]
+    Consent,
+    #[serde(rename = "select_account")]
+    SelectAccount,
+}
+
+#[derive(Serialize)]
+pub struct AuthorizeParams {
+    #[serde(skip_serializing_if = "is_default")]
+    prompt: Option<Prompt>,
+}
+
+/// Microsoft service
+pub struct Service(Config);
+
+impl Service {
+    pub fn new(config: Config) -> Self {
+        Service(config)
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        endpoint_url(
+            &self.0.base_url,
+            &format!(
+                "https://login.microsoftonline.com/{}/oauth2/v2.0/{}",
+                self.0.tenant, endpoint
+            ),
+        ).into_owned()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserProfile {
+    id: String,
+    #[serde(default)]
+    display_name: Option<String>,
+    #[serde(default)]
+    given_name: Option<String>,
+    #[serde(default)]
+    surname: Option<String>,
+    #[serde(default)]
+    mail: Option<String>,
+    #[serde(default)]
+    job_title: Option<String>,
+    #[serde(default)]
+    office_location: Option<String>,
+    #[serde(default)]
+    preferred_language: Option<String>,
+}
+
+impl<S, A> IsThirdService<S, A> for Service
+where
+    S: HasHttpClient,
+    A: IsAccountData
+        + HasFullName
+        + HasGivenName
+        + HasFamilyName
+        + HasEmail
+        + HasPosition
+        + HasLocation
+        + HasLocale
+        + 'static,
+{
+    fn service_name(&self) -> Cow<str> {
+        "microsoft".into()
+    }
+
+    fn get_user_profile(&self, state: &S, access_token: Cow<str>) -> BoxFuture<A, ThirdError> {
+        use self::request::*;
+
+        let client: &S::HttpClient = state.as_ref();
+
+        Box::new(
+            client
+                .fetch(Method(
+                    "GET",
+                    Url(
+                        endpoint_url(&self.0.base_url, "https://graph.microsoft.com/v1.0/me")
+                            .as_ref(),
+                        Header(
+                            "Authorization",
+                            format!("Bearer {}", access_token),
+                            Header("Accept", "application/json", NoBody),
+                        ),
+                    ),
+                )).map_err(|error| {
+                    error!("Error when fetching user profile: {}", error);
+                    ThirdError::ServiceError
+                }).map(JsonBody::into_inner)
+                .map(|data: UserProfile| {
+                    let mut account = A::create_new(data.id);
+
+                    account.set_full_name(data.display_name.filter(|s| !s.is_empty()));
+                    account.set_given_name(data.given_name.filter(|s| !s.is_empty()));
+                    account.set_family_name(data.surname.filter(|s| !s.is_empty()));
+
+                    // the user principal name is not an email necessarily
+                    if let Some(email) = data.mail.and_then(|email| email.parse().ok()) {
+                        account.set_email(Some(email));
+                    }
+
+                    account.set_position(data.job_title.filter(|s| !s.is_empty()));
+                    account.set_location(data.office_location.filter(|s| !s.is_empty()));
+                    account.set_locale(data.preferred_language.filter(|s| !s.is_empty()));
+
+                    account
+                }),
+        )
+    }
+}
+
+impl<S, A> IsOAuth2Provider<S, A> for Service
+where
+    A: IsAccountData
+        + HasFullName
+        + HasGivenName
+        + HasFamilyName
+        + HasEmail
+        + HasPosition
+        + HasLocation
+        + HasLocale
+        + 'static,
+    S: HasHttpClient,
+{
+    type AuthorizeParams = AuthorizeParams;
+
+    fn authorize_url(&self) -> Cow<str> {
+        self.url("authorize").into()
+    }
+
+    fn authorize_scope(&self) -> Cow<str> {
+        DisplayIter::wrap(&self.0.scope)
+            .separator(" ")
+            .to_string()
+            .into()
+    }
+
+    fn authorize_params(&self) -> Self::AuthorizeParams {
+        AuthorizeParams {
+            prompt: self.0.prompt,
+        }
+    }
+
+    fn access_token_url(&self) -> Cow<str> {
+        self.url("token").into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use third::service::recorded::RecordedClient;
+    use user::stub::AccountData;
+
+    #[test]
+    fn user_profile() {
+        let client = RecordedClient::default().with(
+            "https://graph.microsoft.com/v1.0/me",
+            r#"{
+                "@odata.context": "https://graph.microsoft.com/v1.0/$metadata#users/$entity",
+                "businessPhones": [],
+                "displayName": "Adele Vance",
+                "givenName": "Adele",
+                "jobTitle": "Retail Manager",
+                "mail": "AdeleV@contoso.onmicrosoft.com",
+                "mobilePhone": null,
+                "officeLocation": "18/2111",
+                "preferredLanguage": "en-US",
+                "surname": "Vance",
+                "userPrincipalName": "AdeleV@contoso.onmicrosoft.com",
+                "id": "87d349ed-44d7-43e1-9a83-5f2406dee5bd"
+            }"#,
+        );
+
+        let service = Service::new(Config {
+            tenant: "contoso.onmicrosoft.com".into(),
+            ..Config::default()
+        });
+        assert_eq!(
+            IsOAuth2Provider::<RecordedClient, AccountData>::access_token_url(&service),
+            "https://login.microsoftonline.com/contoso.onmicrosoft.com/oauth2/v2.0/token"
+        );
+
+        let account: AccountData = service
+            .get_user_profile(&client, "token".into())
+            .wait()
+            .unwrap();
+        assert_eq!(account.name, "87d349ed-44d7-43e1-9a83-5f2406dee5bd");
+        assert_eq!(account.full_name, Some("Adele Vance".into()));
+        assert_eq!(account.given_name, Some("Adele".into()));
+        assert_eq!(account.family_name, Some("Vance".into()));
+        assert_eq!(
+            account.email,
+            Some("AdeleV@contoso.onmicrosoft.com".parse().unwrap())
+        );
+        assert_eq!(account.position, Some("Retail Manager".into()));
+        assert_eq!(account.locale, Some("en-US".into()));
+    }
+}
@@ -0,0 +1,936 @@
+/*!
+
+### Mock third service for integration tests
+
+The mock service emulates OAuth2 provider using local [warp](https://docs.rs/warp) server,
+so the whole login flow (authorization, account creation and linking) can be tested offline.
+
+The service handles the following endpoints:
+
+* __authorize__ issues authorization code for current user and redirects back to `redirect_uri`
+* __token__ exchanges authorization code or refresh token to access and refresh tokens
+* __user info__ replies profile of user which access token belongs to
+* __routes__ reply scripted data to requests with valid access token
+
+The paths of endpoints is set using [`MockEndpoints`]. Point the service integration
+to mock server using `base_url` config option:
+
+```ignore
+let mock = MockService::new(MockEndpoints::github()).with_user("octocat", profile);
+let (addr, server) = mock.bind(([127, 0, 0, 1], 0));
+spawn(server);
+
+let service = github::Service::new(github::Config {
+    base_url: Some(format!("http://{}", addr)),
+    ..Default::default()
+});
+```
+
+The failures of endpoints can be scripted for next requests using [`MockService::fail_next`].
+
+*/
+
+use base::{BoxFilter, BoxFuture};
+use bytes::Buf;
+use httplib::{header::LOCATION, HeaderMap, Method, Response, StatusCode};
+use serde::Serialize;
+use serde_json::{to_string, to_value, Value};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use url::form_urlencoded;
+use warp::{self, Filter};
+
+/// Endpoint paths of mock service
+#[derive(Debug, Clone)]
+pub struct MockEndpoints {
+    /// Authorization endpoint path
+    pub authorize: String,
+    /// Access token endpoint path
+    pub token: String,
+    /// User info endpoint path
+    pub userinfo: String,
+}
+
+impl MockEndpoints {
+    /// Endpoints of Github
+    pub fn github() -> Self {
+        Self {
+            authorize: "/login/oauth/authorize".into(),
+            token: "/login/oauth/access_token".into(),
+            userinfo: "/user".into(),
+        }
+    }
+
+    /// Endpoints of Google
+    pub fn google() -> Self {
+        Self {
+            authorize: "/o/oauth2/v2/auth".into(),
+            token: "/oauth2/v4/token".into(),
+            userinfo: "/oauth2/v2/userinfo".into(),
+        }
+    }
+}
+
+/// Mock service endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockEndpoint {
+    /// Authorization endpoint
+    Authorize,
+    /// Access token endpoint
+    Token,
+    /// User info endpoint and routes
+    Api,
+}
+
+/// Scripted failure of mock service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockFailure {
+    /// User denied access
+    ///
+    /// The authorization endpoint redirects with `access_denied` error.
+    AccessDenied,
+    /// Code or token rejected
+    ///
+    /// The token endpoint replies `invalid_grant` error, the other endpoints reply `401 Unauthorized`.
+    Rejected,
+    /// Rate limit exceeded
+    ///
+    /// Replies `429 Too Many Requests` with rate limit headers.
+    RateLimited,
+    /// Service unavailable
+    ///
+    /// Replies `503 Service Unavailable`.
+    Unavailable,
+    /// Malformed response
+    ///
+    /// Replies `200 OK` with body which cannot be decoded.
+    Malformed,
+}
+
+struct MockState {
+    endpoints: MockEndpoints,
+    /// User profiles by login
+    users: HashMap<String, Value>,
+    /// Scripted routes data
+    routes: HashMap<String, Value>,
+    /// The user which authorizes
+    login: Option<String>,
+    /// Issued authorization codes
+    codes: HashMap<String, String>,
+    /// Issued access tokens
+    access_tokens: HashMap<String, String>,
+    /// Issued refresh tokens
+    refresh_tokens: HashMap<String, String>,
+    /// Access token life time in seconds
+    expires_in: Option<u32>,
+    /// Scripted failures
+    failures: HashMap<MockEndpoint, VecDeque<MockFailure>>,
+    /// Handled requests
+    requests: Vec<String>,
+    serial: u64,
+}
+
+impl MockState {
+    fn issue(&mut self, kind: &str, login: &str) -> String {
+        self.serial += 1;
+        format!("{}-{}-{}", kind, login, self.serial)
+    }
+
+    fn failure(&mut self, endpoint: MockEndpoint) -> Option<MockFailure> {
+        self.failures
+            .get_mut(&endpoint)
+            .and_then(|failures| failures.pop_front())
+    }
+}
+
+/// Mock third service
+///
+/// The clones of service share the same state.
+#[derive(Clone)]
+pub struct MockService(Arc<Mutex<MockState>>);
+
+impl MockService {
+    /// Create mock service using endpoint paths
+    pub fn new(endpoints: MockEndpoints) -> Self {
+        MockService(Arc::new(Mutex::new(MockState {
+            endpoints,
+            users: HashMap::new(),
+            routes: HashMap::new(),
+            login: None,
+            codes: HashMap::new(),
+            access_tokens: HashMap::new(),
+            refresh_tokens: HashMap::new(),
+            expires_in: Some(3600),
+            failures: HashMap::new(),
+            requests: Vec::new(),
+            serial: 0,
+        })))
+    }
+
+    /// Add user with profile data
+    ///
+    /// The first added user becomes current.
+    pub fn with_user<L: Into<String>, P: Serialize>(self, login: L, profile: P) -> Self {
+        {
+            let mut state = self.0.lock().unwrap();
+            let login = login.into();
+            state
+                .users
+                .insert(login.clone(), to_value(profile).unwrap());
+            if state.login.is_none() {
+                state.login = Some(login);
+            }
+        }
+        self
+    }
+
+    /// Add route which replies data to requests with valid access token
+    pub fn with_route<P: Into<String>, D: Serialize>(self, path: P, data: D) -> Self {
+        self.0
+            .lock()
+            .unwrap()
+            .routes
+            .insert(path.into(), to_value(data).unwrap());
+        self
+    }
+
+    /// Set access token life time in seconds
+    ///
+    /// The `None` means the tokens never expires.
+    pub fn with_expires_in(self, expires_in: Option<u32>) -> Self {
+        self.0.lock().unwrap().expires_in = expires_in;
+        self
+    }
+
+    /// Set the user which authorizes
+    pub fn login_as(&self, login: &str) {
+        self.0.lock().unwrap().login = Some(login.into());
+    }
+
+    /// Fail the next request to endpoint
+    ///
+    /// The failures is queued so the several next requests can be failed.
+    pub fn fail_next(&self, endpoint: MockEndpoint, failure: MockFailure) {
+        self.0
+            .lock()
+            .unwrap()
+            .failures
+            .entry(endpoint)
+            .or_insert_with(VecDeque::new)
+            .push_back(failure);
+    }
+
+    /// Revoke issued access tokens
+    ///
+    /// The refresh tokens is kept so the access tokens can be refreshed.
+    pub fn revoke_access_tokens(&self) {
+        self.0.lock().unwrap().access_tokens.clear();
+    }
+
+    /// Get handled requests
+    ///
+    /// Each request is represented by method and path like `GET /user`.
+    pub fn requests(&self) -> Vec<String> {
+        self.0.lock().unwrap().requests.clone()
+    }
+
+    /// Create warp filter which handles requests
+    pub fn filter(&self) -> BoxFilter<(Response<String>,)> {
+        let mock = self.clone();
+
+        warp::method()
+            .and(warp::path::full())
+            .and(
+                warp::query::raw()
+                    .or(warp::any().map(String::new))
+                    .unify(),
+            ).and(warp::header::headers_cloned())
+            .and(warp::body::concat())
+            .map(
+                move |method: Method,
+                      path: warp::path::FullPath,
+                      query: String,
+                      headers: HeaderMap,
+                      body: warp::body::FullBody| {
+                    mock.handle(&method, path.as_str(), &query, &headers, body.bytes())
+                },
+            ).boxed()
+    }
+
+    /// Bind server to address
+    ///
+    /// Use zero port to bind to any free port.
+    /// Returns the bound address and the server future which should be spawned.
+    pub fn bind<A: Into<SocketAddr> + 'static>(
+        &self,
+        addr: A,
+    ) -> (SocketAddr, BoxFuture<(), ()>) {
+        let (addr, server) = warp::serve(self.filter()).bind_ephemeral(addr);
+        (addr, Box::new(server))
+    }
+
+    fn handle(
+        &self,
+        method: &Method,
+        path: &str,
+        query: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Response<String> {
+        let mut state = self.0.lock().unwrap();
+
+        state.requests.push(format!("{} {}", method, path));
+
+        if *method == Method::GET && path == state.endpoints.authorize {
+            authorize(&mut state, &parse_query(query.as_bytes()))
+        } else if *method == Method::POST && path == state.endpoints.token {
+            token(&mut state, &parse_query(body))
+        } else if *method == Method::GET {
+            api(&mut state, path, &parse_query(query.as_bytes()), headers)
+        } else {
+            reply(StatusCode::METHOD_NOT_ALLOWED, "")
+        }
+    }
+}
+
+fn parse_query(data: &[u8]) -> HashMap<String, String> {
+    form_urlencoded::parse(data).into_owned().collect()
+}
+
+fn reply<B: Into<String>>(status: StatusCode, body: B) -> Response<String> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body.into())
+        .unwrap()
+}
+
+fn reply_failure(failure: MockFailure) -> Response<String> {
+    use self::MockFailure::*;
+    match failure {
+        AccessDenied | Rejected => reply(StatusCode::UNAUTHORIZED, r#"{"error":"unauthorized"}"#),
+        RateLimited => Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header("X-RateLimit-Limit", "60")
+            .header("X-RateLimit-Remaining", "0")
+            .header("X-RateLimit-Reset", "0")
+            .body(r#"{"error":"rate_limited"}"#.into())
+            .unwrap(),
+        Unavailable => reply(StatusCode::SERVICE_UNAVAILABLE, ""),
+        Malformed => reply(StatusCode::OK, "<html>"),
+    }
+}
+
+fn authorize(state: &mut MockState, query: &HashMap<String, String>) -> Response<String> {
+    let redirect_uri = match query.get("redirect_uri") {
+        Some(redirect_uri) => redirect_uri,
+        None => return reply(StatusCode::BAD_REQUEST, r#"{"error":"invalid_request"}"#),
+    };
+
+    let mut params = form_urlencoded::Serializer::new(String::new());
+
+    match (state.failure(MockEndpoint::Authorize), state.login.clone()) {
+        (Some(MockFailure::AccessDenied), _) | (None, None) => {
+            params.append_pair("error", "access_denied");
+        }
+        (Some(failure), _) => return reply_failure(failure),
+        (None, Some(login)) => {
+            let code = state.issue("code", &login);
+            state.codes.insert(code.clone(), login);
+            params.append_pair("code", &code);
+        }
+    }
+
+    if let Some(login_state) = query.get("state") {
+        params.append_pair("state", login_state);
+    }
+
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header(
+            LOCATION,
+            format!("{}{}{}", redirect_uri, separator, params.finish()).as_str(),
+        ).body(String::new())
+        .unwrap()
+}
+
+fn token(state: &mut MockState, query: &HashMap<String, String>) -> Response<String> {
+    match state.failure(MockEndpoint::Token) {
+        Some(MockFailure::Rejected) => {
+            return reply(StatusCode::BAD_REQUEST, r#"{"error":"invalid_grant"}"#)
+        }
+        Some(failure) => return reply_failure(failure),
+        None => (),
+    }
+
+    let login = match query.get("grant_type").map(String::as_str) {
+        Some("authorization_code") => query
+            .get("code")
+            .and_then(|code| state.codes.remove(code)),
+        Some("refresh_token") => query
+            .get("refresh_token")
+            .and_then(|token| state.refresh_tokens.get(token).cloned()),
+        _ => return reply(StatusCode::BAD_REQUEST, r#"{"error":"unsupported_grant_type"}"#),
+    };
+
+    let login = match login {
+        Some(login) => login,
+        None => return reply(StatusCode::BAD_REQUEST, r#"{"error":"invalid_grant"}"#),
+    };
+
+    let access_token = state.issue("access", &login);
+    state
+        .access_tokens
+        .insert(access_token.clone(), login.clone());
+
+    let refresh_token = state.issue("refresh", &login);
+    state.refresh_tokens.insert(refresh_token.clone(), login);
+
+    #[derive(Serialize)]
+    struct TokenResponse<'a> {
+        access_token: &'a str,
+        token_type: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_in: Option<u32>,
+        refresh_token: &'a str,
+    }
+
+    reply(
+        StatusCode::OK,
+        to_string(&TokenResponse {
+            access_token: &access_token,
+            token_type: "bearer",
+            expires_in: state.expires_in,
+            refresh_token: &refresh_token,
+        }).unwrap(),
+    )
+}
+
+fn api(
+    state: &mut MockState,
+    path: &str,
+    query: &HashMap<String, String>,
+    headers: &HeaderMap,
+) -> Response<String> {
+    if let Some(failure) = state.failure(MockEndpoint::Api) {
+        return reply_failure(failure);
+    }
+
+    // the token can be passed using header (`Bearer` or `OAuth`) or query param
+    let access_token = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.splitn(2, ' ').nth(1))
+        .map(String::from)
+        .or_else(|| query.get("access_token").cloned());
+
+    let login = match access_token.and_then(|token| state.access_tokens.get(&token).cloned()) {
+        Some(login) => login,
+        None => return reply(StatusCode::UNAUTHORIZED, r#"{"error":"invalid_token"}"#),
+    };
+
+    let data = if path == state.endpoints.userinfo {
+        state.users.get(&login)
+    } else {
+        state.routes.get(path)
+    };
+
+    match data {
+        Some(data) => reply(StatusCode::OK, to_string(data).unwrap()),
+        None => reply(StatusCode::NOT_FOUND, r#"{"error":"not_found"}"#),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use auth::{
+        oauth2::{
+            has_own_login, AuthInfo, HasLoginMethods, HasOAuth2Providers, OAuth2Auth,
+            OAuth2Options, UserIdent,
+        },
+        AuthError, HasNonceStorage, HasProvisionPolicy, IsAuthMethod, NonceCache, ProvisionOptions,
+    };
+    use crypto::{CanKeygen, HasSecureKey, SecureKey};
+    use http::{
+        client::{HasHttpClient, HttpClient, IsHttpClient},
+        HttpBody,
+    };
+    use httplib::Request;
+    use hyper::client::connect::dns::GaiResolver;
+    use serde_json::from_str;
+    use third::{fetch_api_all, github, google, ThirdError};
+    use tokio::runtime::Runtime;
+    use user::{
+        stub::{AccountData, Accounts, Users},
+        HasAccountStorage, HasUserStorage, IsAccountStorage, IsUserData, IsUserStorage, UserId,
+    };
+
+    #[derive(Clone)]
+    struct State(Arc<StateData>);
+
+    struct StateData {
+        secure_key: SecureKey,
+        nonces: NonceCache,
+        users: Users,
+        accounts: Accounts,
+        client: HttpClient<GaiResolver>,
+        providers: (github::Service, google::Service),
+        provision: ProvisionOptions,
+    }
+
+    impl AsRef<SecureKey> for State {
+        fn as_ref(&self) -> &SecureKey {
+            &self.0.secure_key
+        }
+    }
+
+    impl HasSecureKey for State {
+        type SecureKey = SecureKey;
+    }
+
+    impl AsRef<NonceCache> for State {
+        fn as_ref(&self) -> &NonceCache {
+            &self.0.nonces
+        }
+    }
+
+    impl HasNonceStorage for State {
+        type NonceStorage = NonceCache;
+    }
+
+    impl AsRef<Users> for State {
+        fn as_ref(&self) -> &Users {
+            &self.0.users
+        }
+    }
+
+    impl HasUserStorage for State {
+        type UserStorage = Users;
+    }
+
+    impl AsRef<Accounts> for State {
+        fn as_ref(&self) -> &Accounts {
+            &self.0.accounts
+        }
+    }
+
+    impl HasAccountStorage for State {
+        type AccountStorage = Accounts;
+    }
+
+    impl AsRef<HttpClient<GaiResolver>> for State {
+        fn as_ref(&self) -> &HttpClient<GaiResolver> {
+            &self.0.client
+        }
+    }
+
+    impl HasHttpClient for State {
+        type HttpClient = HttpClient<GaiResolver>;
+    }
+
+    impl AsRef<(github::Service, google::Service)> for State {
+        fn as_ref(&self) -> &(github::Service, google::Service) {
+            &self.0.providers
+        }
+    }
+
+    impl HasOAuth2Providers for State {
+        type OAuth2Providers = (github::Service, google::Service);
+    }
+
+    impl AsRef<ProvisionOptions> for State {
+        fn as_ref(&self) -> &ProvisionOptions {
+            &self.0.provision
+        }
+    }
+
+    impl HasProvisionPolicy for State {}
+
+    impl HasLoginMethods for State {
+        fn has_other_login(&self, user: UserId) -> BoxFuture<bool, AuthError> {
+            has_own_login(self, user)
+        }
+    }
+
+    /// Create state with services which use mock server
+    fn new_state(addr: SocketAddr, provision: ProvisionOptions) -> State {
+        let base_url = Some(format!("http://{}", addr));
+
+        State(Arc::new(StateData {
+            secure_key: SecureKey::gen_key(),
+            nonces: NonceCache::new(),
+            users: Users::new(),
+            accounts: Accounts::new(),
+            client: HttpClient::new(GaiResolver::new(1)),
+            providers: (
+                github::Service::new(github::Config {
+                    base_url: base_url.clone(),
+                    ..Default::default()
+                }),
+                google::Service::new(google::Config {
+                    base_url,
+                    ..Default::default()
+                }),
+            ),
+            provision,
+        }))
+    }
+
+    /// Create auth method with single service
+    fn new_auth(name: &str, link_email: bool) -> OAuth2Auth {
+        OAuth2Auth::new(
+            from_str::<OAuth2Options>(&format!(
+                r#"{{
+                    "services": [{{
+                        "name": "{}",
+                        "client_id": "client",
+                        "client_secret": "secret"
+                    }}],
+                    "redirect": "https://my-site.tld/oauth2",
+                    "link_email": {}
+                }}"#,
+                name, link_email
+            )).unwrap(),
+        )
+    }
+
+    /// Pass authorization like user agent and get authorization code, state and client key
+    fn authorize(
+        runtime: &mut Runtime,
+        state: &State,
+        auth: &OAuth2Auth,
+    ) -> Result<(String, String, String), String> {
+        let (name, url, login_state, key) = match auth.get_auth_info(state) {
+            AuthInfo::OAuth2 { mut services, .. } => {
+                let service = services.remove(0);
+                (service.name, service.url, service.state, service.key)
+            }
+        };
+
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("client_id", "client")
+            .append_pair("redirect_uri", &format!("https://my-site.tld/oauth2/{}", name))
+            .append_pair("state", &login_state)
+            .finish();
+
+        let request = Request::get(format!("{}?{}", url, query))
+            .body(HttpBody::empty())
+            .unwrap();
+
+        let response = runtime
+            .block_on(state.0.client.send_request(request))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+
+        let location = response.headers()[LOCATION].to_str().unwrap();
+        let params = parse_query(location.splitn(2, '?').nth(1).unwrap().as_bytes());
+        assert_eq!(params.get("state"), Some(&login_state));
+
+        match params.get("code") {
+            Some(code) => Ok((code.clone(), login_state, key)),
+            None => Err(params["error"].clone()),
+        }
+    }
+
+    fn login(
+        runtime: &mut Runtime,
+        state: &State,
+        auth: &OAuth2Auth,
+        name: &str,
+    ) -> Result<<Users as IsUserStorage>::User, AuthError> {
+        let (code, login_state, key) = authorize(runtime, state, auth).unwrap();
+        runtime.block_on(auth.try_user_auth(
+            state,
+            &UserIdent::OAuth2 {
+                name: name.into(),
+                code,
+                state: login_state,
+                key,
+                invite: None,
+            },
+        ))
+    }
+
+    #[test]
+    fn login_flow() {
+        let mock = MockService::new(MockEndpoints::github())
+            .with_user(
+                "octocat",
+                from_str::<Value>(
+                    r#"{
+                        "id": 1,
+                        "login": "octocat",
+                        "name": "The Octocat",
+                        "email": "octocat@github.com",
+                        "created_at": "2011-01-25T18:44:36Z"
+                    }"#,
+                ).unwrap(),
+            ).with_user(
+                "hubot",
+                from_str::<Value>(
+                    r#"{
+                        "id": 2,
+                        "login": "hubot",
+                        "created_at": "2012-03-14T10:22:01Z"
+                    }"#,
+                ).unwrap(),
+            ).with_route(
+                "/user/emails",
+                from_str::<Value>(r#"[{ "email": "octocat@github.com", "primary": true }]"#)
+                    .unwrap(),
+            );
+
+        let mut runtime = Runtime::new().unwrap();
+        let (addr, server) = mock.bind(([127, 0, 0, 1], 0));
+        runtime.spawn(server);
+
+        let state = new_state(addr, ProvisionOptions::default());
+        let emails = state.0.providers.0.user_emails();
+        let auth = new_auth("github", false);
+
+        // sign up
+        let user = login(&mut runtime, &state, &auth, "github").unwrap();
+        assert_eq!(user.name, "1@github");
+        assert_eq!(user.email, Some("octocat@github.com".parse().unwrap()));
+
+        // sign in
+        let same_user = login(&mut runtime, &state, &auth, "github").unwrap();
+        assert_eq!(same_user.id, user.id);
+
+        // link other account
+        mock.login_as("hubot");
+        let (code, login_state, _) = authorize(&mut runtime, &state, &auth).unwrap();
+        // state of other client
+        assert!(
+            runtime
+                .block_on(auth.link_account(&state, user.id, "github", &code, &login_state, "key"))
+                .is_err()
+        );
+        let (code, login_state, key) = authorize(&mut runtime, &state, &auth).unwrap();
+        let account: AccountData = runtime
+            .block_on(auth.link_account(&state, user.id, "github", &code, &login_state, &key))
+            .unwrap();
+        assert_eq!(account.name, "2");
+        assert_eq!(account.nick_name, Some("hubot".into()));
+        assert_eq!(
+            runtime
+                .block_on(state.0.accounts.get_user_accounts(user.id))
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // access denied
+        mock.fail_next(MockEndpoint::Authorize, MockFailure::AccessDenied);
+        assert_eq!(
+            authorize(&mut runtime, &state, &auth),
+            Err("access_denied".into())
+        );
+
+        // service unavailable
+        mock.fail_next(MockEndpoint::Token, MockFailure::Unavailable);
+        assert!(login(&mut runtime, &state, &auth, "github").is_err());
+
+        // refresh rejected access token
+        mock.login_as("octocat");
+        mock.revoke_access_tokens();
+        let data = runtime
+            .block_on(
+                auth.call_third_api(&state, user.id, "github", move |state, access_token| {
+                    fetch_api_all(state, emails.clone(), access_token, 1)
+                }),
+            ).unwrap();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0].email, "octocat@github.com");
+
+        // rate limit
+        mock.fail_next(MockEndpoint::Api, MockFailure::RateLimited);
+        assert!(
+            match runtime.block_on(fetch_api_all(
+                &state,
+                state.0.providers.0.user_emails(),
+                "token".into(),
+                1
+            )) {
+                Err(ThirdError::TooManyRequests(_)) => true,
+                _ => false,
+            }
+        );
+
+        // unlink account
+        assert!(
+            runtime
+                .block_on(auth.unlink_account(&state, user.id, account.id))
+                .unwrap()
+                .is_some()
+        );
+        // unknown account
+        assert!(
+            runtime
+                .block_on(auth.unlink_account(&state, user.id, account.id))
+                .unwrap()
+                .is_none()
+        );
+        // the last login method
+        let accounts = runtime
+            .block_on(state.0.accounts.get_user_accounts(user.id))
+            .unwrap();
+        assert!(
+            match runtime.block_on(auth.unlink_account(&state, user.id, accounts[0].id)) {
+                Err(AuthError::Restricted) => true,
+                _ => false,
+            }
+        );
+        // user which has password
+        let mut user = runtime
+            .block_on(state.0.users.get_user_data(user.id))
+            .unwrap()
+            .unwrap();
+        user.hash = Some(b"hash".to_vec());
+        let user = runtime.block_on(state.0.users.put_user_data(user)).unwrap();
+        assert!(
+            runtime
+                .block_on(auth.unlink_account(&state, user.id, accounts[0].id))
+                .unwrap()
+                .is_some()
+        );
+
+        assert!(
+            mock.requests()
+                .iter()
+                .any(|request| request == "POST /login/oauth/access_token")
+        );
+    }
+
+    #[test]
+    fn link_email() {
+        let mock = MockService::new(MockEndpoints::google())
+            .with_user(
+                "alice",
+                from_str::<Value>(
+                    r#"{
+                        "id": "1",
+                        "email": "alice@gmail.com",
+                        "verified_email": false
+                    }"#,
+                ).unwrap(),
+            ).with_user(
+                "bob",
+                from_str::<Value>(
+                    r#"{
+                        "id": "2",
+                        "email": "bob@gmail.com",
+                        "verified_email": true
+                    }"#,
+                ).unwrap(),
+            ).with_user(
+                "carol",
+                from_str::<Value>(
+                    r#"{
+                        "id": "3",
+                        "email": "carol@gmail.com",
+                        "verified_email": true
+                    }"#,
+                ).unwrap(),
+            );
+
+        let mut runtime = Runtime::new().unwrap();
+        let (addr, server) = mock.bind(([127, 0, 0, 1], 0));
+        runtime.spawn(server);
+
+        let state = new_state(addr, ProvisionOptions::default());
+        let auth = new_auth("google", true);
+
+        // existing users which has same emails
+        let add_user = |runtime: &mut Runtime, email: &str, verified: bool| {
+            let mut user = <Users as IsUserStorage>::User::create_new(email);
+            user.email = Some(email.parse().unwrap());
+            user.email_verified = verified;
+            runtime
+                .block_on(state.0.users.put_user_data(user))
+                .unwrap()
+        };
+        let alice = add_user(&mut runtime, "alice@gmail.com", true);
+        let bob = add_user(&mut runtime, "bob@gmail.com", true);
+        let carol = add_user(&mut runtime, "carol@gmail.com", false);
+
+        // unverified email => new user
+        mock.login_as("alice");
+        let user = login(&mut runtime, &state, &auth, "google").unwrap();
+        assert_ne!(user.id, alice.id);
+        assert_eq!(user.name, "1@google");
+
+        // verified email => linked to existing user
+        mock.login_as("bob");
+        let user = login(&mut runtime, &state, &auth, "google").unwrap();
+        assert_eq!(user.id, bob.id);
+
+        // unverified email of existing user => new user
+        mock.login_as("carol");
+        let user = login(&mut runtime, &state, &auth, "google").unwrap();
+        assert_ne!(user.id, carol.id);
+        assert_eq!(user.name, "3@google");
+        let accounts = runtime
+            .block_on(state.0.accounts.get_user_accounts(bob.id))
+            .unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert!(accounts[0].email_verified);
+
+        // user which name is same as email can login using one-time password
+        assert!(
+            runtime
+                .block_on(auth.unlink_account(&state, bob.id, accounts[0].id))
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn sign_up_email_domains() {
+        let mock = MockService::new(MockEndpoints::google())
+            .with_user(
+                "alice",
+                from_str::<Value>(
+                    r#"{
+                        "id": "1",
+                        "email": "alice@gmail.com",
+                        "verified_email": false
+                    }"#,
+                ).unwrap(),
+            ).with_user(
+                "bob",
+                from_str::<Value>(
+                    r#"{
+                        "id": "2",
+                        "email": "bob@gmail.com",
+                        "verified_email": true
+                    }"#,
+                ).unwrap(),
+            );
+
+        let mut runtime = Runtime::new().unwrap();
+        let (addr, server) = mock.bind(([127, 0, 0, 1], 0));
+        runtime.spawn(server);
+
+        let mut provision = ProvisionOptions::default();
+        provision.email_domains = vec!["gmail.com".into()];
+        let state = new_state(addr, provision);
+        let auth = new_auth("google", false);
+
+        // unverified email of allowed domain
+        mock.login_as("alice");
+        assert!(match login(&mut runtime, &state, &auth, "google") {
+            Err(AuthError::Restricted) => true,
+            _ => false,
+        });
+
+        // verified email of allowed domain
+        mock.login_as("bob");
+        let user = login(&mut runtime, &state, &auth, "google").unwrap();
+        assert_eq!(user.name, "2@google");
+    }
+}
@@ -1,5 +1,16 @@
-//pub mod facebook;
+pub mod discord;
+pub mod facebook;
+pub mod generic;
 pub mod github;
+pub mod gitlab;
 pub mod google;
+pub mod mailru;
+pub mod microsoft;
+#[cfg(any(test, feature = "third_mock"))]
+pub mod mock;
+#[cfg(feature = "oidc_auth")]
+pub mod oidc;
+#[cfg(any(test, feature = "third_mock"))]
+pub mod recorded;
 pub mod vkontakte;
 pub mod yandex;
@@ -0,0 +1,866 @@
+/*!
+
+## OpenID Connect service integration and OAuth2 provider
+
+The generic provider for services which supports [OpenID Connect](https://openid.net/specs/openid-connect-core-1_0.html).
+
+The endpoints is discovered using `/.well-known/openid-configuration` of issuer.
+The user info is taken from ID token which signature, issuer, audience, nonce and expiration time is validated.
+The signing keys is fetched from `jwks_uri` and cached.
+The keys is refetched for unknown key identifier at most once per `refetch_time`.
+The email is taken only when it is verified by provider.
+
+Supported signature algorithms: *RS256*, *ES256*.
+
+*/
+
+use auth::{
+    oauth2::{AccessTokenResponse, IsOAuth2Provider},
+    AuthError,
+};
+use base::{BoxFuture, EmptyMap, TimeStamp};
+use base64lib::{decode_config, URL_SAFE_NO_PAD};
+use futures::{
+    future::{err, ok, result},
+    Future,
+};
+use http::{
+    client::{HasHttpClient, IsHttpClient},
+    request,
+};
+use openssl::{
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    ecdsa::EcdsaSig,
+    error::ErrorStack,
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::PKey,
+    rsa::Rsa,
+    sign::Verifier,
+};
+use serde::de::DeserializeOwned;
+use serde_json;
+use sodiumoxide::crypto::hash::sha256;
+use std::borrow::Cow;
+use std::sync::{Arc, RwLock};
+use third::{IsThirdService, ThirdError};
+use user::{
+    HasBirthDate, HasEmail, HasFamilyName, HasFullName, HasGender, HasGivenName, HasHomeUrl,
+    HasImageUrl, HasLocale, HasMiddleName, HasNickName, IsAccountData,
+};
+
+/// OpenID Connect config
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Service name
+    pub name: String,
+
+    /// Issuer identifier
+    ///
+    /// The URL which is used to discover provider metadata.
+    pub issuer: String,
+
+    /// Client identifier
+    ///
+    /// The audience of ID token should contain it.
+    pub client_id: String,
+
+    /// Scope to use
+    #[serde(default = "default_scopes")]
+    pub scope: Vec<String>,
+
+    /// Signing keys cache time in milliseconds
+    #[serde(default = "default_keys_time")]
+    pub keys_time: TimeStamp,
+
+    /// Minimum interval between fetching signing keys in milliseconds
+    ///
+    /// Limits refetching of cached keys for tokens with unknown key identifier.
+    #[serde(default = "default_refetch_time")]
+    pub refetch_time: TimeStamp,
+
+    /// Allowed clock skew in milliseconds
+    #[serde(default = "default_leeway")]
+    pub leeway: TimeStamp,
+}
+
+fn default_scopes() -> Vec<String> {
+    vec!["openid".into(), "profile".into(), "email".into()]
+}
+
+fn default_keys_time() -> TimeStamp {
+    TimeStamp::default().with_hours(1)
+}
+
+fn default_refetch_time() -> TimeStamp {
+    TimeStamp::default().with_mins(1)
+}
+
+fn default_leeway() -> TimeStamp {
+    TimeStamp::default().with_mins(1)
+}
+
+impl Config {
+    /// Create config using service name, issuer and client identifier
+    pub fn new<N, I, C>(name: N, issuer: I, client_id: C) -> Self
+    where
+        N: Into<String>,
+        I: Into<String>,
+        C: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            issuer: issuer.into(),
+            client_id: client_id.into(),
+            scope: default_scopes(),
+            keys_time: default_keys_time(),
+            refetch_time: default_refetch_time(),
+            leeway: default_leeway(),
+        }
+    }
+}
+
+/// OpenID Connect provider metadata
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Issuer identifier
+    pub issuer: String,
+
+    /// Authorization endpoint URL
+    pub authorization_endpoint: String,
+
+    /// Token endpoint URL
+    pub token_endpoint: String,
+
+    /// Signing keys URL
+    pub jwks_uri: String,
+
+    /// User info endpoint URL
+    #[serde(default)]
+    pub userinfo_endpoint: Option<String>,
+}
+
+/// JSON web key
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JsonWebKey {
+    /// Key type
+    pub kty: String,
+
+    /// Key identifier
+    #[serde(default)]
+    pub kid: Option<String>,
+
+    /// RSA modulus
+    #[serde(default)]
+    pub n: String,
+
+    /// RSA exponent
+    #[serde(default)]
+    pub e: String,
+
+    /// EC curve
+    #[serde(default)]
+    pub crv: String,
+
+    /// EC x coordinate
+    #[serde(default)]
+    pub x: String,
+
+    /// EC y coordinate
+    #[serde(default)]
+    pub y: String,
+}
+
+/// JSON web key set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonWebKeySet {
+    pub keys: Vec<JsonWebKey>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct IdClaims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    #[serde(default)]
+    iat: Option<i64>,
+    #[serde(default)]
+    nonce: Option<String>,
+    #[serde(flatten)]
+    user: UserClaims,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserClaims {
+    sub: String,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: Option<bool>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    given_name: Option<String>,
+    #[serde(default)]
+    middle_name: Option<String>,
+    #[serde(default)]
+    family_name: Option<String>,
+    #[serde(default)]
+    nickname: Option<String>,
+    #[serde(default)]
+    preferred_username: Option<String>,
+    #[serde(default)]
+    gender: Option<String>,
+    #[serde(default)]
+    birthdate: Option<String>,
+    #[serde(default)]
+    locale: Option<String>,
+    #[serde(default)]
+    picture: Option<String>,
+    #[serde(default)]
+    website: Option<String>,
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+impl UserClaims {
+    fn into_account<A>(self) -> A
+    where
+        A: IsAccountData
+            + HasNickName
+            + HasFullName
+            + HasGivenName
+            + HasMiddleName
+            + HasFamilyName
+            + HasGender
+            + HasBirthDate
+            + HasLocale
+            + HasEmail
+            + HasImageUrl
+            + HasHomeUrl,
+    {
+        let mut account = A::create_new(self.sub);
+
+        if let Some(name) = self.nickname.or(self.preferred_username) {
+            account.set_nick_name(Some(name));
+        }
+
+        if let Some(name) = self.name {
+            account.set_full_name(Some(name));
+        }
+
+        if let Some(name) = self.given_name {
+            account.set_given_name(Some(name));
+        }
+
+        if let Some(name) = self.middle_name {
+            account.set_middle_name(Some(name));
+        }
+
+        if let Some(name) = self.family_name {
+            account.set_family_name(Some(name));
+        }
+
+        if let Some(Ok(gender)) = self.gender.map(|gender| gender.parse()) {
+            account.set_gender(Some(gender));
+        }
+
+        if let Some(Ok(date)) = self
+            .birthdate
+            .map(|date| TimeStamp::parse(&date, &"%Y-%m-%d"))
+        {
+            account.set_birth_date(Some(date));
+        }
+
+        if let Some(locale) = self.locale {
+            account.set_locale(Some(locale));
+        }
+
+        // unverified email should not be trusted
+        if self.email_verified == Some(true) {
+            if let Some(Ok(email)) = self.email.map(|email| email.parse()) {
+                account.set_email(Some(email));
+                account.set_email_verified(true);
+            }
+        }
+
+        if let Some(Ok(url)) = self.picture.map(|url| url.parse()) {
+            account.set_image_url(Some(url));
+        }
+
+        if let Some(Ok(url)) = self.website.or(self.profile).map(|url| url.parse()) {
+            account.set_home_url(Some(url));
+        }
+
+        account
+    }
+}
+
+struct KeyCache {
+    keys: Vec<JsonWebKey>,
+    /// Expiration time
+    etime: TimeStamp,
+    /// Last fetching time
+    ftime: TimeStamp,
+}
+
+struct State {
+    config: Config,
+    metadata: Metadata,
+    keys: RwLock<KeyCache>,
+}
+
+/// OpenID Connect service
+#[derive(Clone)]
+pub struct Service(Arc<State>);
+
+impl Service {
+    /// Create service using known provider metadata
+    pub fn new(config: Config, metadata: Metadata) -> Self {
+        Service(Arc::new(State {
+            config,
+            metadata,
+            keys: RwLock::new(KeyCache {
+                keys: Vec::new(),
+                etime: TimeStamp::default(),
+                ftime: TimeStamp::default(),
+            }),
+        }))
+    }
+
+    /// Create service discovering provider metadata
+    pub fn discover<C>(client: &C, config: Config) -> BoxFuture<Self, ThirdError>
+    where
+        C: IsHttpClient,
+    {
+        use self::request::*;
+
+        let url = config.issuer.trim_end_matches('/').to_string()
+            + "/.well-known/openid-configuration";
+
+        Box::new(
+            client
+                .fetch(Method(
+                    "GET",
+                    Url(
+                        url.as_str(),
+                        Header("Accept", "application/json", NoBody),
+                    ),
+                )).map_err(|error| {
+                    error!("Error when fetching provider metadata: {}", error);
+                    ThirdError::ServiceError
+                }).map(JsonBody::into_inner)
+                .and_then(|metadata: Metadata| {
+                    if metadata.issuer.trim_end_matches('/')
+                        != config.issuer.trim_end_matches('/')
+                    {
+                        error!("Provider metadata of other issuer: {}", metadata.issuer);
+                        return Err(ThirdError::ServiceError);
+                    }
+                    Ok(Service::new(config, metadata))
+                }),
+        )
+    }
+
+    /// Get provider metadata
+    pub fn metadata(&self) -> &Metadata {
+        &self.0.metadata
+    }
+
+    /// Get signing keys
+    ///
+    /// The keys will be fetched when cache is outdated or has no key with specified identifier.
+    /// The cached keys will be used when the keys was fetched less than `refetch_time` ago.
+    fn get_keys<S>(&self, state: &S, kid: Option<&str>) -> BoxFuture<Vec<JsonWebKey>, ThirdError>
+    where
+        S: HasHttpClient,
+    {
+        {
+            let now = TimeStamp::now();
+            let mut cache = self.0.keys.write().unwrap();
+            if cache.etime > now
+                && (cache.ftime + self.0.config.refetch_time > now || kid
+                    .map(|kid| {
+                        cache
+                            .keys
+                            .iter()
+                            .any(|key| key.kid.as_ref().map(|id| id == kid).unwrap_or(false))
+                    }).unwrap_or(true))
+            {
+                return Box::new(ok(cache.keys.clone()));
+            }
+            // mark fetching to prevent concurrent refetches
+            cache.ftime = now;
+        }
+
+        use self::request::*;
+
+        let client: &S::HttpClient = state.as_ref();
+        let this = self.clone();
+
+        Box::new(
+            client
+                .fetch(Method(
+                    "GET",
+                    Url(
+                        self.0.metadata.jwks_uri.as_str(),
+                        Header("Accept", "application/json", NoBody),
+                    ),
+                )).map_err(|error| {
+                    error!("Error when fetching signing keys: {}", error);
+                    ThirdError::ServiceError
+                }).map(JsonBody::into_inner)
+                .map(move |JsonWebKeySet { keys }| {
+                    let mut cache = this.0.keys.write().unwrap();
+                    cache.keys = keys.clone();
+                    cache.etime = TimeStamp::now() + this.0.config.keys_time;
+                    keys
+                }),
+        )
+    }
+
+    /// Validate ID token and get claims
+    fn verify_id_token(
+        &self,
+        token: &str,
+        keys: &[JsonWebKey],
+        nonce: &str,
+        now: TimeStamp,
+    ) -> Result<IdClaims, ThirdError> {
+        let parts: Vec<&str> = token.split('.').collect();
+        if parts.len() != 3 {
+            warn!("Malformed ID token");
+            return Err(ThirdError::BadToken);
+        }
+
+        let header: TokenHeader = decode_json(parts[0])?;
+        let signature = decode_b64(parts[2])?;
+        let data = &token[..parts[0].len() + 1 + parts[1].len()];
+
+        if !keys
+            .iter()
+            .filter(|key| header.kid.is_none() || key.kid == header.kid)
+            .any(|key| {
+                verify_signature(&header.alg, key, data.as_bytes(), &signature).unwrap_or_else(
+                    |error| {
+                        warn!("Unable to verify ID token signature: {}", error);
+                        false
+                    },
+                )
+            }) {
+            warn!("Invalid ID token signature");
+            return Err(ThirdError::BadToken);
+        }
+
+        let claims: IdClaims = decode_json(parts[1])?;
+        let config = &self.0.config;
+
+        if claims.iss != self.0.metadata.issuer {
+            warn!("ID token of other issuer: {}", claims.iss);
+            return Err(ThirdError::BadToken);
+        }
+
+        if !claims.aud.contains(&config.client_id) {
+            warn!("ID token for other audience");
+            return Err(ThirdError::BadToken);
+        }
+
+        if claims.nonce.as_ref().map(|value| value != nonce).unwrap_or(true) {
+            warn!("ID token with invalid nonce");
+            return Err(ThirdError::BadToken);
+        }
+
+        if from_secs(claims.exp) + config.leeway <= now {
+            warn!("Expired ID token");
+            return Err(ThirdError::BadToken);
+        }
+
+        if claims
+            .iat
+            .map(|iat| from_secs(iat) > now + config.leeway)
+            .unwrap_or(false)
+        {
+            warn!("ID token issued in future");
+            return Err(ThirdError::BadToken);
+        }
+
+        Ok(claims)
+    }
+}
+
+fn from_secs(secs: i64) -> TimeStamp {
+    TimeStamp::default().with_msecs(secs * 1000)
+}
+
+fn decode_b64(data: &str) -> Result<Vec<u8>, ThirdError> {
+    decode_config(data, URL_SAFE_NO_PAD).map_err(|_| {
+        warn!("Malformed ID token");
+        ThirdError::BadToken
+    })
+}
+
+fn decode_json<T: DeserializeOwned>(data: &str) -> Result<T, ThirdError> {
+    serde_json::from_slice(&decode_b64(data)?).map_err(|error| {
+        warn!("Malformed ID token: {}", error);
+        ThirdError::BadToken
+    })
+}
+
+fn verify_signature(
+    alg: &str,
+    key: &JsonWebKey,
+    data: &[u8],
+    signature: &[u8],
+) -> Result<bool, ErrorStack> {
+    let decode = |data: &str| decode_config(data, URL_SAFE_NO_PAD).unwrap_or_default();
+
+    match (alg, key.kty.as_str(), key.crv.as_str()) {
+        ("RS256", "RSA", _) => {
+            let (n, e) = (
+                BigNum::from_slice(&decode(&key.n))?,
+                BigNum::from_slice(&decode(&key.e))?,
+            );
+            let key = PKey::from_rsa(Rsa::from_public_components(n, e)?)?;
+            let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+            verifier.update(data)?;
+            verifier.verify(signature)
+        }
+        ("ES256", "EC", "P-256") => {
+            if signature.len() != 64 {
+                return Ok(false);
+            }
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+            let (x, y) = (
+                BigNum::from_slice(&decode(&key.x))?,
+                BigNum::from_slice(&decode(&key.y))?,
+            );
+            let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)?;
+            let (r, s) = (
+                BigNum::from_slice(&signature[..32])?,
+                BigNum::from_slice(&signature[32..])?,
+            );
+            EcdsaSig::from_private_components(r, s)?.verify(&sha256::hash(data)[..], &key)
+        }
+        _ => Ok(false),
+    }
+}
+
+impl<S, A> IsThirdService<S, A> for Service
+where
+    S: HasHttpClient,
+    A: IsAccountData
+        + HasNickName
+        + HasFullName
+        + HasGivenName
+        + HasMiddleName
+        + HasFamilyName
+        + HasGender
+        + HasBirthDate
+        + HasLocale
+        + HasEmail
+        + HasImageUrl
+        + HasHomeUrl
+        + Send
+        + 'static,
+{
+    fn service_name(&self) -> Cow<str> {
+        self.0.config.name.as_str().into()
+    }
+
+    fn get_user_profile(&self, state: &S, access_token: Cow<str>) -> BoxFuture<A, ThirdError> {
+        use self::request::*;
+
+        let url = if let Some(url) = &self.0.metadata.userinfo_endpoint {
+            url
+        } else {
+            error!("Provider has no user info endpoint");
+            return Box::new(err(ThirdError::ServiceError));
+        };
+
+        let client: &S::HttpClient = state.as_ref();
+
+        Box::new(
+            client
+                .fetch(Method(
+                    "GET",
+                    Url(
+                        url.as_str(),
+                        Header(
+                            "Authorization",
+                            format!("Bearer {}", access_token),
+                            Header("Accept", "application/json", NoBody),
+                        ),
+                    ),
+                )).map_err(|error| {
+                    error!("Error when fetching user profile: {}", error);
+                    ThirdError::ServiceError
+                }).map(JsonBody::into_inner)
+                .map(UserClaims::into_account),
+        )
+    }
+}
+
+impl<S, A> IsOAuth2Provider<S, A> for Service
+where
+    S: HasHttpClient,
+    A: IsAccountData
+        + HasNickName
+        + HasFullName
+        + HasGivenName
+        + HasMiddleName
+        + HasFamilyName
+        + HasGender
+        + HasBirthDate
+        + HasLocale
+        + HasEmail
+        + HasImageUrl
+        + HasHomeUrl
+        + Send
+        + 'static,
+{
+    type AuthorizeParams = EmptyMap;
+
+    fn authorize_url(&self) -> Cow<str> {
+        self.0.metadata.authorization_endpoint.as_str().into()
+    }
+
+    fn authorize_scope(&self) -> Cow<str> {
+        self.0.config.scope.join(" ").into()
+    }
+
+    fn authorize_params(&self) -> Self::AuthorizeParams {
+        EmptyMap
+    }
+
+    fn access_token_url(&self) -> Cow<str> {
+        self.0.metadata.token_endpoint.as_str().into()
+    }
+
+    fn fetch_token_user_info(
+        &self,
+        state: &S,
+        token: &AccessTokenResponse,
+        nonce: &str,
+    ) -> BoxFuture<A, AuthError> {
+        let id_token = if let Some(id_token) = &token.id_token {
+            id_token.clone()
+        } else {
+            error!("Missing ID token");
+            return Box::new(err(AuthError::ServiceError));
+        };
+
+        let kid = id_token
+            .split('.')
+            .next()
+            .and_then(|header| decode_json::<TokenHeader>(header).ok())
+            .and_then(|header| header.kid);
+
+        let this = self.clone();
+        let nonce = nonce.to_string();
+
+        Box::new(
+            self.get_keys(state, kid.as_ref().map(AsRef::as_ref))
+                .and_then(move |keys| {
+                    result(this.verify_id_token(&id_token, &keys, &nonce, TimeStamp::now()))
+                }).map(|claims| claims.user.into_account())
+                .map_err(|error| match error {
+                    ThirdError::BadToken => AuthError::BadIdent,
+                    error => {
+                        error!("Third service error: {}", error);
+                        AuthError::ServiceError
+                    }
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use base64lib::encode_config;
+    use openssl::sign::Signer;
+    use serde_json::json;
+    use third::service::recorded::RecordedClient;
+    use user::stub::AccountData;
+
+    fn b64<T: AsRef<[u8]>>(data: T) -> String {
+        encode_config(data.as_ref(), URL_SAFE_NO_PAD)
+    }
+
+    fn sign_token(key: &PKey<::openssl::pkey::Private>, claims: serde_json::Value) -> String {
+        let data = b64(r#"{"alg":"RS256","kid":"key1"}"#) + "." + &b64(claims.to_string());
+        let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+        signer.update(data.as_bytes()).unwrap();
+        data + "." + &b64(signer.sign_to_vec().unwrap())
+    }
+
+    #[test]
+    fn id_token() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let jwk = JsonWebKey {
+            kty: "RSA".into(),
+            kid: Some("key1".into()),
+            n: b64(rsa.n().to_vec()),
+            e: b64(rsa.e().to_vec()),
+            ..JsonWebKey::default()
+        };
+        let key = PKey::from_rsa(rsa).unwrap();
+
+        let issuer = "https://idp.test";
+        let provider = RecordedClient::default()
+            .with(
+                format!("{}/.well-known/openid-configuration", issuer),
+                json!({
+                    "issuer": issuer,
+                    "authorization_endpoint": "https://idp.test/authorize",
+                    "token_endpoint": "https://idp.test/token",
+                    "jwks_uri": "https://idp.test/keys",
+                }).to_string(),
+            ).with("https://idp.test/keys", json!({ "keys": [jwk] }).to_string());
+
+        let service = Service::discover(&provider, Config::new("idp", issuer, "client"))
+            .wait()
+            .unwrap();
+        assert_eq!(
+            IsOAuth2Provider::<RecordedClient, AccountData>::access_token_url(&service),
+            "https://idp.test/token"
+        );
+
+        let exp = (Into::<i64>::into(TimeStamp::now()) / 1000) + 60;
+        let claims = json!({
+            "iss": issuer,
+            "aud": ["other", "client"],
+            "exp": exp,
+            "nonce": "nonce1",
+            "sub": "user1",
+            "email": "user@idp.test",
+            "email_verified": true,
+            "name": "Test User",
+        });
+
+        let fetch = |token: String, nonce: &str| {
+            let response = AccessTokenResponse {
+                access_token: "access".into(),
+                refresh_token: None,
+                expires_in: None,
+                id_token: Some(token),
+            };
+            IsOAuth2Provider::<RecordedClient, AccountData>::fetch_token_user_info(
+                &service, &provider, &response, nonce,
+            ).wait()
+        };
+
+        let account = fetch(sign_token(&key, claims.clone()), "nonce1").unwrap();
+        assert_eq!(account.name, "user1");
+        assert_eq!(account.full_name, Some("Test User".into()));
+        assert!(account.email.is_some());
+        assert!(account.email_verified);
+
+        // unverified email
+        let mut other = claims.clone();
+        other["email_verified"] = json!(false);
+        let account = fetch(sign_token(&key, other), "nonce1").unwrap();
+        assert_eq!(account.email, None);
+        let mut other = claims.clone();
+        other.as_object_mut().unwrap().remove("email_verified");
+        let account = fetch(sign_token(&key, other), "nonce1").unwrap();
+        assert_eq!(account.email, None);
+
+        // nonce of other login
+        assert!(fetch(sign_token(&key, claims.clone()), "nonce2").is_err());
+
+        // forged signature
+        let token = sign_token(&key, claims.clone());
+        let token = token[..token.rfind('.').unwrap()].to_string() + ".c2lnbg";
+        assert!(fetch(token, "nonce1").is_err());
+
+        // other audience
+        let mut other = claims.clone();
+        other["aud"] = json!("other");
+        assert!(fetch(sign_token(&key, other), "nonce1").is_err());
+
+        // other issuer
+        let mut other = claims.clone();
+        other["iss"] = json!("https://evil.test");
+        assert!(fetch(sign_token(&key, other), "nonce1").is_err());
+
+        // expired token
+        let mut other = claims.clone();
+        other["exp"] = json!(exp - 600);
+        assert!(fetch(sign_token(&key, other), "nonce1").is_err());
+    }
+
+    #[test]
+    fn keys_refetch() {
+        let key = |kid: &str| JsonWebKey {
+            kty: "RSA".into(),
+            kid: Some(kid.into()),
+            ..JsonWebKey::default()
+        };
+        let metadata = Metadata {
+            issuer: "https://idp.test".into(),
+            authorization_endpoint: "https://idp.test/authorize".into(),
+            token_endpoint: "https://idp.test/token".into(),
+            jwks_uri: "https://idp.test/keys".into(),
+            userinfo_endpoint: None,
+        };
+        let old = RecordedClient::default()
+            .with("https://idp.test/keys", json!({ "keys": [key("key1")] }).to_string());
+        let new = RecordedClient::default().with(
+            "https://idp.test/keys",
+            json!({ "keys": [key("key1"), key("key2")] }).to_string(),
+        );
+        let kids = |keys: Vec<JsonWebKey>| -> Vec<String> {
+            keys.into_iter().filter_map(|key| key.kid).collect()
+        };
+
+        let service = Service::new(
+            Config::new("idp", "https://idp.test", "client"),
+            metadata.clone(),
+        );
+        assert_eq!(kids(service.get_keys(&old, None).wait().unwrap()), vec!["key1"]);
+        // unknown key is not refetched too often
+        assert_eq!(
+            kids(service.get_keys(&new, Some("key2")).wait().unwrap()),
+            vec!["key1"]
+        );
+
+        let mut config = Config::new("idp", "https://idp.test", "client");
+        config.refetch_time = TimeStamp::default();
+        let service = Service::new(config, metadata);
+        assert_eq!(kids(service.get_keys(&old, None).wait().unwrap()), vec!["key1"]);
+        // known key is not refetched
+        assert_eq!(
+            kids(service.get_keys(&new, Some("key1")).wait().unwrap()),
+            vec!["key1"]
+        );
+        assert_eq!(
+            kids(service.get_keys(&new, Some("key2")).wait().unwrap()),
+            vec!["key1", "key2"]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+/*!
+
+### Recorded responses of third services for tests
+
+*/
+
+use futures::future::{ok, FutureResult};
+use http::{
+    client::{HasHttpClient, IsHttpClient},
+    HttpBody, HttpRequest, HttpResponse, StatusCode,
+};
+use httplib::header::{HeaderName, HeaderValue};
+use hyper::Error as HyperError;
+use std::collections::HashMap;
+
+/// Recorded response
+#[derive(Clone)]
+struct Record {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+/// HTTP client which replies recorded responses
+///
+/// The responses are selected by request URL with query first and without query next.
+/// The empty body will be replied for unknown URL.
+#[derive(Default, Clone)]
+pub struct RecordedClient(HashMap<String, Record>);
+
+impl RecordedClient {
+    /// Add recorded response
+    pub fn with<U: Into<String>, B: Into<String>>(self, url: U, body: B) -> Self {
+        self.with_reply(url, 200, &[], body)
+    }
+
+    /// Add recorded response with status and headers
+    pub fn with_reply<U: Into<String>, B: Into<String>>(
+        mut self,
+        url: U,
+        status: u16,
+        headers: &[(&str, &str)],
+        body: B,
+    ) -> Self {
+        self.0.insert(
+            url.into(),
+            Record {
+                status,
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                body: body.into(),
+            },
+        );
+        self
+    }
+}
+
+impl IsHttpClient for RecordedClient {
+    type ResponseFuture = FutureResult<HttpResponse, HyperError>;
+
+    fn send_request(&self, request: HttpRequest) -> Self::ResponseFuture {
+        let uri = request.uri();
+        let url = format!(
+            "{}://{}{}",
+            uri.scheme_part().map(|scheme| scheme.as_str()).unwrap_or("http"),
+            uri.authority_part().map(|authority| authority.as_str()).unwrap_or(""),
+            uri.path()
+        );
+        let record = uri
+            .query()
+            .and_then(|query| self.0.get(&format!("{}?{}", url, query)))
+            .or_else(|| self.0.get(&url));
+
+        let mut response = HttpResponse::new(HttpBody::from(
+            record.map(|record| record.body.clone()).unwrap_or_default(),
+        ));
+
+        if let Some(record) = record {
+            *response.status_mut() = StatusCode::from_u16(record.status).unwrap();
+            for (name, value) in &record.headers {
+                response.headers_mut().insert(
+                    HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                    HeaderValue::from_str(value).unwrap(),
+                );
+            }
+        }
+
+        ok(response)
+    }
+}
+
+impl AsRef<RecordedClient> for RecordedClient {
+    fn as_ref(&self) -> &RecordedClient {
+        self
+    }
+}
+
+impl HasHttpClient for RecordedClient {
+    type HttpClient = RecordedClient;
+}
@@ -12,9 +12,12 @@ use http::{
     client::{HasHttpClient, IsHttpClient},
     request,
 };
+use httplib::HeaderMap;
 use std::borrow::Cow;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use third::{IsThirdService, ThirdApiParams, ThirdError};
+use third::{
+    endpoint_url, ApiAuth, ApiQuery, IsThirdApiCall, IsThirdService, ThirdApiParams, ThirdError,
+};
 use user::{
     Gender,
     HasAbout, HasBirthDate, HasCompany, HasEmail, HasFamilyName,
@@ -36,6 +39,12 @@ pub struct Config {
     /// Display of auth page
     #[serde(default)]
     pub display: DisplayKind,
+
+    /// Base URL to use instead of default
+    ///
+    /// See [`endpoint_url`](third::endpoint_url).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
 }
 
 fn default_scope() -> Vec<Scope> {
@@ -52,6 +61,7 @@ impl Default for Config {
             scope: default_scope(),
             version: default_version(),
             display: DisplayKind::default(),
+            base_url: None,
         }
     }
 }
@@ -67,6 +77,9 @@ pub enum Scope {
     /// Offline access to API
     #[serde(rename = "offline")]
     Offline,
+    /// Access to user friends
+    #[serde(rename = "friends")]
+    Friends,
 }
 
 impl Display for Scope {
@@ -75,6 +88,7 @@ impl Display for Scope {
         f.write_str(match self {
             Email => "email",
             Offline => "offline",
+            Friends => "friends",
         })
     }
 }
@@ -128,6 +142,16 @@ impl Service {
     pub fn new(config: Config) -> Self {
         Service(config)
     }
+
+    /// User friends API call
+    ///
+    /// Requires `friends` scope.
+    pub fn friends(&self) -> Friends {
+        Friends {
+            version: self.0.version.clone(),
+            base_url: self.0.base_url.clone(),
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -233,6 +257,94 @@ enum ApiResponse<T> {
     Error(ApiError),
 }
 
+/// User friends API call
+#[derive(Clone)]
+pub struct Friends {
+    version: String,
+    base_url: Option<String>,
+}
+
+/// User friend
+#[derive(Debug, Deserialize)]
+pub struct Friend {
+    pub id: u64,
+    #[serde(default)]
+    pub first_name: String,
+    #[serde(default)]
+    pub last_name: String,
+    #[serde(default)]
+    pub nickname: String,
+    #[serde(default)]
+    pub photo_100: String,
+}
+
+/// Friends API response
+#[derive(Deserialize)]
+pub struct FriendsResponse(ApiResponse<FriendsList>);
+
+#[derive(Deserialize)]
+struct FriendsList {
+    count: usize,
+    #[serde(default)]
+    items: Vec<Friend>,
+}
+
+impl IsThirdApiCall for Friends {
+    type Item = Friend;
+    type Data = FriendsResponse;
+
+    fn auth(&self) -> ApiAuth {
+        ApiAuth::Query
+    }
+
+    fn url(&self) -> Cow<str> {
+        endpoint_url(&self.base_url, "https://api.vk.com/method/friends.get")
+    }
+
+    fn query(&self, cursor: Option<&str>) -> ApiQuery {
+        let mut query = ApiQuery::new();
+        query.insert("v", self.version.clone());
+        query.insert("fields", "nickname,photo_100".into());
+        query.insert("count", "1000".into());
+        if let Some(offset) = cursor {
+            query.insert("offset", offset.into());
+        }
+        query
+    }
+
+    fn page(
+        &self,
+        data: Self::Data,
+        _headers: &HeaderMap,
+        cursor: Option<&str>,
+    ) -> Result<(Vec<Self::Item>, Option<String>), ThirdError> {
+        match data.0 {
+            ApiResponse::Response(list) => {
+                let offset = cursor.and_then(|offset| offset.parse().ok()).unwrap_or(0);
+                let next = offset + list.items.len();
+                Ok((
+                    list.items,
+                    if next > offset && next < list.count {
+                        Some(next.to_string())
+                    } else {
+                        None
+                    },
+                ))
+            }
+            ApiResponse::Error(error) => {
+                error!("Unable to get friends: {:?}", error);
+                Err(match error.code {
+                    // user authorization failed
+                    5 => ThirdError::BadToken,
+                    // too many requests per second
+                    6 => ThirdError::TooManyRequests(TimeStamp::now().with_secs(1)),
+                    _ => ThirdError::ServiceError,
+                })
+            }
+        }
+    }
+}
+
 impl<S, A> IsThirdService<S, A> for Service
 where
     S: HasHttpClient,
@@ -267,7 +379,7 @@ where
                 .fetch(Method(
                     "GET",
                     UrlWithQuery(
-                        "https://api.vk.com/method/users.get",
+                        endpoint_url(&self.0.base_url, "https://api.vk.com/method/users.get"),
                         ThirdApiParams::new(&access_token)
                             .with(UserGetRequest {
                                 version: &self.0.version,
@@ -407,7 +519,7 @@ where
     type AuthorizeParams = AuthorizeParams;
 
     fn authorize_url(&self) -> Cow<str> {
-        "https://oauth.vk.com/authorize".into()
+        endpoint_url(&self.0.base_url, "https://oauth.vk.com/authorize")
     }
 
     fn authorize_scope(&self) -> Cow<str> {
@@ -425,6 +537,106 @@ where
     }
 
     fn access_token_url(&self) -> Cow<str> {
-        "https://oauth.vk.com/access_token".into()
+        endpoint_url(&self.0.base_url, "https://oauth.vk.com/access_token")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use third::{fetch_api_all, fetch_api_page, service::recorded::RecordedClient};
+
+    #[test]
+    fn friends() {
+        let client = RecordedClient::default()
+            .with(
+                "https://api.vk.com/method/friends.get?access_token=token&count=1000&fields=nickname%2Cphoto_100&v=5.87",
+                r#"{
+                    "response": {
+                        "count": 3,
+                        "items": [
+                            { "id": 1, "first_name": "Pavel", "last_name": "Durov", "nickname": "" },
+                            { "id": 2, "first_name": "Ivan" }
+                        ]
+                    }
+                }"#,
+            ).with(
+                "https://api.vk.com/method/friends.get?access_token=token&count=1000&fields=nickname%2Cphoto_100&offset=2&v=5.87",
+                r#"{
+                    "response": {
+                        "count": 3,
+                        "items": [
+                            { "id": 3, "first_name": "Petr", "photo_100": "https://vk.com/images/camera_100.png" }
+                        ]
+                    }
+                }"#,
+            );
+
+        let call = Service::new(Config::default()).friends();
+
+        let page = fetch_api_page(&client, &call, "token", None).wait().unwrap();
+        assert_eq!(page.next, Some("2".into()));
+        assert_eq!(page.items.len(), 2);
+        assert_eq!(page.items[0].id, 1);
+        assert_eq!(page.items[0].first_name, "Pavel");
+        assert_eq!(page.items[0].last_name, "Durov");
+        assert_eq!(page.items[1].last_name, "");
+
+        let page = fetch_api_page(&client, &call, "token", Some("2"))
+            .wait()
+            .unwrap();
+        assert_eq!(page.next, None);
+        assert_eq!(
+            page.items[0].photo_100,
+            "https://vk.com/images/camera_100.png"
+        );
+
+        let friends = fetch_api_all(&client, call, "token".into(), 10)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            friends.iter().map(|friend| friend.id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn friends_errors() {
+        let client = RecordedClient::default()
+            .with(
+                "https://api.vk.com/method/friends.get?access_token=expired&count=1000&fields=nickname%2Cphoto_100&v=5.87",
+                r#"{
+                    "error": {
+                        "code": 5,
+                        "type": "auth",
+                        "description": "User authorization failed: access_token has expired."
+                    }
+                }"#,
+            ).with(
+                "https://api.vk.com/method/friends.get?access_token=token&count=1000&fields=nickname%2Cphoto_100&v=5.87",
+                r#"{
+                    "error": {
+                        "code": 6,
+                        "type": "limit",
+                        "description": "Too many requests per second"
+                    }
+                }"#,
+            );
+
+        let call = Service::new(Config::default()).friends();
+
+        assert!(
+            match fetch_api_page(&client, &call, "expired", None).wait() {
+                Err(ThirdError::BadToken) => true,
+                _ => false,
+            }
+        );
+
+        assert!(
+            match fetch_api_page(&client, &call, "token", None).wait() {
+                Err(ThirdError::TooManyRequests(_)) => true,
+                _ => false,
+            }
+        );
     }
 }
@@ -14,7 +14,7 @@ use http::{
 };
 use std::borrow::Cow;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use third::{IsThirdService, ThirdApiParams, ThirdError};
+use third::{endpoint_url, IsThirdService, ThirdApiParams, ThirdError};
 use user::{
     Gender, HasBirthDate, HasEmail, HasFamilyName, HasFullName, HasGender, HasGivenName,
     HasImageUrl, HasNickName, IsAccountData,
@@ -34,6 +34,12 @@ pub struct Config {
     /// Optional scope to use
     #[serde(default = "default_optional_scope")]
     pub optional_scope: Vec<Scope>,
+
+    /// Base URL to use instead of default
+    ///
+    /// See [`endpoint_url`](third::endpoint_url).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
 }
 
 fn default_scope() -> Vec<Scope> {
@@ -50,6 +56,7 @@ impl Default for Config {
             scope: default_scope(),
             force_confirm: false,
             optional_scope: default_optional_scope(),
+            base_url: None,
         }
     }
 }
@@ -172,7 +179,7 @@ where
                 .fetch(Method(
                     "GET",
                     UrlWithQuery(
-                        "https://login.yandex.ru/info",
+                        endpoint_url(&self.0.base_url, "https://login.yandex.ru/info"),
                         ThirdApiParams::new(&access_token),
                         Header(
                             "Authorization",
@@ -256,8 +263,8 @@ where
 {
     type AuthorizeParams = AuthorizeParams;
 
-    fn authorize_url(&self) -> Cow<'static, str> {
-        "https://oauth.yandex.ru/authorize".into()
+    fn authorize_url(&self) -> Cow<str> {
+        endpoint_url(&self.0.base_url, "https://oauth.yandex.ru/authorize")
     }
 
     fn authorize_scope(&self) -> Cow<str> {
@@ -276,7 +283,7 @@ where
         }
     }
 
-    fn access_token_url(&self) -> Cow<'static, str> {
-        "https://oauth.yandex.ru/token".into()
+    fn access_token_url(&self) -> Cow<str> {
+        endpoint_url(&self.0.base_url, "https://oauth.yandex.ru/token")
     }
 }
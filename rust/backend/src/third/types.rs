@@ -1,4 +1,5 @@
 use base::EmptyMap;
+use std::borrow::Cow;
 
 #[derive(Serialize)]
 pub struct ThirdApiParams<'a, T> {
@@ -25,3 +26,17 @@ impl<'a> ThirdApiParams<'a, EmptyMap> {
         }
     }
 }
+
+/// Get endpoint URL of service using base URL override
+///
+/// When base URL is set the scheme and host of default URL is replaced by it keeping the path.
+/// It allows to point the service to mock server in tests or to some proxy.
+pub fn endpoint_url<'a>(base_url: &Option<String>, url: &'a str) -> Cow<'a, str> {
+    if let Some(base_url) = base_url {
+        // skip `scheme://host`
+        let path = url.splitn(4, '/').nth(3).unwrap_or("");
+        format!("{}/{}", base_url.trim_end_matches('/'), path).into()
+    } else {
+        url.into()
+    }
+}
@@ -1,13 +1,27 @@
-use super::{HasUserStorage, IsUserStorage, UserArg, UserId};
-use access::{Grant, HasAccess};
-use auth::{AuthError, HasSessionStorage, HasUserAuth};
+use super::{
+    verify_password, HasPasswordHash, HasPasswordHistory, HasPasswordPolicy, HasUserStorage,
+    IsUserData, IsUserStorage, NewUser, PasswordChange, PasswordPolicy, PasswordViolations,
+    UserArg, UserId,
+};
+use access::{
+    audit_access_to, audit_denial, AuditDecision, AuditRecord, AuditSubject, Grant, HasAccess,
+    HasAuditSink, IsAuditSubject,
+};
+use auth::{
+    cancel_throttle_attempt, del_throttle_counter, reserve_throttle_attempt, AuthError,
+    HasProvisionPolicy, HasSessionOptions, HasSessionStorage, HasThrottleOptions,
+    HasThrottleStorage, HasTokenStorage, HasUserAuth, IsSessionData, IsSessionStorage, IsTokenAuth,
+    SessionId, SignUp, ThrottleOptions,
+};
 use base::{CanCreateView, CanUpdateData, ResourceError};
 use crypto::HasSecretKey;
 use futures::{
-    future::{err, Either},
+    future::{err, join_all, Either},
     Future,
 };
 use warp::{Filter, Rejection, Reply};
+use filters::{x_client_addr, HasClientAddrOptions};
+use std::net::IpAddr;
 use x_auth;
 
 /// Handle get user data
@@ -15,17 +29,31 @@ pub fn get_user_data<S>(
     state: &S,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
 where
-    S: HasSecretKey + HasUserStorage + HasUserAuth + HasSessionStorage + Send + Sync + Clone,
-    S::UserAuth: HasAccess<UserArg, Grant>,
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<UserArg, Grant> + IsTokenAuth<S>,
     <S::UserStorage as IsUserStorage>::User: CanCreateView<S::UserAuth, UserArg>,
 {
     let state = state.clone();
     warp::get2()
         .and(warp::path::param()) // user id
         .and(x_auth(&state))
-        .and_then(|user, auth: S::UserAuth| {
-            auth.access_to(&UserArg { user }, &Grant::Read)
-                .map(|auth: S::UserAuth| (user, auth))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &UserArg { user }, &Grant::Read)
+                    .map(move |auth: S::UserAuth| (user, auth))
+            }
         }).and_then(move |(user, auth)| {
             get_user_data_fn(&state, user, auth)
                 .map_err(warp::reject::custom)
@@ -63,8 +91,18 @@ pub fn put_user_data<S>(
     state: &S,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
 where
-    S: HasSecretKey + HasUserStorage + HasUserAuth + HasSessionStorage + Send + Sync + Clone,
-    S::UserAuth: HasAccess<UserArg, Grant>,
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<UserArg, Grant> + IsTokenAuth<S>,
     <S::UserStorage as IsUserStorage>::User: CanUpdateData<S::UserAuth, UserArg>,
 {
     let state = state.clone();
@@ -72,9 +110,13 @@ where
     warp::put2()
         .and(warp::path::param()) // user id
         .and(x_auth(&state))
-        .and_then(|user, auth: S::UserAuth| {
-            auth.access_to(&UserArg { user }, &Grant::Update)
-                .map(|auth| (user, auth))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &UserArg { user }, &Grant::Update)
+                    .map(move |auth| (user, auth))
+            }
         }).and(warp::body::json::<
             <<S::UserStorage as IsUserStorage>::User as CanUpdateData<S::UserAuth, UserArg>>::View,
         >()).and_then(move |(user, auth), data| {
@@ -121,10 +163,529 @@ where
 /// Scope with user data handlers
 pub fn user_scope<S>(state: &S) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
 where
-    S: HasSecretKey + HasUserAuth + HasUserStorage + HasSessionStorage + Send + Sync + Clone,
-    S::UserAuth: HasAccess<UserArg, Grant>,
+    S: HasSecretKey
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasUserStorage
+        + HasSessionStorage
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<UserArg, Grant> + IsTokenAuth<S>,
     <S::UserStorage as IsUserStorage>::User:
         CanCreateView<S::UserAuth, UserArg> + CanUpdateData<S::UserAuth, UserArg>,
 {
     get_user_data(state).or(put_user_data(state))
 }
+
+/// Handle change user password
+///
+/// The new password should satisfy [`PasswordPolicy`](user::PasswordPolicy).
+/// The current password is required when user already has it.
+/// The wrong current passwords is throttled using [`ThrottleOptions`](auth::ThrottleOptions) of state.
+/// All other sessions of user will be deleted on success,
+/// the session which used to change password is kept.
+pub fn put_user_password<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasSecretKey
+        + HasUserStorage
+        + HasUserAuth
+        + HasSessionOptions
+        + HasTokenStorage
+        + HasAuditSink
+        + HasSessionStorage
+        + HasPasswordPolicy
+        + HasThrottleStorage
+        + HasThrottleOptions
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    S::UserAuth: HasAccess<UserArg, Grant> + IsTokenAuth<S>,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasPasswordHistory,
+{
+    let state = state.clone();
+
+    warp::put2()
+        .and(warp::path::param()) // user id
+        .and(warp::path("password"))
+        .and(x_auth(&state))
+        .and(x_client_addr(&state))
+        .and_then({
+            let state = state.clone();
+            move |user, auth: S::UserAuth, addr| {
+                audit_access_to(&state, addr, auth, &UserArg { user }, &Grant::Update).map(
+                    move |auth: S::UserAuth| {
+                        let subject = auth.audit_subject();
+                        // keep own interactive session only
+                        let keep = if subject.user == user && subject.sess != 0 {
+                            Some(subject.sess)
+                        } else {
+                            None
+                        };
+                        (user, keep)
+                    },
+                )
+            }
+        }).and(warp::body::json())
+        .and_then(move |(user, keep), req: PasswordChange| {
+            put_user_password_fn(&state, user, keep, req).map(|_| warp::reply())
+        }).recover(PasswordViolations::recover)
+        .recover(AuthError::recover)
+        .recover(ResourceError::recover)
+}
+
+fn put_user_password_fn<S>(
+    state: &S,
+    user: UserId,
+    keep: Option<SessionId>,
+    req: PasswordChange,
+) -> impl Future<Item = (), Error = Rejection>
+where
+    S: HasUserStorage
+        + HasSessionStorage
+        + HasPasswordPolicy
+        + HasThrottleStorage
+        + HasThrottleOptions
+        + Send
+        + Sync
+        + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasPasswordHistory,
+{
+    let state = state.clone();
+    let options = (state.as_ref() as &ThrottleOptions).clone();
+    let key = format!("password:{}", user);
+    let keys = vec![key.clone()];
+
+    // the attempt is counted as failure until current password is verified
+    reserve_throttle_attempt(&state, &options, &keys)
+        .map_err(warp::reject::custom)
+        .and_then({
+            let state = state.clone();
+            move |_| {
+                (state.as_ref() as &S::UserStorage)
+                    .get_user_data(user)
+                    .map_err(|error| {
+                        error!("Unable to get user data: {}", error);
+                        ResourceError::Backend
+                    }).and_then(|user| user.ok_or(ResourceError::Missing))
+                    .or_else({
+                        let state = state.clone();
+                        move |error| {
+                            cancel_throttle_attempt(&state, &keys)
+                                .then(move |_| Err(warp::reject::custom(error)))
+                        }
+                    })
+            }
+        }).and_then(move |mut user| {
+            let valid = match (user.get_password_hash(), &req.old) {
+                (Some(hash), Some(old)) => verify_password(old, hash),
+                (Some(_), None) => false,
+                (None, _) => true,
+            };
+            if !valid {
+                warn!("Invalid current password");
+                return Either::A(err(warp::reject::custom(AuthError::BadIdent)));
+            }
+
+            let violations =
+                (state.as_ref() as &PasswordPolicy).set_password(&mut user, &req.new);
+
+            Either::B(
+                del_throttle_counter(&state, &key)
+                    .map_err(warp::reject::custom)
+                    .and_then(move |_| match violations {
+                        Ok(_) => Either::A(put_user_password_data(&state, user, keep)),
+                        Err(violations) => {
+                            warn!("{}", violations);
+                            Either::B(err(warp::reject::custom(violations)))
+                        }
+                    }),
+            )
+        })
+}
+
+/// Save user with new password and delete user sessions
+///
+/// All sessions will be deleted except the `keep` one.
+pub fn put_user_password_data<S>(
+    state: &S,
+    user: <S::UserStorage as IsUserStorage>::User,
+    keep: Option<SessionId>,
+) -> impl Future<Item = (), Error = Rejection>
+where
+    S: HasUserStorage + HasSessionStorage + Clone,
+{
+    let state = state.clone();
+
+    (state.as_ref() as &S::UserStorage)
+        .put_user_data(user)
+        .map_err(|error| {
+            error!("Unable to put user data: {}", error);
+            warp::reject::custom(ResourceError::Backend)
+        }).and_then(move |user| {
+            let user = user.get_user_id();
+            let storage = state.as_ref() as &S::SessionStorage;
+
+            if let Some(keep) = keep {
+                let state = state.clone();
+                Either::A(
+                    storage
+                        .get_user_sessions(user)
+                        .and_then(move |sessions| {
+                            let storage = state.as_ref() as &S::SessionStorage;
+                            join_all(
+                                sessions
+                                    .iter()
+                                    .map(|session| session.session_data().sess)
+                                    .filter(|sess| *sess != keep)
+                                    .map(|sess| storage.del_user_session(user, sess))
+                                    .collect::<Vec<_>>(),
+                            )
+                        }).map(|_| ()),
+                )
+            } else {
+                Either::B(storage.del_user_sessions(user))
+            }.map_err(|error| {
+                error!("Unable to delete user sessions: {}", error);
+                warp::reject::custom(AuthError::BackendError)
+            })
+        })
+}
+
+/// Handle create user with password
+///
+/// This is an open sign up so it is not included into any scope.
+/// The sign-up should satisfy provisioning policy (see [`HasProvisionPolicy`](auth::HasProvisionPolicy)).
+/// The password should satisfy [`PasswordPolicy`](user::PasswordPolicy).
+/// The denials by provisioning policy is recorded to audit log.
+/// Returns identifier of created user.
+pub fn add_user_data<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasUserStorage
+        + HasPasswordPolicy
+        + HasProvisionPolicy
+        + HasAuditSink
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasPasswordHistory,
+{
+    let state = state.clone();
+
+    warp::post2()
+        .and(warp::path("user"))
+        .and(warp::path::end())
+        .and(x_client_addr(&state))
+        .and(warp::body::json())
+        .and_then(move |addr, req: NewUser| {
+            add_user_data_fn(&state, addr, req).map(|user| warp::reply::json(&user))
+        }).recover(PasswordViolations::recover)
+        .recover(AuthError::recover)
+        .recover(ResourceError::recover)
+}
+
+fn add_user_data_fn<S>(
+    state: &S,
+    addr: Option<IpAddr>,
+    req: NewUser,
+) -> impl Future<Item = UserId, Error = Rejection>
+where
+    S: HasUserStorage + HasPasswordPolicy + HasProvisionPolicy + HasAuditSink + Send + Sync + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasPasswordHistory,
+{
+    let mut user = <S::UserStorage as IsUserStorage>::User::create_new(req.name);
+
+    if let Err(error) = state.provision_user(
+        &mut user,
+        &SignUp {
+            email: None,
+            organizations: &[],
+            invite: req.invite.as_ref().map(AsRef::as_ref),
+        },
+    ) {
+        // the sign-up is done by guest
+        let subject = AuditSubject { user: 0, sess: 0 };
+        let record = AuditRecord::new(subject, AuditDecision::Deny)
+            .with_grant(&Grant::Create)
+            .with_reason(error)
+            .with_addr(addr);
+        return Either::A(Either::A(
+            audit_denial(state, record).then(move |_| Err(warp::reject::custom(error))),
+        ));
+    }
+
+    if let Err(violations) = (state.as_ref() as &PasswordPolicy).set_password(&mut user, &req.pass)
+    {
+        warn!("{}", violations);
+        return Either::A(Either::B(err(warp::reject::custom(violations))));
+    }
+
+    Either::B(
+        (state.as_ref() as &S::UserStorage)
+            .add_user_data(user)
+            .map_err(|error| {
+                error!("Unable to add user data: {}", error);
+                warp::reject::custom(ResourceError::Backend)
+            }).and_then(|user| {
+                user.map(|user| user.get_user_id()).ok_or_else(|| {
+                    warn!("User name already taken");
+                    warp::reject::custom(ResourceError::Stupid)
+                })
+            }),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use access::{AuditQuery, IsAuditSink, MemoryAudit};
+    use auth::{
+        stub::{SessionData, Sessions},
+        ProvisionOptions, ThrottleCache,
+    };
+    use filters::ClientAddrOptions;
+    use httplib::StatusCode;
+    use serde_json::json;
+    use sodiumoxide::crypto::box_::gen_keypair;
+    use std::sync::Arc;
+    use user::{
+        stub::{UserData, Users},
+        PasswordOptions,
+    };
+    use warp::test::request;
+
+    #[derive(Clone)]
+    struct State {
+        users: Users,
+        sessions: Sessions,
+        policy: Arc<PasswordPolicy>,
+        throttle: ThrottleCache,
+        throttle_options: Arc<ThrottleOptions>,
+        provision: Arc<ProvisionOptions>,
+        audit: MemoryAudit,
+        client_addr: Arc<ClientAddrOptions>,
+    }
+
+    impl AsRef<Users> for State {
+        fn as_ref(&self) -> &Users {
+            &self.users
+        }
+    }
+
+    impl HasUserStorage for State {
+        type UserStorage = Users;
+    }
+
+    impl AsRef<Sessions> for State {
+        fn as_ref(&self) -> &Sessions {
+            &self.sessions
+        }
+    }
+
+    impl HasSessionStorage for State {
+        type SessionStorage = Sessions;
+    }
+
+    impl AsRef<PasswordPolicy> for State {
+        fn as_ref(&self) -> &PasswordPolicy {
+            &self.policy
+        }
+    }
+
+    impl HasPasswordPolicy for State {}
+
+    impl AsRef<ThrottleCache> for State {
+        fn as_ref(&self) -> &ThrottleCache {
+            &self.throttle
+        }
+    }
+
+    impl HasThrottleStorage for State {
+        type ThrottleStorage = ThrottleCache;
+    }
+
+    impl AsRef<ThrottleOptions> for State {
+        fn as_ref(&self) -> &ThrottleOptions {
+            &self.throttle_options
+        }
+    }
+
+    impl HasThrottleOptions for State {}
+
+    impl AsRef<ProvisionOptions> for State {
+        fn as_ref(&self) -> &ProvisionOptions {
+            &self.provision
+        }
+    }
+
+    impl HasProvisionPolicy for State {}
+
+    impl AsRef<MemoryAudit> for State {
+        fn as_ref(&self) -> &MemoryAudit {
+            &self.audit
+        }
+    }
+
+    impl HasAuditSink for State {
+        type AuditSink = MemoryAudit;
+    }
+
+    impl AsRef<ClientAddrOptions> for State {
+        fn as_ref(&self) -> &ClientAddrOptions {
+            &self.client_addr
+        }
+    }
+
+    impl HasClientAddrOptions for State {}
+
+    fn state() -> State {
+        State {
+            users: Users::new().with_user(UserData::new(1, "user").with_password("old secret")),
+            sessions: Sessions::new(),
+            policy: Arc::new(PasswordPolicy::new(PasswordOptions::default()).unwrap()),
+            throttle: ThrottleCache::new(),
+            throttle_options: Arc::new(ThrottleOptions::default()),
+            provision: Arc::new(ProvisionOptions::default()),
+            audit: MemoryAudit::new(),
+            client_addr: Arc::new(ClientAddrOptions::default()),
+        }
+    }
+
+    #[test]
+    fn unique_name() {
+        let state = state();
+        let filter = add_user_data(&state);
+        let add = |name: &str| {
+            request()
+                .method("POST")
+                .path("/user")
+                .json(&json!({ "name": name, "pass": "new user secret" }))
+                .reply(&filter)
+                .status()
+        };
+
+        assert_eq!(add("other"), StatusCode::OK);
+        // name is already taken
+        assert_eq!(add("other"), StatusCode::BAD_REQUEST);
+        assert_eq!(add("user"), StatusCode::BAD_REQUEST);
+
+        let user = state.users.find_user_data("other").wait().unwrap().unwrap();
+        assert_eq!(user.id, 2);
+    }
+
+    #[test]
+    fn sign_up_policy() {
+        let add = |state: &State, name: &str, invite: Option<&str>| {
+            request()
+                .method("POST")
+                .path("/user")
+                .json(&json!({ "name": name, "pass": "new user secret", "invite": invite }))
+                .reply(&add_user_data(state))
+                .status()
+        };
+        let with = |provision| State {
+            provision: Arc::new(provision),
+            ..state()
+        };
+
+        let mut provision = ProvisionOptions::default();
+        provision.sign_up = false;
+        let state = with(provision);
+        // sign-up disabled
+        assert_eq!(add(&state, "other", None), StatusCode::FORBIDDEN);
+        assert!(state.users.find_user_data("other").wait().unwrap().is_none());
+
+        let mut provision = ProvisionOptions::default();
+        provision.invite_codes = vec!["code".into()];
+        let state = with(provision);
+        // invite code required
+        assert_eq!(add(&state, "other", None), StatusCode::FORBIDDEN);
+        assert_eq!(add(&state, "other", Some("wrong")), StatusCode::FORBIDDEN);
+        assert_eq!(add(&state, "other", Some("code")), StatusCode::OK);
+        // denials is audited
+        let records = state.audit.find_records(&AuditQuery::default()).wait().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].decision, AuditDecision::Deny);
+        assert_eq!(records[0].reason, Some(AuthError::Restricted.to_string()));
+
+        let mut provision = ProvisionOptions::default();
+        provision.approval = true;
+        let state = with(provision);
+        // approval required
+        assert_eq!(add(&state, "other", None), StatusCode::OK);
+        let user = state.users.find_user_data("other").wait().unwrap().unwrap();
+        assert!(!user.is_approved());
+    }
+
+    #[test]
+    fn password_throttle() {
+        let state = state();
+        let change = |old: &str| {
+            put_user_password_fn(
+                &state,
+                1,
+                None,
+                PasswordChange {
+                    old: Some(old.into()),
+                    new: "new secret".into(),
+                },
+            ).wait()
+            .err()
+            .and_then(|error| error.find_cause::<AuthError>().cloned())
+        };
+
+        for _ in 0..ThrottleOptions::default().max_failures + 1 {
+            match change("wrong") {
+                Some(AuthError::BadIdent) => (),
+                other => panic!("Unexpected error: {:?}", other),
+            }
+        }
+
+        // right password is rejected too while lockout is active
+        match change("old secret") {
+            Some(AuthError::TooManyAttempts(_)) => (),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+
+        let user = state.users.get_user_data(1).wait().unwrap().unwrap();
+        assert!(verify_password("old secret", user.hash.as_ref().unwrap()));
+    }
+
+    #[test]
+    fn keep_session() {
+        let state = state();
+        for _ in 0..3 {
+            state
+                .sessions
+                .put_user_session(SessionData::new(1, gen_keypair().0))
+                .wait()
+                .unwrap();
+        }
+
+        put_user_password_fn(
+            &state,
+            1,
+            Some(2),
+            PasswordChange {
+                old: Some("old secret".into()),
+                new: "new secret".into(),
+            },
+        ).wait()
+        .unwrap();
+
+        let sessions = state.sessions.get_user_sessions(1).wait().unwrap();
+        assert_eq!(
+            sessions.iter().map(|session| session.sess).collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+}
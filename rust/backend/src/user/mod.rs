@@ -6,11 +6,17 @@
 
 mod handler;
 mod password;
+mod policy;
+#[cfg(feature = "send_mail")]
+mod reset;
 pub mod stub;
 mod traits;
 mod types;
 
 pub use self::handler::*;
 pub use self::password::*;
+pub use self::policy::*;
+#[cfg(feature = "send_mail")]
+pub use self::reset::*;
 pub use self::traits::*;
 pub use self::types::*;
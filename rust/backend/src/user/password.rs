@@ -1,25 +1,190 @@
+#[cfg(feature = "bcrypt_hash")]
+use bcrypt;
 use crypto::random_bytes;
 use sodiumoxide::crypto::pwhash::{
-    pwhash, pwhash_verify, HashedPassword, MEMLIMIT_INTERACTIVE, OPSLIMIT_INTERACTIVE,
+    pwhash, pwhash_verify, HashedPassword, MemLimit, OpsLimit, HASHEDPASSWORDBYTES,
+    MEMLIMIT_INTERACTIVE, MEMLIMIT_SENSITIVE, OPSLIMIT_INTERACTIVE, OPSLIMIT_SENSITIVE, STRPREFIX,
 };
 use std::ops::RangeInclusive;
+use std::str::from_utf8;
+
+/// Password hash options
+///
+/// The hash is created using sodium `pwhash` (scrypt) and prefixed by algorithm and parameters:
+/// `$scrypt$ops=<ops_limit>,mem=<mem_limit>$<sodium hash>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordHashOptions {
+    /// Operations limit (CPU cost)
+    #[serde(default = "default_ops_limit")]
+    pub ops_limit: usize,
+
+    /// Memory limit in bytes
+    #[serde(default = "default_mem_limit")]
+    pub mem_limit: usize,
+}
+
+fn default_ops_limit() -> usize {
+    OPSLIMIT_INTERACTIVE.0
+}
+
+fn default_mem_limit() -> usize {
+    MEMLIMIT_INTERACTIVE.0
+}
+
+impl Default for PasswordHashOptions {
+    fn default() -> Self {
+        Self {
+            ops_limit: default_ops_limit(),
+            mem_limit: default_mem_limit(),
+        }
+    }
+}
+
+impl PasswordHashOptions {
+    /// Check that limits is supported
+    ///
+    /// The limits should be between sodium interactive and sensitive presets.
+    pub fn is_valid(&self) -> bool {
+        self.ops_limit >= OPSLIMIT_INTERACTIVE.0
+            && self.ops_limit <= OPSLIMIT_SENSITIVE.0
+            && self.mem_limit >= MEMLIMIT_INTERACTIVE.0
+            && self.mem_limit <= MEMLIMIT_SENSITIVE.0
+    }
+}
+
+const SCRYPT_PREFIX: &[u8] = b"$scrypt$";
+
+/// Parsed password hash
+enum PasswordHash<'a> {
+    /// Versioned sodium hash
+    Scrypt { ops: usize, mem: usize, hash: &'a [u8] },
+    /// Unversioned sodium hash
+    Legacy(&'a [u8]),
+    /// Imported bcrypt hash
+    #[cfg(feature = "bcrypt_hash")]
+    Bcrypt(&'a str),
+}
+
+impl<'a> PasswordHash<'a> {
+    fn parse(hash: &'a [u8]) -> Option<Self> {
+        if hash.starts_with(SCRYPT_PREFIX) {
+            let hash = &hash[SCRYPT_PREFIX.len()..];
+            let split = hash.iter().position(|chr| *chr == b'$')?;
+            let (ops, mem) = parse_params(from_utf8(&hash[..split]).ok()?)?;
+            return Some(PasswordHash::Scrypt {
+                ops,
+                mem,
+                hash: &hash[split + 1..],
+            });
+        }
+
+        if hash.starts_with(STRPREFIX.as_bytes()) {
+            return Some(PasswordHash::Legacy(hash));
+        }
+
+        #[cfg(feature = "bcrypt_hash")]
+        {
+            if hash.starts_with(b"$2a$")
+                || hash.starts_with(b"$2b$")
+                || hash.starts_with(b"$2x$")
+                || hash.starts_with(b"$2y$")
+            {
+                return from_utf8(hash).ok().map(PasswordHash::Bcrypt);
+            }
+        }
+
+        None
+    }
+}
+
+fn parse_params(params: &str) -> Option<(usize, usize)> {
+    let mut ops = None;
+    let mut mem = None;
+
+    for param in params.split(',') {
+        let mut pair = param.splitn(2, '=');
+        match (pair.next(), pair.next()) {
+            (Some("ops"), Some(val)) => ops = Some(val.parse().ok()?),
+            (Some("mem"), Some(val)) => mem = Some(val.parse().ok()?),
+            _ => return None,
+        }
+    }
+
+    Some((ops?, mem?))
+}
+
+fn verify_sodium(password: &[u8], hash: &[u8]) -> bool {
+    // the sodium hash string is padded by zeros
+    let hash = match hash.iter().position(|chr| *chr == 0) {
+        Some(end) => &hash[..end],
+        None => hash,
+    };
+    if hash.len() > HASHEDPASSWORDBYTES {
+        return false;
+    }
+    let mut raw = [0u8; HASHEDPASSWORDBYTES];
+    raw[..hash.len()].copy_from_slice(hash);
+
+    if let Some(hashed) = HashedPassword::from_slice(&raw) {
+        pwhash_verify(&hashed, password)
+    } else {
+        false
+    }
+}
 
 /// Hash password utily
+///
+/// Uses default hash options.
 pub fn create_password<S: AsRef<str>>(password: S) -> Vec<u8> {
-    (&(pwhash(
+    create_password_with(password, &PasswordHashOptions::default())
+}
+
+/// Hash password using options
+///
+/// The options should be valid (see [`PasswordHashOptions`](user::PasswordHashOptions)),
+/// the [`PasswordPolicy`](user::PasswordPolicy) checks it on creation.
+pub fn create_password_with<S: AsRef<str>>(password: S, options: &PasswordHashOptions) -> Vec<u8> {
+    let hashed = pwhash(
         password.as_ref().as_bytes(),
-        OPSLIMIT_INTERACTIVE,
-        MEMLIMIT_INTERACTIVE,
-    ).unwrap()[..]))
-        .into()
+        OpsLimit(options.ops_limit),
+        MemLimit(options.mem_limit),
+    ).unwrap();
+    let hashed = &hashed[..];
+    let end = hashed
+        .iter()
+        .position(|chr| *chr == 0)
+        .unwrap_or(hashed.len());
+
+    let mut hash = Vec::from(SCRYPT_PREFIX);
+    hash.extend(format!("ops={},mem={}$", options.ops_limit, options.mem_limit).bytes());
+    hash.extend(&hashed[..end]);
+    hash
 }
 
 /// Check password utily
+///
+/// Supports versioned and legacy sodium hashes and imported bcrypt hashes.
 pub fn verify_password<S: AsRef<str>, H: AsRef<[u8]>>(password: S, hash: H) -> bool {
-    if let Some(hashed) = HashedPassword::from_slice(hash.as_ref()) {
-        pwhash_verify(&hashed, &password.as_ref().as_bytes())
-    } else {
-        false
+    let password = password.as_ref().as_bytes();
+
+    match PasswordHash::parse(hash.as_ref()) {
+        Some(PasswordHash::Scrypt { hash, .. }) => verify_sodium(password, hash),
+        Some(PasswordHash::Legacy(hash)) => verify_sodium(password, hash),
+        #[cfg(feature = "bcrypt_hash")]
+        Some(PasswordHash::Bcrypt(hash)) => bcrypt::verify(password, hash).unwrap_or(false),
+        None => false,
+    }
+}
+
+/// Check that password hash should be updated
+///
+/// This is true when hash is not versioned or created using other algorithm or parameters.
+pub fn password_needs_rehash<H: AsRef<[u8]>>(hash: H, options: &PasswordHashOptions) -> bool {
+    match PasswordHash::parse(hash.as_ref()) {
+        Some(PasswordHash::Scrypt { ops, mem, .. }) => {
+            ops != options.ops_limit || mem != options.mem_limit
+        }
+        _ => true,
     }
 }
 
@@ -125,4 +290,46 @@ mod test {
         assert_eq!(verify_password("AbracaD@br1", &hashed), true);
         assert_eq!(verify_password("AbracaD@bra", &hashed), false);
     }
+
+    #[test]
+    fn test_password_rehash() {
+        let options = PasswordHashOptions::default();
+        let hashed = create_password("AbracaD@br1");
+        assert!(hashed.starts_with(b"$scrypt$ops="));
+        assert_eq!(password_needs_rehash(&hashed, &options), false);
+
+        let mut stronger = options.clone();
+        stronger.ops_limit *= 2;
+        assert_eq!(password_needs_rehash(&hashed, &stronger), true);
+
+        let legacy = pwhash(b"AbracaD@br1", OPSLIMIT_INTERACTIVE, MEMLIMIT_INTERACTIVE).unwrap();
+        assert_eq!(verify_password("AbracaD@br1", &legacy[..]), true);
+        assert_eq!(verify_password("AbracaD@bra", &legacy[..]), false);
+        assert_eq!(password_needs_rehash(&legacy[..], &options), true);
+    }
+
+    #[test]
+    fn test_hash_options() {
+        assert!(PasswordHashOptions::default().is_valid());
+
+        let mut options = PasswordHashOptions::default();
+        options.ops_limit = 0;
+        assert!(!options.is_valid());
+
+        let mut options = PasswordHashOptions::default();
+        options.mem_limit = usize::max_value();
+        assert!(!options.is_valid());
+    }
+
+    #[cfg(feature = "bcrypt_hash")]
+    #[test]
+    fn test_verify_bcrypt() {
+        let hashed = bcrypt::hash("AbracaD@br1", 4).unwrap();
+        assert_eq!(verify_password("AbracaD@br1", &hashed), true);
+        assert_eq!(verify_password("AbracaD@bra", &hashed), false);
+        assert_eq!(
+            password_needs_rehash(&hashed, &PasswordHashOptions::default()),
+            true
+        );
+    }
 }
@@ -0,0 +1,390 @@
+use super::{
+    create_password_with, verify_password, HasPasswordHash, HasPasswordHistory, PasswordHashOptions,
+};
+use httplib::StatusCode;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error as IoError, ErrorKind};
+use std::path::PathBuf;
+use std::sync::Arc;
+use warp::{reply, Rejection, Reply};
+
+/// Password policy options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordOptions {
+    /// Minimum length in chars
+    #[serde(default = "default_min_length")]
+    pub min_length: usize,
+
+    /// Maximum length in chars
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+
+    /// Require lower case letters
+    #[serde(default)]
+    pub need_lower: bool,
+
+    /// Require upper case letters
+    #[serde(default)]
+    pub need_upper: bool,
+
+    /// Require digits
+    #[serde(default)]
+    pub need_digit: bool,
+
+    /// Require symbols (not letters and digits)
+    #[serde(default)]
+    pub need_symbol: bool,
+
+    /// Path to deny-list of common passwords
+    ///
+    /// The file should contain one password per line.
+    #[serde(default)]
+    pub deny_list: Option<PathBuf>,
+
+    /// Number of previous passwords which cannot be reused
+    ///
+    /// The current password cannot be reused anyway.
+    #[serde(default = "default_history")]
+    pub history: usize,
+
+    /// Password hash options
+    #[serde(default)]
+    pub hash: PasswordHashOptions,
+}
+
+fn default_min_length() -> usize {
+    8
+}
+
+fn default_max_length() -> usize {
+    128
+}
+
+fn default_history() -> usize {
+    3
+}
+
+impl Default for PasswordOptions {
+    fn default() -> Self {
+        Self {
+            min_length: default_min_length(),
+            max_length: default_max_length(),
+            need_lower: false,
+            need_upper: false,
+            need_digit: false,
+            need_symbol: false,
+            deny_list: None,
+            history: default_history(),
+            hash: PasswordHashOptions::default(),
+        }
+    }
+}
+
+/// Password policy violation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PasswordViolation {
+    #[serde(rename = "too_short")]
+    TooShort { min: usize },
+    #[serde(rename = "too_long")]
+    TooLong { max: usize },
+    #[serde(rename = "no_lower")]
+    NoLower,
+    #[serde(rename = "no_upper")]
+    NoUpper,
+    #[serde(rename = "no_digit")]
+    NoDigit,
+    #[serde(rename = "no_symbol")]
+    NoSymbol,
+    #[serde(rename = "common")]
+    Common,
+    #[serde(rename = "reused")]
+    Reused,
+}
+
+impl Display for PasswordViolation {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        use self::PasswordViolation::*;
+        match self {
+            TooShort { min } => write!(f, "Shorter than {} chars", min),
+            TooLong { max } => write!(f, "Longer than {} chars", max),
+            NoLower => f.write_str("No lower case letters"),
+            NoUpper => f.write_str("No upper case letters"),
+            NoDigit => f.write_str("No digits"),
+            NoSymbol => f.write_str("No symbols"),
+            Common => f.write_str("Too common"),
+            Reused => f.write_str("Recently used"),
+        }
+    }
+}
+
+/// Password policy violations
+///
+/// The rejection reply is *400 Bad Request* with violations as JSON array.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PasswordViolations(pub Vec<PasswordViolation>);
+
+impl PasswordViolations {
+    /// Convert violations into reply
+    pub fn recover(error: Rejection) -> Result<impl Reply, Rejection> {
+        if let Some(error) = &error.find_cause::<PasswordViolations>() {
+            return Ok(reply::with_status(
+                reply::json(&error.0),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+        Err(error)
+    }
+}
+
+impl Error for PasswordViolations {}
+
+impl Display for PasswordViolations {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("Bad password")?;
+        for (index, violation) in self.0.iter().enumerate() {
+            f.write_str(if index > 0 { ", " } else { ": " })?;
+            violation.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+struct State {
+    options: PasswordOptions,
+    deny: HashSet<String>,
+}
+
+/// Password policy
+///
+/// The default policy uses default options without deny-list.
+///
+/// ```
+/// # extern crate literium;
+/// # use literium::user::{PasswordOptions, PasswordPolicy, PasswordViolation};
+/// let mut options = PasswordOptions::default();
+/// options.need_digit = true;
+///
+/// let policy = PasswordPolicy::new(options)
+///     .unwrap()
+///     .with_deny_list(vec!["password1"]);
+///
+/// assert_eq!(
+///     policy.check("secret").unwrap_err().0,
+///     vec![PasswordViolation::TooShort { min: 8 }, PasswordViolation::NoDigit],
+/// );
+/// assert_eq!(
+///     policy.check("PassWord1").unwrap_err().0,
+///     vec![PasswordViolation::Common],
+/// );
+/// assert!(policy.check("correct horse 1").is_ok());
+/// ```
+#[derive(Clone)]
+pub struct PasswordPolicy(Arc<State>);
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy(Arc::new(State {
+            options: PasswordOptions::default(),
+            deny: HashSet::new(),
+        }))
+    }
+}
+
+impl PasswordPolicy {
+    /// Create password policy
+    ///
+    /// The deny-list file will be loaded when it configured.
+    /// The password hash options will be checked too.
+    pub fn new(options: PasswordOptions) -> Result<Self, IoError> {
+        if !options.hash.is_valid() {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "Password hash limits is out of range",
+            ));
+        }
+
+        let mut deny = HashSet::new();
+
+        if let Some(path) = &options.deny_list {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                let line = line.trim();
+                if !line.is_empty() {
+                    deny.insert(line.to_lowercase());
+                }
+            }
+        }
+
+        Ok(PasswordPolicy(Arc::new(State { options, deny })))
+    }
+
+    /// Add passwords to deny-list
+    pub fn with_deny_list<I, S>(self, list: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut deny = self.0.deny.clone();
+        deny.extend(list.into_iter().map(|pass| pass.as_ref().to_lowercase()));
+        PasswordPolicy(Arc::new(State {
+            options: self.0.options.clone(),
+            deny,
+        }))
+    }
+
+    /// Get policy options
+    pub fn options(&self) -> &PasswordOptions {
+        &self.0.options
+    }
+
+    /// Check password against policy
+    pub fn check(&self, password: &str) -> Result<(), PasswordViolations> {
+        use self::PasswordViolation::*;
+
+        let options = &self.0.options;
+        let mut violations = Vec::new();
+
+        let length = password.chars().count();
+        if length < options.min_length {
+            violations.push(TooShort {
+                min: options.min_length,
+            });
+        }
+        if length > options.max_length {
+            violations.push(TooLong {
+                max: options.max_length,
+            });
+        }
+
+        let classes = [
+            (options.need_lower, NoLower, char::is_lowercase as fn(char) -> bool),
+            (options.need_upper, NoUpper, char::is_uppercase),
+            (options.need_digit, NoDigit, char::is_numeric),
+            (options.need_symbol, NoSymbol, is_symbol),
+        ];
+        for (need, violation, is_class) in classes.iter() {
+            if *need && !password.chars().any(is_class) {
+                violations.push(*violation);
+            }
+        }
+
+        if self.0.deny.contains(&password.to_lowercase()) {
+            violations.push(Common);
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PasswordViolations(violations))
+        }
+    }
+
+    /// Check password and set it to user
+    ///
+    /// The previous password hash will be kept in history.
+    pub fn set_password<U>(&self, user: &mut U, password: &str) -> Result<(), PasswordViolations>
+    where
+        U: HasPasswordHash + HasPasswordHistory,
+    {
+        let reused = user
+            .get_password_hash()
+            .into_iter()
+            .chain(
+                user.get_password_history()
+                    .iter()
+                    .take(self.0.options.history)
+                    .map(AsRef::as_ref),
+            ).any(|hash| verify_password(password, hash));
+
+        match self.check(password) {
+            Err(mut violations) => {
+                if reused {
+                    violations.0.push(PasswordViolation::Reused);
+                }
+                return Err(violations);
+            }
+            Ok(_) if reused => return Err(PasswordViolations(vec![PasswordViolation::Reused])),
+            _ => (),
+        }
+
+        let mut history = Vec::new();
+        if self.0.options.history > 0 {
+            history.extend(user.get_password_hash().map(Vec::from));
+            history.extend(user.get_password_history().iter().cloned());
+            history.truncate(self.0.options.history);
+        }
+        user.set_password_history(history);
+        user.set_password_hash(Some(create_password_with(password, &self.0.options.hash)));
+
+        Ok(())
+    }
+}
+
+fn is_symbol(chr: char) -> bool {
+    !chr.is_alphanumeric() && !chr.is_whitespace()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct User {
+        hash: Option<Vec<u8>>,
+        history: Vec<Vec<u8>>,
+    }
+
+    impl HasPasswordHash for User {
+        fn get_password_hash(&self) -> Option<&[u8]> {
+            self.hash.as_ref().map(AsRef::as_ref)
+        }
+
+        fn set_password_hash<S: AsRef<[u8]>>(&mut self, new: Option<S>) {
+            self.hash = new.map(|s| Vec::from(s.as_ref()));
+        }
+    }
+
+    impl HasPasswordHistory for User {
+        fn get_password_history(&self) -> &[Vec<u8>] {
+            &self.history
+        }
+
+        fn set_password_history(&mut self, new: Vec<Vec<u8>>) {
+            self.history = new;
+        }
+    }
+
+    #[test]
+    fn password_reuse() {
+        let mut options = PasswordOptions::default();
+        options.history = 1;
+        let policy = PasswordPolicy::new(options).unwrap();
+        let mut user = User::default();
+
+        assert!(policy.set_password(&mut user, "first pass").is_ok());
+        assert_eq!(
+            policy.set_password(&mut user, "first pass").unwrap_err().0,
+            vec![PasswordViolation::Reused]
+        );
+        assert!(policy.set_password(&mut user, "second pass").is_ok());
+        assert!(policy.set_password(&mut user, "first pass").is_err());
+        assert!(policy.set_password(&mut user, "third pass").is_ok());
+        assert_eq!(user.history.len(), 1);
+        // out of history
+        assert!(policy.set_password(&mut user, "first pass").is_ok());
+    }
+
+    #[test]
+    fn bad_hash_options() {
+        let mut options = PasswordOptions::default();
+        options.hash.mem_limit = 1;
+
+        assert_eq!(
+            PasswordPolicy::new(options).err().map(|error| error.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+}
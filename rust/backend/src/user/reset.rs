@@ -0,0 +1,616 @@
+use super::{
+    put_user_password_data, HasEmail, HasPasswordHash, HasPasswordHistory, HasPasswordPolicy,
+    HasUserStorage, IsUserData, IsUserStorage, PasswordPolicy, PasswordViolations, UserId,
+};
+use auth::{
+    reserve_throttle_attempt, AuthError, HasSessionStorage, HasThrottleOptions,
+    HasThrottleStorage, ThrottleOptions,
+};
+use base::{serde_extra::base64, ResourceError, TimeStamp};
+use crypto::{random_bytes, CanDecrypt, CanEncrypt, HasSecureKey};
+use filters::{x_client_addr, HasClientAddrOptions};
+use futures::{
+    future::{err, ok, Either},
+    Future,
+};
+use mail::{header, HasMailer, IsMailer, MailAddress, MailMessage, Mailbox, SinglePart};
+use sodiumoxide::crypto::hash::sha256;
+use std::net::IpAddr;
+use url::Url;
+use warp::{Filter, Rejection, Reply};
+
+/// Password reset options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordResetOptions {
+    /// Reset page URL
+    ///
+    /// Usually this is a page of client app which asks new password.
+    /// The token will be added to it as `token` query parameter.
+    pub base_url: String,
+
+    /// Token life time in milliseconds
+    #[serde(default = "default_token_time")]
+    pub token_time: TimeStamp,
+}
+
+fn default_token_time() -> TimeStamp {
+    TimeStamp::default().with_mins(60)
+}
+
+impl PasswordResetOptions {
+    /// Create options using reset page URL
+    pub fn new<S: Into<String>>(base_url: S) -> Self {
+        Self {
+            base_url: base_url.into(),
+            token_time: default_token_time(),
+        }
+    }
+}
+
+/// State has password reset options
+pub trait HasPasswordReset
+where
+    Self: AsRef<PasswordResetOptions>,
+{
+    /// Create password reset message
+    fn reset_message(&self, name: &str, email: &MailAddress, link: &str) -> MailMessage {
+        MailMessage::create()
+            .to(Mailbox::new(Some(name.into()), email.clone()))
+            .subject("Password reset")
+            .mime_body(
+                SinglePart::base64()
+                    .header(header::ContentType(
+                        "text/plain; charset=utf8".parse().unwrap(),
+                    )).body(format!("Use the next link to set new password: {}", link))
+                    .to_string()
+                    .into(),
+            )
+    }
+}
+
+/// Request password reset
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    /// User name
+    pub name: String,
+}
+
+/// Finish password reset
+#[derive(Debug, Deserialize)]
+pub struct PasswordReset {
+    /// Token from reset link
+    pub token: String,
+    /// New password
+    pub new: String,
+}
+
+/// Password reset token
+///
+/// The data which sealed by server secure key.
+/// Token is bound to current password hash so it can be used only once.
+#[derive(Debug, Serialize, Deserialize)]
+struct PasswordResetToken {
+    /// User identifier
+    user: UserId,
+    /// Digest of current password hash
+    #[serde(with = "base64")]
+    hash: Vec<u8>,
+    /// Random salt
+    #[serde(with = "base64")]
+    salt: Vec<u8>,
+    /// Expiration time
+    etime: TimeStamp,
+}
+
+fn hash_digest(hash: Option<&[u8]>) -> Vec<u8> {
+    sha256::hash(hash.unwrap_or(&[])).as_ref().into()
+}
+
+/// Handle request password reset
+///
+/// Emails the reset link to user when user has email address.
+/// Replies success in any case (even when sending fails) to prevent user names guessing.
+/// The requests is throttled by user name and client address
+/// using [`ThrottleOptions`](auth::ThrottleOptions) of state.
+pub fn add_password_reset<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasUserStorage
+        + HasSecureKey
+        + HasMailer
+        + HasPasswordReset
+        + HasThrottleStorage
+        + HasThrottleOptions
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasEmail,
+{
+    let state = state.clone();
+
+    warp::post2()
+        .and(warp::path("password"))
+        .and(warp::path("reset"))
+        .and(warp::path::end())
+        .and(x_client_addr(&state))
+        .and(warp::body::json())
+        .and_then(move |addr, req: PasswordResetRequest| {
+            add_password_reset_fn(&state, addr, req).map(|_| warp::reply())
+        }).recover(AuthError::recover)
+        .recover(ResourceError::recover)
+}
+
+fn reset_throttle_keys(name: &str, addr: Option<IpAddr>) -> Vec<String> {
+    Some(format!("reset:user:{}", name))
+        .into_iter()
+        .chain(addr.map(|addr| format!("reset:addr:{}", addr)))
+        .collect()
+}
+
+fn add_password_reset_fn<S>(
+    state: &S,
+    addr: Option<IpAddr>,
+    req: PasswordResetRequest,
+) -> impl Future<Item = (), Error = Rejection>
+where
+    S: HasUserStorage
+        + HasSecureKey
+        + HasMailer
+        + HasPasswordReset
+        + HasThrottleStorage
+        + HasThrottleOptions
+        + Send
+        + Sync
+        + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasEmail,
+{
+    let state = state.clone();
+    let options = (state.as_ref() as &ThrottleOptions).clone();
+    let keys = reset_throttle_keys(&req.name, addr);
+
+    // each request is counted to limit the number of sent emails
+    reserve_throttle_attempt(&state, &options, &keys)
+        .map_err(warp::reject::custom)
+        .and_then(move |_| send_password_reset(&state, req).map_err(warp::reject::custom))
+}
+
+fn send_password_reset<S>(
+    state: &S,
+    req: PasswordResetRequest,
+) -> impl Future<Item = (), Error = ResourceError>
+where
+    S: HasUserStorage + HasSecureKey + HasMailer + HasPasswordReset + Send + Sync + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasEmail,
+{
+    let state = state.clone();
+
+    (state.as_ref() as &S::UserStorage)
+        .find_user_data(req.name.as_str())
+        .map_err(|error| {
+            error!("Unable to find user data: {}", error);
+            ResourceError::Backend
+        }).and_then(move |user| {
+            let user = match user {
+                Some(user) => user,
+                None => {
+                    warn!("Password reset for unknown user");
+                    return Either::A(ok(()));
+                }
+            };
+            let email = match user.get_email() {
+                Some(email) => email.clone(),
+                None => {
+                    warn!("Password reset for user without email");
+                    return Either::A(ok(()));
+                }
+            };
+
+            let options = state.as_ref() as &PasswordResetOptions;
+            let token = PasswordResetToken {
+                user: user.get_user_id(),
+                hash: hash_digest(user.get_password_hash()),
+                salt: random_bytes(8),
+                etime: TimeStamp::now() + options.token_time,
+            };
+
+            // the errors is not reported to client to prevent user names guessing
+            let token = match (state.as_ref() as &S::SecureKey).seal_json_b64(&token) {
+                Ok(token) => token,
+                Err(error) => {
+                    error!("Unable to seal password reset token: {}", error);
+                    return Either::A(ok(()));
+                }
+            };
+
+            let mut url = match Url::parse(&options.base_url) {
+                Ok(url) => url,
+                Err(error) => {
+                    error!("Invalid password reset base URL: {}", error);
+                    return Either::A(ok(()));
+                }
+            };
+            url.query_pairs_mut().append_pair("token", &token);
+
+            Either::B(
+                (state.as_ref() as &S::Mailer)
+                    .send_mail(state.reset_message(
+                        user.get_user_name(),
+                        &email,
+                        url.as_str(),
+                    )).or_else(|error| {
+                        error!("Unable to send password reset: {}", error);
+                        Ok(())
+                    }),
+            )
+        })
+}
+
+/// Handle finish password reset
+///
+/// The new password should satisfy [`PasswordPolicy`](user::PasswordPolicy).
+/// All sessions of user will be deleted on success.
+pub fn put_password_reset<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasUserStorage
+        + HasSessionStorage
+        + HasSecureKey
+        + HasPasswordPolicy
+        + Send
+        + Sync
+        + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasPasswordHistory,
+{
+    let state = state.clone();
+
+    warp::put2()
+        .and(warp::path("password"))
+        .and(warp::path("reset"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and_then(move |req: PasswordReset| {
+            put_password_reset_fn(&state, req).map(|_| warp::reply())
+        }).recover(PasswordViolations::recover)
+        .recover(AuthError::recover)
+        .recover(ResourceError::recover)
+}
+
+fn put_password_reset_fn<S>(
+    state: &S,
+    req: PasswordReset,
+) -> impl Future<Item = (), Error = Rejection>
+where
+    S: HasUserStorage
+        + HasSessionStorage
+        + HasSecureKey
+        + HasPasswordPolicy
+        + Send
+        + Sync
+        + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasPasswordHistory,
+{
+    let token: PasswordResetToken =
+        match (state.as_ref() as &S::SecureKey).open_json_b64(&req.token) {
+            Ok(token) => token,
+            Err(error) => {
+                warn!("Invalid password reset token: {}", error);
+                return Either::A(err(warp::reject::custom(AuthError::BadIdent)));
+            }
+        };
+
+    if token.etime <= TimeStamp::now() {
+        warn!("Expired password reset token");
+        return Either::A(err(warp::reject::custom(AuthError::Outdated)));
+    }
+
+    let state = state.clone();
+
+    Either::B(
+        (state.as_ref() as &S::UserStorage)
+            .get_user_data(token.user)
+            .map_err(|error| {
+                error!("Unable to get user data: {}", error);
+                warp::reject::custom(ResourceError::Backend)
+            }).and_then(|user| user.ok_or_else(|| warp::reject::custom(AuthError::BadUser)))
+            .and_then(move |mut user| {
+                if hash_digest(user.get_password_hash()) != token.hash {
+                    warn!("Used password reset token");
+                    return Either::A(err(warp::reject::custom(AuthError::Outdated)));
+                }
+
+                if let Err(violations) =
+                    (state.as_ref() as &PasswordPolicy).set_password(&mut user, &req.new)
+                {
+                    warn!("{}", violations);
+                    return Either::A(err(warp::reject::custom(violations)));
+                }
+
+                Either::B(put_user_password_data(&state, user, None))
+            }),
+    )
+}
+
+/// Scope with password reset handlers
+pub fn password_reset_scope<S>(
+    state: &S,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: HasUserStorage
+        + HasSessionStorage
+        + HasSecureKey
+        + HasMailer
+        + HasPasswordPolicy
+        + HasPasswordReset
+        + HasThrottleStorage
+        + HasThrottleOptions
+        + HasClientAddrOptions
+        + Send
+        + Sync
+        + Clone,
+    <S::UserStorage as IsUserStorage>::User: HasPasswordHash + HasPasswordHistory + HasEmail,
+{
+    add_password_reset(state).or(put_password_reset(state))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use auth::{
+        stub::{SessionData, Sessions},
+        IsSessionStorage, ThrottleCache,
+    };
+    use base::BoxFuture;
+    use futures::future::result;
+    use crypto::{CanKeygen, SecureKey};
+    use filters::ClientAddrOptions;
+    use httplib::StatusCode;
+    use mail::MailerError;
+    use serde_json::json;
+    use sodiumoxide::crypto::box_::gen_keypair;
+    use std::sync::{Arc, Mutex};
+    use user::{
+        stub::{UserData, Users},
+        verify_password, PasswordOptions,
+    };
+    use warp::test::request;
+
+    #[derive(Clone)]
+    struct Mailer(bool);
+
+    impl IsMailer for Mailer {
+        fn send_mail(&self, _message: MailMessage) -> BoxFuture<(), MailerError> {
+            Box::new(result(if self.0 {
+                Ok(())
+            } else {
+                Err(MailerError::ServerError)
+            }))
+        }
+    }
+
+    #[derive(Clone)]
+    struct State {
+        users: Users,
+        sessions: Sessions,
+        key: Arc<SecureKey>,
+        mailer: Mailer,
+        links: Arc<Mutex<Vec<String>>>,
+        policy: Arc<PasswordPolicy>,
+        reset: Arc<PasswordResetOptions>,
+        throttle: ThrottleCache,
+        throttle_options: Arc<ThrottleOptions>,
+        client_addr: Arc<ClientAddrOptions>,
+    }
+
+    impl AsRef<ClientAddrOptions> for State {
+        fn as_ref(&self) -> &ClientAddrOptions {
+            &self.client_addr
+        }
+    }
+
+    impl HasClientAddrOptions for State {}
+
+    impl AsRef<Users> for State {
+        fn as_ref(&self) -> &Users {
+            &self.users
+        }
+    }
+
+    impl HasUserStorage for State {
+        type UserStorage = Users;
+    }
+
+    impl AsRef<Sessions> for State {
+        fn as_ref(&self) -> &Sessions {
+            &self.sessions
+        }
+    }
+
+    impl HasSessionStorage for State {
+        type SessionStorage = Sessions;
+    }
+
+    impl AsRef<SecureKey> for State {
+        fn as_ref(&self) -> &SecureKey {
+            &self.key
+        }
+    }
+
+    impl HasSecureKey for State {
+        type SecureKey = SecureKey;
+    }
+
+    impl AsRef<Mailer> for State {
+        fn as_ref(&self) -> &Mailer {
+            &self.mailer
+        }
+    }
+
+    impl HasMailer for State {
+        type Mailer = Mailer;
+    }
+
+    impl AsRef<PasswordPolicy> for State {
+        fn as_ref(&self) -> &PasswordPolicy {
+            &self.policy
+        }
+    }
+
+    impl HasPasswordPolicy for State {}
+
+    impl AsRef<PasswordResetOptions> for State {
+        fn as_ref(&self) -> &PasswordResetOptions {
+            &self.reset
+        }
+    }
+
+    impl HasPasswordReset for State {
+        fn reset_message(&self, name: &str, email: &MailAddress, link: &str) -> MailMessage {
+            self.links.lock().unwrap().push(link.into());
+            MailMessage::create()
+                .to(Mailbox::new(Some(name.into()), email.clone()))
+                .subject("Password reset")
+                .body(link.to_string().into())
+        }
+    }
+
+    impl AsRef<ThrottleCache> for State {
+        fn as_ref(&self) -> &ThrottleCache {
+            &self.throttle
+        }
+    }
+
+    impl HasThrottleStorage for State {
+        type ThrottleStorage = ThrottleCache;
+    }
+
+    impl AsRef<ThrottleOptions> for State {
+        fn as_ref(&self) -> &ThrottleOptions {
+            &self.throttle_options
+        }
+    }
+
+    impl HasThrottleOptions for State {}
+
+    fn state(mailer: Mailer) -> State {
+        let mut user = UserData::new(1, "user").with_password("old secret");
+        user.email = Some("user@example.com".parse().unwrap());
+
+        State {
+            users: Users::new().with_user(user),
+            sessions: Sessions::new().with_session(SessionData::new(1, gen_keypair().0)),
+            key: Arc::new(SecureKey::gen_key()),
+            mailer,
+            links: Arc::new(Mutex::new(Vec::new())),
+            policy: Arc::new(PasswordPolicy::new(PasswordOptions::default()).unwrap()),
+            reset: Arc::new(PasswordResetOptions::new("https://localhost/reset")),
+            throttle: ThrottleCache::new(),
+            throttle_options: Arc::new(ThrottleOptions::default()),
+            client_addr: Arc::new(ClientAddrOptions::default()),
+        }
+    }
+
+    fn request_reset(state: &State, name: &str) -> StatusCode {
+        request()
+            .method("POST")
+            .path("/password/reset")
+            .json(&json!({ "name": name }))
+            .reply(&password_reset_scope(state))
+            .status()
+    }
+
+    fn finish_reset(state: &State, token: &str, new: &str) -> StatusCode {
+        request()
+            .method("PUT")
+            .path("/password/reset")
+            .json(&json!({ "token": token, "new": new }))
+            .reply(&password_reset_scope(state))
+            .status()
+    }
+
+    fn link_token(link: &str) -> String {
+        Url::parse(link)
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == "token")
+            .unwrap()
+            .1
+            .into_owned()
+    }
+
+    fn user_password(state: &State, pass: &str) -> bool {
+        let user = state.users.get_user_data(1).wait().unwrap().unwrap();
+        verify_password(pass, user.hash.as_ref().unwrap())
+    }
+
+    #[test]
+    fn reset_once() {
+        let state = state(Mailer(true));
+
+        assert_eq!(request_reset(&state, "user"), StatusCode::OK);
+        let links = state.links.lock().unwrap().clone();
+        assert_eq!(links.len(), 1);
+        let token = link_token(&links[0]);
+
+        assert_eq!(finish_reset(&state, &token, "new secret"), StatusCode::OK);
+        assert!(user_password(&state, "new secret"));
+        assert_eq!(state.sessions.get_user_sessions(1).wait().unwrap().len(), 0);
+
+        // token is used already
+        assert_eq!(
+            finish_reset(&state, &token, "other secret"),
+            StatusCode::FORBIDDEN
+        );
+        assert!(user_password(&state, "new secret"));
+    }
+
+    #[test]
+    fn expired_token() {
+        let state = state(Mailer(true));
+        let user = state.users.get_user_data(1).wait().unwrap().unwrap();
+        let token = state
+            .key
+            .seal_json_b64(&PasswordResetToken {
+                user: 1,
+                hash: hash_digest(user.hash.as_ref().map(AsRef::as_ref)),
+                salt: random_bytes(8),
+                etime: TimeStamp::now() - TimeStamp::default().with_secs(1),
+            }).unwrap();
+
+        assert_eq!(
+            finish_reset(&state, &token, "new secret"),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            finish_reset(&state, "garbage", "new secret"),
+            StatusCode::FORBIDDEN
+        );
+        assert!(user_password(&state, "old secret"));
+    }
+
+    #[test]
+    fn same_response() {
+        let state = state(Mailer(false));
+
+        // unknown user and sending failure is not distinguished
+        assert_eq!(request_reset(&state, "other"), StatusCode::OK);
+        assert_eq!(request_reset(&state, "user"), StatusCode::OK);
+        assert_eq!(state.links.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn throttle() {
+        let state = state(Mailer(true));
+
+        for _ in 0..ThrottleOptions::default().max_failures + 1 {
+            assert_eq!(request_reset(&state, "user"), StatusCode::OK);
+        }
+        assert_eq!(request_reset(&state, "user"), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            state.links.lock().unwrap().len() as u32,
+            ThrottleOptions::default().max_failures + 1
+        );
+    }
+}
@@ -7,18 +7,27 @@ This module provides dummy implementation of user data storage backend for examp
 */
 
 use super::{
-    create_password, AccountId, Gender, HasAbout, HasBirthDate, HasCompany, HasCreateDate,
-    HasEmail, HasFamilyName, HasFullName, HasGender, HasGivenName, HasHomeUrl, HasImageUrl,
-    HasLocale, HasLocation, HasMiddleName, HasNickName, HasPasswordHash, HasPosition, HasTimeZone,
-    IsAccountData, IsAccountStorage, IsUserData, IsUserStorage, UserArg, UserId,
+    create_password, AccountId, AccountTokens, Gender, HasAbout, HasBirthDate, HasCompany,
+    HasCreateDate, HasEmail, HasFamilyName, HasFullName, HasGender, HasGivenName, HasHomeUrl,
+    HasImageUrl, HasLocale, HasLocation, HasMiddleName, HasNickName, HasPasswordHash,
+    HasPasswordHistory, HasPosition, HasTimeZone, IsAccountData, IsAccountStorage, IsUserData,
+    IsUserStorage, UserArg, UserId,
 };
 use access::{Grant, HasAccess};
-use auth::{otpass::EmailUserIdent, SessionArg};
+#[cfg(feature = "send_sms")]
+use auth::otpass::PhoneUserIdent;
+use auth::{
+    otpass::EmailUserIdent,
+    totp::{HasTotpData, TotpData},
+    SessionArg,
+};
 use base::{
     BoxFuture, CanCreateView, CanUpdateData, CanUpdateFrom, DummyError, IsBackend, TimeStamp,
 };
 use futures::future::result;
 use mail::MailAddress;
+#[cfg(feature = "send_sms")]
+use sms::PhoneNumber;
 use std::borrow::Cow;
 use std::ops::Deref;
 use std::sync::{Arc, RwLock};
@@ -37,8 +46,33 @@ pub struct UserData {
     /// Email address
     pub email: Option<MailAddress>,
 
+    /// Email address is verified
+    #[serde(default)]
+    pub email_verified: bool,
+
+    /// Phone number
+    #[cfg(feature = "send_sms")]
+    #[serde(default)]
+    pub phone: Option<PhoneNumber>,
+
     /// Password hash
     pub hash: Option<Vec<u8>>,
+
+    /// Previous password hashes
+    #[serde(default)]
+    pub history: Vec<Vec<u8>>,
+
+    /// TOTP second factor
+    #[serde(default)]
+    pub totp: Option<TotpData>,
+
+    /// User is approved
+    #[serde(default = "default_approved")]
+    pub approved: bool,
+}
+
+fn default_approved() -> bool {
+    true
 }
 
 impl UserData {
@@ -48,7 +82,13 @@ impl UserData {
             id,
             name: name.into(),
             email: None,
+            email_verified: false,
+            #[cfg(feature = "send_sms")]
+            phone: None,
             hash: None,
+            history: Vec::new(),
+            totp: None,
+            approved: default_approved(),
         }
     }
 
@@ -75,6 +115,14 @@ impl IsUserData for UserData {
     fn create_new<S: Into<String>>(name: S) -> Self {
         UserData::new(0, name.into())
     }
+
+    fn is_approved(&self) -> bool {
+        self.approved
+    }
+
+    fn set_approved(&mut self, approved: bool) {
+        self.approved = approved;
+    }
 }
 
 impl HasPasswordHash for UserData {
@@ -87,9 +135,49 @@ impl HasPasswordHash for UserData {
     }
 }
 
+impl HasEmail for UserData {
+    fn get_email(&self) -> Option<&MailAddress> {
+        self.email.as_ref()
+    }
+
+    fn set_email(&mut self, new: Option<MailAddress>) {
+        self.email_verified = self.email_verified && self.email == new;
+        self.email = new;
+    }
+}
+
+impl HasPasswordHistory for UserData {
+    fn get_password_history(&self) -> &[Vec<u8>] {
+        &self.history
+    }
+
+    fn set_password_history(&mut self, new: Vec<Vec<u8>>) {
+        self.history = new;
+    }
+}
+
+impl HasTotpData for UserData {
+    fn get_totp_data(&self) -> Option<&TotpData> {
+        self.totp.as_ref()
+    }
+
+    fn set_totp_data(&mut self, new: Option<TotpData>) {
+        self.totp = new;
+    }
+}
+
 impl CanUpdateFrom<EmailUserIdent> for UserData {
     fn update_from(&mut self, ident: &EmailUserIdent) {
+        // the one-time password is received so email is verified
         self.email = Some(ident.email.clone());
+        self.email_verified = true;
+    }
+}
+
+#[cfg(feature = "send_sms")]
+impl CanUpdateFrom<PhoneUserIdent> for UserData {
+    fn update_from(&mut self, ident: &PhoneUserIdent) {
+        self.phone = Some(ident.phone.clone());
     }
 }
 
@@ -152,7 +240,7 @@ where
         }
 
         if let Some(email) = &view.email {
-            self.email = Some(email.clone());
+            self.set_email(Some(email.clone()));
         }
 
         if let Some(pass) = &view.pass {
@@ -215,6 +303,22 @@ impl IsUserStorage for Users {
         ))
     }
 
+    fn find_user_by_email(
+        &self,
+        email: &MailAddress,
+    ) -> BoxFuture<Option<Self::User>, Self::Error> {
+        Box::new(result(
+            self.users
+                .read()
+                .map(|users| {
+                    users
+                        .iter()
+                        .find(|data| data.email_verified && data.email.as_ref() == Some(email))
+                        .map(Clone::clone)
+                }).map_err(|_| DummyError),
+        ))
+    }
+
     fn put_user_data(&self, mut user: Self::User) -> BoxFuture<Self::User, Self::Error> {
         Box::new(result(
             self.users
@@ -230,6 +334,22 @@ impl IsUserStorage for Users {
                 }).map_err(|_| DummyError),
         ))
     }
+
+    fn add_user_data(&self, mut user: Self::User) -> BoxFuture<Option<Self::User>, Self::Error> {
+        Box::new(result(
+            self.users
+                .write()
+                .map(|mut users| {
+                    if users.iter().any(|data| data.name == user.name) {
+                        None
+                    } else {
+                        user.id = users.len() as u32 + 1;
+                        users.push(user.clone());
+                        Some(user)
+                    }
+                }).map_err(|_| DummyError),
+        ))
+    }
 }
 
 /// User personality information
@@ -256,11 +376,21 @@ pub struct AccountData {
     pub home_url: Option<Serde<Url>>,
     pub image_url: Option<Serde<Url>>,
     pub about: Option<String>,
+    #[serde(default)]
+    pub tokens: Option<AccountTokens>,
+    #[serde(default)]
+    pub organizations: Vec<String>,
+    #[serde(default)]
+    pub email_verified: bool,
 }
 
 impl CanUpdateFrom<AccountData> for UserData {
     fn update_from(&mut self, account: &AccountData) {
-        self.email = account.get_email().map(Clone::clone);
+        // the unverified email should not replace the verified one
+        if account.email_verified || !self.email_verified {
+            self.email = account.get_email().map(Clone::clone);
+            self.email_verified = account.email_verified && self.email.is_some();
+        }
     }
 }
 
@@ -297,6 +427,30 @@ impl IsAccountData for AccountData {
         self.user = new;
     }
 
+    fn get_account_tokens(&self) -> Option<&AccountTokens> {
+        self.tokens.as_ref()
+    }
+
+    fn set_account_tokens(&mut self, new: Option<AccountTokens>) {
+        self.tokens = new;
+    }
+
+    fn get_organizations(&self) -> &[String] {
+        &self.organizations
+    }
+
+    fn set_organizations(&mut self, new: Vec<String>) {
+        self.organizations = new;
+    }
+
+    fn email_verified(&self) -> bool {
+        self.email_verified
+    }
+
+    fn set_email_verified(&mut self, new: bool) {
+        self.email_verified = new;
+    }
+
     fn create_new<S: Into<String>>(name: S) -> Self {
         AccountData {
             id: 0,
@@ -320,6 +474,9 @@ impl IsAccountData for AccountData {
             home_url: None,
             image_url: None,
             about: None,
+            tokens: None,
+            organizations: Vec::new(),
+            email_verified: false,
         }
     }
 }
@@ -560,6 +717,21 @@ impl IsAccountStorage for Accounts {
         ))
     }
 
+    /// Get all accounts of user
+    fn get_user_accounts(&self, user: UserId) -> BoxFuture<Vec<Self::Account>, Self::Error> {
+        Box::new(result(
+            self.accounts
+                .read()
+                .map(|accounts| {
+                    accounts
+                        .iter()
+                        .filter(|data| data.user == user)
+                        .map(Clone::clone)
+                        .collect()
+                }).map_err(|_| DummyError),
+        ))
+    }
+
     /// Save user account
     fn put_user_account(
         &self,
@@ -572,11 +744,27 @@ impl IsAccountStorage for Accounts {
                     if let Some(index) = accounts.iter().position(|data| data.id == account.id) {
                         accounts[index] = account.clone();
                     } else {
-                        account.id = accounts.len() as u32 + 1;
+                        account.id = accounts.iter().map(|data| data.id).max().unwrap_or(0) + 1;
                         accounts.push(account.clone());
                     }
                     account
                 }).map_err(|_| DummyError),
         ))
     }
+
+    /// Delete user account
+    fn del_user_account(&self, account: AccountId) -> BoxFuture<Option<()>, Self::Error> {
+        Box::new(result(
+            self.accounts
+                .write()
+                .map(|mut accounts| {
+                    accounts
+                        .iter()
+                        .position(|data| data.id == account)
+                        .map(|index| {
+                            accounts.remove(index);
+                        })
+                }).map_err(|_| DummyError),
+        ))
+    }
 }
@@ -1,5 +1,6 @@
-use super::{AccountId, Gender, UserId};
+use super::{AccountId, AccountTokens, Gender, PasswordPolicy, UserId};
 use base::{BoxFuture, IsBackend, TimeStamp};
+use futures::future::ok;
 use mail::MailAddress;
 use std::borrow::Cow;
 use url::Url;
@@ -17,6 +18,16 @@ pub trait IsUserData: Sized {
 
     /// Create user data using unique name
     fn create_new<S: Into<String>>(name: S) -> Self;
+
+    /// Check that user is approved
+    ///
+    /// The user which does not support approval is always approved.
+    fn is_approved(&self) -> bool {
+        true
+    }
+
+    /// Set user approval
+    fn set_approved(&mut self, _approved: bool) {}
 }
 
 /// User data which has password
@@ -25,6 +36,19 @@ pub trait HasPasswordHash {
     fn set_password_hash<S: AsRef<[u8]>>(&mut self, new: Option<S>);
 }
 
+/// User data which keeps previous password hashes
+pub trait HasPasswordHistory {
+    fn get_password_history(&self) -> &[Vec<u8>];
+    fn set_password_history(&mut self, new: Vec<Vec<u8>>);
+}
+
+/// State has password policy
+pub trait HasPasswordPolicy
+where
+    Self: AsRef<PasswordPolicy>,
+{
+}
+
 /// Access to user data
 pub trait IsUserStorage: IsBackend {
     /// User data type
@@ -41,6 +65,24 @@ pub trait IsUserStorage: IsBackend {
 
     /// Save user data
     fn put_user_data(&self, user: Self::User) -> BoxFuture<Self::User, Self::Error>;
+
+    /// Add new user data
+    ///
+    /// The storage should check uniqueness of user name atomically with adding,
+    /// so `None` should be returned when user with same name already exists.
+    fn add_user_data(&self, user: Self::User) -> BoxFuture<Option<Self::User>, Self::Error>;
+
+    /// Get user data by email
+    ///
+    /// Only the users which email is verified should be found,
+    /// because the found user can be accessed using any account with same email.
+    /// The storage which cannot find users by email should return `None`.
+    fn find_user_by_email(
+        &self,
+        _email: &MailAddress,
+    ) -> BoxFuture<Option<Self::User>, Self::Error> {
+        Box::new(ok(None))
+    }
 }
 
 /// State has access to user data
@@ -80,6 +122,34 @@ pub trait IsAccountData {
 
     /// Create new with account name
     fn create_new<S: Into<String>>(name: S) -> Self;
+
+    /// Get third service tokens
+    ///
+    /// The account which does not store tokens should return `None`.
+    fn get_account_tokens(&self) -> Option<&AccountTokens> {
+        None
+    }
+
+    /// Set third service tokens
+    fn set_account_tokens(&mut self, _new: Option<AccountTokens>) {}
+
+    /// Get organizations which account belongs to
+    fn get_organizations(&self) -> &[String] {
+        &[]
+    }
+
+    /// Set organizations which account belongs to
+    fn set_organizations(&mut self, _new: Vec<String>) {}
+
+    /// Check that email of account is verified by third service
+    ///
+    /// Only verified email can be used to link account to existing user.
+    fn email_verified(&self) -> bool {
+        false
+    }
+
+    /// Set email verification flag
+    fn set_email_verified(&mut self, _new: bool) {}
 }
 
 /// Access to user account
@@ -98,8 +168,14 @@ pub trait IsAccountStorage: IsBackend {
     fn get_user_account(&self, account: AccountId)
         -> BoxFuture<Option<Self::Account>, Self::Error>;
 
+    /// Get all accounts of user
+    fn get_user_accounts(&self, user: UserId) -> BoxFuture<Vec<Self::Account>, Self::Error>;
+
     /// Save user account
     fn put_user_account(&self, account: Self::Account) -> BoxFuture<Self::Account, Self::Error>;
+
+    /// Delete user account
+    fn del_user_account(&self, account: AccountId) -> BoxFuture<Option<()>, Self::Error>;
 }
 
 /// State has access to user accounts
@@ -1,3 +1,4 @@
+use base::TimeStamp;
 use std::str::FromStr;
 
 /// Unique user identifier
@@ -12,6 +13,47 @@ pub struct UserArg {
     pub user: UserId,
 }
 
+/// Third service tokens of account
+///
+/// The tokens is sealed by server secure key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountTokens {
+    /// Sealed access token
+    pub access_token: String,
+    /// Sealed refresh token
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Access token expiration time
+    #[serde(default)]
+    pub etime: Option<TimeStamp>,
+}
+
+/// Change password request
+#[derive(Debug, Deserialize)]
+pub struct PasswordChange {
+    /// Current password
+    ///
+    /// Not required when user has no password yet.
+    #[serde(default)]
+    pub old: Option<String>,
+    /// New password
+    pub new: String,
+}
+
+/// Create user request
+#[derive(Debug, Deserialize)]
+pub struct NewUser {
+    /// Unique user name
+    pub name: String,
+    /// User password
+    pub pass: String,
+    /// Invite code
+    ///
+    /// Used on sign-up of new user (see [`ProvisionOptions`](auth::ProvisionOptions)).
+    #[serde(default)]
+    pub invite: Option<String>,
+}
+
 /// Gender type
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Gender {